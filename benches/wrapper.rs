@@ -0,0 +1,77 @@
+//! Tracks the overhead the Rust wrapper adds on top of minilzo.
+//!
+//! Small inputs are where per-call overhead (allocation, zeroing, bounds
+//! checks) shows up; the large input is dominated by the C code and serves
+//! as the baseline to compare against.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use minilzo_rs::{adler32, crc32, Decompressor, LZO};
+use std::hint::black_box;
+
+const SIZES: [usize; 4] = [64, 4096, 64 * 1024, 1024 * 1024];
+
+fn input(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i * 7 % 251) as u8 ^ (i >> 9) as u8)
+        .collect()
+}
+
+fn bench_compress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compress");
+    let mut lzo = LZO::new();
+    for len in SIZES {
+        let data = input(len);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &data, |b, data| {
+            b.iter(|| lzo.compress(black_box(data)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_decompress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decompress_safe");
+    let mut lzo = LZO::new();
+    let decompressor = Decompressor::new().unwrap();
+    for len in SIZES {
+        let compressed = lzo.compress(input(len)).unwrap();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &compressed, |b, src| {
+            b.iter(|| decompressor.decompress_safe(black_box(src), len).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_adler32(c: &mut Criterion) {
+    let mut group = c.benchmark_group("adler32");
+    for len in SIZES {
+        let data = input(len);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &data, |b, data| {
+            b.iter(|| adler32(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_crc32(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc32");
+    for len in SIZES {
+        let data = input(len);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &data, |b, data| {
+            b.iter(|| crc32(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_compress,
+    bench_decompress,
+    bench_adler32,
+    bench_crc32
+);
+criterion_main!(benches);
@@ -4,10 +4,60 @@
 // Distributed under terms of the MIT license.
 //
 
+use std::env;
+
 fn main() {
-    cc::Build::new()
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let mut build = cc::Build::new();
+    build
         .file("minilzo/minilzo.c")
         .warnings(false)
-        .extra_warnings(false)
-        .compile("minilzo.a");
+        .extra_warnings(false);
+    // wasm32-unknown-unknown has no libc: build minilzo freestanding, with
+    // its own memcpy and friends, against the compiler's own headers.
+    if arch == "wasm32" && os == "unknown" {
+        build
+            .define("LZO_CFG_FREESTANDING", "1")
+            .flag("-ffreestanding");
+    }
+    if env::var_os("CARGO_FEATURE_PORTABLE_C").is_none() {
+        tune(&mut build, &arch);
+    }
+    if env::var_os("CARGO_FEATURE_DEBUG_ASSERTS").is_some() {
+        debug_asserts(&mut build);
+    }
+    build.compile("minilzo.a");
+}
+
+/// Target-specific configuration for speed, unless the `portable-c`
+/// feature asks for minilzo's own conservative defaults.
+fn tune(build: &mut cc::Build, arch: &str) {
+    // minilzo only enables unaligned and 64-bit word access when it
+    // recognizes the compiler's architecture macros, which not every cross
+    // toolchain sets.
+    let unaligned: &[&str] = match arch {
+        "x86" => &["16", "32"],
+        "x86_64" | "aarch64" => &["16", "32", "64"],
+        _ => &[],
+    };
+    for bits in unaligned {
+        build.define(&format!("LZO_OPT_UNALIGNED{}", bits), "1");
+    }
+    // The coder is the crate's hot path in every profile, so don't leave it
+    // at -O0 in debug builds.
+    build.opt_level(3);
+}
+
+/// minilzo's internal assertions, plus the compiler's array bounds checks
+/// where it has them, for fuzzing and integration tests. Failures abort.
+fn debug_asserts(build: &mut cc::Build) {
+    build
+        .define("LZO_DEBUG", "1")
+        .debug(true)
+        .flag_if_supported("-fsanitize=bounds")
+        // Trap rather than call into a sanitizer runtime, which the Rust
+        // side doesn't link.
+        .flag_if_supported("-fsanitize-undefined-trap-on-error")
+        .flag_if_supported("-fsanitize-trap=bounds");
 }
@@ -0,0 +1,33 @@
+/*
+ * minilzo_rs.h
+ * C declarations for the minilzo-rs `capi` feature.
+ *
+ * Functions return 0 on success or a negative LZO_E_* error code, as in
+ * minilzo. On entry *dst_len is the capacity of dst; on success it is the
+ * number of bytes written.
+ */
+
+#ifndef MINILZO_RS_H
+#define MINILZO_RS_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* On LZO_E_OUTPUT_OVERRUN (-5), *dst_len is set to the size needed. */
+int minilzo_rs_compress(const unsigned char *src, size_t src_len,
+                        unsigned char *dst, size_t *dst_len);
+
+int minilzo_rs_decompress_safe(const unsigned char *src, size_t src_len,
+                               unsigned char *dst, size_t *dst_len);
+
+/* A static description of an error code. */
+const char *minilzo_rs_strerror(int code);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MINILZO_RS_H */
@@ -0,0 +1,586 @@
+//! Multi-member archives, committed atomically.
+//!
+//! `ArchiveWriter` streams members to the destination as they are added;
+//! `WriteBatch` stages them in memory and writes the archive in one go.
+//!
+//! An archive starts with `b"MLZA"` and a version byte, followed by its
+//! members, each a complete framed stream. After the members comes the
+//! table of contents and, last of all, a 20-byte trailer: the TOC offset
+//! (`u64`), its length and adler32 (`u32` each) and `b"MLZA"` again.
+//!
+//! Because the trailer is written last, an archive whose writer stopped
+//! midway has no trailer, and `ArchiveReader::open` reports
+//! `Error::Uncommitted` instead of mistaking it for a corrupt archive.
+
+use crate::file::write_atomically;
+use crate::frame::{self, ParityHeader};
+use crate::scratch::try_zeroed;
+use crate::transcode::Decoder;
+use crate::{
+    adler32, Adler32, BlockMeta, Container, Decompressor, Error, LZOResult, LzoReader,
+    DEFAULT_BLOCK_SIZE, LZO,
+};
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"MLZA";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 5;
+const TRAILER_LEN: usize = 20;
+
+/// One member of an archive, as recorded in its table of contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Name of the member.
+    pub name: String,
+    /// Offset of the member's framed stream from the start of the archive.
+    pub offset: u64,
+    /// Length of the member's framed stream.
+    pub compressed_len: u64,
+    /// Length of the member's data.
+    pub uncompressed_len: u64,
+    /// adler32 of the member's data.
+    pub checksum: u32,
+}
+
+fn encode_toc(entries: &[Entry]) -> LZOResult<Vec<u8>> {
+    let count = u32::try_from(entries.len()).map_err(|_| Error::InvalidArgument)?;
+    let mut out = Vec::new();
+    out.extend_from_slice(&count.to_be_bytes());
+    for entry in entries {
+        out.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(&entry.offset.to_be_bytes());
+        out.extend_from_slice(&entry.compressed_len.to_be_bytes());
+        out.extend_from_slice(&entry.uncompressed_len.to_be_bytes());
+        out.extend_from_slice(&entry.checksum.to_be_bytes());
+    }
+    Ok(out)
+}
+
+fn decode_toc(mut buf: &[u8]) -> LZOResult<Vec<Entry>> {
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> LZOResult<&'a [u8]> {
+        if buf.len() < n {
+            return Err(Error::InvalidHeader);
+        }
+        let (head, rest) = buf.split_at(n);
+        *buf = rest;
+        Ok(head)
+    }
+    fn u64_at(buf: &mut &[u8]) -> LZOResult<u64> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(take(buf, 8)?);
+        Ok(u64::from_be_bytes(b))
+    }
+    fn u32_at(buf: &mut &[u8]) -> LZOResult<u32> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(take(buf, 4)?);
+        Ok(u32::from_be_bytes(b))
+    }
+
+    let count = u32_at(&mut buf)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let name_len = take(&mut buf, 2)?;
+        let name_len = u16::from_be_bytes([name_len[0], name_len[1]]) as usize;
+        let name = String::from_utf8(take(&mut buf, name_len)?.to_vec())
+            .map_err(|_| Error::InvalidHeader)?;
+        entries.push(Entry {
+            name,
+            offset: u64_at(&mut buf)?,
+            compressed_len: u64_at(&mut buf)?,
+            uncompressed_len: u64_at(&mut buf)?,
+            checksum: u32_at(&mut buf)?,
+        });
+    }
+    if !buf.is_empty() {
+        return Err(Error::InvalidHeader);
+    }
+    Ok(entries)
+}
+
+fn write_header<W: Write>(out: &mut W) -> io::Result<()> {
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    out.write_all(&header)
+}
+
+/// Write the table of contents, found at `toc_offset`, and the trailer.
+fn write_toc<W: Write>(out: &mut W, toc_offset: u64, entries: &[Entry]) -> io::Result<()> {
+    let toc = encode_toc(entries).map_err(frame::to_io)?;
+    let toc_len = u32::try_from(toc.len()).map_err(|_| frame::to_io(Error::InvalidArgument))?;
+    out.write_all(&toc)?;
+    let mut trailer = Vec::with_capacity(TRAILER_LEN);
+    trailer.extend_from_slice(&toc_offset.to_be_bytes());
+    trailer.extend_from_slice(&toc_len.to_be_bytes());
+    trailer.extend_from_slice(&adler32(&toc).to_be_bytes());
+    trailer.extend_from_slice(MAGIC);
+    out.write_all(&trailer)?;
+    out.flush()
+}
+
+/// Writes an archive member by member, without holding more than one
+/// block of any member in memory.
+///
+/// Each member is compressed and written as it is added; `finish` writes
+/// the table of contents and trailer. An archive whose writer is dropped
+/// without `finish` is left uncommitted.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{ArchiveReader, ArchiveWriter};
+///
+/// let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+/// writer.add("textures/grass.raw", &[0x33u8; 100_000]).unwrap();
+/// writer.add_reader("sounds/step.wav", &b"RIFF...."[..]).unwrap();
+/// let pack = writer.finish().unwrap();
+///
+/// let mut reader = ArchiveReader::open(std::io::Cursor::new(pack)).unwrap();
+/// assert_eq!(reader.entries()[0].uncompressed_len, 100_000);
+/// assert_eq!(reader.read("sounds/step.wav").unwrap(), b"RIFF....");
+/// ```
+pub struct ArchiveWriter<W: Write> {
+    inner: W,
+    lzo: LZO,
+    pos: u64,
+    entries: Vec<Entry>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Start an archive, writing its header to `inner`.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        write_header(&mut inner)?;
+        Ok(ArchiveWriter {
+            inner,
+            lzo: LZO::new(),
+            pos: HEADER_LEN,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// The members written so far.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Add a member holding `data`. Names must be unique and at most
+    /// `u16::MAX` bytes.
+    pub fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.add_reader(name, data)
+    }
+
+    /// Add a member holding everything read from `data`, compressing it a
+    /// block at a time.
+    pub fn add_reader<R: Read>(&mut self, name: &str, mut data: R) -> io::Result<()> {
+        if name.len() > u16::MAX as usize || self.entries.iter().any(|e| e.name == name) {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        let offset = self.pos;
+        let mut out = Vec::new();
+        frame::encode_header(&mut out);
+        let mut checksum = Adler32::new();
+        let mut len = 0u64;
+        let mut block = Vec::with_capacity(DEFAULT_BLOCK_SIZE);
+        loop {
+            self.write(&out)?;
+            out.clear();
+            block.clear();
+            data.by_ref()
+                .take(DEFAULT_BLOCK_SIZE as u64)
+                .read_to_end(&mut block)?;
+            if block.is_empty() {
+                break;
+            }
+            checksum.update(&block);
+            len += block.len() as u64;
+            frame::encode_block(&mut self.lzo, &block, &mut out).map_err(frame::to_io)?;
+        }
+        frame::encode_end(&mut out);
+        self.write(&out)?;
+        self.entries.push(Entry {
+            name: name.to_owned(),
+            offset,
+            compressed_len: self.pos - offset,
+            uncompressed_len: len,
+            checksum: checksum.finish(),
+        });
+        Ok(())
+    }
+
+    /// Add a member holding the data of the `from` container read from
+    /// `input`, decompressing it a block at a time, to migrate data into
+    /// the archive without expanding it anywhere first.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{ArchiveReader, ArchiveWriter, Container};
+    ///
+    /// let framed = minilzo_rs::LZO::new().compress_chunked(&[0x42u8; 300_000]).unwrap();
+    /// let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+    /// writer.add_transcoded("old.lzo", &framed[..], Container::Framed).unwrap();
+    ///
+    /// let mut reader = ArchiveReader::open(std::io::Cursor::new(writer.finish().unwrap())).unwrap();
+    /// assert_eq!(reader.read_range("old.lzo", 299_990..300_000).unwrap(), [0x42u8; 10]);
+    /// ```
+    pub fn add_transcoded<R: Read>(
+        &mut self,
+        name: &str,
+        input: R,
+        from: Container,
+    ) -> io::Result<()> {
+        self.add_reader(name, Decoder::new(input, from)?)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf)?;
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Write the table of contents and trailer, committing the archive, and
+    /// return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        write_toc(&mut self.inner, self.pos, &self.entries)?;
+        Ok(self.inner)
+    }
+}
+
+/// A set of members staged in memory and written out together.
+///
+/// Members are compressed as they are `put`; nothing reaches the
+/// destination until `write_to` or `commit`, which write the whole archive
+/// with its trailer last.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{ArchiveReader, WriteBatch};
+///
+/// let mut batch = WriteBatch::new();
+/// batch.put("a.txt", b"first member").unwrap();
+/// batch.put("b.bin", &[0u8; 4096]).unwrap();
+/// let archive = batch.write_to(Vec::new()).unwrap();
+///
+/// let mut reader = ArchiveReader::open(std::io::Cursor::new(archive)).unwrap();
+/// assert_eq!(reader.entries().len(), 2);
+/// assert_eq!(reader.read("a.txt").unwrap(), b"first member");
+/// ```
+#[derive(Default)]
+pub struct WriteBatch {
+    lzo: LZO,
+    members: Vec<(Entry, Vec<u8>)>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of staged members.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// `true` if nothing has been staged.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Stage a member. Names must be unique and at most `u16::MAX` bytes.
+    pub fn put(&mut self, name: &str, data: &[u8]) -> LZOResult<()> {
+        if name.len() > u16::MAX as usize || self.members.iter().any(|(e, _)| e.name == name) {
+            return Err(Error::InvalidArgument);
+        }
+        let mut framed = Vec::new();
+        frame::encode_header(&mut framed);
+        for block in data.chunks(DEFAULT_BLOCK_SIZE) {
+            frame::encode_block(&mut self.lzo, block, &mut framed)?;
+        }
+        frame::encode_end(&mut framed);
+        let entry = Entry {
+            name: name.to_owned(),
+            offset: 0,
+            compressed_len: framed.len() as u64,
+            uncompressed_len: data.len() as u64,
+            checksum: adler32(data),
+        };
+        self.members.push((entry, framed));
+        Ok(())
+    }
+
+    /// Write the archive to `out` and return it.
+    pub fn write_to<W: Write>(self, mut out: W) -> io::Result<W> {
+        write_header(&mut out)?;
+        let mut pos = HEADER_LEN;
+        let mut entries = Vec::with_capacity(self.members.len());
+        for (mut entry, framed) in self.members {
+            out.write_all(&framed)?;
+            entry.offset = pos;
+            pos += framed.len() as u64;
+            entries.push(entry);
+        }
+        write_toc(&mut out, pos, &entries)?;
+        Ok(out)
+    }
+
+    /// Write the archive to `path` atomically.
+    ///
+    /// The archive is written to a temporary file next to `path`, synced,
+    /// and renamed over `path`, so readers see either the old file or the
+    /// complete new archive.
+    pub fn commit<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        write_atomically(path.as_ref(), |file| self.write_to(file)).map(|_| ())
+    }
+}
+
+fn find<'a>(entries: &'a [Entry], name: &str) -> io::Result<&'a Entry> {
+    entries
+        .iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+}
+
+/// Reads the members of a committed archive.
+pub struct ArchiveReader<R: Read + Seek> {
+    inner: R,
+    entries: Vec<Entry>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Open an archive, reading its table of contents.
+    ///
+    /// Fails with `Error::Uncommitted` (as `io::ErrorKind::InvalidData`) if
+    /// the archive has no trailer.
+    pub fn open(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(frame::to_io(Error::InvalidHeader));
+        }
+        let len = inner.seek(SeekFrom::End(0))?;
+        let mut trailer = [0u8; TRAILER_LEN];
+        if len < HEADER_LEN + TRAILER_LEN as u64 {
+            return Err(frame::to_io(Error::Uncommitted));
+        }
+        inner.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+        inner.read_exact(&mut trailer)?;
+        if &trailer[16..] != MAGIC {
+            return Err(frame::to_io(Error::Uncommitted));
+        }
+        let mut toc_offset = [0u8; 8];
+        toc_offset.copy_from_slice(&trailer[..8]);
+        let toc_offset = u64::from_be_bytes(toc_offset);
+        let toc_len = u32::from_be_bytes([trailer[8], trailer[9], trailer[10], trailer[11]]);
+        let toc_checksum = u32::from_be_bytes([trailer[12], trailer[13], trailer[14], trailer[15]]);
+        if toc_offset + toc_len as u64 + TRAILER_LEN as u64 != len {
+            return Err(frame::to_io(Error::InvalidHeader));
+        }
+        let mut toc = try_zeroed(toc_len as usize).map_err(Error::from)?;
+        inner.seek(SeekFrom::Start(toc_offset))?;
+        inner.read_exact(&mut toc)?;
+        if adler32(&toc) != toc_checksum {
+            return Err(frame::to_io(Error::ChecksumMismatch));
+        }
+        let entries = decode_toc(&toc).map_err(frame::to_io)?;
+        Ok(ArchiveReader { inner, entries })
+    }
+
+    /// The members of the archive, in the order they were written.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Decompress the member called `name`.
+    pub fn read(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let entry = find(&self.entries, name)?;
+        self.inner.seek(SeekFrom::Start(entry.offset))?;
+        let member = (&mut self.inner).take(entry.compressed_len);
+        let mut data = Vec::new();
+        LzoReader::new(member)
+            .map_err(frame::to_io)?
+            .read_to_end(&mut data)?;
+        if data.len() as u64 != entry.uncompressed_len || adler32(&data) != entry.checksum {
+            return Err(frame::to_io(Error::ChecksumMismatch));
+        }
+        Ok(data)
+    }
+
+    /// A reader decompressing the member called `name` as it is read, for
+    /// members too large to hold in memory.
+    ///
+    /// Each block is checked against its own checksum; the member's
+    /// checksum in the table of contents is checked only by `read`.
+    pub fn open_member(&mut self, name: &str) -> io::Result<LzoReader<io::Take<&mut R>>> {
+        let entry = find(&self.entries, name)?;
+        let (offset, len) = (entry.offset, entry.compressed_len);
+        self.inner.seek(SeekFrom::Start(offset))?;
+        LzoReader::new((&mut self.inner).take(len)).map_err(frame::to_io)
+    }
+
+    /// Decompress bytes `range` of the member called `name`.
+    ///
+    /// Only the blocks overlapping `range` are read and decompressed; the
+    /// others are seeked over using their headers. A range reaching past
+    /// the end of the member fails with `io::ErrorKind::InvalidInput`.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{ArchiveReader, ArchiveWriter};
+    ///
+    /// let level: Vec<u8> = (0..1_000_000u32).map(|i| (i % 199) as u8).collect();
+    /// let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+    /// writer.add("level.bin", &level).unwrap();
+    /// let pack = writer.finish().unwrap();
+    ///
+    /// let mut reader = ArchiveReader::open(std::io::Cursor::new(pack)).unwrap();
+    /// let tile = reader.read_range("level.bin", 700_000..700_100).unwrap();
+    /// assert_eq!(tile, &level[700_000..700_100]);
+    /// ```
+    pub fn read_range(&mut self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let entry = find(&self.entries, name)?;
+        if range.start > range.end || range.end > entry.uncompressed_len {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        let offset = entry.offset;
+        // The range has to fit in memory at once.
+        let len = usize::try_from(range.end - range.start)
+            .map_err(|_| frame::to_io(Error::InvalidArgument))?;
+        let mut out = Vec::with_capacity(len);
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; frame::HEADER_LEN];
+        self.inner.read_exact(&mut header)?;
+        let checksum =
+            frame::checksum_from_flags(frame::parse_header(&header).map_err(frame::to_io)?);
+        let decompressor = Decompressor::new().map_err(frame::to_io)?;
+        // Uncompressed offset of the next block.
+        let mut pos = 0u64;
+        while pos < range.end {
+            let mut head = [0u8; frame::BLOCK_HEADER_LEN];
+            self.inner.read_exact(&mut head)?;
+            if head[..4] == frame::PARITY_MARKER {
+                let skip = ParityHeader::read_from(&head)
+                    .map_err(frame::to_io)?
+                    .frame_len();
+                self.inner
+                    .seek(SeekFrom::Current((skip - frame::BLOCK_HEADER_LEN) as i64))?;
+                continue;
+            }
+            let meta = BlockMeta::read_from(&head);
+            if meta.uncompressed_len == 0 {
+                return Err(frame::to_io(Error::InvalidHeader));
+            }
+            let end = pos + meta.uncompressed_len as u64;
+            if end <= range.start {
+                self.inner
+                    .seek(SeekFrom::Current(meta.compressed_len as i64))?;
+            } else {
+                let mut payload = try_zeroed(meta.compressed_len as usize).map_err(Error::from)?;
+                self.inner.read_exact(&mut payload)?;
+                let block = frame::decode_block(&decompressor, &meta, &payload, checksum)
+                    .map_err(frame::to_io)?;
+                let from = range.start.saturating_sub(pos) as usize;
+                let to = (range.end.min(end) - pos) as usize;
+                out.extend_from_slice(&block[from..to]);
+            }
+            pos = end;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_commit_and_uncommitted() {
+        let path = std::env::temp_dir().join("minilzo-rs-archive-commit.mlza");
+        let mut batch = WriteBatch::new();
+        batch.put("one", &[1u8; 10_000]).unwrap();
+        batch.put("two", b"second").unwrap();
+        assert_eq!(batch.put("one", b"again"), Err(Error::InvalidArgument));
+        batch.commit(&path).unwrap();
+
+        let archive = fs::read(&path).unwrap();
+        let mut reader = ArchiveReader::open(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.read("one").unwrap(), vec![1u8; 10_000]);
+        assert_eq!(reader.read("two").unwrap(), b"second");
+        fs::remove_file(&path).unwrap();
+
+        // A writer that died before the trailer leaves no commit.
+        let cut = &archive[..archive.len() - 1];
+        let err = ArchiveReader::open(Cursor::new(cut)).err().unwrap();
+        let inner = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert_eq!(*inner, Error::Uncommitted);
+    }
+
+    #[test]
+    fn test_archive_writer_streams_members() {
+        let big: Vec<u8> = (0..600_000u32).map(|i| (i % 241) as u8).collect();
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer.add_reader("big", &big[..]).unwrap();
+        writer.add("empty", b"").unwrap();
+        assert!(writer.add("big", b"again").is_err());
+        let written = writer.get_ref().len() as u64;
+        let entry = writer.entries()[0].clone();
+        assert_eq!(entry.offset, HEADER_LEN);
+        assert!(entry.offset + entry.compressed_len < written);
+        let pack = writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(Cursor::new(pack)).unwrap();
+        assert_eq!(reader.entries()[0], entry);
+        assert_eq!(reader.read("big").unwrap(), big);
+        assert_eq!(reader.read("empty").unwrap(), b"");
+    }
+
+    #[test]
+    fn test_random_access() {
+        let big: Vec<u8> = (0..3 * DEFAULT_BLOCK_SIZE as u32)
+            .map(|i| (i % 233) as u8)
+            .collect();
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer.add("big", &big).unwrap();
+        writer.add("small", b"tiny").unwrap();
+        let entry = writer.entries()[0].clone();
+        let mut pack = writer.finish().unwrap();
+        // Damage the last block: ranges that don't touch it still read.
+        pack[(entry.offset + entry.compressed_len) as usize - 10] ^= 0xff;
+
+        let mut reader = ArchiveReader::open(Cursor::new(pack)).unwrap();
+        let block = DEFAULT_BLOCK_SIZE as u64;
+        for range in [0..10, block - 5..block + 5, 100..2 * block, 7..7] {
+            let (start, end) = (range.start as usize, range.end as usize);
+            assert_eq!(reader.read_range("big", range).unwrap(), &big[start..end]);
+        }
+        assert!(reader.read_range("big", 2 * block..2 * block + 1).is_err());
+        assert!(reader.read("big").is_err());
+        let err = reader.read_range("small", 0..5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let mut small = String::new();
+        reader
+            .open_member("small")
+            .unwrap()
+            .read_to_string(&mut small)
+            .unwrap();
+        assert_eq!(small, "tiny");
+        assert_eq!(
+            reader.open_member("missing").err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Assets compressed at build time and decompressed on first use.
+//!
+//! A build script compresses each asset into `OUT_DIR`, with minilzo-rs as
+//! a build dependency:
+//!
+//! ```text
+//! // build.rs
+//! fn main() {
+//!     minilzo_rs::build_asset("assets/map.bin").unwrap();
+//! }
+//! ```
+//!
+//! and `include_lzo!` embeds the compressed bytes in the binary as an
+//! `LzoAsset`, which decompresses them the first time they are needed:
+//!
+//! ```text
+//! static MAP: minilzo_rs::LzoAsset = minilzo_rs::include_lzo!("map.bin");
+//!
+//! let map: &[u8] = MAP.get();
+//! ```
+
+use crate::{decompress_chunked, frame, LZO};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Compress the file at `src` into a framed stream at `dst`, for embedding
+/// as an `LzoAsset`.
+pub fn compress_asset<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<()> {
+    let data = fs::read(src)?;
+    let packed = LZO::new().compress_chunked(&data).map_err(frame::to_io)?;
+    fs::write(dst, packed)
+}
+
+/// Compress the file at `src` into `OUT_DIR` under its file name plus
+/// `.lzo`, where `include_lzo!` finds it, and tell cargo to rerun the build
+/// script when it changes. For use in build scripts; returns the path
+/// written.
+pub fn build_asset<P: AsRef<Path>>(src: P) -> io::Result<PathBuf> {
+    let src = src.as_ref();
+    let out_dir = env::var_os("OUT_DIR")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "OUT_DIR is not set"))?;
+    let mut name = src
+        .file_name()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?
+        .to_owned();
+    name.push(".lzo");
+    let dst = Path::new(&out_dir).join(name);
+    compress_asset(src, &dst)?;
+    println!("cargo:rerun-if-changed={}", src.display());
+    Ok(dst)
+}
+
+/// Embed an asset `build_asset` compressed into `OUT_DIR`, named by its
+/// original file name, as an `LzoAsset`.
+#[macro_export]
+macro_rules! include_lzo {
+    ($name:expr) => {
+        $crate::LzoAsset::new(include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".lzo")))
+    };
+}
+
+/// Compressed bytes embedded in the binary, decompressed once, on first
+/// access.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::LzoAsset;
+///
+/// let packed = minilzo_rs::LZO::new().compress_chunked(vec![b'#'; 50_000]).unwrap();
+/// let asset = LzoAsset::new(Box::leak(packed.into_boxed_slice()));
+/// assert!(asset.compressed().len() < 1000);
+/// assert_eq!(asset.get(), &[b'#'; 50_000][..]);
+/// ```
+pub struct LzoAsset {
+    compressed: &'static [u8],
+    data: OnceLock<Vec<u8>>,
+}
+
+impl LzoAsset {
+    /// Wrap a framed stream, as written by `compress_asset`.
+    pub const fn new(compressed: &'static [u8]) -> Self {
+        LzoAsset {
+            compressed,
+            data: OnceLock::new(),
+        }
+    }
+
+    /// The embedded compressed bytes.
+    pub fn compressed(&self) -> &'static [u8] {
+        self.compressed
+    }
+
+    /// The decompressed asset, decompressing it on the first call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the embedded bytes aren't a valid framed stream, which
+    /// for bytes from `compress_asset` means the binary is corrupt.
+    pub fn get(&self) -> &[u8] {
+        self.data.get_or_init(|| {
+            decompress_chunked(self.compressed).expect("embedded LZO asset is corrupt")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_asset_roundtrip() {
+        let dir = env::temp_dir();
+        let (src, dst) = (
+            dir.join("minilzo-rs-asset.bin"),
+            dir.join("minilzo-rs-asset.bin.lzo"),
+        );
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 113) as u8).collect();
+        fs::write(&src, &data).unwrap();
+        compress_asset(&src, &dst).unwrap();
+
+        let asset = LzoAsset::new(Box::leak(fs::read(&dst).unwrap().into_boxed_slice()));
+        assert!(asset.compressed().len() < data.len() / 10);
+        assert_eq!(asset.get(), &data[..]);
+        assert!(std::ptr::eq(asset.get(), asset.get()));
+
+        fs::remove_file(src).unwrap();
+        fs::remove_file(dst).unwrap();
+    }
+}
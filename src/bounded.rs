@@ -0,0 +1,221 @@
+//! A framed-stream reader in a fixed amount of memory.
+
+use crate::frame::{self, ParityHeader, BLOCK_HEADER_LEN, HEADER_LEN, PARITY_MARKER};
+use crate::scratch::try_zeroed;
+use crate::{BlockMeta, Checksum, Decompressor, Error, LZOResult, MAX_BLOCK_SIZE};
+use std::io::{self, Read};
+
+/// Decompresses a framed stream using two buffers allocated up front and
+/// never grown: one holding a compressed block and one holding its output,
+/// each about `max_block_size` bytes.
+///
+/// Made for devices decompressing an image out of flash, with RAM for a
+/// block but not for the whole output. Input is read exactly as needed,
+/// frame by frame, so there is no read-ahead buffer either. A block larger
+/// than `max_block_size` fails with `Error::OutputOverrun`; streams
+/// written with `LzoWriter::with_block_size` at or below it always fit.
+/// Parity frames and header metadata are skipped. Streams compressed
+/// against a preset dictionary, or chained ones written by
+/// `LzoWriter::chained`, fail with `Error::NotYetImplemented`.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// let image: Vec<u8> = (0..50_000u32).map(|i| (i % 201) as u8).collect();
+/// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 2048).unwrap();
+/// writer.write_all(&image).unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let mut reader = minilzo_rs::BoundedLzoReader::new(&framed[..], 2048).unwrap();
+/// assert!(reader.memory_size() <= 2 * 2048 + 12);
+/// let mut data = Vec::new();
+/// reader.read_to_end(&mut data).unwrap();
+/// assert_eq!(data, image);
+/// ```
+pub struct BoundedLzoReader<R: Read> {
+    inner: R,
+    decompressor: Decompressor,
+    input: Box<[u8]>,
+    window: Box<[u8]>,
+    len: usize,
+    pos: usize,
+    checksum: Option<Checksum>,
+    delta: Option<usize>,
+    done: bool,
+}
+
+impl<R: Read> BoundedLzoReader<R> {
+    /// Create a reader for blocks of at most `max_block_size` bytes,
+    /// between 1 and `MAX_BLOCK_SIZE`, allocating both of its buffers.
+    pub fn new(inner: R, max_block_size: usize) -> LZOResult<Self> {
+        if max_block_size == 0 || max_block_size > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(BoundedLzoReader {
+            inner,
+            decompressor: Decompressor::new()?,
+            input: try_zeroed(BLOCK_HEADER_LEN + max_block_size)?.into_boxed_slice(),
+            window: try_zeroed(max_block_size)?.into_boxed_slice(),
+            len: 0,
+            pos: 0,
+            checksum: None,
+            delta: None,
+            done: false,
+        })
+    }
+
+    /// The bytes held by the reader's two buffers.
+    pub fn memory_size(&self) -> usize {
+        self.input.len() + self.window.len()
+    }
+
+    /// `true` once the end marker has been decoded and all data read.
+    pub fn is_done(&self) -> bool {
+        self.done && self.pos == self.len
+    }
+
+    /// Unwrap the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_header(&mut self) -> io::Result<Checksum> {
+        self.inner.read_exact(&mut self.input[..HEADER_LEN])?;
+        // Its fixed fields alone may not fit in the input buffer.
+        if self.input[5] & frame::FLAG_DICT != 0 {
+            return Err(frame::to_io(Error::NotYetImplemented));
+        }
+        let len = frame::header_len(self.input[5]);
+        self.inner.read_exact(&mut self.input[HEADER_LEN..len])?;
+        let flags = frame::parse_header(&self.input[..len]).map_err(frame::to_io)?;
+        if frame::chained(&self.input[..len]) {
+            return Err(frame::to_io(Error::NotYetImplemented));
+        }
+        self.delta = frame::delta_from_header(&self.input[..len]);
+        let header = &self.input[..len];
+        self.skip(frame::metadata_len(header) + frame::extensions_len(header))?;
+        Ok(frame::checksum_from_flags(flags))
+    }
+
+    /// Read and drop `n` bytes, through the input buffer.
+    fn skip(&mut self, mut n: usize) -> io::Result<()> {
+        while n > 0 {
+            let step = n.min(self.input.len());
+            self.inner.read_exact(&mut self.input[..step])?;
+            n -= step;
+        }
+        Ok(())
+    }
+
+    /// Decode the next block into the window, or note the end marker.
+    fn next_block(&mut self) -> io::Result<()> {
+        let checksum = match self.checksum {
+            Some(checksum) => checksum,
+            None => {
+                let checksum = self.read_header()?;
+                self.checksum = Some(checksum);
+                checksum
+            }
+        };
+        self.inner.read_exact(&mut self.input[..4])?;
+        if self.input[..4] == [0u8; 4] {
+            self.done = true;
+            return Ok(());
+        }
+        self.inner
+            .read_exact(&mut self.input[4..BLOCK_HEADER_LEN])?;
+        if self.input[..4] == PARITY_MARKER {
+            let len = ParityHeader::read_from(&self.input)
+                .map_err(frame::to_io)?
+                .frame_len();
+            return self.skip(len - BLOCK_HEADER_LEN);
+        }
+        let meta = BlockMeta::read_from(&self.input);
+        let (len, payload_len) = (meta.uncompressed_len as usize, meta.compressed_len as usize);
+        if len > self.window.len() {
+            return Err(frame::to_io(Error::OutputOverrun {
+                capacity: self.window.len(),
+                needed: Some(len),
+            }));
+        }
+        if payload_len > len {
+            return Err(frame::to_io(Error::InvalidHeader));
+        }
+        let (payload, window) = (&mut self.input[..payload_len], &mut self.window[..len]);
+        self.inner.read_exact(payload)?;
+        if payload_len == len {
+            window.copy_from_slice(payload);
+        } else if self
+            .decompressor
+            .decompress_safe_slice(payload, window)
+            .map_err(frame::to_io)?
+            != len
+        {
+            return Err(frame::to_io(Error::InputOverrun));
+        }
+        if checksum != Checksum::None && checksum.compute(window) != meta.checksum {
+            return Err(frame::to_io(Error::ChecksumMismatch));
+        }
+        if let Some(stride) = self.delta {
+            crate::delta::decode(window, stride);
+        }
+        self.len = len;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BoundedLzoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.len && !self.done {
+            self.next_block()?;
+        }
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.window[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LzoWriter, LZO};
+    use std::io::Write;
+
+    #[test]
+    fn test_bounded_reader() {
+        let input: Vec<u8> = (0..30_000u32).map(|i| (i / 7 % 256) as u8).collect();
+        let lzo = LZO::builder()
+            .block_size(1000)
+            .checksum(Checksum::Crc32)
+            .delta(2)
+            .build()
+            .unwrap();
+        let mut writer = LzoWriter::from_lzo(Vec::new(), lzo).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut reader = BoundedLzoReader::new(&framed[..], 1000).unwrap();
+        let mut output = Vec::new();
+        let mut chunk = [0u8; 37];
+        while !reader.is_done() {
+            let n = reader.read(&mut chunk).unwrap();
+            output.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(output, input);
+        assert_eq!(reader.memory_size(), 2012);
+
+        let mut reader = BoundedLzoReader::new(&framed[..], 999).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut reader = BoundedLzoReader::new(&framed[..framed.len() - 2], 1000).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        assert!(BoundedLzoReader::new(&framed[..], 0).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+//! Configuration of `LZO` instances.
+
+use crate::{Error, LZOResult, DEFAULT_BLOCK_SIZE, LZO, MAX_BLOCK_SIZE};
+use std::fmt;
+use std::str::FromStr;
+
+/// Compression algorithm.
+///
+/// `compress` dispatches on the configured algorithm, so it can be chosen
+/// at runtime, e.g. from a command-line flag, by parsing its name:
+///
+/// ```rust
+/// use minilzo_rs::Algorithm;
+///
+/// let algorithm: Algorithm = "auto".parse().unwrap();
+/// assert_eq!(algorithm, Algorithm::Auto);
+/// assert_eq!(algorithm.to_string(), "auto");
+/// assert_eq!("lzo1x-1".parse(), Ok(Algorithm::Lzo1x1));
+/// ```
+///
+/// minilzo only ships the LZO1X-1 compressor, so both algorithms produce
+/// LZO1X, which the one decompressor reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// LZO1X-1, the only algorithm minilzo ships.
+    #[default]
+    Lzo1x1,
+    /// Pick per input: LZO1X-1, or an LZO1X stream holding the input
+    /// stored as literals when a quick entropy probe finds it
+    /// incompressible, which costs a copy instead of a compression pass.
+    ///
+    /// Both are LZO1X, so any LZO1X decoder reads the output; framed
+    /// streams store such blocks as is, which their block headers already
+    /// record. LZO1X-1 is the only compressor minilzo ships, so it is the
+    /// only one chosen from.
+    Auto,
+}
+
+impl Algorithm {
+    /// `true` if this algorithm produces the LZO1X format, which the
+    /// LZO1X decompressor reads regardless of the compressor used.
+    pub fn is_lzo1x(self) -> bool {
+        matches!(self, Algorithm::Lzo1x1 | Algorithm::Auto)
+    }
+
+    /// Bytes of work memory this algorithm's compressor needs, the LZO
+    /// library's `*_MEM_COMPRESS` for it. Decompressing needs none.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{Algorithm, LZO};
+    ///
+    /// const WORK_MEM: usize = Algorithm::Lzo1x1.work_mem_size();
+    /// assert_eq!(LZO::new().work_mem_size(), WORK_MEM);
+    /// ```
+    pub const fn work_mem_size(self) -> usize {
+        // A hash table of 16384 pointer-sized entries.
+        match self {
+            Algorithm::Lzo1x1 | Algorithm::Auto => crate::minilzo::LZO1X_1_MEM_COMPRESS,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Lzo1x1 => f.write_str("lzo1x-1"),
+            Algorithm::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    /// Parse the names `Display` produces, failing with
+    /// `Error::InvalidArgument` for any other.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "lzo1x-1" => Ok(Algorithm::Lzo1x1),
+            "auto" => Ok(Algorithm::Auto),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+/// Checksum written by the framed encoders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum Checksum {
+    /// adler32 of every block's uncompressed data, verified on decode.
+    #[default]
+    Adler32,
+    /// No checksum; decoders skip verification for streams marked this way.
+    None,
+    /// CRC-32 of every block's uncompressed data, verified on decode.
+    Crc32,
+}
+
+impl Checksum {
+    /// Checksum of `data` under this policy; zero for `Checksum::None`.
+    pub(crate) fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            Checksum::Adler32 => crate::adler32(data),
+            Checksum::Crc32 => crate::crc32(data),
+            Checksum::None => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Config {
+    pub(crate) algorithm: Algorithm,
+    pub(crate) level: u8,
+    pub(crate) checksum: Checksum,
+    pub(crate) max_output: Option<usize>,
+    pub(crate) max_output_size: Option<usize>,
+    pub(crate) block_size: usize,
+    pub(crate) delta: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            algorithm: Algorithm::Lzo1x1,
+            level: 1,
+            checksum: Checksum::Adler32,
+            max_output: None,
+            max_output_size: None,
+            block_size: DEFAULT_BLOCK_SIZE,
+            delta: None,
+        }
+    }
+}
+
+/// Builds a configured `LZO`.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{Checksum, LZO};
+///
+/// let mut lzo = LZO::builder()
+///     .checksum(Checksum::None)
+///     .max_output(64)
+///     .block_size(64 * 1024)
+///     .build()
+///     .unwrap();
+/// assert!(lzo.compress(&[0x00u8; 1024]).is_ok());
+/// let noisy: Vec<u8> = (0..1024u32).map(|i| (i * 97 % 256) as u8).collect();
+/// match lzo.compress(&noisy) {
+///     Err(minilzo_rs::CompressError::OutputOverrun { capacity, needed }) => {
+///         assert_eq!(capacity, 64);
+///         assert!(needed.unwrap() > 64);
+///     }
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LzoBuilder {
+    config: Config,
+}
+
+impl LzoBuilder {
+    /// Select the compression algorithm.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.config.algorithm = algorithm;
+        self
+    }
+
+    /// Select the compression level. LZO1X-1 only has level 1; other
+    /// levels are reserved for algorithms minilzo doesn't include yet.
+    pub fn level(mut self, level: u8) -> Self {
+        self.config.level = level;
+        self
+    }
+
+    /// Select the checksum the framed encoders write.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.config.checksum = checksum;
+        self
+    }
+
+    /// Fail compression with `CompressError::OutputOverrun` when the result would
+    /// be larger than `max_output` bytes.
+    pub fn max_output(mut self, max_output: usize) -> Self {
+        self.config.max_output = Some(max_output);
+        self
+    }
+
+    /// Fail decompression with `DecompressError::OutputLimitExceeded` when the
+    /// requested or produced output is larger than `max_output_size` bytes,
+    /// so untrusted input can't force large allocations.
+    pub fn max_output_size(mut self, max_output_size: usize) -> Self {
+        self.config.max_output_size = Some(max_output_size);
+        self
+    }
+
+    /// Block size used by streaming writers built from this instance.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.config.block_size = block_size;
+        self
+    }
+
+    /// Run each block through a byte-delta filter with distance `stride`
+    /// (1 to 256) before the framed encoders compress it, recording it in
+    /// the stream header so decoders reverse it. Samples and fixed-width
+    /// records compress much better when `stride` is their width.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let samples: Vec<u8> = (0..50_000u32).flat_map(|i| (i * 7).to_le_bytes()).collect();
+    /// let mut lzo = minilzo_rs::LZO::builder().delta(4).build().unwrap();
+    /// let framed = lzo.compress_chunked(&samples).unwrap();
+    /// let plain = minilzo_rs::LZO::new().compress_chunked(&samples).unwrap();
+    /// assert!(framed.len() < plain.len() / 4);
+    /// assert_eq!(minilzo_rs::decompress_chunked(&framed).unwrap(), samples);
+    /// ```
+    pub fn delta(mut self, stride: usize) -> Self {
+        self.config.delta = Some(stride);
+        self
+    }
+
+    /// Validate the configuration and create the instance.
+    pub fn build(self) -> LZOResult<LZO> {
+        let config = self.config;
+        if config.level != 1 {
+            return Err(Error::NotYetImplemented);
+        }
+        if config.block_size == 0 || config.block_size > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        if config
+            .delta
+            .is_some_and(|stride| stride == 0 || stride > crate::delta::MAX_STRIDE)
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let mut lzo = LZO::try_new()?;
+        lzo.config = config;
+        Ok(lzo)
+    }
+}
+
+impl LZO {
+    /// Start configuring an instance.
+    pub fn builder() -> LzoBuilder {
+        LzoBuilder::default()
+    }
+
+    /// The configured algorithm.
+    pub fn algorithm(&self) -> Algorithm {
+        self.config.algorithm
+    }
+
+    /// The configured compression level.
+    pub fn level(&self) -> u8 {
+        self.config.level
+    }
+
+    /// The checksum the framed encoders write.
+    pub fn checksum(&self) -> Checksum {
+        self.config.checksum
+    }
+
+    /// The configured output size limit, if any.
+    pub fn max_output(&self) -> Option<usize> {
+        self.config.max_output
+    }
+
+    /// The configured decompression output limit, if any.
+    pub fn max_output_size(&self) -> Option<usize> {
+        self.config.max_output_size
+    }
+
+    /// The block size used by streaming writers built from this instance.
+    pub fn block_size(&self) -> usize {
+        self.config.block_size
+    }
+
+    /// The stride of the configured delta filter, if any.
+    pub fn delta(&self) -> Option<usize> {
+        self.config.delta
+    }
+
+    /// Bytes of work memory this instance holds for compressing, which is
+    /// `algorithm().work_mem_size()`.
+    pub fn work_mem_size(&self) -> usize {
+        self.wrkmem.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecompressError, LzoReader, LzoWriter};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_builder_unchecked_stream() {
+        assert_eq!(
+            LZO::builder().level(9).build().err(),
+            Some(Error::NotYetImplemented)
+        );
+        assert_eq!(
+            LZO::builder().block_size(0).build().err(),
+            Some(Error::InvalidArgument)
+        );
+
+        let lzo = LZO::builder()
+            .checksum(Checksum::None)
+            .block_size(1000)
+            .build()
+            .unwrap();
+        let input: Vec<u8> = (0..5000u32).map(|i| (i % 13) as u8).collect();
+        let mut writer = LzoWriter::from_lzo(Vec::new(), lzo).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+        // Five blocks, each with a zero checksum field.
+        let blocks = crate::frame::split_blocks(&framed).unwrap();
+        assert_eq!(blocks.len(), 5);
+        for (meta, _) in &blocks {
+            assert_eq!(meta.uncompressed_len, 1000);
+            assert_eq!(meta.checksum, 0);
+        }
+
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        for algorithm in [Algorithm::Lzo1x1, Algorithm::Auto] {
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+            let mut lzo = LZO::builder().algorithm(algorithm).build().unwrap();
+            assert_eq!(lzo.algorithm(), algorithm);
+            let input = b"dispatched on the algorithm ".repeat(50);
+            let out = lzo.compress(&input).unwrap();
+            assert_eq!(crate::decompress(&out, input.len()).unwrap(), input);
+        }
+        for name in ["", "LZO1X-1", "lzo1x-999", "lzo1x-1-15", "lzo-rle", "auto "] {
+            assert_eq!(name.parse::<Algorithm>(), Err(Error::InvalidArgument));
+        }
+    }
+
+    #[test]
+    fn test_build_rejections() {
+        let rejected = |builder: LzoBuilder| builder.build().err();
+        assert_eq!(
+            rejected(LZO::builder().level(0)),
+            Some(Error::NotYetImplemented)
+        );
+        assert_eq!(
+            rejected(LZO::builder().algorithm(Algorithm::Auto).level(9)),
+            Some(Error::NotYetImplemented)
+        );
+        for builder in [
+            LZO::builder().block_size(MAX_BLOCK_SIZE + 1),
+            LZO::builder().delta(0),
+            LZO::builder().delta(crate::delta::MAX_STRIDE + 1),
+        ] {
+            assert_eq!(rejected(builder), Some(Error::InvalidArgument));
+        }
+        assert!(LZO::builder()
+            .block_size(MAX_BLOCK_SIZE)
+            .delta(crate::delta::MAX_STRIDE)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_work_mem_size() {
+        assert_eq!(
+            LZO::new().work_mem_size(),
+            Algorithm::Lzo1x1.work_mem_size()
+        );
+        assert_eq!(
+            Algorithm::Auto.work_mem_size(),
+            Algorithm::Lzo1x1.work_mem_size()
+        );
+    }
+
+    #[test]
+    fn test_max_output_size() {
+        let lzo = LZO::builder().max_output_size(1000).build().unwrap();
+        let out = LZO::new().compress([3u8; 5000]).unwrap();
+        assert_eq!(
+            lzo.decompress_safe(&out, 5000),
+            Err(DecompressError::OutputLimitExceeded)
+        );
+        assert_eq!(
+            lzo.decompress_auto(&out, &crate::Limits::default()),
+            Err(DecompressError::OutputLimitExceeded)
+        );
+        let small = LZO::new().compress([3u8; 800]).unwrap();
+        assert_eq!(lzo.decompress_safe(&small, 1000).unwrap(), vec![3u8; 800]);
+    }
+}
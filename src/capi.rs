@@ -0,0 +1,179 @@
+//! C ABI exports (`capi` feature).
+//!
+//! Build a shared library for C and C++ callers with
+//!
+//! ```text
+//! cargo rustc --release --features capi --crate-type cdylib
+//! ```
+//!
+//! and declare the functions with `include/minilzo_rs.h`. Like minilzo's
+//! own functions they return 0 (`LZO_E_OK`) on success or a negative
+//! `LZO_E_*` code; `minilzo_rs_strerror` describes a code.
+
+use crate::error::lzo_err_code_to_result;
+use crate::{Decompressor, Error};
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int};
+
+/// `src` as a slice, or `None` if it is null with a nonzero length.
+unsafe fn input<'a>(src: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        return Some(&[]);
+    }
+    if src.is_null() {
+        return None;
+    }
+    Some(std::slice::from_raw_parts(src, len))
+}
+
+/// Compress `src_len` bytes at `src` into `dst`.
+///
+/// `*dst_len` holds the capacity of `dst` on entry and the compressed
+/// length on success. If the output doesn't fit, `LZO_E_OUTPUT_OVERRUN` is
+/// returned and `*dst_len` is set to the size needed. A `dst` of
+/// `compress_bound(src_len)` bytes always suffices.
+///
+/// # Safety
+///
+/// `src` must be valid for reading `src_len` bytes, `dst_len` must point
+/// to a writable `size_t` and `dst` must be valid for writing `*dst_len`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn minilzo_rs_compress(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    let src = match input(src, src_len) {
+        Some(src) if !dst_len.is_null() && (!dst.is_null() || *dst_len == 0) => src,
+        _ => return Error::InvalidArgument.as_raw_code(),
+    };
+    let out = match crate::compress(src) {
+        Ok(out) => out,
+        Err(e) => return Error::from(e).as_raw_code(),
+    };
+    if out.len() > *dst_len {
+        let code = Error::OutputOverrun {
+            capacity: *dst_len,
+            needed: Some(out.len()),
+        }
+        .as_raw_code();
+        *dst_len = out.len();
+        return code;
+    }
+    std::ptr::copy_nonoverlapping(out.as_ptr(), dst, out.len());
+    *dst_len = out.len();
+    0
+}
+
+/// Decompress the LZO1X data at `src` into `dst`, with overrun testing.
+///
+/// `*dst_len` holds the capacity of `dst` on entry and the decompressed
+/// length on success.
+///
+/// # Safety
+///
+/// As for `minilzo_rs_compress`.
+#[no_mangle]
+pub unsafe extern "C" fn minilzo_rs_decompress_safe(
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: *mut usize,
+) -> c_int {
+    let src = match input(src, src_len) {
+        Some(src) if !dst_len.is_null() && (!dst.is_null() || *dst_len == 0) => src,
+        _ => return Error::InvalidArgument.as_raw_code(),
+    };
+    let decompressor = match Decompressor::new() {
+        Ok(decompressor) => decompressor,
+        Err(e) => return e.as_raw_code(),
+    };
+    let dst: &mut [MaybeUninit<u8>] = if *dst_len == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(dst as *mut MaybeUninit<u8>, *dst_len)
+    };
+    match decompressor.decompress_safe_uninit(src, dst) {
+        Ok(out) => {
+            *dst_len = out.len();
+            0
+        }
+        Err(e) => Error::from(e).as_raw_code(),
+    }
+}
+
+/// A static, NUL-terminated description of `code`.
+#[no_mangle]
+pub extern "C" fn minilzo_rs_strerror(code: c_int) -> *const c_char {
+    let message: &'static [u8] = match lzo_err_code_to_result(code, ()) {
+        Ok(()) => b"ok\0",
+        Err(Error::OutOfMemory) => b"out of memory\0",
+        Err(Error::NotCompressible) => b"not compressible\0",
+        Err(Error::InputOverrun) => b"input overrun\0",
+        Err(Error::OutputOverrun { .. }) => b"output overrun\0",
+        Err(Error::LookbehindOverrun) => b"lookbehind overrun\0",
+        Err(Error::EOFNotFound) => b"EOF not found\0",
+        Err(Error::InputNotConsumed { .. }) => b"input not consumed\0",
+        Err(Error::NotYetImplemented) => b"not yet implemented\0",
+        Err(Error::InvalidArgument) => b"invalid argument\0",
+        Err(Error::InvalidAlignment) => b"invalid alignment\0",
+        Err(Error::OutputNotConsumed) => b"output not consumed\0",
+        Err(Error::InternalError) => b"internal error\0",
+        Err(_) => b"error\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_capi_roundtrip_and_codes() {
+        let input = [0x61u8; 2048];
+        let mut compressed = [0u8; 64];
+        let mut len = 4;
+        let code = unsafe {
+            minilzo_rs_compress(
+                input.as_ptr(),
+                input.len(),
+                compressed.as_mut_ptr(),
+                &mut len,
+            )
+        };
+        assert_eq!(code, -5);
+        assert!(len > 4 && len <= compressed.len());
+        let code = unsafe {
+            minilzo_rs_compress(
+                input.as_ptr(),
+                input.len(),
+                compressed.as_mut_ptr(),
+                &mut len,
+            )
+        };
+        assert_eq!(code, 0);
+
+        let mut output = [0u8; 2048];
+        let mut out_len = output.len();
+        let code = unsafe {
+            minilzo_rs_decompress_safe(compressed.as_ptr(), len, output.as_mut_ptr(), &mut out_len)
+        };
+        assert_eq!((code, out_len), (0, input.len()));
+        assert_eq!(output, input);
+
+        let mut short = 100;
+        let code = unsafe {
+            minilzo_rs_decompress_safe(compressed.as_ptr(), len, output.as_mut_ptr(), &mut short)
+        };
+        assert_eq!(code, -5);
+        let code =
+            unsafe { minilzo_rs_compress(std::ptr::null(), 10, output.as_mut_ptr(), &mut short) };
+        assert_eq!(code, -10);
+
+        let message = unsafe { CStr::from_ptr(minilzo_rs_strerror(-5)) };
+        assert_eq!(message.to_str(), Ok("output overrun"));
+    }
+}
@@ -0,0 +1,385 @@
+//! Checksums, implemented in Rust so the stream formats don't cross the FFI
+//! boundary per block. The C versions are only used to test against.
+//! Where the CPU allows, the bulk of the data goes through the vectorized
+//! kernels in `simd`.
+
+use std::hash::Hasher;
+use std::io;
+
+pub(crate) const ADLER_BASE: u32 = 65521;
+/// Largest n such that 255n(n+1)/2 + (n+1)(BASE-1) fits in a u32.
+pub(crate) const ADLER_NMAX: usize = 5552;
+
+/// Calculate the adler32 value of the data.
+///
+/// Example
+///
+/// ```rust
+/// let buff = [0x09u8; 1024];
+/// let checksum = minilzo_rs::adler32(&buff[..]);
+/// assert_eq!(checksum, 439886849);
+/// ```
+#[inline]
+pub fn adler32(buf: impl AsRef<[u8]>) -> u32 {
+    adler32_with(1, buf)
+}
+
+/// Continue the adler32 `seed` over more data, like zlib's
+/// `adler32(adler, buf, len)`. A seed of 1 starts a new checksum.
+///
+/// Example
+///
+/// ```rust
+/// let head = minilzo_rs::adler32(b"hello ");
+/// assert_eq!(
+///     minilzo_rs::adler32_with(head, b"world"),
+///     minilzo_rs::adler32(b"hello world")
+/// );
+/// ```
+#[inline]
+pub fn adler32_with(seed: u32, buf: impl AsRef<[u8]>) -> u32 {
+    let mut hasher = Adler32::with_seed(seed);
+    hasher.update(buf.as_ref());
+    hasher.finish()
+}
+
+/// An adler32 computed over data fed in pieces.
+///
+/// Example
+///
+/// ```rust
+/// let mut hasher = minilzo_rs::Adler32::new();
+/// for chunk in [0x09u8; 1024].chunks(100) {
+///     hasher.update(chunk);
+/// }
+/// assert_eq!(hasher.finish(), minilzo_rs::adler32(&[0x09u8; 1024]));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Adler32 {
+    /// Start a checksum over no data.
+    pub const fn new() -> Self {
+        Self::with_seed(1)
+    }
+
+    /// Resume from a checksum returned by `finish` or `adler32`.
+    pub const fn with_seed(seed: u32) -> Self {
+        Adler32 {
+            a: seed & 0xffff,
+            b: seed >> 16,
+        }
+    }
+
+    /// Feed the next piece of data.
+    #[inline]
+    pub fn update(&mut self, buf: &[u8]) {
+        let buf = &buf[crate::simd::adler32(&mut self.a, &mut self.b, buf)..];
+        for chunk in buf.chunks(ADLER_NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_BASE;
+            self.b %= ADLER_BASE;
+        }
+    }
+
+    /// The checksum of all data fed so far.
+    #[inline]
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+/// `Hasher::finish` zero-extends the checksum to `u64`.
+///
+/// Example
+///
+/// ```rust
+/// use std::hash::Hasher;
+///
+/// let mut hasher = minilzo_rs::Adler32::new();
+/// Hasher::write(&mut hasher, b"hello");
+/// assert_eq!(Hasher::finish(&hasher), minilzo_rs::adler32(b"hello") as u64);
+/// ```
+impl Hasher for Adler32 {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        Adler32::finish(self) as u64
+    }
+}
+
+/// Checksums everything written, e.g. as the sink of `io::copy`.
+impl io::Write for Adler32 {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Calculate the CRC-32 (IEEE 802.3, as in zlib and lzop) of the data.
+///
+/// lzop files may carry these instead of adler32 checksums; the result
+/// matches liblzo's `lzo_crc32(0, buf, len)`.
+///
+/// Example
+///
+/// ```rust
+/// assert_eq!(minilzo_rs::crc32(b"123456789"), 0xcbf4_3926);
+/// ```
+#[inline]
+pub fn crc32(buf: impl AsRef<[u8]>) -> u32 {
+    let mut hasher = Crc32::new();
+    hasher.update(buf.as_ref());
+    hasher.finish()
+}
+
+/// Continue the CRC-32 `seed` over more data, like zlib's
+/// `crc32(crc, buf, len)`. A seed of 0 starts a new checksum.
+///
+/// Example
+///
+/// ```rust
+/// let head = minilzo_rs::crc32(b"hello ");
+/// assert_eq!(
+///     minilzo_rs::crc32_with(head, b"world"),
+///     minilzo_rs::crc32(b"hello world")
+/// );
+/// ```
+#[inline]
+pub fn crc32_with(seed: u32, buf: impl AsRef<[u8]>) -> u32 {
+    let mut hasher = Crc32::with_seed(seed);
+    hasher.update(buf.as_ref());
+    hasher.finish()
+}
+
+/// A CRC-32 computed over data fed in pieces, e.g. an lzop block checked
+/// as it is read.
+///
+/// Example
+///
+/// ```rust
+/// let mut hasher = minilzo_rs::Crc32::new();
+/// hasher.update(b"1234");
+/// hasher.update(b"56789");
+/// assert_eq!(hasher.finish(), minilzo_rs::crc32(b"123456789"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    c: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    /// Start a checksum over no data.
+    pub const fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    /// Resume from a checksum returned by `finish` or `crc32`.
+    pub const fn with_seed(seed: u32) -> Self {
+        Crc32 { c: !seed }
+    }
+
+    /// Feed the next piece of data.
+    #[inline]
+    pub fn update(&mut self, buf: &[u8]) {
+        let buf = &buf[crate::simd::crc32(&mut self.c, buf)..];
+        for &byte in buf {
+            self.c = CRC32_TABLE[((self.c ^ byte as u32) & 0xff) as usize] ^ (self.c >> 8);
+        }
+    }
+
+    /// The checksum of all data fed so far.
+    #[inline]
+    pub fn finish(&self) -> u32 {
+        !self.c
+    }
+}
+
+/// `Hasher::finish` zero-extends the checksum to `u64`.
+impl Hasher for Crc32 {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        Crc32::finish(self) as u64
+    }
+}
+
+/// Checksums everything written, e.g. as the sink of `io::copy`.
+///
+/// Example
+///
+/// ```rust
+/// let mut hasher = minilzo_rs::Crc32::new();
+/// std::io::copy(&mut &b"123456789"[..], &mut hasher).unwrap();
+/// assert_eq!(hasher.finish(), 0xcbf4_3926);
+/// ```
+impl io::Write for Crc32 {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_adler32(buf: &[u8]) -> u32 {
+        c_adler32_with(1, buf)
+    }
+
+    fn c_adler32_with(seed: u32, buf: &[u8]) -> u32 {
+        unsafe {
+            crate::minilzo::lzo_adler32(seed, buf.as_ptr(), buf.len() as crate::minilzo::lzo_uint)
+        }
+    }
+
+    #[test]
+    fn test_adler32_matches_c() {
+        let mut state = 12345u32;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        for len in [0, 1, 2, 15, 16, 5551, 5552, 5553, 65_536, 100_000] {
+            assert_eq!(
+                adler32(&data[..len]),
+                c_adler32(&data[..len]),
+                "len {}",
+                len
+            );
+        }
+        let ones = vec![0xffu8; 70_000];
+        assert_eq!(adler32(&ones), c_adler32(&ones));
+    }
+
+    #[test]
+    fn test_adler32_seed_matches_c() {
+        let data: Vec<u8> = (0..12_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        for seed in [0, 1, 0xfff0_fff0, 0xffff_ffff, adler32(b"prefix")] {
+            for len in [0, 1, 5552, 12_000] {
+                assert_eq!(
+                    adler32_with(seed, &data[..len]),
+                    c_adler32_with(seed, &data[..len]),
+                    "seed {:#x} len {}",
+                    seed,
+                    len
+                );
+            }
+        }
+        let (head, tail) = data.split_at(4321);
+        assert_eq!(adler32_with(adler32(head), tail), adler32(&data));
+    }
+
+    #[test]
+    fn test_adler32_in_pieces() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * 31 % 256) as u8).collect();
+        for piece in [1, 7, 5552, 5553, 20_000] {
+            let mut hasher = Adler32::new();
+            for chunk in data.chunks(piece) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), adler32(&data), "piece {}", piece);
+        }
+        assert_eq!(Adler32::default().finish(), adler32(b""));
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_crc32_in_pieces() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 13 % 256) as u8).collect();
+        for piece in [1, 3, 256, 10_000] {
+            let mut hasher = Crc32::new();
+            for chunk in data.chunks(piece) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), crc32(&data), "piece {}", piece);
+        }
+        assert_eq!(Crc32::default().finish(), 0);
+    }
+
+    #[test]
+    fn test_generic_hashing() {
+        fn hash_all<H: Hasher>(mut hasher: H, pieces: &[&[u8]]) -> u64 {
+            for piece in pieces {
+                hasher.write(piece);
+            }
+            hasher.finish()
+        }
+        let pieces: [&[u8]; 3] = [b"one", b"", b"three"];
+        assert_eq!(
+            hash_all(Adler32::new(), &pieces),
+            adler32(b"onethree") as u64
+        );
+        assert_eq!(hash_all(Crc32::new(), &pieces), crc32(b"onethree") as u64);
+
+        let mut hasher = Adler32::new();
+        io::copy(&mut &[0x09u8; 1024][..], &mut hasher).unwrap();
+        assert_eq!(hasher.finish(), 439886849);
+    }
+}
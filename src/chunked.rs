@@ -0,0 +1,198 @@
+//! One-shot compression of inputs of any size.
+//!
+//! `compress` codes its input as a single LZO block, which has to fit the
+//! target's length type and a single worst-case allocation. These helpers
+//! split the input into frame blocks instead, producing the same stream as
+//! `LzoWriter`, which remains the way to go for input that doesn't fit in
+//! memory at once.
+
+use crate::frame::Decoder;
+use crate::frame::{self, HEADER_LEN};
+use crate::{Decompressor, Error, LZOResult, PartialOutput, DEFAULT_BLOCK_SIZE, LZO};
+
+impl LZO {
+    /// Compress `src` of any length into a framed stream of
+    /// `DEFAULT_BLOCK_SIZE` blocks, readable by `decompress_chunked` and
+    /// `LzoReader`.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let input = vec![0x5au8; 3 * minilzo_rs::DEFAULT_BLOCK_SIZE + 1];
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let framed = lzo.compress_chunked(&input).unwrap();
+    /// assert_eq!(minilzo_rs::decompress_chunked(&framed).unwrap(), input);
+    /// ```
+    pub fn compress_chunked(&mut self, src: impl AsRef<[u8]>) -> LZOResult<Vec<u8>> {
+        let src = src.as_ref();
+        let mut out = Vec::with_capacity(HEADER_LEN + src.len() / 4);
+        frame::encode_header_for(&mut out, self);
+        for chunk in src.chunks(DEFAULT_BLOCK_SIZE) {
+            frame::encode_block(self, chunk, &mut out)?;
+        }
+        frame::encode_end(&mut out);
+        Ok(out)
+    }
+}
+
+/// Decompress a whole framed stream, such as `compress_chunked` output,
+/// into one buffer.
+///
+/// Fails with `Error::OutOfMemory` if the output can't be allocated, which
+/// on 32-bit targets includes streams holding more than the address space.
+pub fn decompress_chunked(src: impl AsRef<[u8]>) -> LZOResult<Vec<u8>> {
+    let src = src.as_ref();
+    let checksum = frame::checksum_from_flags(frame::parse_header(src)?);
+    let delta = frame::delta_from_header(src);
+    let blocks = frame::split_blocks(src)?;
+    let dict = frame::stream_dict(frame::dict_from_header(src)?, None)?;
+    let mut chain = frame::chain_from_header(src, dict.as_deref())?;
+    let total = blocks
+        .iter()
+        .try_fold(0usize, |total, (meta, _)| {
+            total.checked_add(meta.uncompressed_len as usize)
+        })
+        .ok_or(Error::OutOfMemory)?;
+    let mut out = Vec::new();
+    out.try_reserve_exact(total)
+        .map_err(|_| Error::OutOfMemory)?;
+    let decompressor = Decompressor::new()?;
+    for (meta, payload) in &blocks {
+        let start = out.len();
+        let dict = match &chain {
+            Some(chain) => chain.window(),
+            None => dict.as_deref().unwrap_or_default(),
+        };
+        out.extend_from_slice(&frame::decode_block_with(
+            &decompressor,
+            meta,
+            payload,
+            checksum,
+            dict,
+        )?);
+        if let Some(chain) = &mut chain {
+            chain.push(&out[start..]);
+        }
+        if let Some(stride) = delta {
+            crate::delta::decode(&mut out[start..], stride);
+        }
+    }
+    Ok(out)
+}
+
+/// Decompress a whole framed stream like `decompress_chunked`, but on
+/// failure return the blocks decoded before it along with the error.
+///
+/// Every block in `partial` matched its checksum, and `consumed` is where
+/// decoding stopped: the start of the failing block, or 0 if the header
+/// is damaged. To carry on
+/// past damaged blocks rather than stop at the first, use `salvage`.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// let input: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+/// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 16 * 1024).unwrap();
+/// writer.write_all(&input).unwrap();
+/// let mut framed = writer.finish().unwrap();
+/// let len = framed.len();
+/// framed[len / 2] ^= 0x40;
+///
+/// let err = minilzo_rs::decompress_chunked_partial(&framed).unwrap_err();
+/// assert_eq!(err.partial.len() % (16 * 1024), 0);
+/// assert_eq!(err.partial, input[..err.partial.len()]);
+/// assert!(err.consumed <= len / 2);
+/// ```
+pub fn decompress_chunked_partial(src: impl AsRef<[u8]>) -> Result<Vec<u8>, PartialOutput> {
+    let src = src.as_ref();
+    let decompressor = Decompressor::initialized();
+    let mut decoder = Decoder::new();
+    decoder.feed(src);
+    let mut out = Vec::new();
+    let error = loop {
+        match decoder.decode(&decompressor) {
+            Ok(Some(_)) if decoder.is_done() => match decoder.remaining().len() {
+                0 => return Ok(out),
+                remaining => break Error::InputNotConsumed { remaining },
+            },
+            Ok(Some(block)) => out.extend_from_slice(&block),
+            Ok(None) => break Error::EOFNotFound,
+            Err(e) => break e,
+        }
+    };
+    Err(PartialOutput {
+        error,
+        partial: out,
+        consumed: src.len() - decoder.remaining().len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Checksum;
+    use std::io::Read;
+
+    #[test]
+    fn test_chunked_roundtrip() {
+        let input: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let mut lzo = LZO::builder().checksum(Checksum::Crc32).build().unwrap();
+        let framed = lzo.compress_chunked(&input).unwrap();
+        assert_eq!(decompress_chunked(&framed).unwrap(), input);
+
+        let mut output = Vec::new();
+        crate::LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+
+        let empty = lzo.compress_chunked([]).unwrap();
+        assert_eq!(decompress_chunked(&empty).unwrap(), Vec::<u8>::new());
+        assert_eq!(
+            decompress_chunked(&framed[..framed.len() - 1]),
+            Err(Error::EOFNotFound)
+        );
+    }
+
+    #[test]
+    fn test_chunked_partial_output() {
+        let input: Vec<u8> = (0..40_000u32).map(|i| (i % 113) as u8).collect();
+        let mut lzo = LZO::builder().block_size(4096).build().unwrap();
+        let mut framed = Vec::new();
+        frame::encode_header(&mut framed);
+        let mut starts = Vec::new();
+        for chunk in input.chunks(4096) {
+            starts.push(framed.len());
+            frame::encode_block(&mut lzo, chunk, &mut framed).unwrap();
+        }
+        frame::encode_end(&mut framed);
+        assert_eq!(decompress_chunked_partial(&framed).unwrap(), input);
+
+        // A damaged block: everything before it comes back.
+        let mut damaged = framed.clone();
+        damaged[starts[6] + 20] ^= 0x01;
+        let err = decompress_chunked_partial(&damaged).unwrap_err();
+        assert_eq!(err.error, Error::ChecksumMismatch);
+        assert_eq!(err.partial, input[..6 * 4096]);
+        assert_eq!(err.consumed, starts[6]);
+        assert_eq!(Error::from(err), Error::ChecksumMismatch);
+
+        // Cut short, or with something after the end marker.
+        let err = decompress_chunked_partial(&framed[..starts[3] + 5]).unwrap_err();
+        assert_eq!((err.error, err.consumed), (Error::EOFNotFound, starts[3]));
+        assert_eq!(err.partial, input[..3 * 4096]);
+        let mut padded = framed.clone();
+        padded.extend_from_slice(&[0; 3]);
+        let err = decompress_chunked_partial(&padded).unwrap_err();
+        assert_eq!(err.error, Error::InputNotConsumed { remaining: 3 });
+        assert_eq!(err.partial, input);
+
+        // A damaged header yields nothing.
+        let err = decompress_chunked_partial(&framed[1..]).unwrap_err();
+        assert_eq!((err.error, err.consumed), (Error::InvalidHeader, 0));
+        assert!(err.partial.is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! Content-defined chunking for dedup-friendly compression.
+//!
+//! `Chunker` cuts a stream where a rolling gear hash of the last 64 bytes
+//! hits a pattern, so boundaries depend on the content around them rather
+//! than on offsets: inserting or deleting bytes only changes the chunks
+//! near the edit, and the rest come out identical, with the same hashes,
+//! for a backup tool to store once. Each chunk is compressed on its own.
+
+use crate::frame;
+use crate::{adler32, crc32, BlockMeta, Checksum, Decompressor, Error, LZOResult, LZO};
+use std::io::{self, Read};
+
+/// Gear table: one pseudo-random word per byte value, from splitmix64.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x4d4c_5a4f_4344_4331u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// One chunk of the input, compressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Offset of the chunk in the input.
+    pub offset: u64,
+    /// Sizes and adler32 of the chunk.
+    pub meta: BlockMeta,
+    /// CRC-32 and adler32 of the chunk's data, in the high and low half.
+    /// Equal chunks have equal hashes; it is not meant to resist chunks
+    /// crafted to collide.
+    pub hash: u64,
+    /// The compressed chunk, or the data itself when compressing didn't
+    /// make it smaller.
+    pub payload: Vec<u8>,
+}
+
+impl Chunk {
+    /// Decompress the chunk, checking its adler32.
+    pub fn decompress(&self) -> LZOResult<Vec<u8>> {
+        frame::decode_block(
+            &Decompressor::new()?,
+            &self.meta,
+            &self.payload,
+            Checksum::Adler32,
+        )
+    }
+}
+
+/// Splits a stream into content-defined chunks and compresses each one.
+///
+/// Example
+///
+/// ```rust
+/// let input: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+/// let mut output = Vec::new();
+/// for chunk in minilzo_rs::Chunker::new(&input[..]) {
+///     output.extend(chunk.unwrap().decompress().unwrap());
+/// }
+/// assert_eq!(output, input);
+/// ```
+pub struct Chunker<R: Read> {
+    inner: R,
+    lzo: LZO,
+    min_size: usize,
+    mask: u64,
+    max_size: usize,
+    buf: Vec<u8>,
+    offset: u64,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    /// Create a chunker making chunks of 2 KiB to 64 KiB, 8 KiB on average.
+    pub fn new(inner: R) -> Self {
+        Self::with_sizes(inner, 2048, 8192, 65536).unwrap()
+    }
+
+    /// Create a chunker making chunks of `min_size` to `max_size` bytes,
+    /// `avg_size` on average.
+    ///
+    /// `avg_size` must be a power of two, and the sizes nonzero, ascending
+    /// and at most `MAX_BLOCK_SIZE`.
+    pub fn with_sizes(
+        inner: R,
+        min_size: usize,
+        avg_size: usize,
+        max_size: usize,
+    ) -> LZOResult<Self> {
+        if min_size == 0
+            || !avg_size.is_power_of_two()
+            || min_size > avg_size
+            || avg_size > max_size
+            || max_size > crate::MAX_BLOCK_SIZE
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let bits = avg_size.trailing_zeros();
+        Ok(Chunker {
+            inner,
+            lzo: LZO::new(),
+            min_size,
+            mask: if bits == 0 { 0 } else { !0 << (64 - bits) },
+            max_size,
+            buf: Vec::with_capacity(max_size),
+            offset: 0,
+            eof: false,
+        })
+    }
+
+    /// Unwrap the chunker, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read until a whole chunk's worth of input is buffered or the input
+    /// ends.
+    fn fill(&mut self) -> io::Result<()> {
+        if !self.eof {
+            let want = self.max_size - self.buf.len();
+            let n = self
+                .inner
+                .by_ref()
+                .take(want as u64)
+                .read_to_end(&mut self.buf)?;
+            self.eof = n < want;
+        }
+        Ok(())
+    }
+
+    /// Length of the next chunk at the start of the buffer.
+    fn cut(&self) -> usize {
+        let mut hash = 0u64;
+        for (i, &byte) in self.buf.iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            if i + 1 >= self.min_size && hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        self.buf.len()
+    }
+
+    fn next_chunk(&mut self) -> LZOResult<Chunk> {
+        let len = self.cut();
+        let data = &self.buf[..len];
+        let payload = frame::block_payload(&mut self.lzo, data)?;
+        let checksum = adler32(data);
+        let chunk = Chunk {
+            offset: self.offset,
+            meta: BlockMeta {
+                uncompressed_len: len as u32,
+                compressed_len: payload.len() as u32,
+                checksum,
+            },
+            hash: u64::from(crc32(data)) << 32 | u64::from(checksum),
+            payload,
+        };
+        self.buf.drain(..len);
+        self.offset += len as u64;
+        Ok(chunk)
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Chunk>;
+
+    fn next(&mut self) -> Option<io::Result<Chunk>> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(self.next_chunk().map_err(frame::to_io))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| ((i ^ seed).wrapping_mul(2_654_435_761) >> 11) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_roundtrip_and_dedup() {
+        let input = noise(500_000, 0);
+        let chunks: Vec<Chunk> = Chunker::new(&input[..]).map(Result::unwrap).collect();
+        assert!(chunks
+            .iter()
+            .all(|c| (c.meta.uncompressed_len as usize) <= 65536));
+        let mut output = Vec::new();
+        for chunk in &chunks {
+            assert_eq!(chunk.offset, output.len() as u64);
+            output.extend(chunk.decompress().unwrap());
+        }
+        assert_eq!(output, input);
+
+        // An insertion near the start only disturbs the chunks around it.
+        let mut edited = noise(100, 1);
+        edited.extend_from_slice(&input);
+        let before: HashSet<u64> = chunks.iter().map(|c| c.hash).collect();
+        let after: Vec<u64> = Chunker::new(&edited[..]).map(|c| c.unwrap().hash).collect();
+        let shared = after.iter().filter(|h| before.contains(h)).count();
+        assert!(shared + 2 >= after.len());
+    }
+
+    #[test]
+    fn test_chunker_sizes() {
+        let input = vec![0u8; 10_000];
+        let lens: Vec<u32> = Chunker::with_sizes(&input[..], 64, 256, 1024)
+            .unwrap()
+            .map(|c| c.unwrap().meta.uncompressed_len)
+            .collect();
+        assert_eq!(lens.iter().sum::<u32>(), 10_000);
+        assert!(lens.iter().all(|&len| len <= 1024));
+        assert!(Chunker::with_sizes(&input[..], 64, 300, 1024).is_err());
+        assert_eq!(Chunker::new(&[][..]).count(), 0);
+    }
+}
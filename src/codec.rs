@@ -0,0 +1,120 @@
+//! tokio-util `Encoder`/`Decoder` for message-oriented transports.
+//!
+//! Every message travels as one block of the framed format: a
+//! [`BlockMeta`](crate::BlockMeta) followed by the compressed payload, so
+//! each message is length-delimited and checksummed on its own.
+
+use crate::frame;
+use crate::{Decompressor, Error, LZOResult, LZO};
+use bytes::{Buf, Bytes, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Compresses outbound messages and decompresses inbound ones.
+///
+/// Example
+///
+/// ```rust
+/// use bytes::{Bytes, BytesMut};
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut codec = minilzo_rs::LzoCodec::new().unwrap();
+/// let mut wire = BytesMut::new();
+/// codec.encode(Bytes::from_static(&[0u8; 1024]), &mut wire).unwrap();
+/// let message = codec.decode(&mut wire).unwrap().unwrap();
+/// assert_eq!(&message[..], &[0u8; 1024][..]);
+/// ```
+pub struct LzoCodec {
+    lzo: LZO,
+    decompressor: Decompressor,
+    max_message_size: usize,
+}
+
+impl LzoCodec {
+    /// Create a codec accepting messages up to `MAX_BLOCK_SIZE` bytes.
+    pub fn new() -> LZOResult<Self> {
+        Self::with_max_message_size(crate::MAX_BLOCK_SIZE)
+    }
+
+    /// Create a codec rejecting messages larger than `max_message_size`.
+    pub fn with_max_message_size(max_message_size: usize) -> LZOResult<Self> {
+        if max_message_size > crate::MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(LzoCodec {
+            lzo: LZO::new(),
+            decompressor: Decompressor::new()?,
+            max_message_size,
+        })
+    }
+}
+
+impl Encoder<&[u8]> for LzoCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        if item.len() > self.max_message_size {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        let mut out = Vec::new();
+        frame::encode_message(&mut self.lzo, item, &mut out).map_err(frame::to_io)?;
+        dst.extend_from_slice(&out);
+        Ok(())
+    }
+}
+
+impl Encoder<Bytes> for LzoCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&item[..], dst)
+    }
+}
+
+impl Decoder for LzoCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        match frame::decode_message(&self.decompressor, src, self.max_message_size)
+            .map_err(frame::to_io)?
+        {
+            Some((message, consumed)) => {
+                src.advance(consumed);
+                Ok(Some(Bytes::from(message)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_partial_and_empty() {
+        let mut codec = LzoCodec::new().unwrap();
+        let mut wire = BytesMut::new();
+        codec.encode(&b"first message"[..], &mut wire).unwrap();
+        codec.encode(&b""[..], &mut wire).unwrap();
+        let full = wire.split().freeze();
+
+        let mut partial = BytesMut::from(&full[..5]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        partial.extend_from_slice(&full[5..]);
+        assert_eq!(
+            &codec.decode(&mut partial).unwrap().unwrap()[..],
+            b"first message"
+        );
+        assert!(codec.decode(&mut partial).unwrap().unwrap().is_empty());
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_codec_rejects_oversized() {
+        let mut codec = LzoCodec::with_max_message_size(8).unwrap();
+        let mut wire = BytesMut::new();
+        assert!(codec.encode(&[0u8; 9][..], &mut wire).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Buffer-to-buffer encoder and decoder of the framed format.
+//!
+//! `LzoEncoder` and `LzoDecoder` have the same shape as the codec traits of
+//! the async-compression crate (`encode`/`decode`, `flush` and `finish`
+//! moving bytes from an input slice into an output slice and reporting
+//! progress), so they can be slotted into stacks that abstract over
+//! gzip/zstd/brotli that way. They do no I/O of their own, and are thin
+//! wrappers over `StreamEncoder` and `StreamDecoder`.
+
+use crate::frame;
+use crate::{LZOResult, StreamDecoder, StreamEncoder};
+use std::io;
+
+/// Bytes moved by one call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes taken from the input slice.
+    pub consumed: usize,
+    /// Bytes written to the output slice.
+    pub written: usize,
+}
+
+/// Compresses input into a framed stream, block by block.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{LzoDecoder, LzoEncoder};
+///
+/// let mut encoder = LzoEncoder::new().unwrap();
+/// let mut compressed = vec![0u8; 4096];
+/// let p = encoder.encode(&[0x00u8; 1024], &mut compressed).unwrap();
+/// assert_eq!(p.consumed, 1024);
+/// let (written, done) = encoder.finish(&mut compressed[p.written..]).unwrap();
+/// assert!(done);
+/// compressed.truncate(p.written + written);
+///
+/// let mut decoder = LzoDecoder::new().unwrap();
+/// let mut output = vec![0u8; 2048];
+/// let p = decoder.decode(&compressed, &mut output).unwrap();
+/// assert_eq!(p.written, 1024);
+/// assert!(decoder.is_done());
+/// ```
+pub struct LzoEncoder {
+    core: StreamEncoder,
+}
+
+impl LzoEncoder {
+    /// Create an encoder using `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn new() -> LZOResult<Self> {
+        Ok(LzoEncoder {
+            core: StreamEncoder::new()?,
+        })
+    }
+
+    /// Create an encoder that compresses `block_size` bytes at a time.
+    pub fn with_block_size(block_size: usize) -> LZOResult<Self> {
+        Ok(LzoEncoder {
+            core: StreamEncoder::with_block_size(block_size)?,
+        })
+    }
+
+    /// Take as much of `input` as possible, writing compressed bytes to
+    /// `output` as whole blocks become available.
+    pub fn encode(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<Progress> {
+        let mut progress = Progress::default();
+        loop {
+            progress.written += self.core.read_output(&mut output[progress.written..]);
+            let n = self
+                .core
+                .feed_input(&input[progress.consumed..])
+                .map_err(frame::to_io)?;
+            progress.consumed += n;
+            if n == 0 {
+                return Ok(progress);
+            }
+        }
+    }
+
+    /// Close the current block so everything encoded so far can be decoded
+    /// by the peer. Returns the bytes written and whether the flush is
+    /// complete; call again with more output space until it is.
+    pub fn flush(&mut self, output: &mut [u8]) -> io::Result<(usize, bool)> {
+        self.core.flush().map_err(frame::to_io)?;
+        let written = self.core.read_output(output);
+        Ok((written, self.core.pending_output().is_empty()))
+    }
+
+    /// Compress any remaining input and write the end marker. Returns the
+    /// bytes written and whether the stream is complete.
+    pub fn finish(&mut self, output: &mut [u8]) -> io::Result<(usize, bool)> {
+        self.core.finish().map_err(frame::to_io)?;
+        let written = self.core.read_output(output);
+        Ok((written, self.core.is_done()))
+    }
+}
+
+/// Decompresses a framed stream from input slices into output slices.
+pub struct LzoDecoder {
+    core: StreamDecoder,
+}
+
+impl LzoDecoder {
+    /// Create a decoder for one framed stream.
+    pub fn new() -> LZOResult<Self> {
+        Ok(LzoDecoder {
+            core: StreamDecoder::new()?,
+        })
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` once a stream would
+    /// decompress to more than `limit` bytes. The limit applies to each
+    /// stream, and survives `reinit`.
+    pub fn max_output_size(self, limit: u64) -> Self {
+        LzoDecoder {
+            core: self.core.max_output_size(limit),
+        }
+    }
+
+    /// Start decoding a new stream, discarding any buffered state.
+    pub fn reinit(&mut self) {
+        self.core.reset();
+    }
+
+    /// `true` once the end marker has been decoded and all output drained.
+    pub fn is_done(&self) -> bool {
+        self.core.is_done()
+    }
+
+    /// Consume `input` and write as much decompressed data to `output` as
+    /// is available. Input is only consumed when no output is pending.
+    pub fn decode(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<Progress> {
+        let mut progress = Progress {
+            consumed: 0,
+            written: self
+                .core
+                .read_output(output)
+                .map_err(|e| self.core.locate(e))?,
+        };
+        progress.consumed = self.core.feed_input(input);
+        loop {
+            let n = self
+                .core
+                .read_output(&mut output[progress.written..])
+                .map_err(|e| self.core.locate(e))?;
+            if n == 0 {
+                return Ok(progress);
+            }
+            progress.written += n;
+        }
+    }
+
+    /// Write out any pending decompressed data. Returns the bytes written
+    /// and whether nothing is left pending.
+    pub fn flush(&mut self, output: &mut [u8]) -> io::Result<(usize, bool)> {
+        let pending = self.core.pending_output();
+        let written = output.len().min(pending.len());
+        output[..written].copy_from_slice(&pending[..written]);
+        self.core.consume_output(written);
+        Ok((written, self.core.pending_output().is_empty()))
+    }
+
+    /// Like `flush`, but fails if the stream ended before its end marker.
+    pub fn finish(&mut self, output: &mut [u8]) -> io::Result<(usize, bool)> {
+        let (written, drained) = self.flush(output)?;
+        if drained && self.core.finish().is_err() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok((written, drained))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coder_tiny_buffers() {
+        let input: Vec<u8> = (0..50_000u32).map(|i| (i % 31) as u8).collect();
+        let mut encoder = LzoEncoder::with_block_size(1000).unwrap();
+        let mut compressed = Vec::new();
+        let mut out = [0u8; 17];
+        let mut pos = 0;
+        while pos < input.len() {
+            let p = encoder
+                .encode(&input[pos..(pos + 13).min(input.len())], &mut out)
+                .unwrap();
+            pos += p.consumed;
+            compressed.extend_from_slice(&out[..p.written]);
+        }
+        loop {
+            let (written, done) = encoder.finish(&mut out).unwrap();
+            compressed.extend_from_slice(&out[..written]);
+            if done {
+                break;
+            }
+        }
+
+        let mut decoder = LzoDecoder::new().unwrap();
+        let mut output = Vec::new();
+        let mut pos = 0;
+        while !decoder.is_done() {
+            let p = decoder
+                .decode(&compressed[pos..(pos + 11).min(compressed.len())], &mut out)
+                .unwrap();
+            pos += p.consumed;
+            output.extend_from_slice(&out[..p.written]);
+        }
+        assert_eq!(output, input);
+        assert_eq!(pos, compressed.len());
+    }
+
+    #[test]
+    fn test_decoder_finish_truncated() {
+        let mut decoder = LzoDecoder::new().unwrap();
+        let mut out = [0u8; 16];
+        decoder.decode(b"MLZO\x01\x00", &mut out).unwrap();
+        assert!(decoder.finish(&mut out).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Transparent compression of serialized values.
+
+use crate::{adler32, Error};
+use serde::de::{self, DeserializeOwned, SeqAccess, Visitor};
+use serde::ser;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Serializes `T` to bincode, LZO-compresses it and emits the result as a
+/// byte string.
+///
+/// The byte string is the uncompressed length and the adler32 of the
+/// bincode bytes (big-endian `u32` each), followed by the compressed data.
+/// Deserializing checks both before decoding `T`, so a corrupted payload is
+/// an error rather than a garbled value.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::CompressedBytes;
+///
+/// let cached = CompressedBytes(vec![String::from("repeated"); 1000]);
+/// let encoded = serde_json::to_vec(&cached).unwrap();
+/// assert!(encoded.len() < 1000);
+///
+/// let decoded: CompressedBytes<Vec<String>> = serde_json::from_slice(&encoded).unwrap();
+/// assert_eq!(decoded, cached);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CompressedBytes<T>(pub T);
+
+impl<T> CompressedBytes<T> {
+    /// Unwrap the value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for CompressedBytes<T> {
+    fn from(value: T) -> Self {
+        CompressedBytes(value)
+    }
+}
+
+impl<T: Serialize> Serialize for CompressedBytes<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let raw = bincode::serialize(&self.0).map_err(ser::Error::custom)?;
+        let len =
+            u32::try_from(raw.len()).map_err(|_| ser::Error::custom(Error::InvalidArgument))?;
+        let compressed = crate::compress(&raw).map_err(ser::Error::custom)?;
+        let mut out = Vec::with_capacity(8 + compressed.len());
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(&adler32(&raw).to_be_bytes());
+        out.extend_from_slice(&compressed);
+        serializer.serialize_bytes(&out)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LZO-compressed bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            out.push(byte);
+        }
+        Ok(out)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for CompressedBytes<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+        if bytes.len() < 8 {
+            return Err(de::Error::custom(Error::InputOverrun));
+        }
+        let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let checksum = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let raw = crate::decompress(&bytes[8..], len).map_err(de::Error::custom)?;
+        if adler32(&raw) != checksum {
+            return Err(de::Error::custom(Error::ChecksumMismatch));
+        }
+        bincode::deserialize(&raw)
+            .map(CompressedBytes)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_bincode_roundtrip_and_corruption() {
+        let map: BTreeMap<u32, String> = (0..500).map(|i| (i, "value".repeat(4))).collect();
+        let wrapped = CompressedBytes(map);
+        let plain = bincode::serialize(&wrapped.0).unwrap();
+        let mut encoded = bincode::serialize(&wrapped).unwrap();
+        assert!(encoded.len() < plain.len() / 2);
+        let decoded: CompressedBytes<BTreeMap<u32, String>> =
+            bincode::deserialize(&encoded).unwrap();
+        assert_eq!(decoded, wrapped);
+
+        // Flip a bit in the stored checksum.
+        encoded[8 + 4] ^= 1;
+        let err = bincode::deserialize::<CompressedBytes<BTreeMap<u32, String>>>(&encoded)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), Error::ChecksumMismatch.to_string());
+    }
+}
@@ -0,0 +1,227 @@
+//! A map keeping its values serialized and compressed.
+
+use crate::CompressedVec;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters reported by `CompressedMap::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MapStats {
+    /// Entries in the map.
+    pub entries: usize,
+    /// `get` calls that found their key.
+    pub hits: u64,
+    /// `get` calls that didn't.
+    pub misses: u64,
+    /// Total bincode size of the values held.
+    pub raw_bytes: usize,
+    /// Total bytes the values take compressed.
+    pub stored_bytes: usize,
+}
+
+impl MapStats {
+    /// The fraction of `get` calls that found their key, or 0 before any.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+
+    /// The bytes compression saves over holding the values serialized.
+    pub fn saved_bytes(&self) -> usize {
+        self.raw_bytes.saturating_sub(self.stored_bytes)
+    }
+}
+
+/// A hash map that bincode-serializes and compresses values on insert and
+/// decodes them on `get`, for in-process caches over their memory budget.
+///
+/// Lookups return owned values, so it suits values read now and then
+/// rather than ones borrowed in a hot loop.
+///
+/// # Panics
+///
+/// Inserting and getting panic if minilzo's self-check fails, like
+/// `LZO::new`.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::CompressedMap;
+///
+/// let mut cache = CompressedMap::new();
+/// cache.insert("page", &"<p>hello</p>".repeat(100)).unwrap();
+/// assert_eq!(cache.get("page").unwrap(), "<p>hello</p>".repeat(100));
+/// assert_eq!(cache.get::<str>("missing"), None);
+///
+/// let stats = cache.stats();
+/// assert_eq!(stats.hit_ratio(), 0.5);
+/// assert!(stats.saved_bytes() > 1000);
+/// ```
+pub struct CompressedMap<K, V> {
+    map: HashMap<K, CompressedVec>,
+    raw_bytes: usize,
+    stored_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    value: PhantomData<fn() -> V>,
+}
+
+impl<K: Hash + Eq, V: Serialize + DeserializeOwned> CompressedMap<K, V> {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        CompressedMap {
+            map: HashMap::new(),
+            raw_bytes: 0,
+            stored_bytes: 0,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            value: PhantomData,
+        }
+    }
+
+    fn account_removed(&mut self, old: Option<CompressedVec>) -> bool {
+        match old {
+            Some(old) => {
+                self.raw_bytes -= old.len();
+                self.stored_bytes -= old.compressed_len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Compress `value` under `key`, replacing any value there. Fails only
+    /// if `value` can't be serialized.
+    pub fn insert(&mut self, key: K, value: &V) -> bincode::Result<()> {
+        let entry = CompressedVec::new(bincode::serialize(value)?);
+        self.raw_bytes += entry.len();
+        self.stored_bytes += entry.compressed_len();
+        let old = self.map.insert(key, entry);
+        self.account_removed(old);
+        Ok(())
+    }
+
+    /// Decode the value under `key`, counting a hit or a miss.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.map.get(key) {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(bincode::deserialize(&entry.get()).expect("compressed value is corrupt"))
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// `true` if the map holds a value under `key`, without counting it as
+    /// a lookup.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Drop the value under `key`, returning whether there was one.
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let old = self.map.remove(key);
+        self.account_removed(old)
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// `true` if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Drop every entry, keeping the hit and miss counts.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.raw_bytes = 0;
+        self.stored_bytes = 0;
+    }
+
+    /// The map's size and lookup counters.
+    pub fn stats(&self) -> MapStats {
+        MapStats {
+            entries: self.map.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            raw_bytes: self.raw_bytes,
+            stored_bytes: self.stored_bytes,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Serialize + DeserializeOwned> Default for CompressedMap<K, V> {
+    fn default() -> Self {
+        CompressedMap::new()
+    }
+}
+
+impl<K, V> fmt::Debug for CompressedMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedMap")
+            .field("entries", &self.map.len())
+            .field("raw_bytes", &self.raw_bytes)
+            .field("stored_bytes", &self.stored_bytes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_map_accounting() {
+        let mut map: CompressedMap<u32, Vec<String>> = CompressedMap::new();
+        for i in 0..100 {
+            map.insert(i, &vec![format!("row {}", i % 7); 50]).unwrap();
+        }
+        let full = map.stats();
+        assert_eq!(full.entries, 100);
+        assert!(full.saved_bytes() * 2 > full.raw_bytes);
+
+        assert_eq!(map.get(&3).unwrap(), vec![String::from("row 3"); 50]);
+        assert_eq!(map.get(&100), None);
+        assert!(map.contains_key(&99));
+
+        map.insert(3, &Vec::new()).unwrap();
+        assert!(map.remove(&4) && !map.remove(&4));
+        let stats = map.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (99, 1, 1));
+        assert_eq!(stats.hit_ratio(), 0.5);
+        assert!(stats.raw_bytes < full.raw_bytes);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.stats().raw_bytes, 0);
+        assert_eq!(map.stats().stored_bytes, 0);
+    }
+}
@@ -0,0 +1,265 @@
+//! Byte and string containers kept compressed in memory.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Bytes kept LZO-compressed in memory and decompressed on access, for
+/// holding many mostly idle blobs where memory, not CPU, is the limit.
+///
+/// Contents that don't compress are kept as is. A value made `hot` also
+/// keeps its decompressed copy after the first access, until `evict`, for
+/// the few blobs being worked on.
+///
+/// # Panics
+///
+/// Accessing the contents panics if minilzo's self-check fails, like
+/// `LZO::new`.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::CompressedVec;
+///
+/// let blob = CompressedVec::new(vec![b'z'; 10_000]);
+/// assert_eq!(blob.len(), 10_000);
+/// assert!(blob.compressed_len() < 1000);
+/// assert_eq!(&blob.get()[..], &[b'z'; 10_000][..]);
+/// ```
+#[derive(Clone)]
+pub struct CompressedVec {
+    data: Box<[u8]>,
+    len: usize,
+    hot: bool,
+    cache: OnceLock<Vec<u8>>,
+}
+
+impl CompressedVec {
+    /// Compress `data` into a new container.
+    pub fn new(data: impl AsRef<[u8]>) -> Self {
+        let data = data.as_ref();
+        let compressed = crate::compress(data)
+            .ok()
+            .filter(|compressed| compressed.len() < data.len());
+        CompressedVec {
+            data: compressed
+                .unwrap_or_else(|| data.to_vec())
+                .into_boxed_slice(),
+            len: data.len(),
+            hot: false,
+            cache: OnceLock::new(),
+        }
+    }
+
+    /// Keep the decompressed contents after the first access.
+    pub fn hot(mut self) -> Self {
+        self.hot = true;
+        self
+    }
+
+    /// Drop the decompressed copy kept by a `hot` value.
+    pub fn evict(&mut self) {
+        self.cache.take();
+    }
+
+    /// The length of the contents.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `true` if the contents are empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes held in memory for the contents.
+    pub fn compressed_len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn decompress(&self) -> Vec<u8> {
+        crate::decompress(&self.data, self.len).expect("compressed contents are corrupt")
+    }
+
+    /// The contents, borrowed if they are stored as is or cached.
+    pub fn get(&self) -> Cow<'_, [u8]> {
+        if self.data.len() == self.len {
+            Cow::Borrowed(&self.data)
+        } else if self.hot {
+            Cow::Borrowed(self.cache.get_or_init(|| self.decompress()))
+        } else {
+            Cow::Owned(self.decompress())
+        }
+    }
+
+    /// Replace the contents, keeping `hot`.
+    pub fn set(&mut self, data: impl AsRef<[u8]>) {
+        *self = CompressedVec {
+            hot: self.hot,
+            ..CompressedVec::new(data)
+        };
+    }
+
+    /// The contents, decompressed.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.get().into_owned()
+    }
+}
+
+impl Default for CompressedVec {
+    fn default() -> Self {
+        CompressedVec::new(b"")
+    }
+}
+
+impl fmt::Debug for CompressedVec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedVec")
+            .field("len", &self.len)
+            .field("compressed_len", &self.data.len())
+            .field("hot", &self.hot)
+            .finish()
+    }
+}
+
+impl PartialEq for CompressedVec {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.get() == other.get()
+    }
+}
+
+impl Eq for CompressedVec {}
+
+impl From<Vec<u8>> for CompressedVec {
+    fn from(data: Vec<u8>) -> Self {
+        CompressedVec::new(data)
+    }
+}
+
+impl From<&[u8]> for CompressedVec {
+    fn from(data: &[u8]) -> Self {
+        CompressedVec::new(data)
+    }
+}
+
+/// A string kept LZO-compressed in memory, like `CompressedVec`.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::CompressedString;
+///
+/// let text = CompressedString::new("lorem ipsum ".repeat(100)).hot();
+/// assert!(text.compressed_len() < text.len() / 4);
+/// assert!(text.get().starts_with("lorem ipsum"));
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct CompressedString(CompressedVec);
+
+impl CompressedString {
+    /// Compress `text` into a new container.
+    pub fn new(text: impl AsRef<str>) -> Self {
+        CompressedString(CompressedVec::new(text.as_ref()))
+    }
+
+    /// Keep the decompressed contents after the first access.
+    pub fn hot(self) -> Self {
+        CompressedString(self.0.hot())
+    }
+
+    /// Drop the decompressed copy kept by a `hot` value.
+    pub fn evict(&mut self) {
+        self.0.evict();
+    }
+
+    /// The length of the contents in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// `true` if the contents are empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The bytes held in memory for the contents.
+    pub fn compressed_len(&self) -> usize {
+        self.0.compressed_len()
+    }
+
+    /// The contents, borrowed if they are stored as is or cached.
+    pub fn get(&self) -> Cow<'_, str> {
+        match self.0.get() {
+            Cow::Borrowed(bytes) => {
+                Cow::Borrowed(std::str::from_utf8(bytes).expect("compressed text is corrupt"))
+            }
+            Cow::Owned(bytes) => {
+                Cow::Owned(String::from_utf8(bytes).expect("compressed text is corrupt"))
+            }
+        }
+    }
+
+    /// Replace the contents, keeping `hot`.
+    pub fn set(&mut self, text: impl AsRef<str>) {
+        self.0.set(text.as_ref());
+    }
+
+    /// The contents, decompressed.
+    pub fn into_string(self) -> String {
+        self.get().into_owned()
+    }
+}
+
+impl fmt::Debug for CompressedString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedString")
+            .field("len", &self.0.len)
+            .field("compressed_len", &self.0.data.len())
+            .field("hot", &self.0.hot)
+            .finish()
+    }
+}
+
+impl From<String> for CompressedString {
+    fn from(text: String) -> Self {
+        CompressedString::new(text)
+    }
+}
+
+impl From<&str> for CompressedString {
+    fn from(text: &str) -> Self {
+        CompressedString::new(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_containers() {
+        let text = "the quick brown fox ".repeat(200);
+        let mut blob = CompressedString::from(text.as_str());
+        assert!(blob.compressed_len() < text.len() / 4);
+        assert!(matches!(blob.get(), Cow::Owned(_)));
+        assert_eq!(blob.get(), text);
+
+        blob = blob.hot();
+        assert!(matches!(blob.get(), Cow::Borrowed(_)));
+        blob.set("short");
+        assert_eq!(blob.get(), "short");
+        assert_eq!(blob.compressed_len(), 5);
+        blob.evict();
+        assert_eq!(blob.clone().into_string(), "short");
+
+        let noise: Vec<u8> = (0..1000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let stored = CompressedVec::from(noise.clone());
+        assert_eq!(stored.compressed_len(), noise.len());
+        assert!(matches!(stored.get(), Cow::Borrowed(_)));
+        assert_eq!(stored.into_vec(), noise);
+        assert!(CompressedVec::default().is_empty());
+        assert_eq!(CompressedVec::new(&noise), CompressedVec::new(&noise).hot());
+    }
+}
@@ -0,0 +1,61 @@
+//! A shareable compression handle backed by per-thread work memory.
+
+use crate::CompressError;
+
+/// A compression handle that only needs `&self`.
+///
+/// `LZO::compress` takes `&mut self` because the instance owns its work
+/// memory. `Compressor` owns none: like the `compress` free function, it
+/// compresses with a work memory kept per thread, so it is zero-sized,
+/// `Send + Sync`, and can sit in an `Arc` or a `static` without a mutex.
+///
+/// Example
+///
+/// ```rust
+/// use std::sync::Arc;
+///
+/// let compressor = Arc::new(minilzo_rs::Compressor::new());
+/// let shared = Arc::clone(&compressor);
+/// let out = std::thread::spawn(move || shared.compress(&[0x00u8; 1024]).unwrap())
+///     .join()
+///     .unwrap();
+/// assert_eq!(minilzo_rs::decompress(&out, 1024).unwrap(), vec![0x00u8; 1024]);
+/// assert_eq!(out, compressor.compress(&[0x00u8; 1024]).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Compressor {
+    _private: (),
+}
+
+impl Compressor {
+    /// Create a compressor.
+    pub const fn new() -> Self {
+        Compressor { _private: () }
+    }
+
+    /// Compress the src data and return an error if it fails.
+    #[inline]
+    pub fn compress(&self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        crate::compress(src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SHARED: Compressor = Compressor::new();
+
+    #[test]
+    fn test_static_compressor_from_threads() {
+        std::thread::scope(|s| {
+            for seed in 0..4u8 {
+                s.spawn(move || {
+                    let input: Vec<u8> = (0..30_000u32).map(|i| (i % 19) as u8 ^ seed).collect();
+                    let out = SHARED.compress(&input).unwrap();
+                    assert_eq!(crate::decompress(&out, input.len()).unwrap(), input);
+                });
+            }
+        });
+    }
+}
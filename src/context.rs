@@ -0,0 +1,151 @@
+//! A compression context that also keeps its output buffer.
+
+use crate::scratch::Scratch;
+use crate::{LZOResult, OutputAlloc, LZO};
+
+/// An `LZO` bundled with an output buffer that is reused across calls.
+///
+/// `compress` and `decompress_safe` return a borrow into the buffer instead
+/// of a fresh `Vec`, so a hot loop allocates only while the buffer grows to
+/// the largest output it has seen.
+///
+/// Example
+///
+/// ```rust
+/// let mut ctx = minilzo_rs::LzoContext::new();
+/// for message in [&b"first message"[..], &[0u8; 4096][..]] {
+///     let compressed = ctx.compress(message).unwrap().to_vec();
+///     assert_eq!(ctx.decompress_safe(&compressed, message.len()).unwrap(), message);
+/// }
+/// ```
+#[derive(Default)]
+pub struct LzoContext {
+    lzo: LZO,
+    buf: Scratch,
+}
+
+impl LzoContext {
+    /// Create a context with an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a context around a configured instance.
+    pub fn from_lzo(lzo: LZO) -> Self {
+        LzoContext {
+            lzo,
+            buf: Scratch::new(),
+        }
+    }
+
+    /// Compress `src` into the buffer and return the compressed bytes.
+    #[inline]
+    pub fn compress(&mut self, src: &[u8]) -> LZOResult<&[u8]> {
+        self.lzo.compress_to(src, &mut self.buf)?;
+        Ok(&self.buf)
+    }
+
+    /// Decompress at most `dst_len` bytes into the buffer, with overrun
+    /// testing, and return the bytes written.
+    #[inline]
+    pub fn decompress_safe(&mut self, src: &[u8], dst_len: usize) -> LZOResult<&[u8]> {
+        self.buf.clear();
+        self.buf.try_reserve(dst_len)?;
+        let len = self
+            .lzo
+            .decompress_safe_uninit(src, &mut self.buf.spare_capacity_mut()[..dst_len])?
+            .len();
+        // SAFETY: the decoder initialized the first `len` bytes.
+        unsafe { self.buf.set_len(len) };
+        Ok(&self.buf)
+    }
+
+    /// Compress `src` and copy the result into an allocation from `alloc`,
+    /// sized exactly, so outputs kept around can live in an arena or pool
+    /// rather than on the global heap.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut arena = Vec::new();
+    /// let mut ctx = minilzo_rs::LzoContext::new();
+    /// let range = ctx
+    ///     .compress_in(&[7u8; 1000], |bytes: &[u8]| {
+    ///         arena.extend_from_slice(bytes);
+    ///         arena.len() - bytes.len()..arena.len()
+    ///     })
+    ///     .unwrap();
+    /// let data = ctx.decompress_in(&arena[range], 1000, <[u8]>::to_vec).unwrap();
+    /// assert_eq!(data, [7u8; 1000]);
+    /// ```
+    pub fn compress_in<A: OutputAlloc>(&mut self, src: &[u8], alloc: A) -> LZOResult<A::Output> {
+        Ok(alloc.alloc_copy(self.compress(src)?))
+    }
+
+    /// Decompress at most `dst_len` bytes, with overrun testing, and copy
+    /// them into an allocation from `alloc`.
+    pub fn decompress_in<A: OutputAlloc>(
+        &mut self,
+        src: &[u8],
+        dst_len: usize,
+        alloc: A,
+    ) -> LZOResult<A::Output> {
+        Ok(alloc.alloc_copy(self.decompress_safe(src, dst_len)?))
+    }
+
+    /// Current capacity of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Release the buffer's memory.
+    pub fn shrink(&mut self) {
+        self.buf = Scratch::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_is_reused() {
+        let mut ctx = LzoContext::new();
+        let input: Vec<u8> = (0..100_000u32).map(|i| (i % 250) as u8).collect();
+        let len = ctx.compress(&input).unwrap().len();
+        let capacity = ctx.capacity();
+        for _ in 0..3 {
+            assert_eq!(ctx.compress(&input).unwrap().len(), len);
+            assert_eq!(
+                ctx.compress(b"tiny").unwrap().len(),
+                LZO::new().compress(b"tiny").unwrap().len()
+            );
+            assert_eq!(ctx.capacity(), capacity);
+        }
+        ctx.shrink();
+        assert_eq!(ctx.capacity(), 0);
+    }
+
+    #[test]
+    fn test_output_in_allocator() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i % 50) as u8).collect();
+        let mut ctx = LzoContext::new();
+        let expected = LZO::new().compress(&input).unwrap();
+        let boxed = ctx
+            .compress_in(&input, |bytes: &[u8]| Box::<[u8]>::from(bytes))
+            .unwrap();
+        assert_eq!(&boxed[..], &expected[..]);
+        let data = ctx
+            .decompress_in(&boxed, input.len(), <[u8]>::to_vec)
+            .unwrap();
+        assert_eq!(data, input);
+        assert!(ctx.decompress_in(&boxed, 100, <[u8]>::to_vec).is_err());
+
+        #[cfg(feature = "bumpalo")]
+        {
+            let bump = bumpalo::Bump::new();
+            let out = ctx.compress_in(&input, &bump).unwrap();
+            assert_eq!(&out[..], &expected[..]);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! `io::copy`-style helpers between plain and framed streams.
+
+use crate::{frame, CompressionStats, LzoReader, LzoWriter};
+use std::io::{self, Read, Write};
+
+/// Compress everything `reader` yields into a framed stream on `writer`,
+/// like `io::copy`, and return the byte and block counts.
+///
+/// Memory use is one block of input and one of output however long the
+/// input is. The writer is flushed but not otherwise finalized, so a file
+/// still needs `sync_all` for durability.
+///
+/// Example
+///
+/// ```rust
+/// let input = vec![0x5au8; 100_000];
+/// let mut framed = Vec::new();
+/// let stats = minilzo_rs::copy_compress(&mut &input[..], &mut framed).unwrap();
+/// assert_eq!(stats.input_bytes, 100_000);
+/// assert_eq!(stats.output_bytes, framed.len() as u64);
+///
+/// let mut output = Vec::new();
+/// let stats = minilzo_rs::copy_decompress(&mut &framed[..], &mut output).unwrap();
+/// assert_eq!(stats.output_bytes, 100_000);
+/// assert_eq!(output, input);
+/// ```
+pub fn copy_compress<R, W>(reader: &mut R, writer: &mut W) -> io::Result<CompressionStats>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut lzo = LzoWriter::new(writer).map_err(frame::to_io)?;
+    io::copy(reader, &mut lzo)?;
+    // Flushing ends the last block, so only the end marker is left out.
+    lzo.flush()?;
+    let mut stats = lzo.stats();
+    lzo.finish()?;
+    stats.output_bytes += 4;
+    Ok(stats)
+}
+
+/// Decompress the framed stream `reader` yields onto `writer`, like
+/// `io::copy`, and return the byte and block counts. The inverse of
+/// `copy_compress`.
+///
+/// Fails with `io::ErrorKind::UnexpectedEof` if the stream is cut short;
+/// whatever was decoded before that has already been written.
+pub fn copy_decompress<R, W>(reader: &mut R, writer: &mut W) -> io::Result<CompressionStats>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut lzo = LzoReader::new(reader).map_err(frame::to_io)?;
+    io::copy(&mut lzo, writer)?;
+    writer.flush()?;
+    Ok(lzo.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_roundtrip() {
+        let input: Vec<u8> = (0..500_000u32).map(|i| (i % 199) as u8).collect();
+        let mut framed = Vec::new();
+        let stats = copy_compress(&mut &input[..], &mut framed).unwrap();
+        assert_eq!(stats.input_bytes, input.len() as u64);
+        assert_eq!(stats.output_bytes, framed.len() as u64);
+        assert_eq!(stats.blocks, 2);
+
+        let mut output = Vec::new();
+        let stats = copy_decompress(&mut &framed[..], &mut output).unwrap();
+        assert_eq!(output, input);
+        assert_eq!(
+            (stats.input_bytes, stats.output_bytes, stats.blocks),
+            (framed.len() as u64, input.len() as u64, 2)
+        );
+
+        let err = copy_decompress(&mut &framed[..framed.len() - 1], &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut empty = Vec::new();
+        copy_compress(&mut io::empty(), &mut empty).unwrap();
+        assert_eq!(crate::decompress_chunked(&empty).unwrap(), b"");
+    }
+}
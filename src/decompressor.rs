@@ -0,0 +1,784 @@
+//! Decompression needs no work memory, so it gets its own zero-sized type.
+
+use crate::scratch::try_with_capacity;
+use crate::{instrument, minilzo, DecompressError, LZOResult, PartialOutput, LZO};
+use std::mem::MaybeUninit;
+
+type DecodeFn = unsafe extern "C" fn(
+    *const u8,
+    minilzo::lzo_uint,
+    *mut u8,
+    *mut minilzo::lzo_uint,
+    *mut std::os::raw::c_void,
+) -> std::os::raw::c_int;
+
+/// Run `decoder` and return the number of bytes it initialized at the start
+/// of `dst`, filling in the byte counts its error codes leave out.
+#[inline]
+fn decode(
+    decoder: DecodeFn,
+    src: &[u8],
+    dst: &mut [MaybeUninit<u8>],
+) -> Result<usize, DecompressError> {
+    let timer = instrument::Timer::start();
+    // SAFETY: both pointers come from live slices of the given lengths.
+    let (code, len) = unsafe {
+        decode_raw(
+            decoder,
+            src.as_ptr(),
+            src.len(),
+            dst.as_mut_ptr() as *mut u8,
+            dst.len(),
+        )
+    };
+    let result = match code {
+        0 => Ok(len),
+        _ => Err(match DecompressError::from_code(code) {
+            DecompressError::OutputOverrun { .. } => DecompressError::OutputOverrun {
+                capacity: dst.len(),
+                needed: None,
+            },
+            DecompressError::InputNotConsumed { .. } => DecompressError::InputNotConsumed {
+                remaining: src.len() - stream_len(src, dst),
+            },
+            e => e,
+        }),
+    };
+    instrument::decompressed(timer, src.len(), result.as_ref().copied());
+    result
+}
+
+/// Run `decoder` on raw buffers, which may overlap, and return its code
+/// and the number of bytes it wrote.
+///
+/// # Safety
+///
+/// `src` must be valid for reading `src_len` bytes and `dst` for writing
+/// `dst_len` bytes.
+#[inline]
+unsafe fn decode_raw(
+    decoder: DecodeFn,
+    src: *const u8,
+    src_len: usize,
+    dst: *mut u8,
+    dst_len: usize,
+) -> (std::os::raw::c_int, usize) {
+    let mut out_len = dst_len as minilzo::lzo_uint;
+    let code = decoder(
+        src,
+        src_len as minilzo::lzo_uint,
+        dst,
+        &mut out_len,
+        std::ptr::null_mut(),
+    );
+    // The safe decoder never goes past `dst_len`; clamping keeps the
+    // length in bounds for the unchecked one, whose callers vouch for the
+    // input.
+    (code, (out_len as usize).min(dst_len))
+}
+
+/// Length of the LZO1X stream at the start of `src`, which the C decoder
+/// doesn't report. A stream ends with the marker `11 00 00`. The decoder
+/// runs out of input on a prefix cut before the stream's end and reaches
+/// the end on any other, so the stream ends at the first marker whose
+/// prefix decodes, found by bisection over the markers.
+#[cold]
+fn stream_len(src: &[u8], dst: &mut [MaybeUninit<u8>]) -> usize {
+    let ends: Vec<usize> = (3..src.len())
+        .filter(|&end| src[end - 3..end] == [0x11, 0, 0])
+        .collect();
+    let (mut lo, mut hi) = (0, ends.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        // SAFETY: `ends[mid]` is within `src`, and `dst` is a live slice.
+        let (code, _) = unsafe {
+            decode_raw(
+                minilzo::lzo1x_decompress_safe,
+                src.as_ptr(),
+                ends[mid],
+                dst.as_mut_ptr() as *mut u8,
+                dst.len(),
+            )
+        };
+        if code == 0 || code == minilzo::LZO_E_INPUT_NOT_CONSUMED {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    ends.get(lo).copied().unwrap_or(src.len())
+}
+
+/// Length of the shortest prefix of `src` the safe decoder gets at least
+/// `len` bytes of output from, with room for `dst_len`. Output only grows
+/// with the input the decoder is given, so it is found by bisection. If
+/// the room can't be allocated, all of `src` is reported.
+#[cold]
+fn consumed_for(src: &[u8], len: usize, dst_len: usize) -> usize {
+    let mut dst = match try_with_capacity(dst_len) {
+        Ok(dst) => dst,
+        Err(_) => return src.len(),
+    };
+    let (mut lo, mut hi) = (0, src.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        // SAFETY: `mid` is within `src`, and `dst` has room for `dst_len`
+        // bytes.
+        let (_, out) = unsafe {
+            decode_raw(
+                minilzo::lzo1x_decompress_safe,
+                src.as_ptr(),
+                mid,
+                dst.as_mut_ptr(),
+                dst_len,
+            )
+        };
+        if out >= len {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    lo
+}
+
+/// A decompression-only handle.
+///
+/// Unlike `LZO`, which carries the compressor's work memory, `Decompressor`
+/// is zero-sized: LZO1X decoding runs entirely out of the source and
+/// destination buffers. Read-only consumers should use it instead of
+/// constructing an `LZO`.
+///
+/// Example
+///
+/// ```rust
+/// let mut lzo = minilzo_rs::LZO::new();
+/// let out = lzo.compress(&[0x00u8; 1024]).unwrap();
+///
+/// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+/// assert_eq!(std::mem::size_of_val(&decompressor), 0);
+/// let input = decompressor.decompress_safe(&out, 1024).unwrap();
+/// assert_eq!(input, vec![0x00u8; 1024]);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Decompressor {
+    _private: (),
+}
+
+impl Decompressor {
+    /// Initializing a decompressor.
+    #[inline]
+    pub fn new() -> LZOResult<Self> {
+        LZO::lzo_init()?;
+        Ok(Self::initialized())
+    }
+
+    /// For callers that already hold proof of initialization, such as an `LZO`.
+    pub(crate) const fn initialized() -> Self {
+        Decompressor { _private: () }
+    }
+
+    /// Decompress at most `dst_len` bytes, returning only the bytes
+    /// actually produced, without overrun testing (`unsafe-decompress`
+    /// feature).
+    ///
+    /// # Safety
+    ///
+    /// As for `LZO::decompress`: `src` must be well-formed and decode to at
+    /// most `dst_len` bytes.
+    #[cfg(feature = "unsafe-decompress")]
+    #[inline]
+    pub unsafe fn decompress(
+        &self,
+        src: &[u8],
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        self.decompress_vec(minilzo::lzo1x_decompress, src, dst_len)
+    }
+
+    /// safe decompression with overrun testing. Returns at most `dst_len`
+    /// bytes, truncated to what the input actually decodes to.
+    #[inline]
+    pub fn decompress_safe(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+        self.decompress_vec(minilzo::lzo1x_decompress_safe, src, dst_len)
+    }
+
+    /// Decompress with `decoder` into a fresh `Vec` of capacity `dst_len`,
+    /// writing its memory only once instead of zeroing it first, and set its
+    /// length to the bytes written.
+    #[inline]
+    fn decompress_vec(
+        &self,
+        decoder: DecodeFn,
+        src: &[u8],
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        let mut dst = try_with_capacity(dst_len)?;
+        let len = decode(decoder, src, &mut dst.spare_capacity_mut()[..dst_len])?;
+        // SAFETY: the decoder has initialized the first `len` bytes.
+        unsafe { dst.set_len(len) };
+        Ok(dst)
+    }
+
+    /// Decompress like `decompress_safe`, but on failure return what was
+    /// decoded before it along with the error, for recovering the intact
+    /// start of damaged data.
+    ///
+    /// The safe decoder checks every instruction before carrying it out, so
+    /// `partial` holds the output of all the instructions before the one
+    /// that failed. Finding how much input that took costs a few more
+    /// decoding passes, paid only on failure.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let text: Vec<u8> = (0..4000u32).map(|i| b"lorem ipsum "[i as usize % 12]).collect();
+    /// let compressed = minilzo_rs::compress(&text).unwrap();
+    ///
+    /// // Lose the end of the compressed data.
+    /// let cut = &compressed[..compressed.len() / 2];
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// let err = decompressor.decompress_partial(cut, text.len()).unwrap_err();
+    /// assert_eq!(err.error, minilzo_rs::Error::InputOverrun);
+    /// assert!(!err.partial.is_empty());
+    /// assert_eq!(err.partial, text[..err.partial.len()]);
+    /// assert!(err.consumed <= cut.len());
+    /// ```
+    pub fn decompress_partial(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, PartialOutput> {
+        let timer = instrument::Timer::start();
+        let mut dst = try_with_capacity(dst_len).map_err(|e| PartialOutput {
+            error: e.into(),
+            partial: Vec::new(),
+            consumed: 0,
+        })?;
+        // SAFETY: `dst` has room for `dst_len` bytes.
+        let (code, len) = unsafe {
+            decode_raw(
+                minilzo::lzo1x_decompress_safe,
+                src.as_ptr(),
+                src.len(),
+                dst.as_mut_ptr(),
+                dst_len,
+            )
+        };
+        // SAFETY: the decoder initialized the first `len` bytes, on failure
+        // too.
+        unsafe { dst.set_len(len) };
+        if code == 0 {
+            instrument::decompressed(timer, src.len(), Ok(len));
+            return Ok(dst);
+        }
+        let consumed = consumed_for(src, len, dst_len);
+        let error = match DecompressError::from_code(code) {
+            DecompressError::OutputOverrun { .. } => DecompressError::OutputOverrun {
+                capacity: dst_len,
+                needed: None,
+            },
+            DecompressError::InputNotConsumed { .. } => DecompressError::InputNotConsumed {
+                remaining: src.len() - consumed,
+            },
+            e => e,
+        };
+        instrument::decompressed(timer, src.len(), Err(&error));
+        Err(PartialOutput {
+            error: error.into(),
+            partial: dst,
+            consumed,
+        })
+    }
+
+    /// Decompress only the first `n` bytes of output, with overrun
+    /// testing, e.g. to sniff a header without decoding a whole blob.
+    ///
+    /// Returns fewer than `n` bytes only if the stream decodes to fewer.
+    /// `src` may be cut short after the data making up the prefix. The
+    /// decoder stops short of any run that would pass the end of its
+    /// buffer, so it runs into a buffer a little larger than `n`, grown and
+    /// rerun in the rare case that isn't enough.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut blob = b"HDR1".to_vec();
+    /// blob.extend_from_slice(&[0x5au8; 1 << 20]);
+    /// let out = minilzo_rs::compress(&blob).unwrap();
+    ///
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// assert_eq!(decompressor.decompress_prefix(&out, 4).unwrap(), b"HDR1");
+    /// ```
+    pub fn decompress_prefix(&self, src: &[u8], n: usize) -> Result<Vec<u8>, DecompressError> {
+        let timer = instrument::Timer::start();
+        let mut capacity = n.saturating_add(n / 4).saturating_add(256);
+        let result = loop {
+            let mut dst = match try_with_capacity(capacity) {
+                Ok(dst) => dst,
+                Err(e) => break Err(e.into()),
+            };
+            // SAFETY: `dst` has room for `capacity` bytes.
+            let (code, len) = unsafe {
+                decode_raw(
+                    minilzo::lzo1x_decompress_safe,
+                    src.as_ptr(),
+                    src.len(),
+                    dst.as_mut_ptr(),
+                    capacity,
+                )
+            };
+            match DecompressError::from_code(code) {
+                // Ran out of input or room after producing the prefix.
+                _ if code == 0 || len >= n => {
+                    // SAFETY: the decoder initialized the first `len` bytes.
+                    unsafe { dst.set_len(len.min(n)) };
+                    break Ok(dst);
+                }
+                DecompressError::OutputOverrun { .. } => capacity = capacity.saturating_mul(2),
+                DecompressError::InputNotConsumed { .. } => {
+                    break Err(DecompressError::InputNotConsumed {
+                        remaining: src.len() - stream_len(src, dst.spare_capacity_mut()),
+                    })
+                }
+                e => break Err(e),
+            }
+        };
+        instrument::decompressed(timer, src.len(), result.as_ref().map(Vec::len));
+        result
+    }
+
+    /// Decompress a stream followed by other data, with overrun testing,
+    /// returning the output and the number of bytes after the stream.
+    ///
+    /// For LZO data embedded in a larger file whose compressed length isn't
+    /// recorded: where `decompress_safe` fails with `InputNotConsumed`,
+    /// this succeeds, and the stream's length is `src.len()` minus the
+    /// returned count.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut file = minilzo_rs::compress(&[0x11u8; 1000]).unwrap();
+    /// let stream_len = file.len();
+    /// file.extend_from_slice(b"next record");
+    ///
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// let (data, trailing) = decompressor.decompress_with_trailing(&file, 1000).unwrap();
+    /// assert_eq!(data, vec![0x11u8; 1000]);
+    /// assert_eq!(&file[file.len() - trailing..], b"next record");
+    /// assert_eq!(file.len() - trailing, stream_len);
+    /// ```
+    pub fn decompress_with_trailing(
+        &self,
+        src: &[u8],
+        dst_len: usize,
+    ) -> Result<(Vec<u8>, usize), DecompressError> {
+        let timer = instrument::Timer::start();
+        let mut dst = try_with_capacity(dst_len)?;
+        // SAFETY: `dst` has room for `dst_len` bytes.
+        let (code, len) = unsafe {
+            decode_raw(
+                minilzo::lzo1x_decompress_safe,
+                src.as_ptr(),
+                src.len(),
+                dst.as_mut_ptr(),
+                dst_len,
+            )
+        };
+        let result = match code {
+            0 => Ok(0),
+            _ => match DecompressError::from_code(code) {
+                // The decoder stopped at the end marker, having written the
+                // whole output.
+                DecompressError::InputNotConsumed { .. } => {
+                    Ok(src.len() - stream_len(src, &mut dst.spare_capacity_mut()[..dst_len]))
+                }
+                DecompressError::OutputOverrun { .. } => Err(DecompressError::OutputOverrun {
+                    capacity: dst_len,
+                    needed: None,
+                }),
+                e => Err(e),
+            },
+        };
+        instrument::decompressed(timer, src.len(), result.as_ref().map(|_| len));
+        let trailing = result?;
+        // SAFETY: the decoder initialized the first `len` bytes.
+        unsafe { dst.set_len(len) };
+        Ok((dst, trailing))
+    }
+
+    /// Decompress into a possibly uninitialized buffer, with overrun
+    /// testing, and return the initialized prefix.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::mem::MaybeUninit;
+    ///
+    /// let out = minilzo_rs::compress(b"hello hello hello").unwrap();
+    /// let mut buf = [MaybeUninit::<u8>::uninit(); 64];
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// let data = decompressor.decompress_safe_uninit(&out, &mut buf).unwrap();
+    /// assert_eq!(data, b"hello hello hello");
+    /// ```
+    #[inline]
+    pub fn decompress_safe_uninit<'a>(
+        &self,
+        src: &[u8],
+        dst: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], DecompressError> {
+        let len = decode(minilzo::lzo1x_decompress_safe, src, dst)?;
+        // SAFETY: the decoder initialized the first `len` bytes.
+        Ok(unsafe { std::slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut u8, len) })
+    }
+
+    /// Decompress the `comp_len` bytes at `comp_offset` in `buf` into the
+    /// start of the same buffer, producing at most `out_len` bytes, and
+    /// return the number of bytes written.
+    ///
+    /// The compressed data must end at least `in_place_len(out_len)` bytes
+    /// into `buf`, which leaves the decoder enough room that it never
+    /// overwrites input it has yet to read; placing it at the very end of a
+    /// buffer of exactly that length is the usual layout. Anything else is
+    /// rejected with `DecompressError::InvalidArgument`.
+    ///
+    /// Decoding is overrun-checked as in `decompress_safe`. The compressed
+    /// data is overwritten, so on `InputNotConsumed` the number of left-over
+    /// bytes can't be recovered and is reported as 0.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let data = vec![0x2au8; 4096];
+    /// let compressed = minilzo_rs::compress(&data).unwrap();
+    ///
+    /// let mut buf = vec![0u8; minilzo_rs::in_place_len(data.len())];
+    /// let comp_offset = buf.len() - compressed.len();
+    /// buf[comp_offset..].copy_from_slice(&compressed);
+    ///
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// let len = decompressor
+    ///     .decompress_in_place(&mut buf, comp_offset, compressed.len(), data.len())
+    ///     .unwrap();
+    /// assert_eq!(&buf[..len], &data[..]);
+    /// ```
+    pub fn decompress_in_place(
+        &self,
+        buf: &mut [u8],
+        comp_offset: usize,
+        comp_len: usize,
+        out_len: usize,
+    ) -> Result<usize, DecompressError> {
+        let comp_end = comp_offset
+            .checked_add(comp_len)
+            .filter(|&end| end <= buf.len())
+            .ok_or(DecompressError::InvalidArgument)?;
+        if comp_end < crate::in_place_len(out_len) {
+            return Err(DecompressError::InvalidArgument);
+        }
+        let timer = instrument::Timer::start();
+        let base = buf.as_mut_ptr();
+        // SAFETY: both ranges lie within `buf`, since `out_len` is below
+        // `in_place_len(out_len)` and hence below `comp_end`. The C decoder
+        // reads and writes through plain pointers, so the overlap is fine.
+        let (code, len) = unsafe {
+            decode_raw(
+                minilzo::lzo1x_decompress_safe,
+                base.add(comp_offset),
+                comp_len,
+                base,
+                out_len,
+            )
+        };
+        let result = match code {
+            0 => Ok(len),
+            _ => Err(match DecompressError::from_code(code) {
+                DecompressError::OutputOverrun { .. } => DecompressError::OutputOverrun {
+                    capacity: out_len,
+                    needed: None,
+                },
+                e => e,
+            }),
+        };
+        instrument::decompressed(timer, comp_len, result.as_ref().copied());
+        result
+    }
+
+    /// Decompress into an array of `N` bytes, with overrun testing,
+    /// returning it with the number of bytes written. Fails with
+    /// `OutputOverrun` if the output doesn't fit; nothing is allocated.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let (packed, len) = lzo.compress_fixed::<128>(b"sensor=7 value=21.5").unwrap();
+    ///
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// let (data, n) = decompressor.decompress_fixed::<32>(&packed[..len]).unwrap();
+    /// assert_eq!(&data[..n], b"sensor=7 value=21.5");
+    /// assert!(decompressor.decompress_fixed::<8>(&packed[..len]).is_err());
+    /// ```
+    pub fn decompress_fixed<const N: usize>(
+        &self,
+        src: &[u8],
+    ) -> Result<([u8; N], usize), DecompressError> {
+        let mut dst = [0u8; N];
+        let len = self.decompress_safe_slice(src, &mut dst)?;
+        Ok((dst, len))
+    }
+
+    /// Decompress into `dst`, with overrun testing, returning the number of
+    /// bytes written.
+    #[inline]
+    pub(crate) fn decompress_safe_slice(
+        &self,
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> Result<usize, DecompressError> {
+        // SAFETY: the decoder only writes initialized bytes, so viewing an
+        // initialized buffer as `MaybeUninit` can't de-initialize it.
+        let dst = unsafe {
+            std::slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut MaybeUninit<u8>, dst.len())
+        };
+        self.decompress_safe_uninit(src, dst).map(|out| out.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_output_is_truncated() {
+        let input: Vec<u8> = (0..1000u32).map(|i| (i % 11) as u8 + 1).collect();
+        let out = LZO::new().compress(&input).unwrap();
+        let decompressor = Decompressor::new().unwrap();
+        assert_eq!(decompressor.decompress_safe(&out, 1500).unwrap(), input);
+        #[cfg(feature = "unsafe-decompress")]
+        assert_eq!(
+            unsafe { decompressor.decompress(&out, 1500) }.unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_uninit_buffer_too_small() {
+        let out = LZO::new().compress([7u8; 4096]).unwrap();
+        let decompressor = Decompressor::new().unwrap();
+        let mut buf = vec![MaybeUninit::<u8>::uninit(); 4095];
+        assert_eq!(
+            decompressor.decompress_safe_uninit(&out, &mut buf),
+            Err(DecompressError::OutputOverrun {
+                capacity: 4095,
+                needed: None
+            })
+        );
+        buf.push(MaybeUninit::uninit());
+        let data = decompressor.decompress_safe_uninit(&out, &mut buf).unwrap();
+        assert_eq!(data, &[7u8; 4096][..]);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let input: Vec<u8> = (0..5000u32).map(|i| (i % 23) as u8).collect();
+        let out = LZO::new().compress(&input).unwrap();
+        let decompressor = Decompressor::new().unwrap();
+        assert_eq!(
+            decompressor.decompress_with_trailing(&out, 5000).unwrap(),
+            (input.clone(), 0)
+        );
+
+        let mut embedded = out.clone();
+        embedded.extend_from_slice(&[0x11, 0, 0, 7, 7]);
+        assert_eq!(
+            decompressor
+                .decompress_with_trailing(&embedded, 5000)
+                .unwrap(),
+            (input, 5)
+        );
+        assert_eq!(
+            decompressor.decompress_with_trailing(&embedded[..out.len() - 1], 5000),
+            Err(DecompressError::InputOverrun)
+        );
+        assert_eq!(
+            decompressor.decompress_with_trailing(&embedded, 4999),
+            Err(DecompressError::OutputOverrun {
+                capacity: 4999,
+                needed: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        // One long match makes the decoder stop well short of a small
+        // buffer, so the prefix needs a larger one.
+        let mut input = vec![1u8, 2, 3];
+        input.extend_from_slice(&[0u8; 100_000]);
+        input.extend_from_slice(b"tail");
+        let out = LZO::new().compress(&input).unwrap();
+        let decompressor = Decompressor::new().unwrap();
+        for n in [0, 3, 10, 5000, input.len(), input.len() + 10] {
+            let prefix = decompressor.decompress_prefix(&out, n).unwrap();
+            assert_eq!(prefix, &input[..n.min(input.len())]);
+        }
+        assert_eq!(
+            decompressor
+                .decompress_prefix(&out[..out.len() - 5], 1000)
+                .unwrap(),
+            &input[..1000]
+        );
+        assert_eq!(
+            decompressor.decompress_prefix(&out[..4], 1000),
+            Err(DecompressError::InputOverrun)
+        );
+    }
+
+    #[test]
+    fn test_in_place_margin() {
+        let mut x = 0x2545_f491u32;
+        let input: Vec<u8> = (0..50_000u32)
+            .map(|i| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                if i < 25_000 {
+                    x as u8
+                } else {
+                    (i % 7) as u8
+                }
+            })
+            .collect();
+        let compressed = LZO::new().compress(&input).unwrap();
+        let decompressor = Decompressor::new().unwrap();
+        let needed = crate::in_place_len(input.len());
+
+        let mut buf = vec![0u8; needed + 10];
+        let comp_offset = needed - compressed.len();
+        buf[comp_offset..needed].copy_from_slice(&compressed);
+        let len = decompressor
+            .decompress_in_place(&mut buf, comp_offset, compressed.len(), input.len())
+            .unwrap();
+        assert_eq!(&buf[..len], &input[..]);
+
+        for (offset, len) in [(comp_offset - 1, compressed.len()), (needed, usize::MAX)] {
+            assert_eq!(
+                decompressor.decompress_in_place(&mut buf, offset, len, input.len()),
+                Err(DecompressError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn test_trailing_input_is_counted() {
+        let mut src = LZO::new().compress([5u8; 3000]).unwrap();
+        src.extend_from_slice(&[0x11, 0, 0, 9, 9]);
+        assert_eq!(
+            Decompressor::new().unwrap().decompress_safe(&src, 3000),
+            Err(DecompressError::InputNotConsumed { remaining: 5 })
+        );
+    }
+
+    #[test]
+    fn test_trailing_input_after_many_markers() {
+        // Literals full of end markers: the stream's length is found by
+        // bisection, not by decoding up to every marker in turn.
+        let mut state = 0x2545_f491u32;
+        let mut data = Vec::new();
+        while data.len() < 400_000 {
+            data.extend_from_slice(&[0x11, 0, 0]);
+            for _ in 0..13 {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                data.push(state as u8);
+            }
+        }
+        let mut src = LZO::new().compress(&data).unwrap();
+        let markers = src.windows(3).filter(|w| *w == [0x11, 0, 0]).count();
+        assert!(markers > 10_000);
+        src.push(0);
+        assert_eq!(
+            Decompressor::new()
+                .unwrap()
+                .decompress_safe(&src, data.len()),
+            Err(DecompressError::InputNotConsumed { remaining: 1 })
+        );
+    }
+
+    #[test]
+    fn test_partial_output() {
+        let decompressor = Decompressor::new().unwrap();
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i * i % 251) as u8).collect();
+        let compressed = crate::compress(&input).unwrap();
+        assert_eq!(
+            decompressor
+                .decompress_partial(&compressed, input.len())
+                .unwrap(),
+            input
+        );
+
+        for cut in [0, 1, compressed.len() / 3, compressed.len() - 1] {
+            let err = decompressor
+                .decompress_partial(&compressed[..cut], input.len())
+                .unwrap_err();
+            assert_eq!(err.error, crate::Error::InputOverrun);
+            assert_eq!(err.partial, input[..err.partial.len()]);
+            assert!(err.consumed <= cut);
+            // The consumed prefix alone gives the same output.
+            let again = decompressor
+                .decompress_partial(&compressed[..err.consumed], input.len())
+                .unwrap_err();
+            assert_eq!(again.partial, err.partial);
+        }
+
+        // Too little room: the output up to the failing instruction.
+        let err = decompressor
+            .decompress_partial(&compressed, 5000)
+            .unwrap_err();
+        assert_eq!(
+            err.error,
+            crate::Error::OutputOverrun {
+                capacity: 5000,
+                needed: None
+            }
+        );
+        assert!(err.partial.len() <= 5000);
+        assert_eq!(err.partial, input[..err.partial.len()]);
+
+        let mut trailing = compressed.clone();
+        trailing.extend_from_slice(b"xyz");
+        let err = decompressor
+            .decompress_partial(&trailing, input.len())
+            .unwrap_err();
+        assert_eq!(err.partial, input);
+        assert_eq!(err.consumed, compressed.len());
+        assert_eq!(err.error, crate::Error::InputNotConsumed { remaining: 3 });
+    }
+
+    #[test]
+    fn test_unallocatable_output_is_an_error() {
+        let compressed = crate::compress(b"declared far larger than it is").unwrap();
+        let decompressor = Decompressor::new().unwrap();
+        for dst_len in [usize::MAX, isize::MAX as usize] {
+            assert_eq!(
+                decompressor.decompress_safe(&compressed, dst_len),
+                Err(DecompressError::OutOfMemory)
+            );
+            assert_eq!(
+                decompressor
+                    .decompress_partial(&compressed, dst_len)
+                    .unwrap_err()
+                    .error,
+                crate::Error::OutOfMemory
+            );
+        }
+        let limits = crate::Limits {
+            max_output: usize::MAX,
+            initial_ratio: usize::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            decompressor.decompress_auto(&compressed, &limits),
+            Err(DecompressError::OutOfMemory)
+        );
+    }
+}
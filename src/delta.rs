@@ -0,0 +1,67 @@
+//! The byte-delta pre-filter (`LzoBuilder::delta`).
+//!
+//! Like xz's delta filter, each byte is replaced by its difference from the
+//! byte `stride` positions earlier, so slowly changing samples or columns of
+//! fixed-width records turn into runs of small, repeating values LZO finds
+//! matches in. The filter restarts at every block, keeping blocks
+//! independent for parallel and random-access decoding.
+
+/// Largest stride the stream header can record.
+pub(crate) const MAX_STRIDE: usize = 256;
+
+/// `data` with the filter applied.
+pub(crate) fn encode(data: &[u8], stride: usize) -> Vec<u8> {
+    let mut out = data.to_vec();
+    for i in (stride..out.len()).rev() {
+        out[i] = out[i].wrapping_sub(out[i - stride]);
+    }
+    out
+}
+
+/// Reverse the filter in place.
+pub(crate) fn decode(data: &mut [u8], stride: usize) {
+    for i in stride..data.len() {
+        data[i] = data[i].wrapping_add(data[i - stride]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decompress_chunked, LzoReader, LzoWriter, LZO};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_delta_roundtrip() {
+        // Interleaved 16-bit samples of two slowly rising channels.
+        let input: Vec<u8> = (0..40_000u32)
+            .flat_map(|i| {
+                let a = (i * 3) as u16;
+                let b = (i * 5 + 1000) as u16;
+                [a.to_le_bytes(), b.to_le_bytes()].concat()
+            })
+            .collect();
+        let mut lzo = LZO::builder().delta(4).block_size(10_000).build().unwrap();
+        let mut writer = LzoWriter::from_lzo(Vec::new(), lzo).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+        assert_eq!(decompress_chunked(&framed).unwrap(), input);
+
+        let plain = LZO::new().compress_chunked(&input).unwrap();
+        assert!(framed.len() * 4 < plain.len());
+
+        lzo = LZO::builder().delta(4).build().unwrap();
+        let chunked = lzo.compress_chunked(&input).unwrap();
+        assert_eq!(decompress_chunked(&chunked).unwrap(), input);
+
+        assert!(LZO::builder().delta(0).build().is_err());
+        assert!(LZO::builder().delta(MAX_STRIDE + 1).build().is_err());
+    }
+}
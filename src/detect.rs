@@ -0,0 +1,142 @@
+//! Recognizing compressed input by its magic bytes.
+
+use crate::frame;
+use crate::lzop::{LzopReader, LZOP_MAGIC};
+use crate::LzoReader;
+use std::io::{self, Chain, Cursor, Read};
+
+/// A compressed format `detect` recognizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// The crate's framed format, as written by `LzoWriter`.
+    Framed,
+    /// An lzop file, as written by `LzopWriter` or the `lzop` tool.
+    Lzop,
+}
+
+/// Identify the format of the data starting with `src` from its magic
+/// bytes, or return `None` for anything else, including a prefix too short
+/// to tell.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::Format;
+///
+/// let framed = minilzo_rs::LZO::new().compress_chunked(b"some text").unwrap();
+/// assert_eq!(minilzo_rs::detect(&framed), Some(Format::Framed));
+/// assert_eq!(minilzo_rs::detect(b"some text"), None);
+/// ```
+pub fn detect(src: &[u8]) -> Option<Format> {
+    if src.starts_with(frame::MAGIC) {
+        Some(Format::Framed)
+    } else if src.starts_with(LZOP_MAGIC) {
+        Some(Format::Lzop)
+    } else {
+        None
+    }
+}
+
+type Peeked<R> = Chain<Cursor<Vec<u8>>, R>;
+
+enum Inner<R: Read> {
+    Framed(Box<LzoReader<Peeked<R>>>),
+    Lzop(LzopReader<Peeked<R>>),
+    Plain(Peeked<R>),
+}
+
+/// Reads the inner reader's data decompressed if it starts with a magic
+/// `detect` recognizes, and unchanged otherwise, so services can accept
+/// compressed and plain uploads alike.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// let framed = minilzo_rs::LZO::new().compress_chunked(b"upload").unwrap();
+/// for input in [&framed[..], b"upload"] {
+///     let mut data = Vec::new();
+///     minilzo_rs::AutoReader::new(input).unwrap().read_to_end(&mut data).unwrap();
+///     assert_eq!(data, b"upload");
+/// }
+/// ```
+pub struct AutoReader<R: Read>(Inner<R>);
+
+impl<R: Read> AutoReader<R> {
+    /// Read the first bytes of `inner` to tell its format.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut prefix = Vec::with_capacity(LZOP_MAGIC.len());
+        (&mut inner)
+            .take(LZOP_MAGIC.len() as u64)
+            .read_to_end(&mut prefix)?;
+        let format = detect(&prefix);
+        let input = Cursor::new(prefix).chain(inner);
+        Ok(AutoReader(match format {
+            Some(Format::Framed) => {
+                Inner::Framed(Box::new(LzoReader::new(input).map_err(frame::to_io)?))
+            }
+            Some(Format::Lzop) => Inner::Lzop(LzopReader::new(input)?),
+            None => Inner::Plain(input),
+        }))
+    }
+
+    /// The format being decompressed, or `None` if the data is passed
+    /// through.
+    pub fn format(&self) -> Option<Format> {
+        match self.0 {
+            Inner::Framed(_) => Some(Format::Framed),
+            Inner::Lzop(_) => Some(Format::Lzop),
+            Inner::Plain(_) => None,
+        }
+    }
+}
+
+impl<R: Read> Read for AutoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Inner::Framed(reader) => reader.read(buf),
+            Inner::Lzop(reader) => reader.read(buf),
+            Inner::Plain(reader) => reader.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzopWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_auto_reader_formats() {
+        let mut writer = LzopWriter::new(Vec::new()).unwrap();
+        writer.write_all(b"packed").unwrap();
+        let lzop = writer.finish().unwrap();
+        assert_eq!(detect(&lzop), Some(Format::Lzop));
+        assert_eq!(detect(&lzop[..4]), None);
+
+        for (input, format) in [
+            (&lzop[..], Some(Format::Lzop)),
+            (&b"pack"[..], None),
+            (&b""[..], None),
+        ] {
+            let mut reader = AutoReader::new(input).unwrap();
+            assert_eq!(reader.format(), format);
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            assert_eq!(
+                data,
+                if format.is_some() {
+                    &b"packed"[..]
+                } else {
+                    input
+                }
+            );
+        }
+
+        // A framed header cut short is reported, not passed through.
+        let result = AutoReader::new(&b"MLZO\x01"[..]).and_then(|mut r| r.read(&mut [0u8; 8]));
+        assert!(result.is_err());
+    }
+}
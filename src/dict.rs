@@ -0,0 +1,421 @@
+//! Preset dictionaries for small records.
+//!
+//! A record of a few hundred bytes gives LZO little to match against, so
+//! JSON messages and the like barely compress on their own. Compressing
+//! each one against a dictionary of content typical for the records lets
+//! matches reach back into it. `train` builds such a dictionary from
+//! sample records, and `LZO::compress_dict`/`LZO::decompress_dict_safe`
+//! use one.
+//!
+//! minilzo has no dictionary entry points, so `compress_dict` uses an
+//! LZO1X encoder of its own that treats the dictionary as a first literal
+//! run already written. Only the dictionary's header is kept in the output,
+//! and `decompress_dict_safe` puts the dictionary back before running
+//! minilzo's safe decoder. With an empty dictionary the output is a plain
+//! LZO1X stream.
+
+use crate::scratch::try_with_capacity;
+use crate::{CompressError, DecompressError, Decompressor, LZO, MAX_INPUT_LEN};
+use std::collections::{HashMap, HashSet};
+
+/// The longest distance an LZO1X match can reach back, and so the most of
+/// a dictionary that is used. Only the last `MAX_DICT_LEN` bytes of a
+/// longer dictionary count.
+pub const MAX_DICT_LEN: usize = 0xbfff;
+
+/// The ID framed streams record for `dict`: the adler32 of the part of it
+/// that is used. Readers given dictionaries match them to streams by it.
+///
+/// Example
+///
+/// ```rust
+/// let dict = b"{\"kind\":\"event\",\"status\":\"active\"}";
+/// assert_eq!(minilzo_rs::dict::id(dict), minilzo_rs::adler32(dict));
+/// ```
+pub fn id(dict: &[u8]) -> u32 {
+    crate::adler32(usable(dict))
+}
+
+/// Length of the substrings `train` counts.
+const GRAM: usize = 8;
+
+const HASH_BITS: u32 = 14;
+const MIN_MATCH: usize = 4;
+const M2_MAX_LEN: usize = 8;
+const M2_MAX_OFFSET: usize = 0x0800;
+const M3_MAX_OFFSET: usize = 0x4000;
+
+/// Build a dictionary of at most `max_size` bytes from `samples`, records
+/// representative of the data it will be used for.
+///
+/// Substrings shared by several samples are collected, the ones covering
+/// the most repeated bytes first, and laid out with the most valuable at
+/// the end of the dictionary, nearest to the data and so reachable with
+/// the shortest matches. With a single sample, substrings repeated within
+/// it are used.
+///
+/// Example
+///
+/// ```rust
+/// let samples: Vec<String> = (0..100)
+///     .map(|i| format!(r#"{{"id":{},"kind":"event","status":"active"}}"#, i))
+///     .collect();
+/// let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_bytes()).collect();
+/// let dict = minilzo_rs::dict::train(&samples, 1024);
+///
+/// let record = br#"{"id":12345,"kind":"event","status":"active"}"#;
+/// let mut lzo = minilzo_rs::LZO::new();
+/// let plain = lzo.compress(record).unwrap();
+/// let small = lzo.compress_dict(record, &dict).unwrap();
+/// assert!(small.len() < plain.len());
+/// assert_eq!(lzo.decompress_dict_safe(&small, &dict, record.len()).unwrap(), &record[..]);
+/// ```
+pub fn train(samples: &[&[u8]], max_size: usize) -> Vec<u8> {
+    let max_size = max_size.min(MAX_DICT_LEN);
+    let threshold = if samples.len() > 1 { 2 } else { 1 };
+
+    // How many samples each substring occurs in.
+    let mut counts: HashMap<&[u8], (u32, usize)> = HashMap::new();
+    for (index, sample) in samples.iter().enumerate() {
+        for gram in sample.windows(GRAM) {
+            let entry = counts.entry(gram).or_insert((0, usize::MAX));
+            if entry.1 != index || samples.len() == 1 {
+                *entry = (entry.0 + 1, index);
+            }
+        }
+    }
+
+    // Maximal runs of frequent substrings, scored by the repeats they hold.
+    let mut segments: HashMap<&[u8], u64> = HashMap::new();
+    for sample in samples {
+        let mut start = None;
+        let mut score = 0;
+        for i in 0..=sample.len() {
+            let count = match sample.get(i..i + GRAM) {
+                Some(gram) if counts[gram].0 >= threshold => counts[gram].0,
+                _ => 0,
+            };
+            if count > 0 {
+                start.get_or_insert(i);
+                score += u64::from(count);
+            } else if let Some(from) = start.take() {
+                let best = segments.entry(&sample[from..i - 1 + GRAM]).or_insert(0);
+                *best = (*best).max(score);
+                score = 0;
+            }
+        }
+    }
+    let mut segments: Vec<(&[u8], u64)> = segments.into_iter().collect();
+    segments.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut picked = Vec::new();
+    let mut covered: HashSet<&[u8]> = HashSet::new();
+    let mut size = 0;
+    for (segment, _) in segments {
+        if max_size - size < GRAM {
+            break;
+        }
+        if segment.len() > max_size - size || segment.windows(GRAM).all(|g| covered.contains(g)) {
+            continue;
+        }
+        covered.extend(segment.windows(GRAM));
+        size += segment.len();
+        picked.push(segment);
+    }
+    picked
+        .iter()
+        .rev()
+        .flat_map(|s| s.iter().copied())
+        .collect()
+}
+
+/// The part of `dict` that matches can reach.
+pub(crate) fn usable(dict: &[u8]) -> &[u8] {
+    &dict[dict.len().saturating_sub(MAX_DICT_LEN)..]
+}
+
+/// Append a run of `count` literals' header, `first` if it opens the stream.
+pub(crate) fn literal_header(out: &mut Vec<u8>, count: usize, first: bool) {
+    if first && count <= 238 {
+        out.push(17 + count as u8);
+    } else if count <= 3 {
+        // Stored in the spare bits of the previous match.
+        let at = out.len() - 2;
+        out[at] |= count as u8;
+    } else if count <= 18 {
+        out.push(count as u8 - 3);
+    } else {
+        out.push(0);
+        push_length(out, count - 18);
+    }
+}
+
+/// Append the zero bytes and remainder of an extended length.
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len > 255 {
+        out.push(0);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Append a match of `len` bytes `dist` back.
+fn encode_match(out: &mut Vec<u8>, len: usize, dist: usize) {
+    if len <= M2_MAX_LEN && dist <= M2_MAX_OFFSET {
+        let off = dist - 1;
+        out.push(((len - 1) << 5 | (off & 7) << 2) as u8);
+        out.push((off >> 3) as u8);
+        return;
+    }
+    let off = if dist <= M3_MAX_OFFSET {
+        if len <= 33 {
+            out.push(32 | (len - 2) as u8);
+        } else {
+            out.push(32);
+            push_length(out, len - 33);
+        }
+        dist - 1
+    } else {
+        let off = dist - M3_MAX_OFFSET;
+        let marker = 16 | ((off >> 11) & 8) as u8;
+        if len <= 9 {
+            out.push(marker | (len - 2) as u8);
+        } else {
+            out.push(marker);
+            push_length(out, len - 9);
+        }
+        off
+    };
+    out.push((off << 2) as u8);
+    out.push((off >> 6) as u8);
+}
+
+fn hash(data: &[u8], at: usize) -> usize {
+    let v = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Encode `src` as the continuation of a stream whose first literal run is
+/// `dict`, leaving the dictionary's bytes out.
+pub(crate) fn encode(src: &[u8], dict: &[u8]) -> Vec<u8> {
+    let data = [dict, src].concat();
+    let start = dict.len();
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    for at in 0..start.saturating_sub(MIN_MATCH - 1) {
+        table[hash(&data, at)] = at;
+    }
+
+    let mut out = Vec::with_capacity(src.len() / 2 + 16);
+    let mut first = true;
+    let mut literals = start;
+    let mut ip = start;
+    let mut emit_literals = |out: &mut Vec<u8>, from: usize, to: usize| {
+        // The dictionary opens the first run, but only its length is kept.
+        let run_start = if first { 0 } else { from };
+        if to > run_start {
+            literal_header(out, to - run_start, first);
+            out.extend_from_slice(&data[from..to]);
+        }
+        first = false;
+    };
+    while ip + MIN_MATCH <= data.len() {
+        let h = hash(&data, ip);
+        let candidate = table[h];
+        table[h] = ip;
+        if candidate == usize::MAX
+            || ip - candidate > MAX_DICT_LEN
+            || data[candidate..candidate + MIN_MATCH] != data[ip..ip + MIN_MATCH]
+        {
+            ip += 1;
+            continue;
+        }
+        let len = MIN_MATCH
+            + data[ip + MIN_MATCH..]
+                .iter()
+                .zip(&data[candidate + MIN_MATCH..])
+                .take_while(|(a, b)| a == b)
+                .count();
+        emit_literals(&mut out, literals, ip);
+        encode_match(&mut out, len, ip - candidate);
+        for at in ip + 1..(ip + len).min(data.len() - (MIN_MATCH - 1)) {
+            table[hash(&data, at)] = at;
+        }
+        ip += len;
+        literals = ip;
+    }
+    emit_literals(&mut out, literals, data.len());
+    out.extend_from_slice(&[17, 0, 0]);
+    out
+}
+
+/// Decompress `encode` output made with `dict`, already cut to its usable
+/// part.
+pub(crate) fn decode(
+    decompressor: &Decompressor,
+    src: &[u8],
+    dict: &[u8],
+    dst_len: usize,
+) -> Result<Vec<u8>, DecompressError> {
+    if dict.is_empty() {
+        return decompressor.decompress_safe(src, dst_len);
+    }
+    let full_len = dict
+        .len()
+        .checked_add(dst_len)
+        .ok_or(DecompressError::InvalidArgument)?;
+    let full = restore(src, dict)?;
+    let mut out = match decompressor.decompress_safe(&full, full_len) {
+        Err(DecompressError::OutputOverrun { .. }) => {
+            return Err(DecompressError::OutputOverrun {
+                capacity: dst_len,
+                needed: None,
+            })
+        }
+        result => result?,
+    };
+    out.drain(..dict.len());
+    Ok(out)
+}
+
+/// Undo `encode`'s omission: the stream as it would be with `dict` stored
+/// in its first literal run.
+fn restore(src: &[u8], dict: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let (run, header_len) = match src {
+        [b, ..] if *b > 17 => (*b as usize - 17, 1),
+        [0, rest @ ..] => {
+            let zeros = rest.iter().take_while(|&&b| b == 0).count();
+            let last = *rest.get(zeros).ok_or(DecompressError::InputOverrun)?;
+            (18 + 255 * zeros + last as usize, 2 + zeros)
+        }
+        [b, ..] if *b < 16 => (*b as usize + 3, 1),
+        _ => (0, 0),
+    };
+    if run < dict.len() {
+        return Err(DecompressError::Error);
+    }
+    let mut full = try_with_capacity(src.len() + dict.len())?;
+    full.extend_from_slice(&src[..header_len]);
+    full.extend_from_slice(dict);
+    full.extend_from_slice(&src[header_len..]);
+    Ok(full)
+}
+
+impl LZO {
+    /// Compress `src` against the preset dictionary `dict`, such as one
+    /// built by `dict::train`. The same dictionary is needed to decompress.
+    pub fn compress_dict(
+        &mut self,
+        src: impl AsRef<[u8]>,
+        dict: impl AsRef<[u8]>,
+    ) -> Result<Vec<u8>, CompressError> {
+        let (src, dict) = (src.as_ref(), dict.as_ref());
+        if self.is_poisoned() {
+            return Err(CompressError::Poisoned);
+        }
+        if src.len() > MAX_INPUT_LEN {
+            return Err(CompressError::InputTooLarge {
+                len: src.len(),
+                max: MAX_INPUT_LEN,
+            });
+        }
+        Ok(encode(src, usable(dict)))
+    }
+
+    /// Decompress `compress_dict` output made with `dict`, with overrun
+    /// testing. Returns at most `dst_len` bytes.
+    pub fn decompress_dict_safe(
+        &self,
+        src: impl AsRef<[u8]>,
+        dict: impl AsRef<[u8]>,
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        let (src, dict) = (src.as_ref(), dict.as_ref());
+        self.check_decompress(dst_len)?;
+        let dict = usable(dict);
+        if dict.is_empty() {
+            return self.decompress_safe(src, dst_len);
+        }
+        self.track(decode(&Decompressor::initialized(), src, dict, dst_len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<String> {
+        (0..200)
+            .map(|i| {
+                format!(
+                    r#"{{"id":{},"user":"user{}","status":"active","tags":["alpha","beta"]}}"#,
+                    i,
+                    i * 7
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_train_and_compress_records() {
+        let records = records();
+        let samples: Vec<&[u8]> = records[..150].iter().map(|s| s.as_bytes()).collect();
+        let dict = train(&samples, 512);
+        assert!(!dict.is_empty() && dict.len() <= 512);
+        assert_eq!(train(&samples, 512), dict);
+
+        let mut lzo = LZO::new();
+        let (mut plain, mut with_dict) = (0, 0);
+        for record in &records[150..] {
+            let record = record.as_bytes();
+            plain += lzo.compress(record).unwrap().len();
+            let out = lzo.compress_dict(record, &dict).unwrap();
+            with_dict += out.len();
+            assert_eq!(
+                lzo.decompress_dict_safe(&out, &dict, record.len()).unwrap(),
+                record
+            );
+        }
+        assert!(with_dict * 2 < plain);
+        assert!(train(&[], 512).is_empty());
+
+        // A declared length the dictionary can't be added to is refused.
+        let out = lzo.compress_dict(records[0].as_bytes(), &dict).unwrap();
+        assert_eq!(
+            lzo.decompress_dict_safe(&out, &dict, usize::MAX),
+            Err(DecompressError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_dict_stream_roundtrip() {
+        // Repeats at every match form's distances, reaching into the dictionary.
+        let dict: Vec<u8> = (0..MAX_DICT_LEN + 100)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+        let mut src = dict[dict.len() - 40_000..dict.len() - 39_000].to_vec();
+        src.extend_from_slice(&[9u8; 700]);
+        src.extend_from_slice(&dict[dict.len() - 10..]);
+        src.extend_from_within(..300);
+        src.extend_from_within(..300);
+        src.extend((0..5000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        let mut lzo = LZO::new();
+        for dict in [&dict[..], &dict[..3], &[][..]] {
+            for src in [&src[..], &src[..2], &[][..]] {
+                let out = lzo.compress_dict(src, dict).unwrap();
+                assert_eq!(
+                    lzo.decompress_dict_safe(&out, dict, src.len()).unwrap(),
+                    src
+                );
+            }
+        }
+
+        // Without a dictionary the output is a plain LZO1X stream.
+        let out = lzo.compress_dict(&src, []).unwrap();
+        assert_eq!(lzo.decompress_safe(&out, src.len()).unwrap(), src);
+        let out = lzo.compress_dict(&src, &dict).unwrap();
+        assert!(matches!(
+            lzo.decompress_dict_safe(&out, &dict, 100),
+            Err(DecompressError::OutputOverrun { capacity: 100, .. })
+        ));
+    }
+}
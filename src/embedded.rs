@@ -0,0 +1,227 @@
+//! `embedded-io` adapters over the framed format (`embedded-io` feature).
+//!
+//! These wrap `embedded_io::Write`/`Read` streams, such as UART or flash
+//! drivers, and themselves implement the `embedded_io` traits, producing
+//! and consuming the same streams as `LzoWriter` and `LzoReader`. Errors are
+//! reported as `std::io::Error`s carrying the inner stream's error kind.
+//! Small devices will want `with_block_size` to bound the buffers.
+//!
+//! Firmware can't afford panics, so the adapters never panic on account of
+//! their input: damaged, truncated or hostile streams, and failing inner
+//! streams, come back as errors. This module itself is linted against
+//! `unwrap`, indexing and unchecked arithmetic, and the decoder underneath
+//! is fuzzed with damaged streams in its tests. Running out of memory still
+//! aborts, as it does everywhere in `std`.
+
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::indexing_slicing,
+        clippy::arithmetic_side_effects
+    )
+)]
+
+use crate::{LZOResult, LzoReader, LzoWriter, DEFAULT_BLOCK_SIZE};
+use embedded_io::{ErrorType, Read, Write};
+use std::io;
+
+/// Presents an `embedded_io` stream as a `std::io` one.
+struct Bridge<T>(T);
+
+fn to_io<E: embedded_io::Error>(e: E) -> io::Error {
+    io::Error::from(io::ErrorKind::from(e.kind()))
+}
+
+impl<T: Write> io::Write for Bridge<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(to_io)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(to_io)
+    }
+}
+
+impl<T: Read> io::Read for Bridge<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(to_io)
+    }
+}
+
+/// Compresses everything written to it into a framed stream on an
+/// `embedded_io::Write`.
+///
+/// Example
+///
+/// ```rust
+/// use embedded_io::{Read, Write};
+/// use minilzo_rs::{EmbeddedLzoReader, EmbeddedLzoWriter};
+///
+/// let mut writer = EmbeddedLzoWriter::with_block_size(Vec::new(), 1024).unwrap();
+/// writer.write_all(&[0x42u8; 4096]).unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let mut reader = EmbeddedLzoReader::new(&framed[..]).unwrap();
+/// let mut output = [0u8; 4096];
+/// reader.read_exact(&mut output).unwrap();
+/// assert_eq!(output, [0x42u8; 4096]);
+/// ```
+pub struct EmbeddedLzoWriter<W: Write>(LzoWriter<Bridge<W>>);
+
+impl<W: Write> EmbeddedLzoWriter<W> {
+    /// Create a writer using `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn new(inner: W) -> LZOResult<Self> {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a writer that compresses `block_size` bytes at a time.
+    pub fn with_block_size(inner: W, block_size: usize) -> LZOResult<Self> {
+        LzoWriter::with_block_size(Bridge(inner), block_size).map(EmbeddedLzoWriter)
+    }
+
+    /// Compress any buffered data, write the end marker and return the
+    /// inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.0.finish().map(|bridge| bridge.0)
+    }
+}
+
+impl<W: Write> ErrorType for EmbeddedLzoWriter<W> {
+    type Error = io::Error;
+}
+
+impl<W: Write> Write for EmbeddedLzoWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut self.0, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(&mut self.0)
+    }
+}
+
+/// Decompresses a framed stream read from an `embedded_io::Read`.
+pub struct EmbeddedLzoReader<R: Read>(LzoReader<Bridge<R>>);
+
+impl<R: Read> EmbeddedLzoReader<R> {
+    /// Create a reader decoding the framed stream in `inner`.
+    pub fn new(inner: R) -> LZOResult<Self> {
+        LzoReader::new(Bridge(inner)).map(EmbeddedLzoReader)
+    }
+
+    /// Unwrap the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.0.into_inner().0
+    }
+}
+
+impl<R: Read> ErrorType for EmbeddedLzoReader<R> {
+    type Error = io::Error;
+}
+
+impl<R: Read> Read for EmbeddedLzoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(&mut self.0, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_io::ErrorKind;
+
+    /// A sink that fails every write.
+    struct Broken;
+
+    impl ErrorType for Broken {
+        type Error = ErrorKind;
+    }
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> Result<usize, ErrorKind> {
+            Err(ErrorKind::NotConnected)
+        }
+
+        fn flush(&mut self) -> Result<(), ErrorKind> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_embedded_roundtrip_and_errors() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i % 13) as u8).collect();
+        let mut writer = EmbeddedLzoWriter::with_block_size(Vec::new(), 2048).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut output = Vec::new();
+        let mut reader = EmbeddedLzoReader::new(&framed[..]).unwrap();
+        let mut chunk = [0u8; 700];
+        loop {
+            match reader.read(&mut chunk).unwrap() {
+                0 => break,
+                n => output.extend_from_slice(&chunk[..n]),
+            }
+        }
+        assert_eq!(output, input);
+
+        let mut writer = EmbeddedLzoWriter::with_block_size(Broken, 16).unwrap();
+        let err = writer.write_all(&[0u8; 64]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+
+    /// A stream that returns a short read of pseudo-random length.
+    struct Trickle<'a>(&'a [u8], u32);
+
+    impl ErrorType for Trickle<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            self.1 ^= self.1 << 13;
+            self.1 ^= self.1 >> 17;
+            self.1 ^= self.1 << 5;
+            let n = (self.1 as usize % 97 + 1).min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_damaged_input_never_panics() {
+        let input: Vec<u8> = (0..6000u32).map(|i| (i * i % 29) as u8).collect();
+        let mut writer = EmbeddedLzoWriter::with_block_size(Vec::new(), 1000).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut seed = 0x9e37_79b9u32;
+        for round in 0..3000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let mut damaged = framed.clone();
+            match round % 3 {
+                0 => damaged[seed as usize % framed.len()] ^= 1 << (seed >> 29),
+                1 => damaged.truncate(seed as usize % framed.len()),
+                _ => {
+                    let at = seed as usize % framed.len();
+                    damaged[at..]
+                        .iter_mut()
+                        .for_each(|b| *b = (seed >> 8) as u8);
+                }
+            }
+            let result = std::panic::catch_unwind(|| {
+                let mut reader = EmbeddedLzoReader::new(Trickle(&damaged, seed | 1))?;
+                let mut chunk = [0u8; 333];
+                while reader.read(&mut chunk)? > 0 {}
+                Ok::<_, io::Error>(())
+            });
+            assert!(result.is_ok(), "panicked on round {}", round);
+        }
+    }
+}
@@ -0,0 +1,605 @@
+//! Error types: one per operation, plus `Error` covering everything.
+//!
+//! `CompressError` and `DecompressError` only hold the variants their
+//! operation can produce. Both convert into `Error`, which the framed
+//! streams, archives and other higher-level APIs report.
+
+use std::collections::TryReserveError;
+use std::io;
+use std::os::raw::c_int;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Error {
+    Error,
+    OutOfMemory,
+    NotCompressible,
+    InputOverrun,
+    /// The output didn't fit in `capacity` bytes. `needed` is the size
+    /// that would have fit, when it is known.
+    OutputOverrun {
+        capacity: usize,
+        needed: Option<usize>,
+    },
+    LookbehindOverrun,
+    EOFNotFound,
+    /// Decoding finished with `remaining` input bytes left over.
+    InputNotConsumed {
+        remaining: usize,
+    },
+    NotYetImplemented,
+    InvalidArgument,
+    InvalidAlignment,
+    OutputNotConsumed,
+    InternalError,
+    Poisoned,
+    InvalidHeader,
+    ChecksumMismatch,
+    WouldBlock,
+    Uncommitted,
+    OutputLimitExceeded,
+    /// The operation was stopped through its `CancelToken`.
+    Cancelled,
+    /// A single-block API got `len` bytes, more than the `max` it can
+    /// code as one block.
+    InputTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// The compressed result would take more than `budget` bytes.
+    BudgetExceeded {
+        budget: usize,
+    },
+    /// The stream was compressed against the dictionary with ID `id`
+    /// (see `dict::id`), which wasn't given.
+    DictionaryMismatch {
+        id: u32,
+    },
+    /// The stream is in format `version`, which this crate can't read.
+    UnsupportedVersion {
+        version: u8,
+    },
+    /// The stream uses the required features `features` (bits of its
+    /// header's required feature word) that this crate doesn't know.
+    UnsupportedFeatures {
+        features: u32,
+    },
+}
+
+/// Messages are lowercase, without trailing punctuation, and stable
+/// across releases so logs can be matched on them.
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Error::Error => f.write_str("error"),
+            Error::OutOfMemory => f.write_str("out of memory"),
+            Error::NotCompressible => f.write_str("not compressible"),
+            Error::InputOverrun => f.write_str("input overrun"),
+            Error::OutputOverrun {
+                capacity,
+                needed: Some(needed),
+            } => write!(
+                f,
+                "output overrun: {} bytes needed, {} available",
+                needed, capacity
+            ),
+            Error::OutputOverrun {
+                capacity,
+                needed: None,
+            } => write!(f, "output overrun: more than {} bytes needed", capacity),
+            Error::LookbehindOverrun => f.write_str("lookbehind overrun"),
+            Error::EOFNotFound => f.write_str("EOF not found"),
+            Error::InputNotConsumed { remaining } => {
+                write!(f, "input not consumed: {} bytes left over", remaining)
+            }
+            Error::NotYetImplemented => f.write_str("not yet implemented"),
+            Error::InvalidArgument => f.write_str("invalid argument"),
+            Error::InvalidAlignment => f.write_str("invalid alignment"),
+            Error::OutputNotConsumed => f.write_str("output not consumed"),
+            Error::InternalError => f.write_str("internal error"),
+            Error::Poisoned => f.write_str("instance poisoned by an earlier internal error"),
+            Error::InvalidHeader => f.write_str("invalid header"),
+            Error::ChecksumMismatch => f.write_str("checksum mismatch"),
+            Error::WouldBlock => f.write_str("no instance available"),
+            Error::Uncommitted => f.write_str("archive was never committed"),
+            Error::OutputLimitExceeded => f.write_str("output size limit exceeded"),
+            Error::Cancelled => f.write_str("operation cancelled"),
+            Error::InputTooLarge { len, max } => write!(
+                f,
+                "input too large: {} bytes, at most {} in one block",
+                len, max
+            ),
+            Error::BudgetExceeded { budget } => {
+                write!(f, "budget exceeded: more than {} bytes compressed", budget)
+            }
+            Error::DictionaryMismatch { id } => {
+                write!(f, "dictionary mismatch: stream needs dictionary {:08x}", id)
+            }
+            Error::UnsupportedVersion { version } => {
+                write!(f, "unsupported format version {}", version)
+            }
+            Error::UnsupportedFeatures { features } => {
+                write!(f, "unsupported stream features {:08x}", features)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// The minilzo `LZO_E_*` code for this error: the code it was mapped
+    /// from, or the nearest one for errors minilzo doesn't have.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::Error;
+    ///
+    /// let code = Error::InputOverrun.as_raw_code();
+    /// assert_eq!(code, -4);
+    /// assert_eq!(Error::from_raw_code(code), Some(Error::InputOverrun));
+    /// assert_eq!(Error::from_raw_code(0), None);
+    /// ```
+    pub fn as_raw_code(&self) -> c_int {
+        match self {
+            Error::OutOfMemory => -2,
+            Error::NotCompressible => -3,
+            Error::InputOverrun => -4,
+            Error::OutputOverrun { .. }
+            | Error::OutputLimitExceeded
+            | Error::BudgetExceeded { .. } => -5,
+            Error::LookbehindOverrun => -6,
+            Error::EOFNotFound => -7,
+            Error::InputNotConsumed { .. } => -8,
+            Error::NotYetImplemented => -9,
+            Error::InvalidArgument | Error::InputTooLarge { .. } => -10,
+            Error::InvalidAlignment => -11,
+            Error::OutputNotConsumed => -12,
+            Error::InternalError | Error::Poisoned => -99,
+            _ => -1,
+        }
+    }
+
+    /// The error for a minilzo `LZO_E_*` code, or `None` for `LZO_E_OK`.
+    ///
+    /// Byte counts the code doesn't carry are zero, and unknown codes map
+    /// to `Error::Error`.
+    pub fn from_raw_code(code: c_int) -> Option<Self> {
+        Some(match code {
+            0 => return None,
+            -1 => Error::Error,
+            -2 => Error::OutOfMemory,
+            -3 => Error::NotCompressible,
+            -4 => Error::InputOverrun,
+            -5 => Error::OutputOverrun {
+                capacity: 0,
+                needed: None,
+            },
+            -6 => Error::LookbehindOverrun,
+            -7 => Error::EOFNotFound,
+            -8 => Error::InputNotConsumed { remaining: 0 },
+            -9 => Error::NotYetImplemented,
+            -10 => Error::InvalidArgument,
+            -11 => Error::InvalidAlignment,
+            -12 => Error::OutputNotConsumed,
+            -99 => Error::InternalError,
+            _ => Error::Error,
+        })
+    }
+}
+
+/// A failed decompression, with what was decoded before the failure.
+///
+/// Returned by `Decompressor::decompress_partial` and
+/// `decompress_chunked_partial` so that forensic and recovery tools can
+/// keep the good prefix of damaged data instead of losing all of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialOutput {
+    /// Why decompression stopped.
+    pub error: Error,
+    /// The output decoded before the failure.
+    pub partial: Vec<u8>,
+    /// How many input bytes `partial` was decoded from.
+    pub consumed: usize,
+}
+
+impl std::fmt::Display for PartialOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} after {} input bytes ({} bytes decoded)",
+            self.error,
+            self.consumed,
+            self.partial.len()
+        )
+    }
+}
+
+impl std::error::Error for PartialOutput {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<PartialOutput> for Error {
+    fn from(e: PartialOutput) -> Self {
+        e.error
+    }
+}
+
+/// A failed framed-stream decode, with where in the input it failed.
+///
+/// Readers of the framed format, such as `LzoReader`, report decoding
+/// errors as an `io::Error` holding one of these, so the damage can be
+/// matched with storage-layer errors, or handed to `salvage`, which
+/// resumes after the bad block.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{DecodeError, Error, LzoWriter};
+/// use std::io::{Read, Write};
+///
+/// let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+/// writer.write_all(&b"0123456789".repeat(1000)).unwrap();
+/// let mut framed = writer.finish().unwrap();
+/// let len = framed.len();
+/// framed[len - 10] ^= 0xff;
+///
+/// let mut reader = minilzo_rs::LzoReader::new(&framed[..]).unwrap();
+/// let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+/// let at = err.get_ref().unwrap().downcast_ref::<DecodeError>().unwrap();
+/// assert_eq!(at.block, 2);
+/// assert!(at.offset > 0 && at.offset < len as u64);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeError {
+    /// Why decoding failed.
+    pub error: Error,
+    /// Offset in the input of the frame that failed to decode.
+    pub offset: u64,
+    /// Index of the data block that failed to decode, counting from 0.
+    pub block: u64,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in block {} at input offset {}",
+            self.error, self.block, self.offset
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        e.error
+    }
+}
+
+/// Of the same kind as the `io::Error` for `error`, holding the
+/// `DecodeError` itself.
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> Self {
+        io::Error::new(io::Error::from(e.error).kind(), e)
+    }
+}
+
+/// Why compressing failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CompressError {
+    Error,
+    /// A buffer couldn't be allocated.
+    OutOfMemory,
+    /// The output didn't fit in `capacity` bytes. `needed` is the size
+    /// that would have fit, when it is known.
+    OutputOverrun {
+        capacity: usize,
+        needed: Option<usize>,
+    },
+    NotYetImplemented,
+    InternalError,
+    Poisoned,
+    /// `len` bytes of input are more than the `max` a single block holds.
+    InputTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// The compressed result would take more than `budget` bytes.
+    BudgetExceeded {
+        budget: usize,
+    },
+}
+
+/// Why decompressing failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DecompressError {
+    Error,
+    /// A buffer couldn't be allocated, such as one of a corrupt stream's
+    /// declared size.
+    OutOfMemory,
+    InputOverrun,
+    /// The output didn't fit in `capacity` bytes. `needed` is the size
+    /// that would have fit, when it is known.
+    OutputOverrun {
+        capacity: usize,
+        needed: Option<usize>,
+    },
+    LookbehindOverrun,
+    EOFNotFound,
+    /// Decoding finished with `remaining` input bytes left over.
+    InputNotConsumed {
+        remaining: usize,
+    },
+    NotYetImplemented,
+    InvalidArgument,
+    InternalError,
+    Poisoned,
+    OutputLimitExceeded,
+    ChecksumMismatch,
+}
+
+impl CompressError {
+    /// Map a failing return code of the C compressor.
+    pub(crate) fn from_code(code: c_int) -> Self {
+        match code {
+            -2 => CompressError::OutOfMemory,
+            -9 => CompressError::NotYetImplemented,
+            -99 => CompressError::InternalError,
+            _ => CompressError::Error,
+        }
+    }
+}
+
+impl DecompressError {
+    /// Map a failing return code of the C decoders. Byte counts are left
+    /// for the caller to fill in.
+    pub(crate) fn from_code(code: c_int) -> Self {
+        match code {
+            -2 => DecompressError::OutOfMemory,
+            -4 => DecompressError::InputOverrun,
+            -5 => DecompressError::OutputOverrun {
+                capacity: 0,
+                needed: None,
+            },
+            -6 => DecompressError::LookbehindOverrun,
+            -7 => DecompressError::EOFNotFound,
+            -8 => DecompressError::InputNotConsumed { remaining: 0 },
+            -9 => DecompressError::NotYetImplemented,
+            -99 => DecompressError::InternalError,
+            _ => DecompressError::Error,
+        }
+    }
+}
+
+impl From<CompressError> for Error {
+    fn from(e: CompressError) -> Self {
+        match e {
+            CompressError::Error => Error::Error,
+            CompressError::OutOfMemory => Error::OutOfMemory,
+            CompressError::OutputOverrun { capacity, needed } => {
+                Error::OutputOverrun { capacity, needed }
+            }
+            CompressError::NotYetImplemented => Error::NotYetImplemented,
+            CompressError::InternalError => Error::InternalError,
+            CompressError::Poisoned => Error::Poisoned,
+            CompressError::InputTooLarge { len, max } => Error::InputTooLarge { len, max },
+            CompressError::BudgetExceeded { budget } => Error::BudgetExceeded { budget },
+        }
+    }
+}
+
+impl From<DecompressError> for Error {
+    fn from(e: DecompressError) -> Self {
+        match e {
+            DecompressError::Error => Error::Error,
+            DecompressError::OutOfMemory => Error::OutOfMemory,
+            DecompressError::InputOverrun => Error::InputOverrun,
+            DecompressError::OutputOverrun { capacity, needed } => {
+                Error::OutputOverrun { capacity, needed }
+            }
+            DecompressError::LookbehindOverrun => Error::LookbehindOverrun,
+            DecompressError::EOFNotFound => Error::EOFNotFound,
+            DecompressError::InputNotConsumed { remaining } => {
+                Error::InputNotConsumed { remaining }
+            }
+            DecompressError::NotYetImplemented => Error::NotYetImplemented,
+            DecompressError::InvalidArgument => Error::InvalidArgument,
+            DecompressError::InternalError => Error::InternalError,
+            DecompressError::Poisoned => Error::Poisoned,
+            DecompressError::OutputLimitExceeded => Error::OutputLimitExceeded,
+            DecompressError::ChecksumMismatch => Error::ChecksumMismatch,
+        }
+    }
+}
+
+/// A failed `try_reserve` is `OutOfMemory`.
+impl From<TryReserveError> for Error {
+    fn from(_: TryReserveError) -> Self {
+        Error::OutOfMemory
+    }
+}
+
+/// A failed `try_reserve` is `OutOfMemory`.
+impl From<TryReserveError> for CompressError {
+    fn from(_: TryReserveError) -> Self {
+        CompressError::OutOfMemory
+    }
+}
+
+/// A failed `try_reserve` is `OutOfMemory`.
+impl From<TryReserveError> for DecompressError {
+    fn from(_: TryReserveError) -> Self {
+        DecompressError::OutOfMemory
+    }
+}
+
+/// Same messages as the matching `Error` variant.
+impl std::fmt::Display for CompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Error::from(*self).fmt(f)
+    }
+}
+
+/// Same messages as the matching `Error` variant.
+impl std::fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Error::from(*self).fmt(f)
+    }
+}
+
+/// Lets the crate's errors be returned from `io::Read`/`io::Write`
+/// implementations. Corrupt or oversized input is `InvalidData`; the
+/// error itself is kept as the `io::Error`'s inner error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::OutOfMemory => io::ErrorKind::OutOfMemory,
+            Error::InvalidArgument
+            | Error::InvalidAlignment
+            | Error::InputTooLarge { .. }
+            | Error::DictionaryMismatch { .. } => io::ErrorKind::InvalidInput,
+            Error::NotYetImplemented
+            | Error::UnsupportedVersion { .. }
+            | Error::UnsupportedFeatures { .. } => io::ErrorKind::Unsupported,
+            Error::WouldBlock => io::ErrorKind::WouldBlock,
+            // Not `Interrupted`, which `write_all` and `read_to_end` retry.
+            Error::Error | Error::InternalError | Error::Poisoned | Error::Cancelled => {
+                io::ErrorKind::Other
+            }
+            Error::NotCompressible
+            | Error::InputOverrun
+            | Error::OutputOverrun { .. }
+            | Error::BudgetExceeded { .. }
+            | Error::LookbehindOverrun
+            | Error::EOFNotFound
+            | Error::InputNotConsumed { .. }
+            | Error::OutputNotConsumed
+            | Error::InvalidHeader
+            | Error::ChecksumMismatch
+            | Error::Uncommitted
+            | Error::OutputLimitExceeded => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+impl From<CompressError> for io::Error {
+    fn from(e: CompressError) -> Self {
+        Error::from(e).into()
+    }
+}
+
+impl From<DecompressError> for io::Error {
+    fn from(e: DecompressError) -> Self {
+        Error::from(e).into()
+    }
+}
+
+impl std::error::Error for CompressError {}
+
+impl std::error::Error for DecompressError {}
+
+pub(crate) fn lzo_err_code_to_result<T>(code: c_int, value: T) -> Result<T, Error> {
+    match Error::from_raw_code(code) {
+        None => Ok(value),
+        Some(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(Error::ChecksumMismatch.to_string(), "checksum mismatch");
+        assert_eq!(
+            CompressError::OutputOverrun {
+                capacity: 64,
+                needed: Some(80)
+            }
+            .to_string(),
+            "output overrun: 80 bytes needed, 64 available"
+        );
+        assert_eq!(
+            DecompressError::InputNotConsumed { remaining: 5 }.to_string(),
+            "input not consumed: 5 bytes left over"
+        );
+        assert_eq!(
+            CompressError::InputTooLarge { len: 10, max: 8 }.to_string(),
+            "input too large: 10 bytes, at most 8 in one block"
+        );
+        assert_eq!(
+            CompressError::BudgetExceeded { budget: 1200 }.to_string(),
+            "budget exceeded: more than 1200 bytes compressed"
+        );
+        let io_err = io::Error::from(Error::InvalidHeader);
+        assert_eq!(io_err.to_string(), "invalid header");
+    }
+
+    #[test]
+    fn test_io_error_kinds() {
+        let cases = [
+            (Error::ChecksumMismatch, io::ErrorKind::InvalidData),
+            (Error::OutOfMemory, io::ErrorKind::OutOfMemory),
+            (Error::InvalidArgument, io::ErrorKind::InvalidInput),
+            (
+                Error::InputTooLarge { len: 10, max: 8 },
+                io::ErrorKind::InvalidInput,
+            ),
+            (Error::NotYetImplemented, io::ErrorKind::Unsupported),
+            (
+                Error::UnsupportedFeatures { features: 1 },
+                io::ErrorKind::Unsupported,
+            ),
+            (Error::WouldBlock, io::ErrorKind::WouldBlock),
+            (Error::Poisoned, io::ErrorKind::Other),
+        ];
+        for (e, kind) in cases {
+            let io_err = io::Error::from(e);
+            assert_eq!(io_err.kind(), kind);
+            assert_eq!(io_err.get_ref().unwrap().downcast_ref::<Error>(), Some(&e));
+        }
+        let overrun = DecompressError::InputNotConsumed { remaining: 3 };
+        assert_eq!(io::Error::from(overrun).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_code_roundtrip() {
+        for code in [-1, -2, -3, -4, -5, -6, -7, -8, -9, -10, -11, -12, -99] {
+            assert_eq!(Error::from_raw_code(code).unwrap().as_raw_code(), code);
+        }
+        assert_eq!(Error::from_raw_code(-1000), Some(Error::Error));
+        assert_eq!(Error::Cancelled.as_raw_code(), -1);
+
+        let seen: std::collections::HashSet<Error> =
+            [Error::InputOverrun, Error::InputOverrun, Error::Cancelled]
+                .iter()
+                .copied()
+                .collect();
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn test_codes_agree_with_combined_error() {
+        for code in [-1, -4, -5, -6, -7, -8, -9, -99, -1000] {
+            let combined = lzo_err_code_to_result(code, ()).unwrap_err();
+            assert_eq!(Error::from(DecompressError::from_code(code)), combined);
+        }
+        for code in [-1, -9, -99] {
+            let combined = lzo_err_code_to_result(code, ()).unwrap_err();
+            assert_eq!(Error::from(CompressError::from_code(code)), combined);
+        }
+    }
+}
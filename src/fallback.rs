@@ -0,0 +1,479 @@
+//! Compression that falls back to the original bytes when it doesn't pay.
+
+use crate::{CompressError, LZO};
+use std::borrow::Cow;
+
+/// Every LZO1X stream ends with a 3-byte end marker, so no input
+/// compresses to less.
+const MIN_COMPRESSED_LEN: usize = 3;
+
+/// Each byte of a length extension adds at most 255 to a literal run or
+/// match, so no input compresses to less than this many bytes per byte,
+/// inverted.
+const MAX_BYTES_PER_OUTPUT_BYTE: usize = 256;
+
+/// What `LZO::compress_block` did with a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressOutcome {
+    /// The block was compressed into this many bytes at the start of `dst`.
+    Compressed(usize),
+    /// Compression didn't save the minimum; store the block as is.
+    Incompressible,
+    /// The block is too short to ever save the minimum, so compression
+    /// wasn't attempted; store it as is.
+    TooSmall,
+}
+
+/// How the framed writer encodes a block, as chosen by the policy given to
+/// `LzoWriter::block_policy`. Either way, the block header records the
+/// choice: a block whose compressed length equals its length is stored.
+///
+/// minilzo ships no LZO1X-999, so it can't be chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BlockChoice {
+    /// Store the block as is, without trying to compress it.
+    Store,
+    /// Compress the block with LZO1X-1, storing it anyway if that doesn't
+    /// make it smaller.
+    Compress,
+}
+
+impl BlockChoice {
+    /// The quick probe `Algorithm::Auto` uses: `Store` for a block whose
+    /// bytes look compressed or encrypted already, and `Compress` for the
+    /// rest.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::BlockChoice;
+    ///
+    /// let xorshift = |&x: &u32| Some(x ^ x << 13).map(|x| x ^ x >> 17).map(|x| x ^ x << 5);
+    /// let noise: Vec<u8> = std::iter::successors(Some(1u32), xorshift).map(|x| x as u8).take(8192).collect();
+    /// assert_eq!(BlockChoice::probe(&noise), BlockChoice::Store);
+    /// assert_eq!(BlockChoice::probe(&[0u8; 8192]), BlockChoice::Compress);
+    /// ```
+    pub fn probe(block: &[u8]) -> Self {
+        if incompressible(block) {
+            BlockChoice::Store
+        } else {
+            BlockChoice::Compress
+        }
+    }
+}
+
+/// Shortest sample `estimate_ratio` takes: LZO finds matches up to 48 KiB
+/// back, so much shorter samples miss repeats the whole input would use.
+const MIN_SAMPLE_LEN: usize = 16 * 1024;
+
+/// Inputs shorter than this are compressed without probing.
+const PROBE_MIN_LEN: usize = 4096;
+
+/// Length of each of the four windows `incompressible` looks at.
+const PROBE_WINDOW: usize = 1024;
+
+/// Whether `src` looks incompressible: bytes spread so evenly across a few
+/// windows of it, above 7.8 bits of entropy per byte, that it is most
+/// likely compressed or encrypted already. Data made of repeats of
+/// random-looking stretches longer than a window can fool it.
+pub(crate) fn incompressible(src: &[u8]) -> bool {
+    if src.len() < PROBE_MIN_LEN {
+        return false;
+    }
+    let mut counts = [0u32; 256];
+    for i in 0..4 {
+        let offset = (src.len() - PROBE_WINDOW) * i / 3;
+        for &byte in &src[offset..offset + PROBE_WINDOW] {
+            counts[byte as usize] += 1;
+        }
+    }
+    let total = (4 * PROBE_WINDOW) as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum();
+    entropy > 7.8
+}
+
+/// Write `src` into `out` as an LZO1X stream of one literal run, which
+/// any LZO1X decoder reads. Fails only if `out` can't grow to hold it.
+pub(crate) fn store(src: &[u8], out: &mut Vec<u8>) -> Result<(), CompressError> {
+    out.clear();
+    out.try_reserve(crate::compress_bound(src.len()))?;
+    if !src.is_empty() {
+        crate::dict::literal_header(out, src.len(), true);
+        out.extend_from_slice(src);
+    }
+    out.extend_from_slice(&[17, 0, 0]);
+    Ok(())
+}
+
+/// Whether `compressed_len` is at least `min_saving` bytes under `len`.
+fn saves(compressed_len: usize, len: usize, min_saving: usize) -> bool {
+    compressed_len.saturating_add(min_saving) <= len
+}
+
+impl LZO {
+    /// Compress `src`, keeping the result only if it is at least
+    /// `min_saving` bytes smaller than `src`.
+    ///
+    /// Returns the bytes to store and whether they are compressed. When
+    /// compression doesn't save enough, `src` itself is returned borrowed,
+    /// so incompressible data is neither expanded nor copied.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::borrow::Cow;
+    ///
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let (out, compressed) = lzo.compress_or_borrow(&[0x00u8; 1024], 16).unwrap();
+    /// assert!(compressed && out.len() < 1024);
+    ///
+    /// let (out, compressed) = lzo.compress_or_borrow(b"too short to shrink", 16).unwrap();
+    /// assert!(!compressed && matches!(out, Cow::Borrowed(_)));
+    /// ```
+    pub fn compress_or_borrow<'a>(
+        &mut self,
+        src: &'a [u8],
+        min_saving: usize,
+    ) -> Result<(Cow<'a, [u8]>, bool), CompressError> {
+        let out = self.compress(src)?;
+        if saves(out.len(), src.len(), min_saving) {
+            Ok((Cow::Owned(out), true))
+        } else {
+            Ok((Cow::Borrowed(src), false))
+        }
+    }
+
+    /// Estimate the ratio `src` would compress at, output bytes per input
+    /// byte as in `CompressionStats::ratio`, by compressing about
+    /// `sample_bytes` of it.
+    ///
+    /// The samples are spread evenly across `src`, each at least 16 KiB, so
+    /// deciding whether a large object is worth compressing costs a fixed
+    /// amount of work. When `sample_bytes` covers `src`, all of it is
+    /// compressed and the ratio is exact. An empty `src` gives 0.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let text = b"the quick brown fox jumps over the lazy dog. ".repeat(100_000);
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let ratio = lzo.estimate_ratio(&text, 64 * 1024).unwrap();
+    /// assert!(ratio < 0.2);
+    /// ```
+    pub fn estimate_ratio(
+        &mut self,
+        src: impl AsRef<[u8]>,
+        sample_bytes: usize,
+    ) -> Result<f64, CompressError> {
+        let src = src.as_ref();
+        if src.is_empty() {
+            return Ok(0.0);
+        }
+        let sample_bytes = sample_bytes.max(MIN_SAMPLE_LEN);
+        let count = if sample_bytes >= src.len() {
+            1
+        } else {
+            sample_bytes / MIN_SAMPLE_LEN
+        };
+        let len = sample_bytes.min(src.len()) / count;
+        let mut scratch = Vec::new();
+        let (mut input, mut output) = (0, 0);
+        for i in 0..count {
+            // The middle of the i-th of `count` equal stretches of `src`.
+            let offset =
+                ((src.len() - len) as u128 * (2 * i + 1) as u128 / (2 * count) as u128) as usize;
+            self.compress_to(&src[offset..offset + len], &mut scratch)?;
+            input += len;
+            output += scratch.len();
+        }
+        Ok(output as f64 / input as f64)
+    }
+
+    /// Compress `src` into `dst` if that saves at least `min_saving` bytes.
+    ///
+    /// `dst` is left untouched unless the outcome is `Compressed`. A block
+    /// that saves enough but doesn't fit in `dst` fails with
+    /// `CompressError::OutputOverrun`; a `dst` of `src.len() - min_saving`
+    /// bytes always suffices.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::CompressOutcome;
+    ///
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let mut dst = [0u8; 4096];
+    /// match lzo.compress_block(&[0x11u8; 4096], &mut dst, 512).unwrap() {
+    ///     CompressOutcome::Compressed(len) => assert!(len <= 4096 - 512),
+    ///     CompressOutcome::Incompressible | CompressOutcome::TooSmall => unreachable!(),
+    /// }
+    /// assert_eq!(
+    ///     lzo.compress_block(&[0x11u8; 64], &mut dst, 512),
+    ///     Ok(CompressOutcome::TooSmall)
+    /// );
+    /// ```
+    pub fn compress_block(
+        &mut self,
+        src: &[u8],
+        dst: &mut [u8],
+        min_saving: usize,
+    ) -> Result<CompressOutcome, CompressError> {
+        if !saves(MIN_COMPRESSED_LEN, src.len(), min_saving) {
+            return Ok(CompressOutcome::TooSmall);
+        }
+        let out = self.compress(src)?;
+        if !saves(out.len(), src.len(), min_saving) {
+            return Ok(CompressOutcome::Incompressible);
+        }
+        if out.len() > dst.len() {
+            return Err(CompressError::OutputOverrun {
+                capacity: dst.len(),
+                needed: Some(out.len()),
+            });
+        }
+        dst[..out.len()].copy_from_slice(&out);
+        Ok(CompressOutcome::Compressed(out.len()))
+    }
+
+    /// Compress `page` into itself if the result takes at most `target`
+    /// bytes.
+    ///
+    /// Returns the compressed length, with the compressed bytes at the
+    /// start of `page`, or `None` if the page should be kept uncompressed,
+    /// in which case `page` is left untouched. The compressor can't work
+    /// within a single buffer, so this allocates a scratch buffer per call;
+    /// use `compress_page_with` to reuse one.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let mut page = [0x07u8; 4096];
+    /// let len = lzo.compress_page(&mut page, 2048).unwrap().expect("fits");
+    /// let data = lzo.decompress_safe(&page[..len], 4096).unwrap();
+    /// assert_eq!(data, vec![0x07u8; 4096]);
+    /// ```
+    pub fn compress_page(
+        &mut self,
+        page: &mut [u8],
+        target: usize,
+    ) -> Result<Option<usize>, CompressError> {
+        self.compress_page_with(page, target, &mut Vec::new())
+    }
+
+    /// `compress_page`, compressing through `scratch`, whose allocation is
+    /// reused from call to call.
+    pub fn compress_page_with(
+        &mut self,
+        page: &mut [u8],
+        target: usize,
+        scratch: &mut Vec<u8>,
+    ) -> Result<Option<usize>, CompressError> {
+        self.compress_to(page, scratch)?;
+        if scratch.len() > target || scratch.len() > page.len() {
+            return Ok(None);
+        }
+        page[..scratch.len()].copy_from_slice(scratch);
+        Ok(Some(scratch.len()))
+    }
+
+    /// Compress `src`, failing with `CompressError::BudgetExceeded` if the
+    /// result would take more than `max_out` bytes.
+    ///
+    /// For per-datagram protocols with a hard size limit. A budget no LZO
+    /// stream of `src`'s length can meet is refused before compressing;
+    /// minilzo can't stop partway through a block, so otherwise the block
+    /// is compressed and measured, into an allocation that is dropped when
+    /// it doesn't fit.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::CompressError;
+    ///
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let out = lzo.compress_bounded(&[0x20u8; 8000], 1200).unwrap();
+    /// assert!(out.len() <= 1200);
+    ///
+    /// let noise: Vec<u8> = (0..8000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+    /// assert_eq!(
+    ///     lzo.compress_bounded(&noise, 1200),
+    ///     Err(CompressError::BudgetExceeded { budget: 1200 })
+    /// );
+    /// ```
+    pub fn compress_bounded(
+        &mut self,
+        src: impl AsRef<[u8]>,
+        max_out: usize,
+    ) -> Result<Vec<u8>, CompressError> {
+        let src = src.as_ref();
+        let least = MIN_COMPRESSED_LEN + src.len() / MAX_BYTES_PER_OUTPUT_BYTE;
+        if max_out < least {
+            return Err(CompressError::BudgetExceeded { budget: max_out });
+        }
+        let out = self.compress(src)?;
+        if out.len() > max_out {
+            return Err(CompressError::BudgetExceeded { budget: max_out });
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_or_borrow_threshold() {
+        let input = vec![0x42u8; 4096];
+        let mut lzo = LZO::new();
+        let exact = lzo.compress(&input).unwrap();
+        let saving = input.len() - exact.len();
+
+        let (out, compressed) = lzo.compress_or_borrow(&input, saving).unwrap();
+        assert!(compressed);
+        assert_eq!(out, Cow::<[u8]>::Owned(exact));
+
+        let (out, compressed) = lzo.compress_or_borrow(&input, saving + 1).unwrap();
+        assert!(!compressed);
+        assert!(matches!(out, Cow::Borrowed(b) if b.as_ptr() == input.as_ptr()));
+
+        let (out, compressed) = lzo.compress_or_borrow(&[], 0).unwrap();
+        assert!(!compressed);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_estimate_ratio() {
+        let mut lzo = LZO::new();
+        let mut x = 0x2545_f491u32;
+        let mut input: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        // Noise in the first half, zeros in the second.
+        input[1 << 19..].fill(0);
+        let exact = lzo.compress(&input).unwrap().len() as f64 / input.len() as f64;
+
+        let estimate = lzo.estimate_ratio(&input, 256 * 1024).unwrap();
+        assert!((estimate - exact).abs() < 0.05, "{} vs {}", estimate, exact);
+        assert_eq!(lzo.estimate_ratio(&input, usize::MAX).unwrap(), exact);
+        assert!(lzo.estimate_ratio(&input[..1 << 19], 0).unwrap() > 1.0);
+        assert_eq!(lzo.estimate_ratio(b"", 1024).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_auto_stores_noise() {
+        let mut auto = LZO::builder()
+            .algorithm(crate::Algorithm::Auto)
+            .build()
+            .unwrap();
+        let mut x = 0x9e37_79b9u32;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        assert!(incompressible(&noise));
+        let mut out = Vec::new();
+        for len in [0, 1, 3, 4, 238, 239, 500, 4096, 100_000] {
+            store(&noise[..len], &mut out).unwrap();
+            assert!(out.len() <= crate::compress_bound(len));
+            assert_eq!(auto.verify(&out), Ok(len));
+            assert_eq!(auto.decompress_safe(&out, len).unwrap(), &noise[..len]);
+        }
+        assert_eq!(auto.compress(&noise).unwrap(), out);
+
+        let text = b"stored or compressed, the output is LZO1X. ".repeat(1000);
+        assert!(!incompressible(&text));
+        assert_eq!(
+            auto.compress(&text).unwrap(),
+            LZO::new().compress(&text).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compress_block_outcomes() {
+        let mut lzo = LZO::new();
+        let mut dst = [0u8; 256];
+        let data = [0x33u8; 256];
+        let len = match lzo.compress_block(&data, &mut dst, 0).unwrap() {
+            CompressOutcome::Compressed(len) => len,
+            outcome => panic!("{:?}", outcome),
+        };
+        assert_eq!(lzo.decompress_safe(&dst[..len], 256).unwrap(), &data[..]);
+
+        let saving = data.len() - len;
+        assert_eq!(
+            lzo.compress_block(&data, &mut dst, saving + 1),
+            Ok(CompressOutcome::Incompressible)
+        );
+        assert_eq!(
+            lzo.compress_block(&data, &mut dst, 254),
+            Ok(CompressOutcome::TooSmall)
+        );
+        assert_eq!(
+            lzo.compress_block(&data, &mut dst[..len - 1], saving),
+            Err(CompressError::OutputOverrun {
+                capacity: len - 1,
+                needed: Some(len)
+            })
+        );
+    }
+
+    #[test]
+    fn test_compress_page_target() {
+        let original: Vec<u8> = (0..4096u32).map(|i| (i / 64) as u8).collect();
+        let mut lzo = LZO::new();
+        let mut scratch = Vec::new();
+        let mut page = original.clone();
+        let len = lzo
+            .compress_page_with(&mut page, 1024, &mut scratch)
+            .unwrap()
+            .unwrap();
+        assert_eq!(lzo.decompress_safe(&page[..len], 4096).unwrap(), original);
+
+        let mut page = original.clone();
+        assert_eq!(lzo.compress_page(&mut page, len - 1), Ok(None));
+        assert_eq!(page, original);
+    }
+
+    #[test]
+    fn test_compress_bounded() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 61) as u8).collect();
+        let mut lzo = LZO::new();
+        let exact = lzo.compress(&input).unwrap();
+        assert_eq!(lzo.compress_bounded(&input, exact.len()), Ok(exact.clone()));
+        assert_eq!(
+            lzo.compress_bounded(&input, exact.len() - 1),
+            Err(CompressError::BudgetExceeded {
+                budget: exact.len() - 1
+            })
+        );
+
+        // The lower bound holds for the most compressible input there is.
+        let zeros = vec![0u8; 1 << 20];
+        let least = MIN_COMPRESSED_LEN + zeros.len() / MAX_BYTES_PER_OUTPUT_BYTE;
+        assert!(lzo.compress(&zeros).unwrap().len() >= least);
+        assert!(lzo.compress_bounded(&zeros, least - 1).is_err());
+        assert_eq!(
+            lzo.compress_bounded(b"", 2),
+            Err(CompressError::BudgetExceeded { budget: 2 })
+        );
+    }
+}
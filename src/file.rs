@@ -0,0 +1,634 @@
+//! Path-based helpers.
+
+use crate::{
+    frame, instrument, AutoReader, BlockMeta, CompressionStats, Decompressor, LzoReader, LzoWriter,
+    DEFAULT_BLOCK_SIZE,
+};
+#[cfg(any(feature = "mmap", target_os = "linux", target_os = "android"))]
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Create `path` atomically with the contents `write` puts in the file it
+/// is given.
+///
+/// The file is written under a temporary name next to `path`, synced, and
+/// renamed over `path`, so readers see either the old file or the
+/// complete new one. The temporary file is removed if anything fails.
+pub(crate) fn write_atomically<F>(path: &Path, write: F) -> io::Result<File>
+where
+    F: FnOnce(File) -> io::Result<File>,
+{
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let result = File::create(&tmp)
+        .and_then(write)
+        .and_then(|file| file.sync_all().map(|_| file))
+        .and_then(|file| fs::rename(&tmp, path).map(|_| file));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Compress the file at `src` into a framed stream at `dst`, created
+/// atomically.
+///
+/// The input is streamed through `LzoWriter` one block at a time, so
+/// memory use doesn't grow with the file size. With the `mmap` feature the
+/// input is memory-mapped and compressed straight from the mapping instead
+/// of being read into a buffer; the file must not be truncated while that
+/// happens.
+///
+/// Example
+///
+/// ```rust
+/// let dir = std::env::temp_dir();
+/// let (plain, packed) = (dir.join("minilzo-rs-file-doc"), dir.join("minilzo-rs-file-doc.lzo"));
+/// std::fs::write(&plain, vec![0x61u8; 100_000]).unwrap();
+///
+/// let stats = minilzo_rs::compress_file(&plain, &packed).unwrap();
+/// assert_eq!(stats.input_bytes, 100_000);
+/// assert!(stats.ratio() < 1.0);
+///
+/// let stats = minilzo_rs::decompress_file(&packed, &plain).unwrap();
+/// assert_eq!(stats.output_bytes, 100_000);
+/// # std::fs::remove_file(&plain).unwrap();
+/// # std::fs::remove_file(&packed).unwrap();
+/// ```
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> io::Result<CompressionStats> {
+    let _span = instrument::enter_file("compress_file", src.as_ref(), dst.as_ref());
+    compress_with(File::open(src)?, dst.as_ref(), copy_input)
+}
+
+/// Like `compress_file`, but without reading the holes of a sparse input
+/// file, such as a VM disk image: they are compressed from zeros in memory
+/// instead. The output is the same as `compress_file`'s.
+///
+/// Holes are found with `SEEK_DATA` on Linux and Android; elsewhere, or on
+/// filesystems without sparse files, this is just `compress_file`. The
+/// input isn't memory-mapped even with the `mmap` feature.
+pub fn compress_sparse_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> io::Result<CompressionStats> {
+    let _span = instrument::enter_file("compress_sparse_file", src.as_ref(), dst.as_ref());
+    compress_with(File::open(src)?, dst.as_ref(), copy_sparse_input)
+}
+
+/// Like `compress_file`, but resumable: the output is written to `dst`
+/// with `.part` appended, which is kept if compression is interrupted, and
+/// renamed to `dst` once it is complete.
+///
+/// The partial file is its own checkpoint, its block headers recording how
+/// much input has been compressed. When it exists, its complete blocks are
+/// walked, the last one is decompressed and compared with the input, and
+/// compression continues from the end of that block, discarding anything
+/// written after it; a partial file that doesn't match the input is
+/// started over. The result is the same as `compress_file`'s, and the
+/// returned stats cover the whole input. The input isn't memory-mapped.
+///
+/// Example
+///
+/// ```rust
+/// let dir = std::env::temp_dir();
+/// let (plain, packed) = (dir.join("minilzo-rs-resume-doc"), dir.join("minilzo-rs-resume-doc.lzo"));
+/// std::fs::write(&plain, vec![0x62u8; 100_000]).unwrap();
+///
+/// let stats = minilzo_rs::compress_file_resumable(&plain, &packed).unwrap();
+/// assert_eq!(stats.input_bytes, 100_000);
+/// assert_eq!(std::fs::read(&packed).unwrap(), minilzo_rs::LZO::new().compress_chunked(vec![0x62u8; 100_000]).unwrap());
+/// # std::fs::remove_file(&plain).unwrap();
+/// # std::fs::remove_file(&packed).unwrap();
+/// ```
+pub fn compress_file_resumable<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> io::Result<CompressionStats> {
+    let dst = dst.as_ref();
+    let _span = instrument::enter_file("compress_file_resumable", src.as_ref(), dst);
+    let started = Instant::now();
+    let mut input = File::open(src)?;
+    let mut part = dst.as_os_str().to_owned();
+    part.push(".part");
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&part)?;
+    let (mut writer, resumed) = match resume_point(&mut file, &mut input)? {
+        Some((end, pos)) => {
+            // Put an end marker after the last complete block for `append`
+            // to continue from.
+            file.set_len(end)?;
+            file.seek(SeekFrom::Start(end))?;
+            file.write_all(&[0u8; 4])?;
+            input.seek(SeekFrom::Start(pos))?;
+            (LzoWriter::append(file, DEFAULT_BLOCK_SIZE)?, pos)
+        }
+        None => {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            input.seek(SeekFrom::Start(0))?;
+            (LzoWriter::new(file).map_err(frame::to_io)?, 0)
+        }
+    };
+    let input_len = resumed + io::copy(&mut BufReader::new(input), &mut writer)?;
+    let file = writer.finish()?;
+    file.sync_all()?;
+    fs::rename(&part, dst)?;
+    Ok(CompressionStats {
+        input_bytes: input_len,
+        output_bytes: file.metadata()?.len(),
+        blocks: input_len.div_ceil(DEFAULT_BLOCK_SIZE as u64),
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Find where the complete blocks of the partial framed stream in `file`
+/// end and how much of `input` they hold, or `None` if there are none or
+/// the last one doesn't match `input`.
+fn resume_point(file: &mut File, input: &mut File) -> io::Result<Option<(u64, u64)>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut header = [0u8; frame::HEADER_LEN];
+    file.seek(SeekFrom::Start(0))?;
+    if file_len < header.len() as u64 {
+        return Ok(None);
+    }
+    file.read_exact(&mut header)?;
+    // `compress_file_resumable` writes a plain header, with no sections.
+    let flags = match frame::parse_header(&header) {
+        Ok(flags) if frame::header_len(flags) == header.len() => flags,
+        _ => return Ok(None),
+    };
+    let (mut end, mut pos) = (header.len() as u64, 0u64);
+    let mut last = None;
+    let mut head = [0u8; frame::BLOCK_HEADER_LEN];
+    while end + head.len() as u64 <= file_len {
+        file.read_exact(&mut head)?;
+        let meta = BlockMeta::read_from(&head);
+        let next = end + (head.len() + meta.compressed_len as usize) as u64;
+        if meta.uncompressed_len == 0
+            || meta.uncompressed_len as usize > DEFAULT_BLOCK_SIZE
+            || next > file_len
+        {
+            break;
+        }
+        file.seek(SeekFrom::Start(next))?;
+        last = Some((end, meta));
+        end = next;
+        pos += meta.uncompressed_len as u64;
+    }
+    let (offset, meta) = match last {
+        Some(last) => last,
+        None => return Ok(None),
+    };
+    if pos > input.metadata()?.len() {
+        return Ok(None);
+    }
+    let mut payload = vec![0u8; meta.compressed_len as usize];
+    file.seek(SeekFrom::Start(offset + frame::BLOCK_HEADER_LEN as u64))?;
+    file.read_exact(&mut payload)?;
+    let decompressor = Decompressor::new().map_err(frame::to_io)?;
+    let checksum = frame::checksum_from_flags(flags);
+    let block = match frame::decode_block(&decompressor, &meta, &payload, checksum) {
+        Ok(block) => block,
+        Err(_) => return Ok(None),
+    };
+    let mut original = vec![0u8; block.len()];
+    input.seek(SeekFrom::Start(pos - block.len() as u64))?;
+    input.read_exact(&mut original)?;
+    Ok(if original == block {
+        Some((end, pos))
+    } else {
+        None
+    })
+}
+
+/// Compress `input` into `dst`, fed to the writer by `copy`.
+fn compress_with<F>(input: File, dst: &Path, copy: F) -> io::Result<CompressionStats>
+where
+    F: FnOnce(File, &mut LzoWriter<BufWriter<File>>) -> io::Result<u64>,
+{
+    let started = Instant::now();
+    let mut input_len = 0;
+    let file = write_atomically(dst, |file| {
+        let mut writer = LzoWriter::new(BufWriter::new(file)).map_err(frame::to_io)?;
+        input_len = copy(input, &mut writer)?;
+        writer.finish()?.into_inner().map_err(|e| e.into_error())
+    })?;
+    Ok(CompressionStats {
+        input_bytes: input_len,
+        output_bytes: file.metadata()?.len(),
+        // `LzoWriter` cuts its input into whole blocks and a shorter last one.
+        blocks: input_len.div_ceil(DEFAULT_BLOCK_SIZE as u64),
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Feed all of `input` to `writer`.
+#[cfg(not(feature = "mmap"))]
+fn copy_input<W: Write>(input: File, writer: &mut W) -> io::Result<u64> {
+    io::copy(&mut BufReader::new(input), writer)
+}
+
+/// Feed all of `input` to `writer` straight from a read-only mapping, which
+/// `LzoWriter` compresses a block at a time without copying.
+#[cfg(feature = "mmap")]
+fn copy_input<W: Write>(input: File, writer: &mut W) -> io::Result<u64> {
+    // A file too large for the address space, as on 32-bit targets, is
+    // read the ordinary way instead.
+    if usize::try_from(input.metadata()?.len()).map_or(true, |len| len > isize::MAX as usize) {
+        return io::copy(&mut BufReader::new(input), writer);
+    }
+    // SAFETY: the mapping is only read while this function runs. As with
+    // any mapped file, the caller must not let it be truncated meanwhile.
+    let map = unsafe { memmap2::Mmap::map(&input)? };
+    #[cfg(unix)]
+    map.advise(memmap2::Advice::Sequential)?;
+    writer.write_all(&map)?;
+    Ok(map.len() as u64)
+}
+
+/// Feed all of `input` to `writer`, filling its holes with zeros instead of
+/// reading them.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn copy_sparse_input<W: Write>(input: File, writer: &mut W) -> io::Result<u64> {
+    let len = input.metadata()?.len();
+    io::copy(&mut SparseReader::new(input, len), writer)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn copy_sparse_input<W: Write>(input: File, writer: &mut W) -> io::Result<u64> {
+    io::copy(&mut BufReader::new(input), writer)
+}
+
+/// Reads the first `len` bytes of a file, producing the zeros of its holes
+/// without reading them.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct SparseReader {
+    file: File,
+    len: u64,
+    pos: u64,
+    /// The next data region, `data..hole`, with `pos <= hole`.
+    data: u64,
+    hole: u64,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl SparseReader {
+    fn new(file: File, len: u64) -> Self {
+        SparseReader {
+            file,
+            len,
+            pos: 0,
+            data: 0,
+            hole: 0,
+        }
+    }
+
+    /// `lseek` with `SEEK_DATA` or `SEEK_HOLE`, or `None` past the last
+    /// data region.
+    fn lseek(&self, offset: u64, whence: libc::c_int) -> io::Result<Option<u64>> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: `lseek64` only reads its arguments; the descriptor is
+        // owned by `self.file` and open.
+        let found =
+            unsafe { libc::lseek64(self.file.as_raw_fd(), offset as libc::off64_t, whence) };
+        if found >= 0 {
+            return Ok(Some(found as u64));
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENXIO) => Ok(None),
+            _ => Err(err),
+        }
+    }
+
+    /// Find the data region at or after `pos`.
+    fn next_region(&mut self) -> io::Result<()> {
+        match self.lseek(self.pos, libc::SEEK_DATA) {
+            Ok(Some(data)) => {
+                self.data = data.min(self.len);
+                self.hole = self.lseek(data, libc::SEEK_HOLE)?.unwrap_or(self.len);
+            }
+            Ok(None) => self.data = self.len,
+            // No hole support: all of the rest is data.
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                self.data = self.pos;
+                self.hole = self.len;
+            }
+            Err(e) => return Err(e),
+        }
+        self.hole = self.hole.clamp(self.data, self.len);
+        self.file.seek(SeekFrom::Start(self.data))?;
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Read for SparseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        if self.pos >= self.hole.max(self.data) {
+            self.next_region()?;
+        }
+        let n = if self.pos < self.data {
+            let n =
+                usize::try_from(self.data - self.pos).map_or(buf.len(), |left| buf.len().min(left));
+            buf[..n].fill(0);
+            n
+        } else {
+            let n =
+                usize::try_from(self.hole - self.pos).map_or(buf.len(), |left| buf.len().min(left));
+            self.file.read(&mut buf[..n])?
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// The granularity of the holes `SparseWriter` leaves, the usual
+/// filesystem block size.
+const HOLE_SIZE: u64 = 4096;
+
+/// Writes a new file, seeking past aligned `HOLE_SIZE` blocks of zeros
+/// instead of writing them, which leaves holes on filesystems that support
+/// sparse files and reads back the same everywhere.
+struct SparseWriter {
+    inner: BufWriter<File>,
+    pos: u64,
+    /// Zeros skipped since the last write.
+    skipped: u64,
+}
+
+impl SparseWriter {
+    fn new(file: File) -> Self {
+        SparseWriter {
+            inner: BufWriter::new(file),
+            pos: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Flush the writer and extend the file over trailing holes.
+    fn finish(self) -> io::Result<File> {
+        let file = self.inner.into_inner().map_err(|e| e.into_error())?;
+        file.set_len(self.pos)?;
+        Ok(file)
+    }
+}
+
+impl Write for SparseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Up to the next block boundary, so whole blocks can be skipped.
+        let n = buf.len().min((HOLE_SIZE - self.pos % HOLE_SIZE) as usize);
+        let chunk = &buf[..n];
+        if n as u64 == HOLE_SIZE && chunk.iter().all(|&b| b == 0) {
+            self.skipped += HOLE_SIZE;
+        } else {
+            if self.skipped > 0 {
+                self.inner.seek(SeekFrom::Current(self.skipped as i64))?;
+                self.skipped = 0;
+            }
+            self.inner.write_all(chunk)?;
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decompress the framed stream at `src` into `dst`, created atomically.
+/// The inverse of `compress_file`.
+///
+/// Aligned 4 KiB blocks of zeros are left as holes rather than written, so
+/// a mostly empty disk image takes only the space of its data, on
+/// filesystems with sparse files.
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> io::Result<CompressionStats> {
+    let _span = instrument::enter_file("decompress_file", src.as_ref(), dst.as_ref());
+    let input = File::open(src)?;
+    let mut reader = LzoReader::new(BufReader::new(input)).map_err(frame::to_io)?;
+    write_atomically(dst.as_ref(), |file| {
+        let mut writer = SparseWriter::new(file);
+        io::copy(&mut reader, &mut writer)?;
+        writer.finish()
+    })?;
+    Ok(reader.stats())
+}
+
+/// Open `path` for reading, transparently decompressing it if it is a
+/// framed LZO stream or an lzop file, and passing it through unchanged
+/// otherwise.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// let path = std::env::temp_dir().join("minilzo-rs-open-auto-doc.lzo");
+/// let mut writer = minilzo_rs::LzoWriter::new(std::fs::File::create(&path).unwrap()).unwrap();
+/// writer.write_all(b"hello").unwrap();
+/// writer.finish().unwrap();
+///
+/// let mut data = Vec::new();
+/// minilzo_rs::open_auto(&path).unwrap().read_to_end(&mut data).unwrap();
+/// assert_eq!(data, b"hello");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn open_auto<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    Ok(Box::new(AutoReader::new(File::open(path)?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_open_auto() {
+        let dir = std::env::temp_dir();
+        let raw = dir.join("minilzo-rs-open-auto-raw");
+        let lzop = dir.join("minilzo-rs-open-auto.lzo");
+        std::fs::write(&raw, b"plain").unwrap();
+        let mut writer = crate::LzopWriter::new(File::create(&lzop).unwrap()).unwrap();
+        writer.write_all(b"packed").unwrap();
+        writer.finish().unwrap();
+
+        let mut data = Vec::new();
+        open_auto(&raw).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"plain");
+        data.clear();
+        open_auto(&lzop).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"packed");
+
+        std::fs::remove_file(raw).unwrap();
+        std::fs::remove_file(lzop).unwrap();
+    }
+
+    #[test]
+    fn test_file_roundtrip_and_failed_write() {
+        let dir = std::env::temp_dir();
+        let plain = dir.join("minilzo-rs-file-plain");
+        let packed = dir.join("minilzo-rs-file-packed.lzo");
+        let restored = dir.join("minilzo-rs-file-restored");
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&plain, &data).unwrap();
+
+        let stats = compress_file(&plain, &packed).unwrap();
+        assert_eq!(stats.input_bytes, data.len() as u64);
+        let blocks = (data.len() as u64).div_ceil(DEFAULT_BLOCK_SIZE as u64);
+        assert_eq!(stats.blocks, blocks);
+        assert!(stats.ratio() < 1.0);
+        assert_eq!(
+            stats.output_bytes,
+            std::fs::metadata(&packed).unwrap().len()
+        );
+        let unpacked = decompress_file(&packed, &restored).unwrap();
+        assert_eq!(unpacked.input_bytes, stats.output_bytes);
+        assert_eq!(unpacked.output_bytes, data.len() as u64);
+        assert_eq!(unpacked.blocks, stats.blocks);
+        assert_eq!(std::fs::read(&restored).unwrap(), data);
+
+        // Decompressing a file that isn't framed leaves the old output be.
+        assert!(decompress_file(&plain, &restored).is_err());
+        assert_eq!(std::fs::read(&restored).unwrap(), data);
+        let mut tmp = restored.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!Path::new(&tmp).exists());
+
+        std::fs::write(&plain, b"").unwrap();
+        compress_file(&plain, &packed).unwrap();
+        assert_eq!(decompress_file(&packed, &restored).unwrap().output_bytes, 0);
+
+        for path in [plain, packed, restored] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_input() {
+        let dir = std::env::temp_dir().join(format!("minilzo-rs-mmap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (plain, packed) = (dir.join("plain"), dir.join("packed.lzo"));
+        let restored = dir.join("restored");
+        let data: Vec<u8> = (0..600_000u32).map(|i| (i / 5 % 239) as u8).collect();
+        for input in [&data[..], b""] {
+            std::fs::write(&plain, input).unwrap();
+            let mut writer = LzoWriter::new(Vec::new()).unwrap();
+            let len = copy_input(File::open(&plain).unwrap(), &mut writer).unwrap();
+            assert_eq!(len, input.len() as u64);
+            let framed = writer.finish().unwrap();
+            assert_eq!(crate::decompress_chunked(&framed).unwrap(), input);
+
+            let stats = compress_file(&plain, &packed).unwrap();
+            assert_eq!(stats.input_bytes, input.len() as u64);
+            assert_eq!(std::fs::read(&packed).unwrap(), framed);
+            decompress_file(&packed, &restored).unwrap();
+            assert_eq!(std::fs::read(&restored).unwrap(), input);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resumable_compression() {
+        let dir = std::env::temp_dir().join(format!("minilzo-rs-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (plain, packed) = (dir.join("plain"), dir.join("packed.lzo"));
+        let part = dir.join("packed.lzo.part");
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i / 3 % 241) as u8).collect();
+        std::fs::write(&plain, &data).unwrap();
+        compress_file(&plain, &packed).unwrap();
+        let whole = std::fs::read(&packed).unwrap();
+        std::fs::remove_file(&packed).unwrap();
+
+        // Interrupted within the header, mid-block, just after a block and
+        // just before the rename, and left over from another input.
+        let mut after_two = frame::HEADER_LEN;
+        for _ in 0..2 {
+            let meta = BlockMeta::read_from(&whole[after_two..]);
+            after_two += frame::BLOCK_HEADER_LEN + meta.compressed_len as usize;
+        }
+        let other = crate::LZO::new()
+            .compress_chunked(data.iter().map(|b| b ^ 0x55).collect::<Vec<u8>>())
+            .unwrap();
+        for partial in [
+            &whole[..3],
+            &whole[..whole.len() / 2],
+            &whole[..after_two],
+            &whole[..],
+            &other[..other.len() - 100],
+        ] {
+            std::fs::write(&part, partial).unwrap();
+            let stats = compress_file_resumable(&plain, &packed).unwrap();
+            assert_eq!(stats.input_bytes, data.len() as u64);
+            assert_eq!(stats.output_bytes, whole.len() as u64);
+            assert_eq!(
+                stats.blocks,
+                (data.len() as u64).div_ceil(DEFAULT_BLOCK_SIZE as u64)
+            );
+            assert_eq!(std::fs::read(&packed).unwrap(), whole);
+            assert!(!part.exists());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sparse_files() {
+        let dir = std::env::temp_dir();
+        let image = dir.join("minilzo-rs-sparse-image");
+        let packed = dir.join("minilzo-rs-sparse-image.lzo");
+        let restored = dir.join("minilzo-rs-sparse-restored");
+
+        // Data, a 4 MiB hole, unaligned data, and a trailing hole.
+        let mut file = File::create(&image).unwrap();
+        file.write_all(&[1u8; 10_000]).unwrap();
+        file.seek(SeekFrom::Start(4 << 20)).unwrap();
+        file.write_all(&[2u8; 5000]).unwrap();
+        file.set_len(6 << 20).unwrap();
+        drop(file);
+        let data = std::fs::read(&image).unwrap();
+
+        let stats = compress_sparse_file(&image, &packed).unwrap();
+        assert_eq!(stats.input_bytes, 6 << 20);
+        assert_eq!(
+            decompress_file(&packed, &restored).unwrap().output_bytes,
+            6 << 20
+        );
+        assert_eq!(std::fs::read(&restored).unwrap(), data);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let meta = std::fs::metadata(&restored).unwrap();
+            assert!(meta.blocks() * 512 < 1 << 20, "{} blocks", meta.blocks());
+        }
+
+        // The same stream as from reading every byte.
+        let dense = dir.join("minilzo-rs-sparse-dense.lzo");
+        compress_file(&image, &dense).unwrap();
+        assert_eq!(
+            std::fs::read(&dense).unwrap(),
+            std::fs::read(&packed).unwrap()
+        );
+
+        for path in [image, packed, restored, dense] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,1158 @@
+//! The framed stream format shared by the streaming encoders and decoders.
+//!
+//! A stream starts with a 6-byte header (`b"MLZO"`, a version byte and a
+//! flags byte), followed by any number of blocks and a zero `u32` end
+//! marker. Every block is a [`BlockMeta`] (uncompressed length, compressed
+//! length and checksum of the uncompressed data, all big-endian `u32`)
+//! followed by the compressed payload.
+//!
+//! A block whose compressed length equals its uncompressed length is
+//! stored: the payload is the data itself. Encoders store blocks LZO would
+//! not shrink, so incompressible input costs only the block header.
+//!
+//! The checksum is adler32 unless the header selects another: streams with
+//! `FLAG_CRC32` set carry CRC-32s, and streams with `FLAG_NO_CHECKSUM` set
+//! carry zeros, which decoders don't verify. At most one of the two is set.
+//!
+//! Streams with `FLAG_DELTA` set have a seventh header byte holding a
+//! stride minus one: each block's data went through a byte-delta filter
+//! with that stride before compression, and decoders reverse it after
+//! checking the checksum, which covers the filtered bytes.
+//!
+//! Streams with `FLAG_METADATA` set continue the header with a `u32` length
+//! and a section of that many bytes holding a `FrameMetadata`, after the
+//! delta byte if there is one.
+//!
+//! Streams with `FLAG_DICT` set had every block compressed against a
+//! preset dictionary (see `dict`). Its ID and length, two `u32`s, follow
+//! the other fixed header fields, and the dictionary itself follows the
+//! metadata section; a length of zero records only the ID, and readers
+//! must be given the dictionary.
+//!
+//! Streams with [`FLAG_PARITY`] set may also carry parity frames between
+//! blocks: a `u32::MAX` marker, the number of data and parity shards (`u16`
+//! each), the shard length (`u32`) and the parity shards themselves. Each
+//! data shard is one whole block, header included, zero-padded to the shard
+//! length. Decoders that can't use them (or don't need to) skip them.
+//!
+//! The format evolves without misreading streams from newer writers. The
+//! version byte only changes for layouts older decoders couldn't even
+//! skip, and decoders reject other versions with
+//! `Error::UnsupportedVersion`; unknown bits in the flags byte are invalid.
+//! New features are announced by `FLAG_FEATURES` instead, which adds three
+//! `u32`s after the other fixed fields: required feature bits, optional
+//! feature bits and the length of an extension section, which follows the
+//! dictionary. Decoders fail with `Error::UnsupportedFeatures` on required
+//! bits they don't know, as those change how the blocks decode, but ignore
+//! unknown optional bits and skip the extension section, so streams that
+//! only add optional data still read.
+//!
+//! Streams with the required feature `FEATURE_CHAINED` start their
+//! extension section with a `u32` window length, at most `MAX_DICT_LEN`.
+//! Every block was compressed against that many of the last bytes of the
+//! blocks before it (their data after the delta filter), the preset
+//! dictionary if there is one coming first, as though that tail were a
+//! preset dictionary. Their blocks only decode in order, from the first.
+
+use crate::dict::{self, MAX_DICT_LEN};
+use crate::metadata::MAX_METADATA_LEN;
+use crate::scratch::Scratch;
+use crate::{
+    instrument, BlockChoice, BlockMeta, Checksum, DecodeError, Decompressor, Error, FrameMetadata,
+    LZOResult, LZO,
+};
+use std::io;
+use std::sync::Arc;
+
+pub(crate) const MAGIC: &[u8; 4] = b"MLZO";
+pub(crate) const VERSION: u8 = 1;
+pub(crate) const HEADER_LEN: usize = 6;
+/// `header_len` with every flag set.
+pub(crate) const MAX_HEADER_LEN: usize = HEADER_LEN + 1 + 4 + 8 + 12;
+pub(crate) const BLOCK_HEADER_LEN: usize = 12;
+pub(crate) const FLAG_PARITY: u8 = 0x01;
+pub(crate) const FLAG_NO_CHECKSUM: u8 = 0x02;
+pub(crate) const FLAG_CRC32: u8 = 0x04;
+pub(crate) const FLAG_DELTA: u8 = 0x08;
+pub(crate) const FLAG_METADATA: u8 = 0x10;
+pub(crate) const FLAG_DICT: u8 = 0x20;
+pub(crate) const FLAG_FEATURES: u8 = 0x40;
+const KNOWN_FLAGS: u8 = FLAG_PARITY
+    | FLAG_NO_CHECKSUM
+    | FLAG_CRC32
+    | FLAG_DELTA
+    | FLAG_METADATA
+    | FLAG_DICT
+    | FLAG_FEATURES;
+/// Required feature: blocks are compressed against the ones before them.
+pub(crate) const FEATURE_CHAINED: u32 = 0x0000_0001;
+/// Required feature bits this version decodes.
+const KNOWN_REQUIRED_FEATURES: u32 = FEATURE_CHAINED;
+/// Largest extension section the decoders accept.
+const MAX_EXTENSIONS_LEN: usize = 64 * 1024;
+pub(crate) const PARITY_MARKER: [u8; 4] = [0xff; 4];
+
+/// Block size used by the streaming encoders unless configured otherwise.
+pub const DEFAULT_BLOCK_SIZE: usize = 256 * 1024;
+
+/// Largest block the decoders accept, to bound memory on corrupt input.
+pub const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+pub(crate) fn encode_header(out: &mut Vec<u8>) {
+    encode_header_with_flags(out, 0);
+}
+
+pub(crate) fn encode_header_with_flags(out: &mut Vec<u8>, flags: u8) {
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(flags);
+}
+
+/// Write the header of a stream encoded by `lzo`, recording its checksum
+/// and delta filter.
+pub(crate) fn encode_header_for(out: &mut Vec<u8>, lzo: &LZO) {
+    let flags = checksum_flags(lzo.checksum());
+    match lzo.delta() {
+        Some(stride) => {
+            encode_header_with_flags(out, flags | FLAG_DELTA);
+            out.push((stride - 1) as u8);
+        }
+        None => encode_header_with_flags(out, flags),
+    }
+}
+
+/// Complete the header `out` holds with `metadata` and a preset dictionary,
+/// embedded in the header if `embed` is set, setting their flags.
+pub(crate) fn encode_sections(
+    out: &mut Vec<u8>,
+    metadata: Option<&FrameMetadata>,
+    dict: Option<(&[u8], bool)>,
+) -> LZOResult<()> {
+    let mut sections = Vec::new();
+    if let Some(metadata) = metadata {
+        let section = metadata.encode()?;
+        out[HEADER_LEN - 1] |= FLAG_METADATA;
+        out.extend_from_slice(&(section.len() as u32).to_be_bytes());
+        sections.extend_from_slice(&section);
+    }
+    if let Some((dict, embed)) = dict {
+        let dict = dict::usable(dict);
+        let embedded = if embed { dict } else { &[] };
+        out[HEADER_LEN - 1] |= FLAG_DICT;
+        out.extend_from_slice(&dict::id(dict).to_be_bytes());
+        out.extend_from_slice(&(embedded.len() as u32).to_be_bytes());
+        sections.extend_from_slice(embedded);
+    }
+    out.extend_from_slice(&sections);
+    Ok(())
+}
+
+/// Announce the `required` feature bits in the header `out` holds, which
+/// must not have its sections yet, with the extension section `extensions`.
+pub(crate) fn encode_features(out: &mut Vec<u8>, required: u32, extensions: &[u8]) {
+    out[HEADER_LEN - 1] |= FLAG_FEATURES;
+    for word in [required, 0, extensions.len() as u32] {
+        out.extend_from_slice(&word.to_be_bytes());
+    }
+    out.extend_from_slice(extensions);
+}
+
+/// Check the fixed part of the stream header and return its flags. The
+/// metadata section isn't checked.
+pub(crate) fn parse_header(buf: &[u8]) -> LZOResult<u8> {
+    if buf.len() < HEADER_LEN || &buf[..4] != MAGIC {
+        return Err(Error::InvalidHeader);
+    }
+    if buf[4] != VERSION {
+        return Err(Error::UnsupportedVersion { version: buf[4] });
+    }
+    if buf[5] & !KNOWN_FLAGS != 0
+        || buf[5] & (FLAG_NO_CHECKSUM | FLAG_CRC32) == FLAG_NO_CHECKSUM | FLAG_CRC32
+        || buf.len() < header_len(buf[5])
+        || metadata_len(buf) > MAX_METADATA_LEN
+        || dict_len(buf) > MAX_DICT_LEN
+        || extensions_len(buf) > MAX_EXTENSIONS_LEN
+    {
+        return Err(Error::InvalidHeader);
+    }
+    let unknown = required_features(buf) & !KNOWN_REQUIRED_FEATURES;
+    if unknown != 0 {
+        return Err(Error::UnsupportedFeatures { features: unknown });
+    }
+    Ok(buf[5])
+}
+
+/// Length of the fixed part of a stream header with `flags`, up to the
+/// metadata section.
+pub(crate) fn header_len(flags: u8) -> usize {
+    let mut len = HEADER_LEN;
+    if flags & FLAG_DELTA != 0 {
+        len += 1;
+    }
+    if flags & FLAG_METADATA != 0 {
+        len += 4;
+    }
+    if flags & FLAG_DICT != 0 {
+        len += 8;
+    }
+    if flags & FLAG_FEATURES != 0 {
+        len += 12;
+    }
+    len
+}
+
+/// The `u32` at `at` in a header.
+fn header_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// Offset of the dictionary ID and length in a header with `flags`.
+fn dict_field(flags: u8) -> usize {
+    header_len(flags & !FLAG_FEATURES) - 8
+}
+
+/// Offset of the feature words and extension length in a header with
+/// `flags`.
+fn features_field(flags: u8) -> usize {
+    header_len(flags) - 12
+}
+
+/// The required feature bits of a header, which `buf` must hold.
+fn required_features(buf: &[u8]) -> u32 {
+    if buf[5] & FLAG_FEATURES == 0 {
+        return 0;
+    }
+    header_u32(buf, features_field(buf[5]))
+}
+
+/// Whether a header `parse_header` accepted has its blocks chained.
+pub(crate) fn chained(buf: &[u8]) -> bool {
+    required_features(buf) & FEATURE_CHAINED != 0
+}
+
+/// The tail of the blocks of a chained stream so far, which the next
+/// block is compressed against.
+pub(crate) struct Chain {
+    window: Vec<u8>,
+    len: usize,
+}
+
+impl Chain {
+    /// A chain keeping `len` bytes, starting with the tail of `dict`.
+    pub(crate) fn new(len: usize, dict: &[u8]) -> Self {
+        let mut chain = Chain {
+            window: Vec::with_capacity(len),
+            len,
+        };
+        chain.push(dict);
+        chain
+    }
+
+    pub(crate) fn window(&self) -> &[u8] {
+        &self.window
+    }
+
+    /// Add a block's `data`, as it was compressed.
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.len)..];
+        let excess = (self.window.len() + data.len()).saturating_sub(self.len);
+        self.window.drain(..excess);
+        self.window.extend_from_slice(data);
+    }
+}
+
+/// The chain of a header `parse_header` accepted, starting with `dict`, if
+/// its blocks are chained. Fails with `Error::EOFNotFound` if `buf` doesn't
+/// hold the whole header.
+pub(crate) fn chain_from_header(buf: &[u8], dict: Option<&[u8]>) -> LZOResult<Option<Chain>> {
+    if !chained(buf) {
+        return Ok(None);
+    }
+    if extensions_len(buf) < 4 {
+        return Err(Error::InvalidHeader);
+    }
+    let start = header_len(buf[5]) + metadata_len(buf) + dict_len(buf);
+    let field = buf.get(start..start + 4).ok_or(Error::EOFNotFound)?;
+    let len = header_u32(field, 0) as usize;
+    if len == 0 || len > MAX_DICT_LEN {
+        return Err(Error::InvalidHeader);
+    }
+    Ok(Some(Chain::new(len, dict.unwrap_or_default())))
+}
+
+/// Length of the extension section after the embedded dictionary.
+pub(crate) fn extensions_len(buf: &[u8]) -> usize {
+    if buf[5] & FLAG_FEATURES == 0 {
+        return 0;
+    }
+    header_u32(buf, features_field(buf[5]) + 8) as usize
+}
+
+/// Length of the metadata section after a header's fixed part, which
+/// `buf` must hold.
+pub(crate) fn metadata_len(buf: &[u8]) -> usize {
+    if buf[5] & FLAG_METADATA == 0 {
+        return 0;
+    }
+    header_u32(buf, header_len(buf[5] & !(FLAG_DICT | FLAG_FEATURES)) - 4) as usize
+}
+
+/// Length of the dictionary embedded after the metadata section.
+pub(crate) fn dict_len(buf: &[u8]) -> usize {
+    if buf[5] & FLAG_DICT == 0 {
+        return 0;
+    }
+    header_u32(buf, dict_field(buf[5]) + 4) as usize
+}
+
+/// Length of a header `parse_header` accepted, metadata, dictionary and
+/// extensions included.
+pub(crate) fn full_header_len(buf: &[u8]) -> usize {
+    header_len(buf[5]) + metadata_len(buf) + dict_len(buf) + extensions_len(buf)
+}
+
+/// The ID of the dictionary a header `parse_header` accepted records, and
+/// the dictionary if it is embedded (empty otherwise). Fails with
+/// `Error::EOFNotFound` if `buf` doesn't hold all of it.
+pub(crate) fn dict_from_header(buf: &[u8]) -> LZOResult<Option<(u32, &[u8])>> {
+    if buf[5] & FLAG_DICT == 0 {
+        return Ok(None);
+    }
+    let id = header_u32(buf, dict_field(buf[5]));
+    let start = header_len(buf[5]) + metadata_len(buf);
+    let embedded = buf
+        .get(start..start + dict_len(buf))
+        .ok_or(Error::EOFNotFound)?;
+    if !embedded.is_empty() && dict::id(embedded) != id {
+        return Err(Error::InvalidHeader);
+    }
+    Ok(Some((id, embedded)))
+}
+
+/// The dictionary the blocks of a stream with `header`'s dictionary fields
+/// need: the embedded one, or `given` if its ID matches.
+pub(crate) fn stream_dict(
+    header: Option<(u32, &[u8])>,
+    given: Option<&Arc<[u8]>>,
+) -> LZOResult<Option<Arc<[u8]>>> {
+    match header {
+        None => Ok(None),
+        Some((_, embedded)) if !embedded.is_empty() => Ok(Some(Arc::from(embedded))),
+        Some((id, _)) => match given {
+            Some(dict) if dict::id(dict) == id => Ok(Some(Arc::clone(dict))),
+            _ => Err(Error::DictionaryMismatch { id }),
+        },
+    }
+}
+
+/// The metadata of a header `parse_header` accepted, if it has any. Fails
+/// with `Error::EOFNotFound` if `buf` doesn't hold all of it.
+pub(crate) fn metadata_from_header(buf: &[u8]) -> LZOResult<Option<FrameMetadata>> {
+    if buf[5] & FLAG_METADATA == 0 {
+        return Ok(None);
+    }
+    let section = buf
+        .get(header_len(buf[5])..header_len(buf[5]) + metadata_len(buf))
+        .ok_or(Error::EOFNotFound)?;
+    FrameMetadata::decode(section).map(Some)
+}
+
+/// The delta stride recorded in a header `parse_header` accepted.
+pub(crate) fn delta_from_header(buf: &[u8]) -> Option<usize> {
+    (buf[5] & FLAG_DELTA != 0).then(|| buf[HEADER_LEN] as usize + 1)
+}
+
+/// Header flags recording `checksum`.
+pub(crate) fn checksum_flags(checksum: Checksum) -> u8 {
+    match checksum {
+        Checksum::Adler32 => 0,
+        Checksum::Crc32 => FLAG_CRC32,
+        Checksum::None => FLAG_NO_CHECKSUM,
+    }
+}
+
+/// The checksum a stream with header `flags` carries.
+pub(crate) fn checksum_from_flags(flags: u8) -> Checksum {
+    if flags & FLAG_NO_CHECKSUM != 0 {
+        Checksum::None
+    } else if flags & FLAG_CRC32 != 0 {
+        Checksum::Crc32
+    } else {
+        Checksum::Adler32
+    }
+}
+
+/// The fixed-size part of a parity frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ParityHeader {
+    pub(crate) data_shards: usize,
+    pub(crate) parity_shards: usize,
+    pub(crate) shard_len: usize,
+}
+
+impl ParityHeader {
+    #[cfg(feature = "parity")]
+    pub(crate) fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&PARITY_MARKER);
+        out.extend_from_slice(&(self.data_shards as u16).to_be_bytes());
+        out.extend_from_slice(&(self.parity_shards as u16).to_be_bytes());
+        out.extend_from_slice(&(self.shard_len as u32).to_be_bytes());
+    }
+
+    /// Parse the first `BLOCK_HEADER_LEN` bytes of a parity frame.
+    pub(crate) fn read_from(buf: &[u8]) -> LZOResult<Self> {
+        let header = ParityHeader {
+            data_shards: u16::from_be_bytes([buf[4], buf[5]]) as usize,
+            parity_shards: u16::from_be_bytes([buf[6], buf[7]]) as usize,
+            shard_len: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]) as usize,
+        };
+        if header.data_shards == 0
+            || header.parity_shards == 0
+            || header.data_shards + header.parity_shards > 256
+            || header.shard_len > BLOCK_HEADER_LEN + crate::compress_bound(MAX_BLOCK_SIZE)
+        {
+            return Err(Error::InvalidHeader);
+        }
+        Ok(header)
+    }
+
+    /// Length of the whole frame, marker included.
+    pub(crate) fn frame_len(&self) -> usize {
+        BLOCK_HEADER_LEN + self.parity_shards * self.shard_len
+    }
+}
+
+pub(crate) fn encode_end(out: &mut Vec<u8>) {
+    out.extend_from_slice(&0u32.to_be_bytes());
+}
+
+/// The payload of a block holding `data`: its compressed form, or `data`
+/// itself when compressing wouldn't make it smaller.
+pub(crate) fn block_payload(lzo: &mut LZO, data: &[u8]) -> LZOResult<Vec<u8>> {
+    block_payload_with(lzo, data, &[])
+}
+
+/// `block_payload`, compressing against the preset dictionary `dict` unless
+/// it is empty.
+fn block_payload_with(lzo: &mut LZO, data: &[u8], dict: &[u8]) -> LZOResult<Vec<u8>> {
+    let compressed = match dict {
+        [] => lzo.compress(data)?,
+        dict => lzo.compress_dict(data, dict)?,
+    };
+    if compressed.len() >= data.len() {
+        return Ok(data.to_vec());
+    }
+    Ok(compressed)
+}
+
+/// Compress `data` as one block and append it to `out`.
+pub(crate) fn encode_block(lzo: &mut LZO, data: &[u8], out: &mut Vec<u8>) -> LZOResult<()> {
+    encode_block_with(lzo, data, &[], out)
+}
+
+/// `encode_block`, compressing against the preset dictionary `dict` unless
+/// it is empty.
+pub(crate) fn encode_block_with(
+    lzo: &mut LZO,
+    data: &[u8],
+    dict: &[u8],
+    out: &mut Vec<u8>,
+) -> LZOResult<()> {
+    encode_block_as(lzo, data, dict, BlockChoice::Compress, out)
+}
+
+/// `encode_block_with` for a block encoded as `choice` says.
+pub(crate) fn encode_block_as(
+    lzo: &mut LZO,
+    data: &[u8],
+    dict: &[u8],
+    choice: BlockChoice,
+    out: &mut Vec<u8>,
+) -> LZOResult<()> {
+    if data.is_empty() || data.len() > MAX_BLOCK_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+    match lzo.delta() {
+        Some(stride) => {
+            encode_filtered(lzo, &crate::delta::encode(data, stride), dict, choice, out)
+        }
+        None => encode_filtered(lzo, data, dict, choice, out),
+    }
+}
+
+/// `encode_block_as` for a block of a chained stream, compressed against
+/// `chain`, which it then joins.
+pub(crate) fn encode_chained_block(
+    lzo: &mut LZO,
+    data: &[u8],
+    chain: &mut Chain,
+    choice: BlockChoice,
+    out: &mut Vec<u8>,
+) -> LZOResult<()> {
+    if data.is_empty() || data.len() > MAX_BLOCK_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+    let filtered;
+    let data = match lzo.delta() {
+        Some(stride) => {
+            filtered = crate::delta::encode(data, stride);
+            &filtered[..]
+        }
+        None => data,
+    };
+    encode_filtered(lzo, data, chain.window(), choice, out)?;
+    chain.push(data);
+    Ok(())
+}
+
+/// Encode `data`, already through the delta filter, as one block.
+fn encode_filtered(
+    lzo: &mut LZO,
+    data: &[u8],
+    dict: &[u8],
+    choice: BlockChoice,
+    out: &mut Vec<u8>,
+) -> LZOResult<()> {
+    let payload = match choice {
+        BlockChoice::Store => data.to_vec(),
+        BlockChoice::Compress => block_payload_with(lzo, data, dict)?,
+    };
+    let meta = BlockMeta {
+        uncompressed_len: data.len() as u32,
+        compressed_len: payload.len() as u32,
+        checksum: lzo.checksum().compute(data),
+    };
+    instrument::block_encoded(&meta);
+    out.try_reserve(BLOCK_HEADER_LEN + payload.len())?;
+    meta.write_to(out);
+    out.extend_from_slice(&payload);
+    Ok(())
+}
+
+/// Decompress a block, checking it against `checksum` unless that is
+/// `Checksum::None`.
+pub(crate) fn decode_block(
+    decompressor: &Decompressor,
+    meta: &BlockMeta,
+    payload: &[u8],
+    checksum: Checksum,
+) -> LZOResult<Vec<u8>> {
+    decode_block_with(decompressor, meta, payload, checksum, &[])
+}
+
+/// `decode_block` for a block compressed against the preset dictionary
+/// `dict`, unless it is empty.
+pub(crate) fn decode_block_with(
+    decompressor: &Decompressor,
+    meta: &BlockMeta,
+    payload: &[u8],
+    checksum: Checksum,
+    dict: &[u8],
+) -> LZOResult<Vec<u8>> {
+    let result = (|| {
+        let data = if payload.len() == meta.uncompressed_len as usize {
+            payload.to_vec()
+        } else {
+            dict::decode(decompressor, payload, dict, meta.uncompressed_len as usize)?
+        };
+        if data.len() != meta.uncompressed_len as usize {
+            return Err(Error::InputOverrun);
+        }
+        if checksum != Checksum::None && checksum.compute(&data) != meta.checksum {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(data)
+    })();
+    instrument::block_decoded(meta, result.as_ref().map(|_| ()));
+    result
+}
+
+#[cfg(any(feature = "tokio-util", feature = "futures", feature = "tonic"))]
+/// Encode one self-delimiting message: a block that may also be empty.
+pub(crate) fn encode_message(lzo: &mut LZO, item: &[u8], out: &mut Vec<u8>) -> LZOResult<()> {
+    if item.is_empty() {
+        let meta = BlockMeta {
+            uncompressed_len: 0,
+            compressed_len: 0,
+            checksum: crate::adler32(item),
+        };
+        meta.write_to(out);
+        Ok(())
+    } else {
+        encode_block(lzo, item, out)
+    }
+}
+
+#[cfg(any(feature = "tokio-util", feature = "futures", feature = "tonic"))]
+/// Decode one message from the front of `buf`, returning it with the number
+/// of bytes consumed, or `None` if `buf` doesn't hold a whole message yet.
+pub(crate) fn decode_message(
+    decompressor: &Decompressor,
+    buf: &[u8],
+    max_len: usize,
+) -> LZOResult<Option<(Vec<u8>, usize)>> {
+    if buf.len() < BLOCK_HEADER_LEN {
+        return Ok(None);
+    }
+    let meta = BlockMeta::read_from(buf);
+    if meta.uncompressed_len as usize > max_len
+        || meta.compressed_len as usize > crate::compress_bound(max_len)
+    {
+        return Err(Error::InvalidHeader);
+    }
+    let end = BLOCK_HEADER_LEN + meta.compressed_len as usize;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    let message = if meta.uncompressed_len == 0 {
+        Vec::new()
+    } else {
+        decode_block(
+            decompressor,
+            &meta,
+            &buf[BLOCK_HEADER_LEN..end],
+            Checksum::Adler32,
+        )?
+    };
+    Ok(Some((message, end)))
+}
+
+/// Index the blocks of a complete framed stream without decoding them.
+pub(crate) fn split_blocks(src: &[u8]) -> LZOResult<Vec<(BlockMeta, &[u8])>> {
+    parse_header(src)?;
+    metadata_from_header(src)?;
+    dict_from_header(src)?;
+    let mut pos = full_header_len(src);
+    if pos > src.len() {
+        return Err(Error::EOFNotFound);
+    }
+    let mut blocks = Vec::new();
+    loop {
+        let rest = &src[pos..];
+        if rest.len() < 4 {
+            return Err(Error::EOFNotFound);
+        }
+        if rest[..4] == [0u8; 4] {
+            if rest.len() > 4 {
+                return Err(Error::InputNotConsumed {
+                    remaining: rest.len() - 4,
+                });
+            }
+            return Ok(blocks);
+        }
+        if rest.len() < BLOCK_HEADER_LEN {
+            return Err(Error::EOFNotFound);
+        }
+        if rest[..4] == PARITY_MARKER {
+            pos += ParityHeader::read_from(rest)?.frame_len();
+            if pos > src.len() {
+                return Err(Error::EOFNotFound);
+            }
+            continue;
+        }
+        let meta = BlockMeta::read_from(rest);
+        if meta.uncompressed_len as usize > MAX_BLOCK_SIZE
+            || meta.compressed_len as usize > crate::compress_bound(MAX_BLOCK_SIZE)
+        {
+            return Err(Error::InvalidHeader);
+        }
+        let end = BLOCK_HEADER_LEN + meta.compressed_len as usize;
+        if rest.len() < end {
+            return Err(Error::InvalidHeader);
+        }
+        blocks.push((meta, &rest[BLOCK_HEADER_LEN..end]));
+        pos += end;
+    }
+}
+
+pub(crate) fn to_io(e: impl Into<Error>) -> io::Error {
+    io::Error::from(e.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Header,
+    Block,
+    Done,
+}
+
+/// Incremental, I/O-free decoder of the framed format.
+///
+/// Bytes are pushed in with `feed`, and `decode` yields whole blocks as soon
+/// as they are complete.
+///
+/// With the `parity` feature, a block that fails to decode in a stream
+/// carrying parity frames is held back, along with the rest of its group,
+/// and rebuilt when the group's parity frame arrives.
+pub(crate) struct Decoder {
+    buf: Scratch,
+    pos: usize,
+    state: State,
+    checksum: Checksum,
+    delta: Option<usize>,
+    metadata: Option<Box<FrameMetadata>>,
+    /// The dictionary given for the stream until the header is parsed,
+    /// then the one its blocks need.
+    dict: Option<Arc<[u8]>>,
+    /// The tail of the blocks decoded so far, for a chained stream.
+    chain: Option<Chain>,
+    max_output: Option<u64>,
+    produced: u64,
+    /// Input bytes dropped from the front of `buf`.
+    drained: u64,
+    /// Data blocks decoded or skipped so far.
+    blocks: u64,
+    #[cfg(feature = "parity")]
+    recovery: Option<crate::parity::Recovery>,
+}
+
+impl Decoder {
+    pub(crate) fn new() -> Self {
+        Decoder {
+            buf: Scratch::new(),
+            pos: 0,
+            state: State::Header,
+            checksum: Checksum::Adler32,
+            delta: None,
+            metadata: None,
+            dict: None,
+            chain: None,
+            max_output: None,
+            produced: 0,
+            drained: 0,
+            blocks: 0,
+            #[cfg(feature = "parity")]
+            recovery: None,
+        }
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` once the blocks decoded would
+    /// add up to more than `max` bytes.
+    pub(crate) fn set_max_output(&mut self, max: u64) {
+        self.max_output = Some(max);
+    }
+
+    fn check_output(&self, len: u64) -> LZOResult<()> {
+        if self
+            .max_output
+            .is_some_and(|max| self.produced.saturating_add(len) > max)
+        {
+            return Err(Error::OutputLimitExceeded);
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, mut data: Vec<u8>) -> LZOResult<Option<Vec<u8>>> {
+        self.check_output(data.len() as u64)?;
+        self.produced += data.len() as u64;
+        if let Some(stride) = self.delta {
+            crate::delta::decode(&mut data, stride);
+        }
+        Ok(Some(data))
+    }
+
+    /// Offset in the input of the frame being decoded, which is where
+    /// the damage is after a decoding error.
+    pub(crate) fn input_offset(&self) -> u64 {
+        self.drained + self.pos as u64
+    }
+
+    /// Index of the data block being decoded, counting from 0.
+    pub(crate) fn block_index(&self) -> u64 {
+        self.blocks
+    }
+
+    /// `error`, with where in the stream it happened.
+    pub(crate) fn locate(&self, error: Error) -> DecodeError {
+        DecodeError {
+            error,
+            offset: self.input_offset(),
+            block: self.block_index(),
+        }
+    }
+
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        self.drained += self.pos as u64;
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(data);
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Bytes fed in but not consumed, such as data after the end marker.
+    pub(crate) fn remaining(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// The checksum the stream carries, known once the header is parsed.
+    pub(crate) fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// The stride of the stream's delta filter, known once the header is
+    /// parsed. `next_raw` leaves reversing it to the caller.
+    pub(crate) fn delta(&self) -> Option<usize> {
+        self.delta
+    }
+
+    /// Use `dict` for a stream whose header records only the ID of its
+    /// dictionary.
+    pub(crate) fn set_dictionary(&mut self, dict: &[u8]) {
+        self.dict = Some(Arc::from(dict::usable(dict)));
+    }
+
+    /// The dictionary the stream's blocks were compressed against, known
+    /// once the header is parsed.
+    pub(crate) fn dict(&self) -> Option<&Arc<[u8]>> {
+        self.dict.as_ref()
+    }
+
+    /// Whether the stream's blocks are chained, so that they only decode
+    /// in order; known once the header is parsed.
+    pub(crate) fn is_chained(&self) -> bool {
+        self.chain.is_some()
+    }
+
+    /// The metadata the stream carries, known once the header is parsed.
+    pub(crate) fn metadata(&self) -> Option<&FrameMetadata> {
+        self.metadata.as_deref()
+    }
+
+    /// Parse the header if it is due, returning whether it has been parsed
+    /// or more input is needed.
+    pub(crate) fn read_header(&mut self) -> LZOResult<bool> {
+        if self.state != State::Header {
+            return Ok(true);
+        }
+        let avail = &self.buf[self.pos..];
+        if avail.len() < HEADER_LEN || avail.len() < header_len(avail[5]) {
+            return Ok(false);
+        }
+        let flags = parse_header(avail)?;
+        if avail.len() < full_header_len(avail) {
+            return Ok(false);
+        }
+        self.checksum = checksum_from_flags(flags);
+        self.delta = delta_from_header(avail);
+        self.metadata = metadata_from_header(avail)?.map(Box::new);
+        self.dict = stream_dict(dict_from_header(avail)?, self.dict.as_ref())?;
+        self.chain = chain_from_header(avail, self.dict.as_deref())?;
+        // Blocks of a chained stream can't be rebuilt out of order, so its
+        // parity frames are passed over.
+        #[cfg(feature = "parity")]
+        if flags & FLAG_PARITY != 0 && self.chain.is_none() {
+            self.recovery = Some(crate::parity::Recovery::default());
+        }
+        self.pos += full_header_len(avail);
+        self.state = State::Block;
+        Ok(true)
+    }
+
+    /// Parse the header if it is due, then the next frame without consuming
+    /// it, or return `None` if more input is needed.
+    fn peek_frame(&mut self) -> LZOResult<Option<Frame>> {
+        if !self.read_header()? {
+            return Ok(None);
+        }
+        let avail = &self.buf[self.pos..];
+        if avail.len() < 4 {
+            return Ok(None);
+        }
+        if avail[..4] == [0u8; 4] {
+            return Ok(Some(Frame::End));
+        }
+        if avail.len() < BLOCK_HEADER_LEN {
+            return Ok(None);
+        }
+        if avail[..4] == PARITY_MARKER {
+            let header = ParityHeader::read_from(avail)?;
+            let len = header.frame_len();
+            return Ok((avail.len() >= len).then_some(Frame::Parity(header, len)));
+        }
+        let meta = BlockMeta::read_from(avail);
+        if meta.uncompressed_len as usize > MAX_BLOCK_SIZE
+            || meta.compressed_len as usize > crate::compress_bound(MAX_BLOCK_SIZE)
+        {
+            return Err(Error::InvalidHeader);
+        }
+        self.check_output(meta.uncompressed_len as u64)?;
+        let len = BLOCK_HEADER_LEN + meta.compressed_len as usize;
+        Ok((avail.len() >= len).then_some(Frame::Block(meta, len)))
+    }
+
+    /// Consume the end marker.
+    fn end(&mut self) -> LZOResult<()> {
+        #[cfg(feature = "parity")]
+        if let Some(recovery) = &self.recovery {
+            recovery.check_end()?;
+        }
+        self.pos += 4;
+        self.state = State::Done;
+        Ok(())
+    }
+
+    /// Decode the next block, or return `None` if more input is needed.
+    ///
+    /// Returns an empty block once the end marker has been read.
+    pub(crate) fn decode(&mut self, decompressor: &Decompressor) -> LZOResult<Option<Vec<u8>>> {
+        loop {
+            if self.state == State::Done {
+                return Ok(Some(Vec::new()));
+            }
+            let (meta, end) = match self.peek_frame()? {
+                None => return Ok(None),
+                Some(Frame::End) => {
+                    self.end()?;
+                    return Ok(Some(Vec::new()));
+                }
+                Some(Frame::Parity(_header, end)) => {
+                    #[cfg(feature = "parity")]
+                    if let Some(recovery) = &mut self.recovery {
+                        let parity = &self.buf[self.pos + BLOCK_HEADER_LEN..self.pos + end];
+                        let dict = self.dict.as_deref().unwrap_or_default();
+                        let rebuilt =
+                            recovery.parity(decompressor, &_header, parity, self.checksum, dict)?;
+                        self.pos += end;
+                        match rebuilt {
+                            Some(data) => return self.emit(data),
+                            None => continue,
+                        }
+                    }
+                    self.pos += end;
+                    continue;
+                }
+                Some(Frame::Block(meta, end)) => (meta, end),
+            };
+            let frame = &self.buf[self.pos..self.pos + end];
+            let dict = match &self.chain {
+                Some(chain) => chain.window(),
+                None => self.dict.as_deref().unwrap_or_default(),
+            };
+            let result = decode_block_with(
+                decompressor,
+                &meta,
+                &frame[BLOCK_HEADER_LEN..],
+                self.checksum,
+                dict,
+            );
+            if let (Some(chain), Ok(data)) = (&mut self.chain, &result) {
+                chain.push(data);
+            }
+            #[cfg(feature = "parity")]
+            if let Some(recovery) = &mut self.recovery {
+                let data = recovery.block(frame, result)?;
+                self.pos += end;
+                self.blocks += 1;
+                match data {
+                    Some(data) => return self.emit(data),
+                    None => continue,
+                }
+            }
+            let data = result?;
+            self.pos += end;
+            self.blocks += 1;
+            return self.emit(data);
+        }
+    }
+
+    /// Take the next block's header and payload without decompressing it,
+    /// skipping parity frames, or return `None` if more input is needed.
+    ///
+    /// The blocks' checksums are left to the caller, as is rebuilding
+    /// damaged blocks from parity.
+    pub(crate) fn next_raw(&mut self) -> LZOResult<Option<RawFrame>> {
+        loop {
+            if self.state == State::Done {
+                return Ok(Some(RawFrame::End));
+            }
+            match self.peek_frame()? {
+                None => return Ok(None),
+                Some(Frame::End) => {
+                    self.end()?;
+                    return Ok(Some(RawFrame::End));
+                }
+                Some(Frame::Parity(_, end)) => self.pos += end,
+                Some(Frame::Block(meta, end)) => {
+                    let payload = self.buf[self.pos + BLOCK_HEADER_LEN..self.pos + end].to_vec();
+                    self.pos += end;
+                    self.blocks += 1;
+                    self.produced += meta.uncompressed_len as u64;
+                    return Ok(Some(RawFrame::Block(meta, payload)));
+                }
+            }
+        }
+    }
+}
+
+/// The next frame in a `Decoder`'s buffer and its length.
+enum Frame {
+    Block(BlockMeta, usize),
+    Parity(ParityHeader, usize),
+    End,
+}
+
+/// A block as `Decoder::next_raw` hands it out, or the end of the stream.
+pub(crate) enum RawFrame {
+    Block(BlockMeta, Vec<u8>),
+    End,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_byte_by_byte() {
+        let mut lzo = LZO::new();
+        let input: Vec<u8> = (0..5000u32).map(|i| (i % 17) as u8).collect();
+        let mut stream = Vec::new();
+        encode_header(&mut stream);
+        encode_block(&mut lzo, &input[..3000], &mut stream).unwrap();
+        encode_block(&mut lzo, &input[3000..], &mut stream).unwrap();
+        encode_end(&mut stream);
+
+        let mut decoder = Decoder::new();
+        let mut output = Vec::new();
+        for byte in &stream {
+            decoder.feed(std::slice::from_ref(byte));
+            while let Some(block) = decoder.decode(&Decompressor::new().unwrap()).unwrap() {
+                if block.is_empty() {
+                    break;
+                }
+                output.extend_from_slice(&block);
+            }
+        }
+        assert!(decoder.is_done());
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_decoder_checksum_mismatch() {
+        let mut lzo = LZO::new();
+        let mut stream = Vec::new();
+        encode_header(&mut stream);
+        encode_block(&mut lzo, &[7u8; 100], &mut stream).unwrap();
+        stream[HEADER_LEN + 8] ^= 0xff;
+        let mut decoder = Decoder::new();
+        decoder.feed(&stream);
+        assert_eq!(
+            decoder.decode(&Decompressor::new().unwrap()),
+            Err(Error::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn test_checksum_flags() {
+        for checksum in [Checksum::Adler32, Checksum::Crc32, Checksum::None] {
+            let mut header = Vec::new();
+            encode_header_with_flags(&mut header, checksum_flags(checksum));
+            let flags = parse_header(&header).unwrap();
+            assert_eq!(checksum_from_flags(flags), checksum);
+        }
+        let mut header = Vec::new();
+        encode_header_with_flags(&mut header, FLAG_NO_CHECKSUM | FLAG_CRC32);
+        assert_eq!(parse_header(&header), Err(Error::InvalidHeader));
+    }
+
+    #[test]
+    fn test_forward_compatibility() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 71) as u8).collect();
+        let mut blocks = Vec::new();
+        encode_block(&mut LZO::new(), &input, &mut blocks).unwrap();
+        encode_end(&mut blocks);
+        // A stream from a newer writer, with features.
+        let stream = |version: u8, required: u32, optional: u32, extensions: &[u8]| {
+            let mut stream = Vec::new();
+            encode_header_with_flags(&mut stream, FLAG_FEATURES);
+            stream[4] = version;
+            for word in [required, optional, extensions.len() as u32] {
+                stream.extend_from_slice(&word.to_be_bytes());
+            }
+            stream.extend_from_slice(extensions);
+            stream.extend_from_slice(&blocks);
+            stream
+        };
+
+        // Optional features and their extension data are passed over.
+        let optional = stream(VERSION, 0, 0x8000_0001, b"future data");
+        assert_eq!(crate::decompress_chunked(&optional).unwrap(), input);
+        let mut decoder = Decoder::new();
+        let mut output = Vec::new();
+        for byte in &optional {
+            decoder.feed(std::slice::from_ref(byte));
+            while let Some(block) = decoder.decode(&Decompressor::new().unwrap()).unwrap() {
+                output.extend_from_slice(&block);
+                if block.is_empty() {
+                    break;
+                }
+            }
+        }
+        assert!(decoder.is_done());
+        assert_eq!(output, input);
+
+        // Unknown required features and versions are refused up front.
+        let required = stream(VERSION, 0x0000_0104, 0, b"");
+        assert_eq!(
+            crate::decompress_chunked(&required),
+            Err(Error::UnsupportedFeatures {
+                features: 0x0000_0104
+            })
+        );
+        let mut decoder = Decoder::new();
+        decoder.feed(&required);
+        assert_eq!(
+            decoder.decode(&Decompressor::new().unwrap()),
+            Err(Error::UnsupportedFeatures {
+                features: 0x0000_0104
+            })
+        );
+        assert_eq!(
+            crate::decompress_chunked(stream(2, 0, 0, b"")),
+            Err(Error::UnsupportedVersion { version: 2 })
+        );
+        let mut unknown_flag = stream(VERSION, 0, 0, b"");
+        unknown_flag[5] |= 0x80;
+        assert_eq!(parse_header(&unknown_flag), Err(Error::InvalidHeader));
+
+        // A cut-off extension section isn't read past.
+        let cut = stream(VERSION, 0, 1, &[0; 100]);
+        assert_eq!(
+            crate::decompress_chunked(&cut[..HEADER_LEN + 12 + 50]),
+            Err(Error::EOFNotFound)
+        );
+    }
+
+    #[test]
+    fn test_incompressible_block_is_stored() {
+        let mut state = 7u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect();
+        let mut lzo = LZO::new();
+        assert!(lzo.compress(&noise).unwrap().len() > noise.len());
+
+        let mut stream = Vec::new();
+        encode_header(&mut stream);
+        encode_block(&mut lzo, &noise, &mut stream).unwrap();
+        encode_end(&mut stream);
+        assert_eq!(
+            stream.len(),
+            HEADER_LEN + BLOCK_HEADER_LEN + noise.len() + 4
+        );
+        assert_eq!(
+            &stream[HEADER_LEN + BLOCK_HEADER_LEN..][..noise.len()],
+            &noise[..]
+        );
+
+        let mut decoder = Decoder::new();
+        decoder.feed(&stream);
+        let decompressor = Decompressor::new().unwrap();
+        assert_eq!(decoder.decode(&decompressor).unwrap(), Some(noise));
+    }
+}
@@ -0,0 +1,195 @@
+//! `futures::io` `AsyncWrite`/`AsyncRead` adapters over the framed format,
+//! for async-std, smol and other runtimes without tokio.
+//!
+//! These produce and consume exactly the same streams as `LzoWriter` and
+//! `LzoReader`, and work like the tokio adapters: blocks are compressed
+//! inline in the poll functions, which is cheap enough at the default
+//! block size not to need a blocking task.
+
+use crate::frame;
+use crate::{LZOResult, StreamDecoder, StreamEncoder};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Compresses everything written to it into a framed stream.
+///
+/// `poll_close` writes the end marker before closing the inner writer.
+///
+/// At most one block of input and its compressed form are held: input is
+/// only taken while no compressed data is waiting for the inner writer, so
+/// while a slow inner writer catches up, writes return `Poll::Pending`.
+pub struct FuturesLzoWriter<W> {
+    inner: W,
+    encoder: StreamEncoder,
+}
+
+impl<W: AsyncWrite + Unpin> FuturesLzoWriter<W> {
+    /// Create a writer using `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn new(inner: W) -> LZOResult<Self> {
+        Ok(FuturesLzoWriter {
+            inner,
+            encoder: StreamEncoder::new()?,
+        })
+    }
+
+    /// Create a writer that compresses `block_size` bytes at a time.
+    pub fn with_block_size(inner: W, block_size: usize) -> LZOResult<Self> {
+        Ok(FuturesLzoWriter {
+            inner,
+            encoder: StreamEncoder::with_block_size(block_size)?,
+        })
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Unwrap the writer, returning the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.encoder.pending_output().is_empty() {
+            let pending = self.encoder.pending_output();
+            let n = match Pin::new(&mut self.inner).poll_write(cx, pending) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.encoder.consume_output(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for FuturesLzoWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.encoder.is_finished() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        if !this.encoder.pending_output().is_empty() {
+            return Poll::Pending;
+        }
+        Poll::Ready(this.encoder.feed_input(buf).map_err(frame::to_io))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.encoder.finish().map_err(frame::to_io)?;
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+/// Decompresses a framed stream read from the inner reader.
+pub struct FuturesLzoReader<R> {
+    inner: R,
+    decoder: StreamDecoder,
+}
+
+impl<R: AsyncRead + Unpin> FuturesLzoReader<R> {
+    /// Create a reader decoding the framed stream in `inner`.
+    pub fn new(inner: R) -> LZOResult<Self> {
+        Ok(FuturesLzoReader {
+            inner,
+            decoder: StreamDecoder::new()?,
+        })
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` once the stream would
+    /// decompress to more than `limit` bytes; see
+    /// `LzoReader::max_output_size`.
+    pub fn max_output_size(self, limit: u64) -> Self {
+        FuturesLzoReader {
+            inner: self.inner,
+            decoder: self.decoder.max_output_size(limit),
+        }
+    }
+
+    /// Unwrap the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for FuturesLzoReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut chunk = [0u8; 8192];
+        loop {
+            let read = this.decoder.read_output(buf);
+            let n = read.map_err(|e| this.decoder.locate(e))?;
+            if n > 0 || buf.is_empty() || this.decoder.is_done() {
+                return Poll::Ready(Ok(n));
+            }
+            let n = match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.decoder.feed_input(&chunk[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_futures_roundtrip() {
+        let input: Vec<u8> = (0..100_000u32).map(|i| (i % 89) as u8).collect();
+        let framed = block_on(async {
+            let mut writer = FuturesLzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+            writer.write_all(&input).await.unwrap();
+            writer.close().await.unwrap();
+            writer.into_inner()
+        });
+
+        let mut serial = crate::LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        std::io::Write::write_all(&mut serial, &input).unwrap();
+        assert_eq!(framed, serial.finish().unwrap());
+
+        let output = block_on(async {
+            let mut reader = FuturesLzoReader::new(&framed[..]).unwrap();
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).await.unwrap();
+            output
+        });
+        assert_eq!(output, input);
+    }
+}
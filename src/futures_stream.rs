@@ -0,0 +1,193 @@
+//! `futures` `Stream`/`Sink` adapters compressing one chunk at a time.
+//!
+//! Every chunk becomes one message in the same wire format `LzoCodec` uses,
+//! so the compressed side can be split and rejoined arbitrarily by the
+//! transport: `DecompressStream` reassembles messages across chunk
+//! boundaries.
+
+use crate::frame;
+use crate::{Decompressor, LZOResult, LZO, MAX_BLOCK_SIZE};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Compresses each chunk of the inner stream into one message.
+pub struct CompressStream<S> {
+    inner: S,
+    lzo: LZO,
+}
+
+impl<S: Stream<Item = Bytes> + Unpin> CompressStream<S> {
+    /// Wrap a stream of uncompressed chunks.
+    pub fn new(inner: S) -> LZOResult<Self> {
+        Ok(CompressStream {
+            inner,
+            lzo: LZO::new(),
+        })
+    }
+}
+
+impl<S: Stream<Item = Bytes> + Unpin> Stream for CompressStream<S> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(chunk)) => {
+                let mut out = Vec::new();
+                let result = frame::encode_message(&mut this.lzo, &chunk, &mut out)
+                    .map(|_| Bytes::from(out))
+                    .map_err(frame::to_io);
+                Poll::Ready(Some(result))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Decompresses a stream of messages, however the transport chunked them.
+pub struct DecompressStream<S> {
+    inner: S,
+    decompressor: Decompressor,
+    buf: BytesMut,
+    done: bool,
+}
+
+impl<S: Stream<Item = Bytes> + Unpin> DecompressStream<S> {
+    /// Wrap a stream of compressed bytes.
+    pub fn new(inner: S) -> LZOResult<Self> {
+        Ok(DecompressStream {
+            inner,
+            decompressor: Decompressor::new()?,
+            buf: BytesMut::new(),
+            done: false,
+        })
+    }
+}
+
+impl<S: Stream<Item = Bytes> + Unpin> Stream for DecompressStream<S> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match frame::decode_message(&this.decompressor, &this.buf, MAX_BLOCK_SIZE) {
+                Ok(Some((message, consumed))) => {
+                    this.buf.advance(consumed);
+                    return Poll::Ready(Some(Ok(Bytes::from(message))));
+                }
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(frame::to_io(e)))),
+            }
+            if this.done {
+                if this.buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                this.buf.clear();
+                return Poll::Ready(Some(Err(io::ErrorKind::UnexpectedEof.into())));
+            }
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(chunk)) => this.buf.extend_from_slice(&chunk),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Compresses each item before forwarding it to the inner sink.
+pub struct CompressSink<Si> {
+    inner: Si,
+    lzo: LZO,
+}
+
+impl<Si: Sink<Bytes, Error = io::Error> + Unpin> CompressSink<Si> {
+    /// Wrap a sink of compressed messages.
+    pub fn new(inner: Si) -> LZOResult<Self> {
+        Ok(CompressSink {
+            inner,
+            lzo: LZO::new(),
+        })
+    }
+
+    /// Unwrap the sink, returning the inner sink.
+    pub fn into_inner(self) -> Si {
+        self.inner
+    }
+}
+
+impl<Si: Sink<Bytes, Error = io::Error> + Unpin> Sink<Bytes> for CompressSink<Si> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> io::Result<()> {
+        let this = self.get_mut();
+        let mut out = Vec::new();
+        frame::encode_message(&mut this.lzo, &item, &mut out).map_err(frame::to_io)?;
+        Pin::new(&mut this.inner).start_send(Bytes::from(out))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::{stream, SinkExt, StreamExt, TryStreamExt};
+
+    #[test]
+    fn test_stream_roundtrip_rechunked() {
+        let chunks = vec![
+            Bytes::from_static(b"alpha alpha alpha"),
+            Bytes::new(),
+            Bytes::from(vec![9u8; 70_000]),
+        ];
+        let compressed: Vec<Bytes> = block_on(
+            CompressStream::new(stream::iter(chunks.clone()))
+                .unwrap()
+                .try_collect(),
+        )
+        .unwrap();
+        let wire: Vec<u8> = compressed.concat();
+        let rechunked: Vec<Bytes> = wire.chunks(7).map(Bytes::copy_from_slice).collect();
+
+        let decoded: Vec<Bytes> = block_on(
+            DecompressStream::new(stream::iter(rechunked))
+                .unwrap()
+                .try_collect(),
+        )
+        .unwrap();
+        assert_eq!(decoded, chunks);
+    }
+
+    #[test]
+    fn test_sink_and_truncated_stream() {
+        let (tx, rx) = futures::channel::mpsc::unbounded::<Bytes>();
+        let mut sink =
+            CompressSink::new(tx.sink_map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe)))
+                .unwrap();
+        block_on(sink.send(Bytes::from_static(b"payload"))).unwrap();
+        drop(sink);
+        let wire: Vec<Bytes> = block_on(rx.collect());
+        let mut truncated = wire[0].clone();
+        truncated.truncate(truncated.len() - 1);
+
+        let mut decoded = DecompressStream::new(stream::iter(vec![truncated])).unwrap();
+        let err = block_on(decoded.next()).unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,135 @@
+//! `Arbitrary` implementations for fuzzing (`arbitrary` feature).
+//!
+//! Fuzzers feeding the decoders raw bytes spend nearly all their time
+//! failing the magic check. The types here generate only structurally valid
+//! values instead: `LzoBuilder` always builds, `FrameHeader` always parses,
+//! and `BlockMeta` has lengths the decoders accept, so a fuzz target can
+//! assemble streams that get past the framing and into the blocks.
+//!
+//! Example
+//!
+//! ```rust
+//! use arbitrary::{Arbitrary, Unstructured};
+//! use minilzo_rs::LzoBuilder;
+//!
+//! // In a fuzz target, `bytes` is the fuzzer's input.
+//! let bytes = [7u8; 64];
+//! let mut u = Unstructured::new(&bytes);
+//! let mut lzo = LzoBuilder::arbitrary(&mut u).unwrap().build().unwrap();
+//! let data = <Vec<u8>>::arbitrary(&mut u).unwrap();
+//! let framed = lzo.compress_chunked(&data).unwrap();
+//! assert_eq!(minilzo_rs::decompress_chunked(&framed).unwrap(), data);
+//! ```
+
+use crate::frame::{self, FLAG_DELTA, FLAG_PARITY};
+use crate::{Algorithm, BlockMeta, Checksum, LzoBuilder, DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// The header of a framed stream, as the decoders accept it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameHeader {
+    /// The checksum the stream's blocks carry.
+    pub checksum: Checksum,
+    /// The stride of the stream's delta filter, 1 to 256.
+    pub delta: Option<usize>,
+    /// Whether the stream says it carries parity frames.
+    pub parity: bool,
+}
+
+impl FrameHeader {
+    /// The header's encoding, which starts a stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = frame::checksum_flags(self.checksum);
+        if self.parity {
+            flags |= FLAG_PARITY;
+        }
+        let mut out = Vec::new();
+        match self.delta {
+            Some(stride) => {
+                frame::encode_header_with_flags(&mut out, flags | FLAG_DELTA);
+                out.push((stride - 1) as u8);
+            }
+            None => frame::encode_header_with_flags(&mut out, flags),
+        }
+        out
+    }
+}
+
+fn arbitrary_checksum(u: &mut Unstructured<'_>) -> Result<Checksum> {
+    u.choose(&[Checksum::Adler32, Checksum::Crc32, Checksum::None])
+        .copied()
+}
+
+fn arbitrary_delta(u: &mut Unstructured<'_>) -> Result<Option<usize>> {
+    Ok(match bool::arbitrary(u)? {
+        true => Some(u.int_in_range(1..=crate::delta::MAX_STRIDE)?),
+        false => None,
+    })
+}
+
+impl<'a> Arbitrary<'a> for FrameHeader {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(FrameHeader {
+            checksum: arbitrary_checksum(u)?,
+            delta: arbitrary_delta(u)?,
+            parity: bool::arbitrary(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for BlockMeta {
+    /// A block header with a length the decoders accept and a payload no
+    /// longer than the data; the checksum is arbitrary.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let uncompressed_len = u.int_in_range(1..=MAX_BLOCK_SIZE as u32)?;
+        Ok(BlockMeta {
+            uncompressed_len,
+            compressed_len: u.int_in_range(1..=uncompressed_len)?,
+            checksum: u32::arbitrary(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for LzoBuilder {
+    /// A configuration that builds, with blocks of up to
+    /// `DEFAULT_BLOCK_SIZE` to keep fuzz iterations fast.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let algorithm = *u.choose(&[Algorithm::Lzo1x1, Algorithm::Auto])?;
+        let mut builder = LzoBuilder::default()
+            .algorithm(algorithm)
+            .checksum(arbitrary_checksum(u)?)
+            .block_size(u.int_in_range(1..=DEFAULT_BLOCK_SIZE)?);
+        if let Some(stride) = arbitrary_delta(u)? {
+            builder = builder.delta(stride);
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary_values_are_valid() {
+        let bytes: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut u = Unstructured::new(&bytes);
+        for _ in 0..100 {
+            let header = FrameHeader::arbitrary(&mut u).unwrap();
+            let encoded = header.to_bytes();
+            let flags = frame::parse_header(&encoded).unwrap();
+            assert_eq!(encoded.len(), frame::header_len(flags));
+            assert_eq!(frame::checksum_from_flags(flags), header.checksum);
+            assert_eq!(frame::delta_from_header(&encoded), header.delta);
+
+            let meta = BlockMeta::arbitrary(&mut u).unwrap();
+            assert!(meta.uncompressed_len as usize <= MAX_BLOCK_SIZE);
+            assert!((1..=meta.uncompressed_len).contains(&meta.compressed_len));
+
+            let lzo = LzoBuilder::arbitrary(&mut u).unwrap().build().unwrap();
+            assert!(lzo.block_size() <= DEFAULT_BLOCK_SIZE);
+        }
+    }
+}
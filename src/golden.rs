@@ -0,0 +1,130 @@
+//! Interop fixtures for decoders written in other languages.
+//!
+//! `generate` writes a fixed set of canonical inputs and their compressed
+//! forms in every format this crate produces. The output is deterministic,
+//! so fixtures can be regenerated and diffed after any change here.
+
+use crate::frame::to_io;
+use crate::{adler32, LzoWriter, LzopWriter, LZO};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Output formats, with the file extension used for each.
+const FORMATS: [&str; 4] = ["lzo1x_1", "mlzo", "lzop", "pylzo"];
+
+/// The canonical inputs, by name.
+pub fn inputs() -> Vec<(&'static str, Vec<u8>)> {
+    let mut state = 0x2545_f491u32;
+    let noise = (0..65_536)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let text = b"The quick brown fox jumps over the lazy dog. "
+        .iter()
+        .cycle()
+        .take(10_000)
+        .copied()
+        .collect();
+    vec![
+        ("empty", Vec::new()),
+        ("one_byte", vec![0x61]),
+        ("zeros_64k", vec![0u8; 65_536]),
+        ("text_10k", text),
+        ("noise_64k", noise),
+        (
+            "ramp_300k",
+            (0..300_000u32).map(|i| (i % 251) as u8).collect(),
+        ),
+    ]
+}
+
+fn encode(lzo: &mut LZO, format: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    match format {
+        "lzo1x_1" => lzo.compress(data).map_err(to_io),
+        "mlzo" => {
+            let mut writer = LzoWriter::new(Vec::new()).map_err(to_io)?;
+            writer.write_all(data)?;
+            writer.finish()
+        }
+        "lzop" => {
+            let mut writer = LzopWriter::new(Vec::new()).map_err(to_io)?;
+            writer.write_all(data)?;
+            writer.finish()
+        }
+        "pylzo" => lzo.compress_pylzo(data).map_err(to_io),
+        _ => unreachable!(),
+    }
+}
+
+/// Write the golden corpus to `dir`, creating it if needed.
+///
+/// For every input `<name>` this writes `<name>.bin` and one
+/// `<name>.<format>` file per format (`lzo1x_1` raw, `mlzo` framed, `lzop`,
+/// `pylzo`), plus a `MANIFEST` listing each input's length and adler32.
+/// Returns the paths written.
+///
+/// Example
+///
+/// ```rust
+/// let dir = std::env::temp_dir().join("minilzo-rs-golden-doc");
+/// let files = minilzo_rs::golden::generate(&dir).unwrap();
+/// assert!(files.iter().any(|f| f.ends_with("text_10k.lzop")));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn generate<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let mut lzo = LZO::new();
+    let mut written = Vec::new();
+    let mut manifest = String::from("# name length adler32\n");
+    for (name, data) in inputs() {
+        manifest.push_str(&format!("{} {} {:08x}\n", name, data.len(), adler32(&data)));
+        let path = dir.join(format!("{}.bin", name));
+        fs::write(&path, &data)?;
+        written.push(path);
+        for format in FORMATS {
+            let path = dir.join(format!("{}.{}", name, format));
+            fs::write(&path, encode(&mut lzo, format, &data)?)?;
+            written.push(path);
+        }
+    }
+    let path = dir.join("MANIFEST");
+    fs::write(&path, manifest)?;
+    written.push(path);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_golden_roundtrip_and_deterministic() {
+        let dir = std::env::temp_dir().join("minilzo-rs-golden-test");
+        let first = generate(&dir).unwrap();
+        let snapshot: Vec<Vec<u8>> = first.iter().map(|p| fs::read(p).unwrap()).collect();
+        let second = generate(&dir).unwrap();
+        assert_eq!(first, second);
+        for (path, bytes) in second.iter().zip(&snapshot) {
+            assert_eq!(&fs::read(path).unwrap(), bytes);
+        }
+
+        for (name, data) in inputs() {
+            let mut out = Vec::new();
+            crate::open_auto(dir.join(format!("{}.mlzo", name)))
+                .unwrap()
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out, data);
+            let blob = fs::read(dir.join(format!("{}.pylzo", name))).unwrap();
+            assert_eq!(LZO::new().decompress_pylzo(&blob).unwrap(), data);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! A tonic message codec compressing protobuf messages (`tonic` feature).
+//!
+//! tonic negotiates `grpc-encoding` only among the codings it ships (gzip,
+//! deflate, zstd), with no way to register another, so LZO is chosen per
+//! service instead: point tonic-build's `codec_path` at `LzoProstCodec`
+//! on both ends, and every message travels as one block of the message
+//! format `LzoCodec` uses, with its own adler32. Messages are compressed
+//! with the same per-thread work memory as `compress`, so no work memory is
+//! allocated per message or per call.
+
+use crate::frame;
+use crate::{Decompressor, MAX_BLOCK_SIZE};
+use bytes::{Buf, BufMut};
+use prost::Message;
+use std::fmt;
+use std::marker::PhantomData;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::Status;
+
+/// Encodes `T` and decodes `U` as LZO-compressed protobuf.
+///
+/// Example
+///
+/// ```rust
+/// use futures::stream;
+/// use minilzo_rs::LzoProstCodec;
+/// use tonic::codec::{Codec, EncodeBody, Streaming};
+///
+/// let mut codec = LzoProstCodec::<String, String>::default();
+/// let messages = vec![Ok("a".repeat(10_000)), Ok(String::new())];
+/// let body = EncodeBody::new_client(codec.encoder(), stream::iter(messages), None, None);
+///
+/// let mut received = Streaming::new_request(codec.decoder(), body, None, None);
+/// futures::executor::block_on(async {
+///     assert_eq!(received.message().await.unwrap().unwrap(), "a".repeat(10_000));
+///     assert_eq!(received.message().await.unwrap().unwrap(), "");
+///     assert!(received.message().await.unwrap().is_none());
+/// });
+/// ```
+pub struct LzoProstCodec<T, U> {
+    _marker: PhantomData<(T, U)>,
+}
+
+impl<T, U> Default for LzoProstCodec<T, U> {
+    fn default() -> Self {
+        LzoProstCodec {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U> fmt::Debug for LzoProstCodec<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LzoProstCodec").finish()
+    }
+}
+
+impl<T, U> Codec for LzoProstCodec<T, U>
+where
+    T: Message + Send + 'static,
+    U: Message + Default + Send + 'static,
+{
+    type Encode = T;
+    type Decode = U;
+    type Encoder = LzoProstEncoder<T>;
+    type Decoder = LzoProstDecoder<U>;
+
+    fn encoder(&mut self) -> LzoProstEncoder<T> {
+        LzoProstEncoder {
+            message: Vec::new(),
+            out: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn decoder(&mut self) -> LzoProstDecoder<U> {
+        LzoProstDecoder {
+            decompressor: Decompressor::new().expect("minilzo self-check failed"),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// The encoder of `LzoProstCodec`, reusing its buffers across the
+/// messages of a call.
+pub struct LzoProstEncoder<T> {
+    message: Vec<u8>,
+    out: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Message> Encoder for LzoProstEncoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn encode(&mut self, item: T, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        self.message.clear();
+        self.out.clear();
+        item.encode(&mut self.message)
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let (message, out) = (&self.message, &mut self.out);
+        crate::with_thread_lzo(|lzo| frame::encode_message(lzo, message, out))
+            .map_err(|err| Status::internal(err.to_string()))?;
+        dst.reserve(self.out.len());
+        dst.put_slice(&self.out);
+        Ok(())
+    }
+}
+
+/// The decoder of `LzoProstCodec`. A message that fails its checksum or
+/// doesn't decompress is reported as `Code::DataLoss`.
+pub struct LzoProstDecoder<U> {
+    decompressor: Decompressor,
+    _marker: PhantomData<U>,
+}
+
+impl<U: Message + Default> Decoder for LzoProstDecoder<U> {
+    type Item = U;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<U>, Status> {
+        let buf = src.copy_to_bytes(src.remaining());
+        let (message, consumed) =
+            match frame::decode_message(&self.decompressor, &buf, MAX_BLOCK_SIZE) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => return Err(Status::data_loss("truncated LZO message")),
+                Err(err) => return Err(Status::data_loss(err.to_string())),
+            };
+        if consumed != buf.len() {
+            return Err(Status::data_loss("trailing data after LZO message"));
+        }
+        U::decode(&message[..])
+            .map(Some)
+            .map_err(|err| Status::internal(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use tonic::codec::{EncodeBody, Streaming};
+    use tonic::Code;
+
+    /// Sends its items as they are, as messages another encoder produced.
+    struct Raw;
+
+    impl Encoder for Raw {
+        type Item = Vec<u8>;
+        type Error = Status;
+
+        fn encode(&mut self, item: Vec<u8>, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+            dst.put_slice(&item);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_grpc_codec() {
+        let mut codec = LzoProstCodec::<Vec<u8>, Vec<u8>>::default();
+        let messages: Vec<Vec<u8>> = vec![
+            (0..200_000u32).map(|i| (i / 9) as u8).collect(),
+            Vec::new(),
+            b"tiny".to_vec(),
+        ];
+        let body = EncodeBody::new_client(
+            codec.encoder(),
+            stream::iter(messages.clone().into_iter().map(Ok)),
+            None,
+            None,
+        );
+        let mut received = Streaming::new_request(codec.decoder(), body, None, None);
+        futures::executor::block_on(async {
+            for message in &messages {
+                assert_eq!(received.message().await.unwrap().as_ref(), Some(message));
+            }
+            assert!(received.message().await.unwrap().is_none());
+        });
+
+        // Damaged and truncated messages are reported as lost data.
+        let mut framed = Vec::new();
+        crate::with_thread_lzo(|lzo| {
+            frame::encode_message(lzo, b"hello, hello, hello", &mut framed)
+        })
+        .unwrap();
+        let mut damaged = framed.clone();
+        damaged[framed.len() - 1] ^= 0x01;
+        for wire in [damaged, framed[..framed.len() - 1].to_vec()] {
+            let body = EncodeBody::new_client(Raw, stream::iter(vec![Ok(wire)]), None, None);
+            let mut received = Streaming::new_request(codec.decoder(), body, None, None);
+            let err = futures::executor::block_on(received.message()).unwrap_err();
+            assert_eq!(err.code(), Code::DataLoss);
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! `x-lzo` HTTP content coding as tower middleware (`tower` feature).
+//!
+//! `LzoLayer` decompresses request bodies sent with
+//! `Content-Encoding: x-lzo` and compresses response bodies for clients
+//! whose `Accept-Encoding` lists it. Bodies are coded as they stream, one
+//! framed stream per body: a block is sent whenever the inner body stalls,
+//! so long-lived responses aren't held back until a block fills. `LzoBody`
+//! can also be used on its own, say by a client wrapping its request body.
+
+use crate::{Error, StreamDecoder, StreamEncoder};
+use bytes::{Buf, Bytes};
+use http::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY,
+};
+use http::{Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// The content-coding token, for `Content-Encoding` and `Accept-Encoding`.
+pub const LZO_CONTENT_CODING: &str = "x-lzo";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Adds `x-lzo` content coding to a service: see `LzoService`.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::LzoLayer;
+/// use tower_layer::Layer;
+///
+/// # #[derive(Clone)]
+/// # struct App;
+/// let service = LzoLayer::new().decompress_requests(false).layer(App);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct LzoLayer {
+    compress_responses: bool,
+    decompress_requests: bool,
+}
+
+impl LzoLayer {
+    /// A layer that compresses responses and decompresses requests.
+    pub fn new() -> Self {
+        LzoLayer {
+            compress_responses: true,
+            decompress_requests: true,
+        }
+    }
+
+    /// Compress responses for clients that accept `x-lzo` (the default).
+    pub fn compress_responses(mut self, enable: bool) -> Self {
+        self.compress_responses = enable;
+        self
+    }
+
+    /// Decompress `x-lzo` request bodies (the default). When off, such
+    /// requests reach the service with their body and headers untouched.
+    pub fn decompress_requests(mut self, enable: bool) -> Self {
+        self.decompress_requests = enable;
+        self
+    }
+}
+
+impl Default for LzoLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for LzoLayer {
+    type Service = LzoService<S>;
+
+    fn layer(&self, inner: S) -> LzoService<S> {
+        LzoService {
+            inner,
+            layer: *self,
+        }
+    }
+}
+
+/// A service decoding `x-lzo` requests and encoding responses as `x-lzo`.
+///
+/// A request with `Content-Encoding: x-lzo` reaches the inner service with
+/// the body decompressed and `Content-Encoding` and `Content-Length`
+/// removed. A response is compressed when the request's `Accept-Encoding`
+/// lists `x-lzo` with a non-zero weight and the response has no
+/// `Content-Encoding` of its own; it gets `Content-Encoding: x-lzo` and
+/// `Vary: accept-encoding`, and loses `Content-Length`. Damaged request
+/// bodies surface as body errors holding an `Error`.
+///
+/// Example
+///
+/// ```rust
+/// use bytes::Bytes;
+/// use http::{Request, Response};
+/// use http_body::{Body, Frame};
+/// use minilzo_rs::{LzoBody, LzoLayer};
+/// use std::convert::Infallible;
+/// use std::future::{ready, Ready};
+/// use std::pin::Pin;
+/// use std::task::{Context, Poll};
+/// use tower_layer::Layer;
+/// use tower_service::Service;
+///
+/// /// A body of one chunk.
+/// struct Once(Option<Bytes>);
+///
+/// impl Body for Once {
+///     type Data = Bytes;
+///     type Error = Infallible;
+///
+///     fn poll_frame(
+///         mut self: Pin<&mut Self>,
+///         _: &mut Context<'_>,
+///     ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+///         Poll::Ready(self.0.take().map(|data| Ok(Frame::data(data))))
+///     }
+/// }
+///
+/// /// Answers every request with a kilobyte of zeros.
+/// struct App;
+///
+/// impl Service<Request<LzoBody<Once>>> for App {
+///     type Response = Response<Once>;
+///     type Error = Infallible;
+///     type Future = Ready<Result<Response<Once>, Infallible>>;
+///
+///     fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+///         Poll::Ready(Ok(()))
+///     }
+///
+///     fn call(&mut self, _: Request<LzoBody<Once>>) -> Self::Future {
+///         ready(Ok(Response::new(Once(Some(Bytes::from(vec![0; 1024]))))))
+///     }
+/// }
+///
+/// let mut service = LzoLayer::new().layer(App);
+/// let request = Request::builder()
+///     .header("accept-encoding", "gzip, x-lzo")
+///     .body(Once(None))
+///     .unwrap();
+/// let response = futures::executor::block_on(service.call(request)).unwrap();
+/// assert_eq!(response.headers()["content-encoding"], "x-lzo");
+///
+/// let mut body = response.into_body();
+/// let mut framed = Vec::new();
+/// while let Some(frame) = futures::executor::block_on(std::future::poll_fn(|cx| {
+///     Pin::new(&mut body).poll_frame(cx)
+/// })) {
+///     framed.extend_from_slice(frame.unwrap().data_ref().unwrap());
+/// }
+/// assert_eq!(minilzo_rs::decompress_chunked(&framed).unwrap(), vec![0; 1024]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct LzoService<S> {
+    inner: S,
+    layer: LzoLayer,
+}
+
+impl<S> LzoService<S> {
+    /// Wrap `inner`, compressing responses and decompressing requests.
+    pub fn new(inner: S) -> Self {
+        LzoLayer::new().layer(inner)
+    }
+
+    /// The wrapped service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwrap the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for LzoService<S>
+where
+    S: Service<Request<LzoBody<ReqBody>>, Response = Response<ResBody>>,
+{
+    type Response = Response<LzoBody<ResBody>>;
+    type Error = S::Error;
+    type Future = LzoResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let compress = self.layer.compress_responses && accepts_lzo(request.headers());
+        let (mut parts, body) = request.into_parts();
+        let body = if self.layer.decompress_requests && is_lzo(&parts.headers) {
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            LzoBody::decompress(body)
+        } else {
+            LzoBody::identity(body)
+        };
+        LzoResponseFuture {
+            inner: self.inner.call(Request::from_parts(parts, body)),
+            compress,
+        }
+    }
+}
+
+pin_project! {
+    /// The response future of `LzoService`.
+    pub struct LzoResponseFuture<F> {
+        #[pin]
+        inner: F,
+        compress: bool,
+    }
+}
+
+impl<F, B, E> Future for LzoResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<LzoBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = match this.inner.poll(cx) {
+            Poll::Ready(response) => response?,
+            Poll::Pending => return Poll::Pending,
+        };
+        let (mut parts, body) = response.into_parts();
+        let body = if *this.compress && !parts.headers.contains_key(CONTENT_ENCODING) {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(LZO_CONTENT_CODING),
+            );
+            parts
+                .headers
+                .append(VARY, HeaderValue::from_static("accept-encoding"));
+            LzoBody::compress(body)
+        } else {
+            LzoBody::identity(body)
+        };
+        Poll::Ready(Ok(Response::from_parts(parts, body)))
+    }
+}
+
+/// Whether `headers` say the body is `x-lzo` coded.
+fn is_lzo(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case(LZO_CONTENT_CODING))
+}
+
+/// Whether an `Accept-Encoding` in `headers` lists `x-lzo` with a
+/// non-zero weight.
+fn accepts_lzo(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or("").trim();
+            let weight = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .next()
+                .unwrap_or(1.0);
+            coding.eq_ignore_ascii_case(LZO_CONTENT_CODING) && weight > 0.0
+        })
+}
+
+enum Mode {
+    Identity,
+    Compress(Option<StreamEncoder>),
+    Decompress(Option<StreamDecoder>),
+}
+
+pin_project! {
+    /// An HTTP body passed through, compressed into an `x-lzo` framed
+    /// stream, or decompressed from one.
+    ///
+    /// Trailers of the inner body are passed on after the last data frame.
+    /// Errors are boxed: the inner body's own, or an `Error` when
+    /// compression fails or the stream being decompressed is damaged,
+    /// truncated or followed by more data.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use bytes::Bytes;
+    /// use http_body::{Body, Frame};
+    /// use minilzo_rs::LzoBody;
+    /// use std::convert::Infallible;
+    /// use std::pin::Pin;
+    /// use std::task::{Context, Poll};
+    ///
+    /// /// A body of one chunk.
+    /// struct Once(Option<Bytes>);
+    ///
+    /// impl Body for Once {
+    ///     type Data = Bytes;
+    ///     type Error = Infallible;
+    ///
+    ///     fn poll_frame(
+    ///         mut self: Pin<&mut Self>,
+    ///         _: &mut Context<'_>,
+    ///     ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+    ///         Poll::Ready(self.0.take().map(|data| Ok(Frame::data(data))))
+    ///     }
+    /// }
+    ///
+    /// let framed = minilzo_rs::LZO::new().compress_chunked(b"ping").unwrap();
+    /// let mut body = LzoBody::decompress(Once(Some(framed.into())));
+    /// let frame = futures::executor::block_on(std::future::poll_fn(|cx| {
+    ///     Pin::new(&mut body).poll_frame(cx)
+    /// }));
+    /// assert_eq!(frame.unwrap().unwrap().into_data().unwrap(), "ping");
+    /// ```
+    pub struct LzoBody<B> {
+        #[pin]
+        inner: B,
+        mode: Mode,
+        input: Bytes,
+        trailers: Option<HeaderMap>,
+        inner_done: bool,
+    }
+}
+
+impl<B> LzoBody<B> {
+    fn with_mode(inner: B, mode: Mode) -> Self {
+        LzoBody {
+            inner,
+            mode,
+            input: Bytes::new(),
+            trailers: None,
+            inner_done: false,
+        }
+    }
+
+    /// Pass `inner` through unchanged.
+    pub fn identity(inner: B) -> Self {
+        Self::with_mode(inner, Mode::Identity)
+    }
+
+    /// Compress `inner` into a framed stream of `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn compress(inner: B) -> Self {
+        Self::with_mode(inner, Mode::Compress(None))
+    }
+
+    /// Decompress the framed stream `inner` carries.
+    pub fn decompress(inner: B) -> Self {
+        Self::with_mode(inner, Mode::Decompress(None))
+    }
+
+    /// Unwrap the inner body.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+/// Take in the next frame of an inner body: data into `input`, trailers
+/// into `trailers`, and the end into `inner_done`.
+fn take_frame<D: Buf, E: Into<BoxError>>(
+    frame: Option<Result<Frame<D>, E>>,
+    input: &mut Bytes,
+    trailers: &mut Option<HeaderMap>,
+    inner_done: &mut bool,
+) -> Result<(), BoxError> {
+    match frame {
+        Some(Ok(frame)) => match frame.into_data() {
+            Ok(mut data) => *input = data.copy_to_bytes(data.remaining()),
+            Err(frame) => *trailers = frame.into_trailers().ok(),
+        },
+        Some(Err(err)) => return Err(err.into()),
+        None => *inner_done = true,
+    }
+    Ok(())
+}
+
+impl<B> Body for LzoBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        let mut this = self.project();
+        match this.mode {
+            Mode::Identity => this.inner.poll_frame(cx).map(|frame| {
+                frame.map(|frame| {
+                    frame
+                        .map(|frame| {
+                            frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+                        })
+                        .map_err(Into::into)
+                })
+            }),
+            Mode::Compress(encoder) => {
+                let encoder = match encoder {
+                    Some(encoder) => encoder,
+                    None => encoder.insert(StreamEncoder::new()?),
+                };
+                loop {
+                    if !encoder.pending_output().is_empty() {
+                        let out = Bytes::copy_from_slice(encoder.pending_output());
+                        encoder.consume_output(out.len());
+                        return Poll::Ready(Some(Ok(Frame::data(out))));
+                    }
+                    if encoder.is_done() {
+                        return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+                    }
+                    if this.input.has_remaining() {
+                        let n = encoder.feed_input(this.input)?;
+                        this.input.advance(n);
+                    } else if *this.inner_done {
+                        encoder.finish()?;
+                    } else {
+                        match this.inner.as_mut().poll_frame(cx) {
+                            Poll::Ready(frame) => {
+                                take_frame(frame, this.input, this.trailers, this.inner_done)?
+                            }
+                            Poll::Pending => {
+                                // Send what has come in so far rather than
+                                // wait for the block to fill.
+                                encoder.flush()?;
+                                if encoder.pending_output().is_empty() {
+                                    return Poll::Pending;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Mode::Decompress(decoder) => {
+                let decoder = match decoder {
+                    Some(decoder) => decoder,
+                    None => decoder.insert(StreamDecoder::new()?),
+                };
+                loop {
+                    // Decodes the next complete block into `pending_output`
+                    // without copying any of it out.
+                    decoder.read_output(&mut [])?;
+                    if !decoder.pending_output().is_empty() {
+                        let out = Bytes::copy_from_slice(decoder.pending_output());
+                        decoder.consume_output(out.len());
+                        return Poll::Ready(Some(Ok(Frame::data(out))));
+                    }
+                    if decoder.is_done() {
+                        let remaining = decoder.remaining().len() + this.input.remaining();
+                        if remaining > 0 {
+                            return Poll::Ready(Some(Err(
+                                Error::InputNotConsumed { remaining }.into()
+                            )));
+                        }
+                    }
+                    if this.input.has_remaining() {
+                        decoder.feed_input(this.input);
+                        this.input.clear();
+                    } else if *this.inner_done {
+                        decoder.finish()?;
+                        return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+                    } else {
+                        match this.inner.as_mut().poll_frame(cx) {
+                            Poll::Ready(frame) => {
+                                take_frame(frame, this.input, this.trailers, this.inner_done)?
+                            }
+                            Poll::Pending => return Poll::Pending,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.mode {
+            Mode::Identity => self.inner.is_end_stream(),
+            _ => false,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.mode {
+            Mode::Identity => self.inner.size_hint(),
+            _ => SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::future::{poll_fn, ready, Ready};
+
+    /// A body handing out its frames one per poll, stalling before each.
+    struct Chunks {
+        frames: VecDeque<Frame<Bytes>>,
+        stall: bool,
+    }
+
+    impl Chunks {
+        fn new(data: &[u8], chunk: usize, trailers: Option<HeaderMap>) -> Self {
+            let mut frames: VecDeque<_> = data
+                .chunks(chunk)
+                .map(|piece| Frame::data(Bytes::copy_from_slice(piece)))
+                .collect();
+            frames.extend(trailers.map(Frame::trailers));
+            Chunks {
+                frames,
+                stall: false,
+            }
+        }
+    }
+
+    impl Body for Chunks {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+            self.stall = !self.stall;
+            if self.stall && !self.frames.is_empty() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(self.frames.pop_front().map(Ok))
+        }
+    }
+
+    /// Collect a body's data, and its trailers if it has any.
+    fn collect<B: Body<Data = Bytes>>(body: B) -> Result<(Vec<u8>, Option<HeaderMap>), B::Error> {
+        let mut body = Box::pin(body);
+        let mut data = Vec::new();
+        let mut trailers = None;
+        while let Some(frame) =
+            futures::executor::block_on(poll_fn(|cx| body.as_mut().poll_frame(cx)))
+        {
+            match frame?.into_data() {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(frame) => trailers = frame.into_trailers().ok(),
+            }
+        }
+        Ok((data, trailers))
+    }
+
+    /// Echoes the request body back.
+    struct Echo;
+
+    impl Service<Request<LzoBody<Chunks>>> for Echo {
+        type Response = Response<Chunks>;
+        type Error = BoxError;
+        type Future = Ready<Result<Response<Chunks>, BoxError>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<LzoBody<Chunks>>) -> Self::Future {
+            assert!(!request.headers().contains_key(CONTENT_ENCODING));
+            ready(collect(request.into_body()).map(|(data, trailers)| {
+                Response::builder()
+                    .header(CONTENT_LENGTH, data.len())
+                    .body(Chunks::new(&data, 1000, trailers))
+                    .unwrap()
+            }))
+        }
+    }
+
+    #[test]
+    fn test_http_coding() {
+        let data: Vec<u8> = (0..700_000u32).map(|i| (i / 7 % 251) as u8).collect();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+
+        let (framed, got) = collect(LzoBody::compress(Chunks::new(
+            &data,
+            3000,
+            Some(trailers.clone()),
+        )))
+        .unwrap();
+        assert_eq!(got, Some(trailers.clone()));
+        assert_eq!(crate::decompress_chunked(&framed).unwrap(), data);
+        assert!(framed.len() < data.len());
+
+        let mut service = LzoLayer::new().layer(Echo);
+        let request = Request::builder()
+            .header(CONTENT_ENCODING, "x-lzo")
+            .header(ACCEPT_ENCODING, "gzip;q=1.0, X-LZO;q=0.5")
+            .body(Chunks::new(&framed, 777, Some(trailers.clone())))
+            .unwrap();
+        let response = futures::executor::block_on(service.call(request)).unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "x-lzo");
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+        let (body, got) = collect(response.into_body()).unwrap();
+        assert_eq!(got, Some(trailers));
+        assert_eq!(crate::decompress_chunked(&body).unwrap(), data);
+
+        // Not accepted, so sent as is.
+        let request = Request::builder()
+            .header(ACCEPT_ENCODING, "x-lzo;q=0, gzip")
+            .body(Chunks::new(b"plain", 2, None))
+            .unwrap();
+        let response = futures::executor::block_on(service.call(request)).unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(collect(response.into_body()).unwrap().0, b"plain");
+
+        // Truncated, damaged and over-long streams fail the body.
+        let error = |body: &[u8]| {
+            let err = collect(LzoBody::decompress(Chunks::new(body, 100, None))).unwrap_err();
+            *err.downcast::<Error>().unwrap()
+        };
+        assert_eq!(error(&framed[..framed.len() - 1]), Error::EOFNotFound);
+        let mut damaged = framed.clone();
+        damaged[framed.len() / 2] ^= 0x40;
+        assert!(collect(LzoBody::decompress(Chunks::new(&damaged, 100, None))).is_err());
+        let mut longer = framed.clone();
+        longer.extend_from_slice(b"junk");
+        assert_eq!(error(&longer), Error::InputNotConsumed { remaining: 4 });
+    }
+}
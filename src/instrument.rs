@@ -0,0 +1,288 @@
+//! `tracing` and `metrics` instrumentation (`tracing` and `metrics`
+//! features).
+//!
+//! Call sites report through these functions, which compile to nothing
+//! without either feature. With `tracing`, successful calls and frame
+//! blocks are `TRACE` events, failures `DEBUG` events, and the file helpers
+//! run in `DEBUG` spans. With `metrics`, the following are recorded through
+//! the `metrics` facade:
+//!
+//! - `minilzo_bytes_in_total` and `minilzo_bytes_out_total` counters and a
+//!   `minilzo_duration_seconds` histogram, labelled with `op` (`compress`
+//!   or `decompress`)
+//! - a `minilzo_blocks_total` counter of frame blocks, labelled with `op`
+//!   (`encode` or `decode`)
+//! - a `minilzo_errors_total` counter, labelled with `op` and the error
+//!   `kind`
+
+// Which arguments get used depends on the features enabled.
+#![cfg_attr(
+    not(all(feature = "tracing", feature = "metrics")),
+    allow(unused_variables)
+)]
+
+use crate::{BlockMeta, CompressError, DecompressError, Error};
+use std::path::Path;
+
+/// Keeps a span entered until dropped.
+#[cfg(feature = "tracing")]
+pub(crate) type SpanGuard = tracing::span::EnteredSpan;
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct SpanGuard;
+
+/// Measures a call's duration for the `metrics` feature.
+pub(crate) struct Timer {
+    #[cfg(feature = "metrics")]
+    started: std::time::Instant,
+}
+
+impl Timer {
+    #[inline]
+    pub(crate) fn start() -> Self {
+        Timer {
+            #[cfg(feature = "metrics")]
+            started: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn ratio(output: usize, input: usize) -> f64 {
+    if input == 0 {
+        return 0.0;
+    }
+    output as f64 / input as f64
+}
+
+/// Record a finished one-shot call.
+#[cfg(feature = "metrics")]
+fn record_call(op: &'static str, timer: Timer, input: usize, result: Result<usize, &Error>) {
+    metrics::histogram!("minilzo_duration_seconds", "op" => op)
+        .record(timer.started.elapsed().as_secs_f64());
+    match result {
+        Ok(output) => {
+            metrics::counter!("minilzo_bytes_in_total", "op" => op).increment(input as u64);
+            metrics::counter!("minilzo_bytes_out_total", "op" => op).increment(output as u64);
+        }
+        Err(error) => record_error(op, error),
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn record_error(op: &'static str, error: &Error) {
+    metrics::counter!("minilzo_errors_total", "op" => op, "kind" => kind(error)).increment(1);
+}
+
+/// A stable label for `error`'s variant.
+#[cfg(feature = "metrics")]
+fn kind(error: &Error) -> &'static str {
+    match error {
+        Error::Error => "error",
+        Error::OutOfMemory => "out_of_memory",
+        Error::NotCompressible => "not_compressible",
+        Error::InputOverrun => "input_overrun",
+        Error::OutputOverrun { .. } => "output_overrun",
+        Error::LookbehindOverrun => "lookbehind_overrun",
+        Error::EOFNotFound => "eof_not_found",
+        Error::InputNotConsumed { .. } => "input_not_consumed",
+        Error::NotYetImplemented => "not_yet_implemented",
+        Error::InvalidArgument => "invalid_argument",
+        Error::InvalidAlignment => "invalid_alignment",
+        Error::OutputNotConsumed => "output_not_consumed",
+        Error::InternalError => "internal_error",
+        Error::Poisoned => "poisoned",
+        Error::InvalidHeader => "invalid_header",
+        Error::ChecksumMismatch => "checksum_mismatch",
+        Error::WouldBlock => "would_block",
+        Error::Uncommitted => "uncommitted",
+        Error::OutputLimitExceeded => "output_limit_exceeded",
+        Error::Cancelled => "cancelled",
+        Error::InputTooLarge { .. } => "input_too_large",
+        Error::BudgetExceeded { .. } => "budget_exceeded",
+        Error::DictionaryMismatch { .. } => "dictionary_mismatch",
+        Error::UnsupportedVersion { .. } => "unsupported_version",
+        Error::UnsupportedFeatures { .. } => "unsupported_features",
+    }
+}
+
+/// A one-shot compression of `input` bytes finished.
+#[inline]
+pub(crate) fn compressed(timer: Timer, input: usize, result: Result<usize, &CompressError>) {
+    #[cfg(feature = "tracing")]
+    match result {
+        Ok(output) => tracing::trace!(input, output, ratio = ratio(output, input), "compressed"),
+        Err(error) => tracing::debug!(input, %error, "compression failed"),
+    }
+    #[cfg(feature = "metrics")]
+    record_call(
+        "compress",
+        timer,
+        input,
+        result.map_err(|e| Error::from(*e)).as_ref().copied(),
+    );
+}
+
+/// A one-shot decompression of `input` bytes finished.
+#[inline]
+pub(crate) fn decompressed(timer: Timer, input: usize, result: Result<usize, &DecompressError>) {
+    #[cfg(feature = "tracing")]
+    match result {
+        Ok(output) => tracing::trace!(input, output, "decompressed"),
+        Err(error) => tracing::debug!(input, %error, "decompression failed"),
+    }
+    #[cfg(feature = "metrics")]
+    record_call(
+        "decompress",
+        timer,
+        input,
+        result.map_err(|e| Error::from(*e)).as_ref().copied(),
+    );
+}
+
+/// A frame block was encoded.
+#[inline]
+pub(crate) fn block_encoded(meta: &BlockMeta) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        uncompressed_len = meta.uncompressed_len,
+        compressed_len = meta.compressed_len,
+        stored = meta.compressed_len == meta.uncompressed_len,
+        "frame block encoded"
+    );
+    #[cfg(feature = "metrics")]
+    metrics::counter!("minilzo_blocks_total", "op" => "encode").increment(1);
+}
+
+/// A frame block was decoded, or failed to.
+#[inline]
+pub(crate) fn block_decoded(meta: &BlockMeta, result: Result<(), &Error>) {
+    #[cfg(feature = "tracing")]
+    match result {
+        Ok(()) => tracing::trace!(
+            uncompressed_len = meta.uncompressed_len,
+            compressed_len = meta.compressed_len,
+            "frame block decoded"
+        ),
+        Err(error) => tracing::debug!(
+            uncompressed_len = meta.uncompressed_len,
+            compressed_len = meta.compressed_len,
+            %error,
+            "frame block rejected"
+        ),
+    }
+    #[cfg(feature = "metrics")]
+    match result {
+        Ok(()) => metrics::counter!("minilzo_blocks_total", "op" => "decode").increment(1),
+        Err(error) => record_error("decode", error),
+    }
+}
+
+/// Enter a span for a file helper reading `src` and writing `dst`.
+#[inline]
+pub(crate) fn enter_file(op: &'static str, src: &Path, dst: &Path) -> SpanGuard {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!("file", op, src = %src.display(), dst = %dst.display()).entered();
+    #[cfg(not(feature = "tracing"))]
+    SpanGuard
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tracing_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Counts the events it sees.
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Subscriber for Counter {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_events_emitted() {
+        let count = Arc::new(AtomicUsize::new(0));
+        tracing::subscriber::with_default(Counter(count.clone()), || {
+            let mut lzo = crate::LZO::new();
+            let out = lzo.compress([1u8; 1000]).unwrap();
+            lzo.decompress_safe(&out, 1000).unwrap();
+            assert!(lzo.decompress_safe(&out[..4], 1000).is_err());
+        });
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod metrics_tests {
+    use metrics::{
+        Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Sums counters by their full key, labels included.
+    #[derive(Default)]
+    struct Totals(Arc<Mutex<BTreeMap<String, u64>>>);
+
+    struct Slot(Arc<Mutex<BTreeMap<String, u64>>>, String);
+
+    impl CounterFn for Slot {
+        fn increment(&self, value: u64) {
+            *self.0.lock().unwrap().entry(self.1.clone()).or_default() += value;
+        }
+        fn absolute(&self, value: u64) {
+            self.0.lock().unwrap().insert(self.1.clone(), value);
+        }
+    }
+
+    impl Recorder for Totals {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let labels: Vec<String> = key
+                .labels()
+                .map(|l| format!("{}={}", l.key(), l.value()))
+                .collect();
+            let name = format!("{}{{{}}}", key.name(), labels.join(","));
+            Counter::from_arc(Arc::new(Slot(self.0.clone(), name)))
+        }
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn test_counters_recorded() {
+        let totals = Totals::default();
+        metrics::with_local_recorder(&totals, || {
+            let mut lzo = crate::LZO::new();
+            let out = lzo.compress([1u8; 1000]).unwrap();
+            lzo.decompress_safe(&out, 1000).unwrap();
+            assert!(lzo.decompress_safe(&out[..4], 1000).is_err());
+        });
+        let totals = totals.0.lock().unwrap();
+        assert_eq!(totals["minilzo_bytes_in_total{op=compress}"], 1000);
+        assert_eq!(totals["minilzo_bytes_out_total{op=decompress}"], 1000);
+        assert_eq!(
+            totals["minilzo_errors_total{op=decompress,kind=input_overrun}"],
+            1
+        );
+    }
+}
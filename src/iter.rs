@@ -0,0 +1,198 @@
+//! Iterator adapters over the framed format.
+//!
+//! For pull-based pipelines: `compress_iter` compresses chunks as they are
+//! pulled from the source, and `decompress_iter` yields blocks as soon as
+//! the compressed chunks pulled so far complete them, with no `Write` or
+//! `Read` in between.
+
+use crate::frame::{self, Decoder};
+use crate::{Decompressor, Error, LZOResult, LZO, MAX_BLOCK_SIZE};
+
+struct CompressIter<I> {
+    chunks: I,
+    lzo: LZO,
+    started: bool,
+    done: bool,
+}
+
+impl<I: Iterator> Iterator for CompressIter<I>
+where
+    I::Item: AsRef<[u8]>,
+{
+    type Item = LZOResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<LZOResult<Vec<u8>>> {
+        if self.done {
+            return None;
+        }
+        let mut out = Vec::new();
+        if !self.started {
+            frame::encode_header(&mut out);
+            self.started = true;
+        }
+        let chunk = match self.chunks.next() {
+            Some(chunk) => chunk,
+            None => {
+                frame::encode_end(&mut out);
+                self.done = true;
+                return Some(Ok(out));
+            }
+        };
+        for block in chunk.as_ref().chunks(MAX_BLOCK_SIZE) {
+            if let Err(e) = frame::encode_block(&mut self.lzo, block, &mut out) {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(out))
+    }
+}
+
+/// Compress each chunk pulled from `chunks` into the blocks of a framed
+/// stream, lazily.
+///
+/// Every chunk yields one item, the first one preceded by the stream
+/// header, and a last item holds the end marker, so the items joined
+/// together are a stream `LzoReader` and `decompress_iter` read. Chunks
+/// longer than `MAX_BLOCK_SIZE` are split into several blocks.
+///
+/// Example
+///
+/// ```rust
+/// let pages = (0..4u8).map(|i| vec![i; 4096]);
+/// let compressed = minilzo_rs::compress_iter(pages).map(Result::unwrap);
+/// let blocks: Vec<Vec<u8>> = minilzo_rs::decompress_iter(compressed)
+///     .map(Result::unwrap)
+///     .collect();
+/// assert_eq!(blocks.len(), 4);
+/// assert_eq!(blocks[3], vec![3u8; 4096]);
+/// ```
+pub fn compress_iter<I>(chunks: I) -> impl Iterator<Item = LZOResult<Vec<u8>>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    CompressIter {
+        chunks: chunks.into_iter(),
+        lzo: LZO::new(),
+        started: false,
+        done: false,
+    }
+}
+
+struct DecompressIter<I> {
+    chunks: I,
+    decoder: Decoder,
+    decompressor: Decompressor,
+    done: bool,
+}
+
+impl<I: Iterator> DecompressIter<I>
+where
+    I::Item: AsRef<[u8]>,
+{
+    fn next_block(&mut self) -> LZOResult<Option<Vec<u8>>> {
+        loop {
+            match self.decoder.decode(&self.decompressor)? {
+                Some(block) if block.is_empty() => {
+                    // Only one more chunk is pulled, so as not to drain an
+                    // unbounded source.
+                    let mut remaining = self.decoder.remaining().len();
+                    if remaining == 0 {
+                        remaining = self
+                            .chunks
+                            .find(|c| !c.as_ref().is_empty())
+                            .map_or(0, |c| c.as_ref().len());
+                    }
+                    if remaining > 0 {
+                        return Err(Error::InputNotConsumed { remaining });
+                    }
+                    return Ok(None);
+                }
+                Some(block) => return Ok(Some(block)),
+                None => match self.chunks.next() {
+                    Some(chunk) => self.decoder.feed(chunk.as_ref()),
+                    None => return Err(Error::EOFNotFound),
+                },
+            }
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for DecompressIter<I>
+where
+    I::Item: AsRef<[u8]>,
+{
+    type Item = LZOResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<LZOResult<Vec<u8>>> {
+        if self.done {
+            return None;
+        }
+        let result = self.next_block();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result.transpose()
+    }
+}
+
+/// Decompress a framed stream pulled from `chunks`, split anywhere,
+/// yielding each block's data as soon as it is complete.
+///
+/// A stream cut short yields `Error::EOFNotFound`, and data after the end
+/// marker `Error::InputNotConsumed`.
+///
+/// # Panics
+///
+/// Panics if minilzo's self-check fails, like `LZO::new`.
+pub fn decompress_iter<I>(chunks: I) -> impl Iterator<Item = LZOResult<Vec<u8>>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    LZO::lzo_init().expect("minilzo self-check failed");
+    DecompressIter {
+        chunks: chunks.into_iter(),
+        decoder: Decoder::new(),
+        decompressor: Decompressor::initialized(),
+        done: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_iter_roundtrip() {
+        let chunks: Vec<Vec<u8>> = (0..20u32)
+            .map(|i| (0..i * 997).map(|j| (j % 31) as u8).collect())
+            .collect();
+        let items: Vec<Vec<u8>> = compress_iter(&chunks).map(Result::unwrap).collect();
+        assert_eq!(items.len(), chunks.len() + 1);
+        let framed = items.concat();
+
+        let mut output = Vec::new();
+        crate::LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, chunks.concat());
+
+        // Split the stream at arbitrary points.
+        let pieces = framed.chunks(7);
+        let blocks: Vec<Vec<u8>> = decompress_iter(pieces).map(Result::unwrap).collect();
+        assert_eq!(blocks.concat(), chunks.concat());
+
+        let cut: Vec<_> = decompress_iter(framed[..framed.len() - 1].chunks(100)).collect();
+        assert_eq!(cut.last(), Some(&Err(Error::EOFNotFound)));
+        let padded = [&framed[..], b"xx"];
+        let padded: Vec<_> = decompress_iter(padded).collect();
+        assert_eq!(
+            padded.last(),
+            Some(&Err(Error::InputNotConsumed { remaining: 2 }))
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Fan-out of block compression to remote workers.
+//!
+//! A coordinator splits its input with `plan_jobs`, ships each `Job` (in
+//! its `to_bytes` form) to a worker along with the bytes it covers, and
+//! feeds the returned `JobResult`s, in any order, to a `JobAssembler` that
+//! writes the framed stream. Results are validated before they are
+//! accepted, so a faulty worker can't corrupt the container.
+
+use crate::frame::{self, MAX_BLOCK_SIZE};
+use crate::{adler32, BlockMeta, Checksum, Decompressor, Error, LZOResult, LZO};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::ops::Range;
+
+const JOB_LEN: usize = 21;
+const RESULT_HEADER_LEN: usize = 16;
+/// How far past the next slot to write `JobAssembler::new` accepts results.
+const DEFAULT_WINDOW: u32 = 1024;
+
+fn be32(buf: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]])
+}
+
+/// One block of work: compress `len` input bytes starting at `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Job {
+    /// Position of the block in the output container.
+    pub slot: u32,
+    /// Offset of the block in the coordinator's input.
+    pub offset: u64,
+    /// Length of the block.
+    pub len: u32,
+    /// Preset dictionary to compress with. Reserved: no dictionaries are
+    /// supported yet, and `run` rejects jobs that name one.
+    pub dictionary_id: Option<u32>,
+}
+
+impl Job {
+    /// Serialize the job description.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(JOB_LEN);
+        out.extend_from_slice(&self.slot.to_be_bytes());
+        out.extend_from_slice(&self.offset.to_be_bytes());
+        out.extend_from_slice(&self.len.to_be_bytes());
+        out.push(self.dictionary_id.is_some() as u8);
+        out.extend_from_slice(&self.dictionary_id.unwrap_or(0).to_be_bytes());
+        out
+    }
+
+    /// Parse a job description produced by `to_bytes`.
+    pub fn from_bytes(buf: &[u8]) -> LZOResult<Self> {
+        if buf.len() != JOB_LEN || buf[16] > 1 {
+            return Err(Error::InvalidHeader);
+        }
+        let mut offset = [0u8; 8];
+        offset.copy_from_slice(&buf[4..12]);
+        Ok(Job {
+            slot: be32(buf, 0),
+            offset: u64::from_be_bytes(offset),
+            len: be32(buf, 12),
+            dictionary_id: if buf[16] == 1 {
+                Some(be32(buf, 17))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// The input bytes this job covers, as an index range.
+    ///
+    /// Fails with `Error::InvalidArgument` if they lie beyond what a slice
+    /// can address, as they can for large inputs on 32-bit targets.
+    pub fn range(&self) -> LZOResult<Range<usize>> {
+        let start = usize::try_from(self.offset).map_err(|_| Error::InvalidArgument)?;
+        let end = start
+            .checked_add(self.len as usize)
+            .ok_or(Error::InvalidArgument)?;
+        Ok(start..end)
+    }
+
+    /// Compress `input`, the bytes this job covers.
+    pub fn run(&self, lzo: &mut LZO, input: &[u8]) -> LZOResult<JobResult> {
+        if self.dictionary_id.is_some() {
+            return Err(Error::NotYetImplemented);
+        }
+        if input.len() != self.len as usize {
+            return Err(Error::InvalidArgument);
+        }
+        let payload = frame::block_payload(lzo, input)?;
+        Ok(JobResult {
+            slot: self.slot,
+            meta: BlockMeta {
+                uncompressed_len: self.len,
+                compressed_len: payload.len() as u32,
+                checksum: adler32(input),
+            },
+            payload,
+        })
+    }
+}
+
+/// Split `total_len` input bytes into jobs of at most `block_size` bytes.
+pub fn plan_jobs(total_len: u64, block_size: usize) -> LZOResult<Vec<Job>> {
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+    let count = total_len.div_ceil(block_size as u64);
+    if count > u32::MAX as u64 {
+        return Err(Error::InvalidArgument);
+    }
+    Ok((0..count)
+        .map(|slot| {
+            let offset = slot * block_size as u64;
+            Job {
+                slot: slot as u32,
+                offset,
+                len: (total_len - offset).min(block_size as u64) as u32,
+                dictionary_id: None,
+            }
+        })
+        .collect())
+}
+
+/// A compressed block produced by a worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobResult {
+    /// The slot of the job that produced this block.
+    pub slot: u32,
+    /// Sizes and checksum of the block.
+    pub meta: BlockMeta,
+    /// The compressed bytes.
+    pub payload: Vec<u8>,
+}
+
+impl JobResult {
+    /// Serialize the result for the trip back to the coordinator.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(RESULT_HEADER_LEN + self.payload.len());
+        out.extend_from_slice(&self.slot.to_be_bytes());
+        self.meta.write_to(&mut out);
+        out.extend_from_slice(&self.payload);
+        out
+    }
+
+    /// Parse a result produced by `to_bytes`.
+    pub fn from_bytes(buf: &[u8]) -> LZOResult<Self> {
+        if buf.len() < RESULT_HEADER_LEN {
+            return Err(Error::InvalidHeader);
+        }
+        let meta = BlockMeta::read_from(&buf[4..]);
+        if buf.len() - RESULT_HEADER_LEN != meta.compressed_len as usize {
+            return Err(Error::InvalidHeader);
+        }
+        Ok(JobResult {
+            slot: be32(buf, 0),
+            meta,
+            payload: buf[RESULT_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// Writes worker results into a framed stream in slot order.
+///
+/// Results that arrive early are held until the slots before them are
+/// written, but only within a window of slots past the next one to write
+/// (1024 unless set with `with_window`), which bounds what a coordinator
+/// that hands out jobs far ahead, or a faulty worker, can make it buffer.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{plan_jobs, JobAssembler, LZO};
+///
+/// let input = vec![0x11u8; 10_000];
+/// let mut lzo = LZO::new();
+/// let mut assembler = JobAssembler::new(Vec::new()).unwrap();
+/// for job in plan_jobs(input.len() as u64, 4096).unwrap().iter().rev() {
+///     let range = job.range().unwrap();
+///     assembler.push(job.run(&mut lzo, &input[range]).unwrap()).unwrap();
+/// }
+/// let framed = assembler.finish().unwrap();
+/// ```
+pub struct JobAssembler<W: Write> {
+    inner: W,
+    decompressor: Decompressor,
+    next_slot: u32,
+    window: u32,
+    pending: BTreeMap<u32, JobResult>,
+}
+
+impl<W: Write> JobAssembler<W> {
+    /// Start a framed stream on `inner`.
+    pub fn new(inner: W) -> io::Result<Self> {
+        Self::with_window(inner, DEFAULT_WINDOW)
+    }
+
+    /// Start a framed stream on `inner` that accepts results for at most
+    /// `window` slots from the next one to write, so no more than
+    /// `window - 1` results are ever held back.
+    ///
+    /// Fails with `Error::InvalidArgument` if `window` is zero.
+    pub fn with_window(mut inner: W, window: u32) -> io::Result<Self> {
+        if window == 0 {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        let mut header = Vec::new();
+        frame::encode_header(&mut header);
+        inner.write_all(&header)?;
+        Ok(JobAssembler {
+            inner,
+            decompressor: Decompressor::new().map_err(frame::to_io)?,
+            next_slot: 0,
+            window,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    /// Validate a result and write it, along with any results it unblocks.
+    ///
+    /// The payload is decompressed and checked against its recorded length
+    /// and checksum; duplicate and already-written slots are rejected, as
+    /// are slots beyond the window.
+    pub fn push(&mut self, result: JobResult) -> io::Result<()> {
+        if result.slot < self.next_slot
+            || result.slot - self.next_slot >= self.window
+            || self.pending.contains_key(&result.slot)
+        {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        validate(
+            &self.decompressor,
+            &result.meta,
+            &result.payload,
+            Checksum::Adler32,
+        )
+        .map_err(frame::to_io)?;
+        self.pending.insert(result.slot, result);
+        while let Some(result) = self.pending.remove(&self.next_slot) {
+            let mut block = Vec::with_capacity(frame::BLOCK_HEADER_LEN + result.payload.len());
+            result.meta.write_to(&mut block);
+            block.extend_from_slice(&result.payload);
+            self.inner.write_all(&block)?;
+            self.next_slot += 1;
+        }
+        Ok(())
+    }
+
+    /// Write the end marker and return the inner writer. Fails if results
+    /// are still waiting for an earlier slot.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            return Err(frame::to_io(Error::EOFNotFound));
+        }
+        let mut end = Vec::new();
+        frame::encode_end(&mut end);
+        self.inner.write_all(&end)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Check that `payload` really is the compressed form described by `meta`,
+/// with `meta.checksum` computed under `checksum`.
+pub(crate) fn validate(
+    decompressor: &Decompressor,
+    meta: &BlockMeta,
+    payload: &[u8],
+    checksum: Checksum,
+) -> LZOResult<()> {
+    if meta.uncompressed_len == 0
+        || meta.uncompressed_len as usize > MAX_BLOCK_SIZE
+        || payload.len() != meta.compressed_len as usize
+    {
+        return Err(Error::InvalidArgument);
+    }
+    frame::decode_block(decompressor, meta, payload, checksum).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_job_range_checked() {
+        let job = plan_jobs(10_000, 4096).unwrap()[2];
+        assert_eq!(job.range(), Ok(8192..10_000));
+        let far = Job {
+            offset: u64::MAX,
+            ..job
+        };
+        assert_eq!(far.range(), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn test_jobs_out_of_order() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 7) as u8).collect();
+        let jobs = plan_jobs(input.len() as u64, 6000).unwrap();
+        assert_eq!(jobs.len(), 4);
+        let mut lzo = LZO::new();
+        let mut results: Vec<JobResult> = jobs
+            .iter()
+            .map(|job| {
+                let job = Job::from_bytes(&job.to_bytes()).unwrap();
+                let result = job.run(&mut lzo, &input[job.range().unwrap()]).unwrap();
+                JobResult::from_bytes(&result.to_bytes()).unwrap()
+            })
+            .collect();
+        results.swap(0, 3);
+
+        let mut assembler = JobAssembler::new(Vec::new()).unwrap();
+        let mut corrupt = results[1].clone();
+        corrupt.meta.checksum ^= 1;
+        assert!(assembler.push(corrupt).is_err());
+        for result in results {
+            assembler.push(result).unwrap();
+        }
+        let framed = assembler.finish().unwrap();
+        let mut output = Vec::new();
+        crate::LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_assembler_window() {
+        let input = vec![0x11u8; 4 * 100];
+        let mut lzo = LZO::new();
+        let results: Vec<JobResult> = plan_jobs(input.len() as u64, 100)
+            .unwrap()
+            .iter()
+            .map(|job| job.run(&mut lzo, &input[job.range().unwrap()]).unwrap())
+            .collect();
+
+        assert!(JobAssembler::with_window(Vec::new(), 0).is_err());
+        let mut assembler = JobAssembler::with_window(Vec::new(), 2).unwrap();
+        let err = assembler.push(results[2].clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assembler.push(results[1].clone()).unwrap();
+        // Writing slot 0 flushes slot 1 and moves the window up to 2..4.
+        assembler.push(results[0].clone()).unwrap();
+        assembler.push(results[3].clone()).unwrap();
+        assembler.push(results[2].clone()).unwrap();
+        let framed = assembler.finish().unwrap();
+        assert_eq!(crate::decompress_chunked(framed).unwrap(), input);
+    }
+}
@@ -0,0 +1,80 @@
+//! Migration of bare 0.x blobs into the framed format.
+//!
+//! Before the framed format existed, `LZO::compress` output was the only
+//! thing this crate produced: raw LZO1X bytes whose uncompressed length had
+//! to be stored by the caller. Those blobs remain readable with
+//! `LZO::decompress_safe(blob, len)`; `wrap_legacy` turns them into
+//! self-describing framed streams.
+
+use crate::frame::{self, MAX_BLOCK_SIZE};
+use crate::{adler32, BlockMeta, Decompressor, Error, LZOResult, LZO};
+
+/// Wrap a bare 0.x blob of `len` uncompressed bytes into a framed stream.
+///
+/// The blob is decompressed once to validate it and compute the block
+/// checksum. Payloads up to `MAX_BLOCK_SIZE` keep their original compressed
+/// bytes; larger ones have to be recompressed into several blocks.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// let mut lzo = minilzo_rs::LZO::new();
+/// let blob = lzo.compress(&[0x00u8; 1024]).unwrap();
+/// let framed = minilzo_rs::wrap_legacy(&blob, 1024).unwrap();
+///
+/// let mut output = Vec::new();
+/// minilzo_rs::LzoReader::new(&framed[..]).unwrap().read_to_end(&mut output).unwrap();
+/// assert_eq!(output, vec![0x00u8; 1024]);
+/// ```
+pub fn wrap_legacy(blob: &[u8], len: usize) -> LZOResult<Vec<u8>> {
+    if blob.len() > u32::MAX as usize {
+        return Err(Error::InvalidArgument);
+    }
+    let data = Decompressor::new()?.decompress_safe(blob, len)?;
+    let mut out = Vec::new();
+    frame::encode_header(&mut out);
+    if data.len() <= MAX_BLOCK_SIZE {
+        if !data.is_empty() {
+            // A blob no smaller than its data would read back as a stored
+            // block, so store the data instead.
+            let payload = if blob.len() < data.len() { blob } else { &data };
+            let meta = BlockMeta {
+                uncompressed_len: data.len() as u32,
+                compressed_len: payload.len() as u32,
+                checksum: adler32(&data),
+            };
+            meta.write_to(&mut out);
+            out.extend_from_slice(payload);
+        }
+    } else {
+        let mut lzo = LZO::new();
+        for chunk in data.chunks(MAX_BLOCK_SIZE) {
+            frame::encode_block(&mut lzo, chunk, &mut out)?;
+        }
+    }
+    frame::encode_end(&mut out);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `LZO::compress(&[0u8; 64])` as produced by minilzo-rs 0.6.
+    const LEGACY_ZEROS_64: &[u8] = &[
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x0b, 0x10, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_legacy_blob_still_decodes() {
+        let lzo = LZO::new();
+        let data = lzo.decompress_safe(LEGACY_ZEROS_64, 64).unwrap();
+        assert_eq!(data, vec![0u8; 64]);
+
+        let framed = wrap_legacy(LEGACY_ZEROS_64, 64).unwrap();
+        assert!(framed.ends_with(&[LEGACY_ZEROS_64, &[0u8; 4][..]].concat()));
+    }
+}
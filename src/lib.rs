@@ -4,7 +4,7 @@
 //!
 //! ```rust
 //! // compress
-//! let mut lzo = minilzo_rs::LZO::init().unwrap();
+//! let mut lzo = minilzo_rs::LZO::new();
 //! let input = [0x00u8; 1024];
 //! let out = lzo.compress(&input).unwrap();
 //!
@@ -15,75 +15,213 @@
 //! assert_eq!(&input_o[..], &input[..]);
 //! ```
 //!
+//! For one-off calls in scripts and tests, the `compress` and `decompress`
+//! free functions need no `LZO` value and initialize the library
+//! themselves:
+//!
+//! ```rust
+//! let out = minilzo_rs::compress(b"once upon a time, once upon a time").unwrap();
+//! let text = minilzo_rs::decompress(&out, 34).unwrap();
+//! assert_eq!(text, b"once upon a time, once upon a time");
+//! ```
+//!
+mod archive;
+mod asset;
+mod bounded;
+mod builder;
+#[cfg(feature = "capi")]
+mod capi;
+mod checksum;
+mod chunked;
+mod chunker;
+#[cfg(feature = "tokio-util")]
+mod codec;
+mod coder;
+#[cfg(feature = "serde")]
+mod compressed_bytes;
+#[cfg(feature = "serde")]
+mod compressed_map;
+mod compressed_vec;
+mod compressor;
+mod context;
+mod copy;
+mod decompressor;
+mod delta;
+mod detect;
+pub mod dict;
+#[cfg(feature = "embedded-io")]
+mod embedded;
+mod error;
+mod fallback;
+/// The raw minilzo bindings (`ffi` feature), for entry points the safe API
+/// doesn't cover.
+///
+/// minilzo has to be initialized before any of its functions are called;
+/// `LZO::init`, or creating any `LZO` or `Decompressor`, does that.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::ffi;
+///
+/// minilzo_rs::LZO::init().unwrap();
+/// let out = minilzo_rs::compress(b"hello hello hello").unwrap();
+/// let mut buf = [0u8; 17];
+/// let mut len = buf.len() as ffi::lzo_uint;
+/// let code = unsafe {
+///     ffi::lzo1x_decompress_safe(
+///         out.as_ptr(),
+///         out.len() as ffi::lzo_uint,
+///         buf.as_mut_ptr(),
+///         &mut len,
+///         std::ptr::null_mut(),
+///     )
+/// };
+/// assert_eq!(code, ffi::LZO_E_OK);
+/// assert_eq!(&buf[..len], b"hello hello hello");
+/// ```
+#[cfg(feature = "ffi")]
+pub mod ffi {
+    pub use crate::minilzo::*;
+}
+mod file;
+mod frame;
+#[cfg(feature = "futures-io")]
+mod futures_io;
+#[cfg(feature = "futures")]
+mod futures_stream;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+pub mod golden;
+#[cfg(feature = "tonic")]
+mod grpc;
+#[cfg(feature = "tower")]
+mod http_coding;
+mod instrument;
+mod iter;
+mod job;
+mod legacy;
+mod limits;
+mod lzop;
+mod manifest;
+mod metadata;
 mod minilzo;
+#[cfg(feature = "node")]
+mod node;
+mod output_alloc;
+mod page_log;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod parallel_reader;
+mod parallel_writer;
+#[cfg(feature = "parity")]
+mod parity;
+mod pipeline;
+mod pool;
+mod progress;
+mod pylzo;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "rkyv")]
+mod rkyv_archive;
+mod salvage;
+mod sans_io;
+mod scratch;
+mod simd;
+mod stream;
+#[cfg(feature = "tar")]
+mod tar_lzo;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+mod traits;
+mod transcode;
+mod verified;
+mod verify;
+mod version;
+use std::cell::RefCell;
+use std::io::IoSlice;
 use std::mem::{size_of, MaybeUninit};
 use std::os::raw::{c_int, c_long, c_short};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
-type LZOResult<T> = Result<T, Error>;
-
-#[derive(Debug, PartialEq)]
-pub enum Error {
-    Error,
-    OutOfMemory,
-    NotCompressible,
-    InputOverrun,
-    OutputOverrun,
-    LookbehindOverrun,
-    EOFNotFound,
-    InputNotConsumed,
-    NotYetImplemented,
-    InvalidArgument,
-    InvalidAlignment,
-    OutputNotConsumed,
-    InternalError,
-}
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        match *self {
-            Error::OutOfMemory => "out of memory",
-            Error::NotCompressible => "not compressible",
-            Error::InputOverrun => "input overrun",
-            Error::OutputOverrun => "output overrun",
-            Error::LookbehindOverrun => "lookbehind overrun",
-            Error::EOFNotFound => "EOF not found",
-            Error::InputNotConsumed => "input not consumed",
-            Error::NotYetImplemented => "not yet implemented",
-            Error::InvalidArgument => "invalid argument",
-            Error::InvalidAlignment => "invalid alignment",
-            Error::OutputNotConsumed => "output not consumed",
-            Error::InternalError => "internal error",
-            Error::Error => "error",
-        }
-    }
-}
+pub use archive::{ArchiveReader, ArchiveWriter, Entry, WriteBatch};
+pub use asset::{build_asset, compress_asset, LzoAsset};
+pub use bounded::BoundedLzoReader;
+pub use builder::{Algorithm, Checksum, LzoBuilder};
+pub use checksum::{adler32, adler32_with, crc32, crc32_with, Adler32, Crc32};
+pub use chunked::{decompress_chunked, decompress_chunked_partial};
+pub use chunker::{Chunk, Chunker};
+#[cfg(feature = "tokio-util")]
+pub use codec::LzoCodec;
+pub use coder::{LzoDecoder, LzoEncoder, Progress};
+#[cfg(feature = "serde")]
+pub use compressed_bytes::CompressedBytes;
+#[cfg(feature = "serde")]
+pub use compressed_map::{CompressedMap, MapStats};
+pub use compressed_vec::{CompressedString, CompressedVec};
+pub use compressor::Compressor;
+pub use context::LzoContext;
+pub use copy::{copy_compress, copy_decompress};
+pub use decompressor::Decompressor;
+pub use detect::{detect, AutoReader, Format};
+#[cfg(feature = "embedded-io")]
+pub use embedded::{EmbeddedLzoReader, EmbeddedLzoWriter};
+use error::lzo_err_code_to_result;
+pub use error::{CompressError, DecodeError, DecompressError, Error, PartialOutput};
+pub use fallback::{BlockChoice, CompressOutcome};
+pub use file::{
+    compress_file, compress_file_resumable, compress_sparse_file, decompress_file, open_auto,
+};
+pub use frame::{DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE};
+#[cfg(feature = "futures-io")]
+pub use futures_io::{FuturesLzoReader, FuturesLzoWriter};
+#[cfg(feature = "futures")]
+pub use futures_stream::{CompressSink, CompressStream, DecompressStream};
+#[cfg(feature = "arbitrary")]
+pub use fuzz::FrameHeader;
+#[cfg(feature = "tonic")]
+pub use grpc::{LzoProstCodec, LzoProstDecoder, LzoProstEncoder};
+#[cfg(feature = "tower")]
+pub use http_coding::{LzoBody, LzoLayer, LzoResponseFuture, LzoService, LZO_CONTENT_CODING};
+pub use iter::{compress_iter, decompress_iter};
+pub use job::{plan_jobs, Job, JobAssembler, JobResult};
+pub use legacy::wrap_legacy;
+pub use limits::Limits;
+pub use lzop::{LzopReader, LzopWriter};
+pub use manifest::{BlockMeta, Manifest};
+pub use metadata::{frame_metadata, FrameMetadata};
+pub use output_alloc::OutputAlloc;
+pub use page_log::{read_page, PageLogReader, PageLogWriter, PAGE_HEADER_LEN};
+#[cfg(feature = "rayon")]
+pub use parallel::{
+    compress_parallel, compress_parallel_cancellable, decompress_parallel,
+    decompress_parallel_cancellable,
+};
+pub use parallel_reader::ParallelLzoReader;
+pub use parallel_writer::ParallelLzoWriter;
+pub use pipeline::{Pipeline, PipelineDecoder, PipelineEncoder};
+pub use pool::{LzoPool, PooledLzo};
+pub use progress::{CancelToken, CompressionStats, ProgressFn};
+#[cfg(feature = "rkyv")]
+pub use rkyv_archive::{ArchivedRef, CompressedArchive};
+pub use salvage::{salvage, Salvaged, Skipped};
+pub use sans_io::{StreamDecoder, StreamEncoder};
+pub use stream::{
+    AutoFinishLzoWriter, LzoReader, LzoWriter, TrailingData, DEFAULT_READ_BUFFER_SIZE,
+};
+#[cfg(feature = "tar")]
+pub use tar_lzo::{create_tar_lzo, extract_tar_lzo};
+#[cfg(feature = "tokio")]
+pub use tokio_io::{AsyncLzoReader, AsyncLzoWriter};
+pub use traits::{Compress, Decompress, Store};
+pub use transcode::{transcode, Container};
+pub use verify::verify_stream;
+pub use version::{version, VersionInfo};
 
-fn lzo_err_code_to_result<T>(code: i32, value: T) -> LZOResult<T> {
-    let error = match code {
-        0 => return Ok(value),
-        -1 => Error::Error,
-        -2 => Error::OutOfMemory,
-        -3 => Error::NotCompressible,
-        -4 => Error::InputOverrun,
-        -5 => Error::OutputOverrun,
-        -6 => Error::LookbehindOverrun,
-        -7 => Error::EOFNotFound,
-        -8 => Error::InputNotConsumed,
-        -9 => Error::NotYetImplemented,
-        -10 => Error::InvalidArgument,
-        -11 => Error::InvalidArgument,
-        -12 => Error::OutputNotConsumed,
-        -99 => Error::InternalError,
-        _ => Error::Error,
-    };
-    Err(error)
-}
+type LZOResult<T> = Result<T, Error>;
 
 /// An example of LZO compression.
 ///
@@ -91,7 +229,7 @@ fn lzo_err_code_to_result<T>(code: i32, value: T) -> LZOResult<T> {
 ///
 /// ```rust
 /// // test compress
-/// let mut lzo = minilzo_rs::LZO::init().unwrap();
+/// let mut lzo = minilzo_rs::LZO::new();
 /// let input = [0x00u8; 1024];
 /// let out = lzo.compress(&input).unwrap();
 ///
@@ -100,22 +238,108 @@ fn lzo_err_code_to_result<T>(code: i32, value: T) -> LZOResult<T> {
 /// let input = input.unwrap();
 /// assert_eq!(input.len(), 1024);
 /// ```
+///
+/// The 128 KiB work memory lives on the heap, so an `LZO` is cheap to move
+/// and safe to keep on small stacks or in async state machines.
+///
+/// # Thread safety
+///
+/// `LZO` is `Send` and `Sync`. Compression needs `&mut self`, because it
+/// uses the work memory, so each thread compressing at the same time needs
+/// its own instance (see `LzoPool`). Decompression only takes `&self` and
+/// never touches the work memory, so one instance can be shared between
+/// threads for decompressing concurrently.
 pub struct LZO {
-    wrkmem: [u8; minilzo::LZO1X_1_MEM_COMPRESS],
+    wrkmem: Box<[u8]>,
+    poisoned: AtomicBool,
+    config: builder::Config,
+}
+
+impl Default for LZO {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shows the configuration, not the work memory.
+impl std::fmt::Debug for LZO {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LZO")
+            .field("algorithm", &self.config.algorithm)
+            .field("level", &self.config.level)
+            .field("checksum", &self.config.checksum)
+            .field("block_size", &self.config.block_size)
+            .field("max_output", &self.config.max_output)
+            .field("max_output_size", &self.config.max_output_size)
+            .field("poisoned", &self.is_poisoned())
+            .finish()
+    }
+}
+
+/// The clone has the same configuration and work memory of its own, so it
+/// starts out unpoisoned; work memory carries nothing between calls.
+impl Clone for LZO {
+    fn clone(&self) -> Self {
+        LZO {
+            wrkmem: vec![0u8; minilzo::LZO1X_1_MEM_COMPRESS].into_boxed_slice(),
+            poisoned: AtomicBool::new(false),
+            config: self.config,
+        }
+    }
+}
+
+/// With the `zeroize` feature the work memory, which holds positions in
+/// recently compressed input, is wiped before it is freed.
+#[cfg(feature = "zeroize")]
+impl Drop for LZO {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut *self.wrkmem);
+    }
 }
 
 impl LZO {
+    /// Create an LZO instance.
+    ///
+    /// The library's one-time initialization runs on first use and is
+    /// cached for the rest of the process.
+    ///
+    /// # Panics
+    ///
+    /// Panics if minilzo's self-check fails, which means the C library was
+    /// built for a different ABI than this crate.
+    pub fn new() -> Self {
+        Self::lzo_init().expect("minilzo self-check failed");
+        LZO {
+            wrkmem: vec![0u8; minilzo::LZO1X_1_MEM_COMPRESS].into_boxed_slice(),
+            poisoned: AtomicBool::new(false),
+            config: builder::Config::default(),
+        }
+    }
+
+    /// `new`, failing with `Error::OutOfMemory` instead of aborting if the
+    /// work memory can't be allocated.
+    pub(crate) fn try_new() -> LZOResult<Self> {
+        Self::lzo_init()?;
+        Ok(LZO {
+            wrkmem: scratch::try_zeroed(minilzo::LZO1X_1_MEM_COMPRESS)?.into_boxed_slice(),
+            poisoned: AtomicBool::new(false),
+            config: builder::Config::default(),
+        })
+    }
+
     /// Initializing an LZO instance.
+    #[deprecated(since = "0.7.0", note = "use `LZO::new`, which cannot fail")]
     pub fn init() -> LZOResult<Self> {
-        match Self::lzo_init() {
-            Ok(_) => Ok(LZO {
-                wrkmem: unsafe { MaybeUninit::uninit().assume_init() },
-            }),
-            Err(e) => Err(e),
-        }
+        Self::lzo_init()?;
+        Ok(Self::new())
     }
 
     fn lzo_init() -> LZOResult<()> {
+        static INIT: OnceLock<LZOResult<()>> = OnceLock::new();
+        *INIT.get_or_init(Self::lzo_init_once)
+    }
+
+    fn lzo_init_once() -> LZOResult<()> {
         let code = unsafe {
             minilzo::__lzo_init_v2(
                 minilzo::lzo_version(),
@@ -133,75 +357,472 @@ impl LZO {
         lzo_err_code_to_result(code, ())
     }
 
-    /// Compress the src data and return an error if it fails.
-    pub fn compress(&mut self, src: &[u8]) -> LZOResult<Vec<u8>> {
-        let mut out_len = (src.len() + src.len() / 16 + 64 + 3) as u64;
-        let mut out: Vec<u8> = vec![0u8; out_len as usize];
+    /// Returns `true` if an earlier call hit an internal error.
+    ///
+    /// A poisoned instance refuses further work with a `Poisoned` error,
+    /// since its work memory can no longer be trusted.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn track<T, E: Clone + Into<Error>>(&self, result: Result<T, E>) -> Result<T, E> {
+        if let Err(e) = &result {
+            if e.clone().into() == Error::InternalError {
+                self.poisoned.store(true, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    /// Compress the src data with the configured algorithm and return an
+    /// error if it fails.
+    ///
+    /// The input is coded as one block, so inputs longer than
+    /// `MAX_INPUT_LEN` fail with `CompressError::InputTooLarge`. Use
+    /// `compress_chunked`, or `LzoWriter` for input that arrives in pieces,
+    /// to compress data of any size.
+    #[inline]
+    pub fn compress(&mut self, src: impl AsRef<[u8]>) -> Result<Vec<u8>, CompressError> {
+        let mut out = Vec::new();
+        self.compress_to(src.as_ref(), &mut out)?;
+        Ok(out)
+    }
+
+    /// Compress `src` into an allocation of exactly the compressed length.
+    ///
+    /// `compress` returns a `Vec` whose capacity is the worst-case bound;
+    /// this shrinks it, reallocating once, which pays off for outputs kept
+    /// around in large numbers, such as cache entries.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let entry: Box<[u8]> = lzo.compress_boxed([0u8; 4096]).unwrap();
+    /// assert_eq!(minilzo_rs::decompress(&entry, 4096).unwrap(), [0u8; 4096]);
+    /// ```
+    #[inline]
+    pub fn compress_boxed(&mut self, src: impl AsRef<[u8]>) -> Result<Box<[u8]>, CompressError> {
+        self.compress(src).map(Vec::into_boxed_slice)
+    }
+
+    /// Compress data scattered over several buffers as one block, as if
+    /// they had been joined.
+    ///
+    /// minilzo needs its input in one piece, so the buffers are copied
+    /// together internally unless there is only one.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::IoSlice;
+    ///
+    /// let (head, body) = (b"HTTP/1.1 200 OK\r\n\r\n", [0x20u8; 1000]);
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let out = lzo
+    ///     .compress_vectored(&[IoSlice::new(head), IoSlice::new(&body)])
+    ///     .unwrap();
+    /// assert_eq!(out, lzo.compress(&[&head[..], &body].concat()).unwrap());
+    /// ```
+    pub fn compress_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<Vec<u8>, CompressError> {
+        let mut parts = bufs.iter().filter(|buf| !buf.is_empty());
+        match (parts.next(), parts.next()) {
+            (None, _) => self.compress([]),
+            (Some(buf), None) => self.compress(&buf[..]),
+            _ => {
+                let len = bufs.iter().map(|buf| buf.len()).sum();
+                let mut joined = scratch::try_with_capacity(len)?;
+                for buf in bufs {
+                    joined.extend_from_slice(buf);
+                }
+                self.compress(&joined)
+            }
+        }
+    }
+
+    /// Compress into `out`, replacing its contents and reusing its
+    /// allocation.
+    #[inline]
+    pub(crate) fn compress_to(
+        &mut self,
+        src: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), CompressError> {
+        if self.is_poisoned() {
+            return Err(CompressError::Poisoned);
+        }
+        let timer = instrument::Timer::start();
+        let result = match self.config.algorithm {
+            Algorithm::Lzo1x1 => self.compress_lzo1x_1(src, out),
+            Algorithm::Auto if fallback::incompressible(src) => {
+                fallback::store(src, out).and_then(|()| self.check_max_output(out))
+            }
+            Algorithm::Auto => self.compress_lzo1x_1(src, out),
+        };
+        instrument::compressed(timer, src.len(), result.as_ref().map(|_| out.len()));
+        result
+    }
+
+    #[inline]
+    fn compress_lzo1x_1(&mut self, src: &[u8], out: &mut Vec<u8>) -> Result<(), CompressError> {
+        let bound = compress_bound(src.len());
+        out.clear();
+        out.try_reserve(bound)?;
+        let len = self.compress_lzo1x_1_uninit(src, &mut out.spare_capacity_mut()[..bound])?;
+        // The compressor initialized the first `len` bytes.
+        unsafe { out.set_len(len) };
+        self.check_max_output(out)
+    }
+
+    /// Compress `src` into `dst`, which holds at least
+    /// `compress_bound(src.len())` bytes, and return the compressed length.
+    #[inline]
+    fn compress_lzo1x_1_uninit(
+        &mut self,
+        src: &[u8],
+        dst: &mut [MaybeUninit<u8>],
+    ) -> Result<usize, CompressError> {
+        if src.len() > MAX_INPUT_LEN {
+            return Err(CompressError::InputTooLarge {
+                len: src.len(),
+                max: MAX_INPUT_LEN,
+            });
+        }
+        assert!(dst.len() >= compress_bound(src.len()));
+        let mut out_len = dst.len() as minilzo::lzo_uint;
         let code = unsafe {
             minilzo::lzo1x_1_compress(
                 src.as_ptr(),
-                src.len() as u64,
-                out.as_mut_ptr(),
+                src.len() as minilzo::lzo_uint,
+                dst.as_mut_ptr() as *mut u8,
                 &mut out_len,
                 self.wrkmem.as_mut_ptr() as *mut _,
             )
         };
-        out.resize(out_len as usize, 0);
-        lzo_err_code_to_result(code, out)
+        if code != 0 {
+            return self.track(Err(CompressError::from_code(code)));
+        }
+        // The compressor never writes more than the bound it was given.
+        Ok(out_len as usize)
     }
 
-    /// Decompress data.
-    pub fn decompress(&self, src: &[u8], dst_len: usize) -> LZOResult<Vec<u8>> {
-        let mut dst = vec![0u8; dst_len];
-        let code = unsafe {
-            minilzo::lzo1x_decompress(
-                src.as_ptr(),
-                src.len() as u64,
-                dst.as_mut_ptr(),
-                &dst_len as *const _ as *mut _,
-                std::ptr::null_mut(),
-            )
+    /// Compress `src` into an array of `N` bytes, returning it with the
+    /// compressed length, without allocating: for targets without a heap,
+    /// where the work memory is allocated once, up front.
+    ///
+    /// The compressor needs room for the worst case, so this fails with
+    /// `CompressError::OutputOverrun` whenever `compress_bound(src.len())`
+    /// is more than `N`, even if the actual output would have fit; size `N`
+    /// as `compress_bound` of the largest input. Input `Algorithm::Auto`
+    /// would store is compressed all the same.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{compress_bound, CompressError, LZO};
+    ///
+    /// let mut lzo = LZO::new();
+    /// let (out, len) = lzo.compress_fixed::<{ compress_bound(64) }>([1u8; 64]).unwrap();
+    /// assert!(len < 64);
+    /// assert_eq!(minilzo_rs::decompress(&out[..len], 64).unwrap(), [1u8; 64]);
+    ///
+    /// assert!(matches!(
+    ///     lzo.compress_fixed::<{ compress_bound(64) }>([1u8; 65]),
+    ///     Err(CompressError::OutputOverrun { .. })
+    /// ));
+    /// ```
+    pub fn compress_fixed<const N: usize>(
+        &mut self,
+        src: impl AsRef<[u8]>,
+    ) -> Result<([u8; N], usize), CompressError> {
+        let src = src.as_ref();
+        if self.is_poisoned() {
+            return Err(CompressError::Poisoned);
+        }
+        let bound = compress_bound(src.len());
+        if bound > N {
+            return Err(CompressError::OutputOverrun {
+                capacity: N,
+                needed: Some(bound),
+            });
+        }
+        let timer = instrument::Timer::start();
+        let mut out = [0u8; N];
+        // SAFETY: the compressor only writes initialized bytes, so viewing
+        // an initialized buffer as `MaybeUninit` can't de-initialize it.
+        let dst =
+            unsafe { std::slice::from_raw_parts_mut(out.as_mut_ptr() as *mut MaybeUninit<u8>, N) };
+        let result = self
+            .compress_lzo1x_1_uninit(src, dst)
+            .and_then(|len| self.check_max_output(&out[..len]).map(|()| len));
+        instrument::compressed(timer, src.len(), result.as_ref().copied());
+        result.map(|len| (out, len))
+    }
+
+    /// Compress `src` into a `SmallVec` holding up to `N` bytes inline,
+    /// without touching the heap when the worst case fits (`smallvec`
+    /// feature).
+    ///
+    /// `N` is the threshold: input whose `compress_bound` is at most `N` is
+    /// compressed straight into the inline buffer, and anything larger, or
+    /// input `Algorithm::Auto` stores, takes the `compress` path and spills
+    /// to the heap. Size it for the records at hand, such as
+    /// `compress_bound(256)` for records of up to 256 bytes; the result is
+    /// the same as `compress`'s either way.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{compress_bound, LZO};
+    ///
+    /// let mut lzo = LZO::new();
+    /// let record = br#"{"host":"web-1","cpu":0.25,"mem":0.5,"cpu_max":0.25}"#;
+    /// let out = lzo.compress_small::<{ compress_bound(256) }>(record).unwrap();
+    /// assert!(!out.spilled());
+    /// assert_eq!(minilzo_rs::decompress(&out, record.len()).unwrap(), record);
+    /// ```
+    #[cfg(feature = "smallvec")]
+    pub fn compress_small<const N: usize>(
+        &mut self,
+        src: impl AsRef<[u8]>,
+    ) -> Result<smallvec::SmallVec<[u8; N]>, CompressError> {
+        let src = src.as_ref();
+        let inline = match self.config.algorithm {
+            Algorithm::Lzo1x1 => true,
+            Algorithm::Auto => !fallback::incompressible(src),
         };
+        if !inline || compress_bound(src.len()) > N {
+            return self.compress(src).map(smallvec::SmallVec::from_vec);
+        }
+        if self.is_poisoned() {
+            return Err(CompressError::Poisoned);
+        }
+        let timer = instrument::Timer::start();
+        let mut out = smallvec::SmallVec::<[u8; N]>::new();
+        // SAFETY: a new `SmallVec` has room for `N` bytes inline.
+        let dst =
+            unsafe { std::slice::from_raw_parts_mut(out.as_mut_ptr() as *mut MaybeUninit<u8>, N) };
+        let result = self.compress_lzo1x_1_uninit(src, dst).and_then(|len| {
+            // SAFETY: the compressor initialized the first `len` bytes.
+            unsafe { out.set_len(len) };
+            self.check_max_output(&out)
+        });
+        instrument::compressed(timer, src.len(), result.as_ref().map(|_| out.len()));
+        result.map(|()| out)
+    }
 
-        if code == 0 && dst.len() < dst_len as usize {
-            dst.resize(dst_len as usize, 0);
+    #[inline]
+    fn check_max_output(&self, out: &[u8]) -> Result<(), CompressError> {
+        if let Some(max) = self.config.max_output.filter(|&max| out.len() > max) {
+            return Err(CompressError::OutputOverrun {
+                capacity: max,
+                needed: Some(out.len()),
+            });
         }
-        lzo_err_code_to_result(code, dst)
+        Ok(())
     }
 
-    /// safe decompression with overrun testing.
-    pub fn decompress_safe(&self, src: &[u8], dst_len: usize) -> LZOResult<Vec<u8>> {
-        let mut dst = vec![0u8; dst_len];
-        let code = unsafe {
-            minilzo::lzo1x_decompress_safe(
-                src.as_ptr(),
-                src.len() as minilzo::lzo_uint,
-                dst.as_mut_ptr(),
-                &dst_len as *const _ as *mut _,
-                std::ptr::null_mut(),
-            )
-        };
+    #[inline]
+    fn check_decompress(&self, dst_len: usize) -> Result<(), DecompressError> {
+        if self.is_poisoned() {
+            return Err(DecompressError::Poisoned);
+        }
+        if !self.config.algorithm.is_lzo1x() {
+            return Err(DecompressError::NotYetImplemented);
+        }
+        if self.config.max_output_size.is_some_and(|max| dst_len > max) {
+            return Err(DecompressError::OutputLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Decompress data produced by the configured algorithm, returning at
+    /// most `dst_len` bytes, without overrun testing (`unsafe-decompress`
+    /// feature).
+    ///
+    /// # Safety
+    ///
+    /// `src` must be well-formed LZO1X data decoding to at most `dst_len`
+    /// bytes, such as the output of `compress`: malformed input makes the
+    /// decoder read and write out of bounds. Use `decompress_safe` for data
+    /// that isn't trusted.
+    #[cfg(feature = "unsafe-decompress")]
+    #[inline]
+    pub unsafe fn decompress(
+        &self,
+        src: impl AsRef<[u8]>,
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        self.check_decompress(dst_len)?;
+        self.track(Decompressor::initialized().decompress(src.as_ref(), dst_len))
+    }
 
-        if code == 0 && dst.len() < dst_len as usize {
-            dst.resize(dst_len as usize, 0);
+    /// safe decompression with overrun testing. Returns at most `dst_len`
+    /// bytes, truncated to what the input actually decodes to.
+    #[inline]
+    pub fn decompress_safe(
+        &self,
+        src: impl AsRef<[u8]>,
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        self.check_decompress(dst_len)?;
+        self.track(Decompressor::initialized().decompress_safe(src.as_ref(), dst_len))
+    }
+
+    /// Decompress only the first `n` bytes of output; see
+    /// `Decompressor::decompress_prefix`.
+    #[inline]
+    pub fn decompress_prefix(
+        &self,
+        src: impl AsRef<[u8]>,
+        n: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        self.check_decompress(n)?;
+        self.track(Decompressor::initialized().decompress_prefix(src.as_ref(), n))
+    }
+
+    /// Decompress a stream followed by other data, returning the output
+    /// and the number of trailing bytes; see
+    /// `Decompressor::decompress_with_trailing`.
+    #[inline]
+    pub fn decompress_with_trailing(
+        &self,
+        src: impl AsRef<[u8]>,
+        dst_len: usize,
+    ) -> Result<(Vec<u8>, usize), DecompressError> {
+        self.check_decompress(dst_len)?;
+        self.track(Decompressor::initialized().decompress_with_trailing(src.as_ref(), dst_len))
+    }
+
+    /// Decompress in place, within one buffer; see
+    /// `Decompressor::decompress_in_place`.
+    #[inline]
+    pub fn decompress_in_place(
+        &self,
+        buf: &mut [u8],
+        comp_offset: usize,
+        comp_len: usize,
+        out_len: usize,
+    ) -> Result<usize, DecompressError> {
+        self.check_decompress(out_len)?;
+        self.track(Decompressor::initialized().decompress_in_place(
+            buf,
+            comp_offset,
+            comp_len,
+            out_len,
+        ))
+    }
+
+    /// Decompress into a possibly uninitialized buffer, with overrun
+    /// testing, and return the initialized prefix.
+    #[inline]
+    pub fn decompress_safe_uninit<'a>(
+        &self,
+        src: &[u8],
+        dst: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8], DecompressError> {
+        self.check_decompress(0)?;
+        let limited = self.config.max_output_size.filter(|&max| dst.len() > max);
+        let dst = match limited {
+            Some(max) => &mut dst[..max],
+            None => dst,
+        };
+        match Decompressor::initialized().decompress_safe_uninit(src, dst) {
+            Err(DecompressError::OutputOverrun { .. }) if limited.is_some() => {
+                Err(DecompressError::OutputLimitExceeded)
+            }
+            result => self.track(result),
         }
-        lzo_err_code_to_result(code, dst)
     }
 }
 
-/// Calculate the adler32 value of the data.
+/// Worst-case compressed size of `len` bytes of input.
+///
+/// `compress` never produces more than this, so a destination of this size
+/// always suffices, including a static array:
+///
+/// ```rust
+/// const OUT: usize = minilzo_rs::compress_bound(1024);
+/// let buf = [0u8; OUT];
+/// let out = minilzo_rs::compress(&[0x55u8; 1024]).unwrap();
+/// assert!(out.len() <= buf.len());
+/// ```
+#[inline]
+pub const fn compress_bound(len: usize) -> usize {
+    len + len / 16 + 64 + 3
+}
+
+/// The longest input `compress` accepts as one block: its worst-case
+/// output has to fit in a single allocation. Only 32-bit targets come near
+/// it; use `compress_chunked` or `LzoWriter` for larger inputs.
+pub const MAX_INPUT_LEN: usize = (isize::MAX as usize - 67) / 17 * 16;
+
+/// Smallest buffer `decompress_in_place` accepts for `out_len` bytes of
+/// output, with the compressed data stored at its end.
+///
+/// LZO1X's decoder never overtakes its input by more than the compressor's
+/// worst-case expansion, so this is the same as `compress_bound`.
+#[inline]
+pub const fn in_place_len(out_len: usize) -> usize {
+    compress_bound(out_len)
+}
+
+// `LZO`, `Compressor`, `Decompressor` and the pool are documented as
+// thread-safe; keep that a compile-time guarantee.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<LZO>();
+    assert_send_sync::<Compressor>();
+    assert_send_sync::<Decompressor>();
+    assert_send_sync::<LzoPool>();
+};
+
+thread_local! {
+    static THREAD_LZO: RefCell<Option<LZO>> = const { RefCell::new(None) };
+}
+
+/// Compress `src` using a work memory kept per thread.
+///
+/// The first call on a thread allocates the work memory and later calls
+/// reuse it, so hot paths pay no setup cost and threads never contend.
 ///
 /// Example
 ///
 /// ```rust
-/// let buff = [0x09u8; 1024];
-/// let checksum = minilzo_rs::adler32(&buff[..]);
-/// assert_eq!(checksum, 439886849);
+/// let out = minilzo_rs::compress(&[0x00u8; 1024]).unwrap();
+/// let input = minilzo_rs::decompress(&out, 1024).unwrap();
+/// assert_eq!(input, vec![0x00u8; 1024]);
 /// ```
-pub fn adler32(buf: &[u8]) -> u32 {
-    let checksum = 1u32;
-    let checksum = unsafe { minilzo::lzo_adler32(checksum, buf.as_ptr(), buf.len() as u64) };
-    checksum
+pub fn compress(src: impl AsRef<[u8]>) -> Result<Vec<u8>, CompressError> {
+    with_thread_lzo(|lzo| lzo.compress(src))
+}
+
+/// Run `f` with this thread's `LZO`, replacing it first if an earlier
+/// call poisoned it with an internal error.
+pub(crate) fn with_thread_lzo<T>(f: impl FnOnce(&mut LZO) -> T) -> T {
+    THREAD_LZO.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        let lzo = match slot.as_mut() {
+            Some(lzo) if !lzo.is_poisoned() => lzo,
+            _ => slot.insert(LZO::new()),
+        };
+        f(lzo)
+    })
+}
+
+/// Decompress `src` into at most `dst_len` bytes, with overrun testing.
+///
+/// Like `compress`, it needs no `LZO` value: minilzo is initialized on
+/// first use.
+///
+/// # Panics
+///
+/// Panics if minilzo's self-check fails, like `LZO::new`.
+pub fn decompress(src: impl AsRef<[u8]>, dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+    LZO::lzo_init().expect("minilzo self-check failed");
+    Decompressor::initialized().decompress_safe(src.as_ref(), dst_len)
 }
 
 #[cfg(test)]
@@ -211,9 +832,9 @@ mod tests {
     #[test]
     fn test_lzo_cmpress() {
         // test compress
-        let mut lzo = LZO::init().unwrap();
+        let mut lzo = LZO::new();
         let input = [0x00u8; 1024];
-        let out = lzo.compress(&input).unwrap();
+        let out = lzo.compress(input).unwrap();
 
         // test decompress
         let input_o = input.to_owned();
@@ -222,10 +843,190 @@ mod tests {
         assert_eq!(&input_o[..], &input[..]);
     }
 
+    #[test]
+    fn test_debug_and_clone() {
+        let lzo = LZO::builder()
+            .checksum(Checksum::Crc32)
+            .block_size(4096)
+            .build()
+            .unwrap();
+        let shown = format!("{:?}", lzo);
+        assert!(shown.starts_with("LZO { algorithm: Lzo1x1"));
+        assert!(shown.contains("checksum: Crc32") && shown.contains("block_size: 4096"));
+        assert!(shown.len() < 200);
+
+        let mut copy = lzo.clone();
+        assert_eq!(copy.checksum(), Checksum::Crc32);
+        assert_eq!(copy.block_size(), 4096);
+        let out = copy.compress([9u8; 500]).unwrap();
+        assert_eq!(lzo.decompress_safe(out, 500).unwrap(), [9u8; 500]);
+        assert_eq!(format!("{:?}", LZO::default()), format!("{:?}", LZO::new()));
+    }
+
+    #[test]
+    fn test_compress_boxed_exact() {
+        let mut lzo = LZO::new();
+        let input = [0x41u8; 10_000];
+        let out = lzo.compress(input).unwrap();
+        assert!(out.capacity() > out.len());
+        let boxed = lzo.compress_boxed(input).unwrap();
+        assert_eq!(&boxed[..], &out[..]);
+    }
+
+    #[test]
+    fn test_as_ref_inputs() {
+        let text = String::from("as ref as ref as ref as ref");
+        let mut lzo = LZO::new();
+        let out = lzo.compress(&text).unwrap();
+        assert_eq!(out, lzo.compress(text.clone().into_bytes()).unwrap());
+        assert_eq!(
+            lzo.decompress_safe(&out, text.len()).unwrap(),
+            text.as_bytes()
+        );
+        assert_eq!(decompress(out, text.len()).unwrap(), text.as_bytes());
+        assert_eq!(adler32(&text), adler32(text.as_bytes()));
+        assert_eq!(crc32("123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_init_alias() {
+        let mut lzo = LZO::init().unwrap();
+        let out = lzo.compress([7u8; 100]).unwrap();
+        assert_eq!(
+            LZO::default().decompress_safe(&out, 100).unwrap(),
+            [7u8; 100]
+        );
+    }
+
+    #[test]
+    fn test_lzo_is_small() {
+        assert!(size_of::<LZO>() < 128);
+        let lzo = LZO::new();
+        let moved = std::thread::spawn(move || lzo).join().unwrap();
+        assert!(!moved.is_poisoned());
+    }
+
     #[test]
     fn test_adler32() {
         let buff = [0x09u8; 1024];
-        let checksum = adler32(&buff);
+        let checksum = adler32(buff);
         assert_eq!(checksum, 439886849);
     }
+
+    #[test]
+    fn test_poisoned() {
+        let mut lzo = LZO::new();
+        assert_eq!(
+            lzo.track(lzo_err_code_to_result(-99, ())),
+            Err(Error::InternalError)
+        );
+        assert!(lzo.is_poisoned());
+        assert_eq!(lzo.compress([0u8; 16]), Err(CompressError::Poisoned));
+        assert_eq!(
+            lzo.decompress_safe([0u8; 16], 16),
+            Err(DecompressError::Poisoned)
+        );
+    }
+
+    #[test]
+    fn test_compress_fixed() {
+        const N: usize = compress_bound(1000);
+        let mut lzo = LZO::new();
+        let decompressor = Decompressor::new().unwrap();
+        for len in [0usize, 1, 300, 1000] {
+            let input: Vec<u8> = (0..len).map(|i| (i % 31) as u8).collect();
+            let (out, n) = lzo.compress_fixed::<N>(&input).unwrap();
+            assert_eq!(&out[..n], &lzo.compress(&input).unwrap()[..]);
+            let (back, m) = decompressor.decompress_fixed::<1000>(&out[..n]).unwrap();
+            assert_eq!(&back[..m], &input[..]);
+            if len > 1 {
+                assert!(matches!(
+                    decompressor.decompress_fixed::<1>(&out[..n]),
+                    Err(DecompressError::OutputOverrun { capacity: 1, .. })
+                ));
+            }
+        }
+        assert_eq!(
+            lzo.compress_fixed::<N>(vec![0u8; 1001]).unwrap_err(),
+            CompressError::OutputOverrun {
+                capacity: N,
+                needed: Some(compress_bound(1001))
+            }
+        );
+    }
+
+    #[cfg(feature = "smallvec")]
+    #[test]
+    fn test_compress_small() {
+        const N: usize = compress_bound(4096);
+        let mut lzo = LZO::new();
+        let mut auto = LZO::builder().algorithm(Algorithm::Auto).build().unwrap();
+        for len in [0usize, 1, 200, 4096, 4097, 100_000] {
+            let input: Vec<u8> = (0..len).map(|i| (i % 97) as u8).collect();
+            let out = lzo.compress_small::<N>(&input).unwrap();
+            assert_eq!(&out[..], &lzo.compress(&input).unwrap()[..]);
+            assert_eq!(out.spilled(), len > 4096);
+            assert_eq!(&auto.compress_small::<N>(&input).unwrap()[..], &out[..]);
+        }
+        let noise: Vec<u8> = (0..4000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let stored = auto.compress_small::<N>(&noise).unwrap();
+        assert_eq!(&stored[..], &auto.compress(&noise).unwrap()[..]);
+        assert_eq!(decompress(&stored, noise.len()).unwrap(), noise);
+
+        let mut capped = LZO::builder().max_output(10).build().unwrap();
+        assert!(matches!(
+            capped.compress_small::<N>([7u8; 100]),
+            Err(CompressError::OutputOverrun { capacity: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn test_thread_local_oneshot() {
+        std::thread::scope(|s| {
+            for seed in 0..4u8 {
+                s.spawn(move || {
+                    for len in [0usize, 1, 1000, 100_000] {
+                        let input: Vec<u8> = (0..len).map(|i| (i as u8) ^ seed).collect();
+                        let out = compress(&input).unwrap();
+                        assert_eq!(decompress(&out, len).unwrap(), input);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_shared_decompress_and_moved_compress() {
+        let input: Vec<u8> = (0..50_000u32).map(|i| (i % 41) as u8).collect();
+        let mut lzo = LZO::new();
+        let compressed = lzo.compress(&input).unwrap();
+        let shared = &lzo;
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        let out = shared.decompress_safe(&compressed, input.len()).unwrap();
+                        assert_eq!(out, input);
+                    }
+                });
+            }
+        });
+
+        let handles: Vec<_> = (0..4u8)
+            .map(|seed| {
+                let mut lzo = LZO::new();
+                std::thread::spawn(move || {
+                    let data = vec![seed; 10_000];
+                    let out = lzo.compress(&data).unwrap();
+                    lzo.decompress_safe(&out, data.len()).unwrap() == data
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
 }
@@ -0,0 +1,130 @@
+//! Decompression of data whose uncompressed size isn't known.
+
+use crate::{DecompressError, Decompressor, LZO, MAX_BLOCK_SIZE};
+
+/// Bounds and buffer-growth policy for `decompress_auto`.
+///
+/// The output buffer starts at `initial_ratio` times the input length and
+/// is grown by `growth_factor` each time decompression overruns it, up to
+/// `max_output` bytes. The same buffer is reused across retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest output accepted, in bytes.
+    pub max_output: usize,
+    /// Initial output buffer size, as a multiple of the input length.
+    pub initial_ratio: usize,
+    /// Factor the output buffer grows by after each overrun.
+    pub growth_factor: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_output: MAX_BLOCK_SIZE,
+            initial_ratio: 4,
+            growth_factor: 2,
+        }
+    }
+}
+
+impl Decompressor {
+    /// Decompress `src` without knowing its uncompressed size, retrying
+    /// with a larger buffer as `limits` allow.
+    ///
+    /// Fails with `DecompressError::OutputOverrun` if the output would exceed
+    /// `limits.max_output`, in which case its `capacity` is the largest
+    /// buffer tried.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{Decompressor, Limits, LZO};
+    ///
+    /// let out = LZO::new().compress(&[0x00u8; 100_000]).unwrap();
+    /// let decompressor = Decompressor::new().unwrap();
+    /// let input = decompressor.decompress_auto(&out, &Limits::default()).unwrap();
+    /// assert_eq!(input.len(), 100_000);
+    /// ```
+    pub fn decompress_auto(&self, src: &[u8], limits: &Limits) -> Result<Vec<u8>, DecompressError> {
+        if limits.max_output == 0 || limits.initial_ratio == 0 || limits.growth_factor < 2 {
+            return Err(DecompressError::InvalidArgument);
+        }
+        let mut len = src
+            .len()
+            .saturating_mul(limits.initial_ratio)
+            .clamp(1, limits.max_output);
+        let mut buf = crate::scratch::try_zeroed(len)?;
+        loop {
+            match self.decompress_safe_slice(src, &mut buf) {
+                Ok(n) => {
+                    buf.truncate(n);
+                    return Ok(buf);
+                }
+                Err(DecompressError::OutputOverrun { .. }) if len < limits.max_output => {
+                    len = len
+                        .saturating_mul(limits.growth_factor)
+                        .min(limits.max_output);
+                    buf.try_reserve_exact(len - buf.len())?;
+                    buf.resize(len, 0);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl LZO {
+    /// Decompress `src` without knowing its uncompressed size; see
+    /// `Decompressor::decompress_auto`.
+    pub fn decompress_auto(&self, src: &[u8], limits: &Limits) -> Result<Vec<u8>, DecompressError> {
+        if self.is_poisoned() {
+            return Err(DecompressError::Poisoned);
+        }
+        let mut limits = *limits;
+        let limited = self
+            .config
+            .max_output_size
+            .filter(|&max| max < limits.max_output);
+        if let Some(max) = limited {
+            limits.max_output = max;
+        }
+        match Decompressor::initialized().decompress_auto(src, &limits) {
+            Err(DecompressError::OutputOverrun { .. }) if limited.is_some() => {
+                Err(DecompressError::OutputLimitExceeded)
+            }
+            result => self.track(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_auto_growth_and_cap() {
+        let input: Vec<u8> = (0..200_000u32).map(|i| (i % 5) as u8).collect();
+        let mut lzo = LZO::new();
+        let out = lzo.compress(&input).unwrap();
+        assert!(out.len() * 4 < input.len());
+
+        let limits = Limits {
+            max_output: input.len(),
+            initial_ratio: 1,
+            growth_factor: 3,
+        };
+        assert_eq!(lzo.decompress_auto(&out, &limits).unwrap(), input);
+
+        let capped = Limits {
+            max_output: input.len() - 1,
+            ..limits
+        };
+        assert_eq!(
+            lzo.decompress_auto(&out, &capped),
+            Err(DecompressError::OutputOverrun {
+                capacity: input.len() - 1,
+                needed: None
+            })
+        );
+    }
+}
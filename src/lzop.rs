@@ -0,0 +1,443 @@
+//! Reading and writing `.lzo` files in the lzop container format.
+//!
+//! Only single-member files are supported, and header filters are
+//! rejected. Every checksum a file carries, adler32 or CRC-32, is verified
+//! as its block is read, so damage surfaces at the block it hits.
+
+use crate::frame::to_io;
+use crate::scratch::{try_zeroed, Scratch};
+use crate::{adler32, crc32, Decompressor, Error, LZOResult, LZO};
+use std::io::{self, Read, Write};
+
+pub(crate) const LZOP_MAGIC: &[u8; 9] = &[0x89, b'L', b'Z', b'O', 0x00, 0x0d, 0x0a, 0x1a, 0x0a];
+
+const LZOP_VERSION: u16 = 0x1030;
+const LZOP_VERSION_NEEDED: u16 = 0x0940;
+const M_LZO1X_1: u8 = 1;
+const M_LZO1X_1_15: u8 = 2;
+const M_LZO1X_999: u8 = 3;
+
+const F_ADLER32_D: u32 = 0x0000_0001;
+const F_ADLER32_C: u32 = 0x0000_0002;
+const F_H_EXTRA_FIELD: u32 = 0x0000_0040;
+const F_CRC32_D: u32 = 0x0000_0100;
+const F_CRC32_C: u32 = 0x0000_0200;
+const F_H_FILTER: u32 = 0x0000_0800;
+const F_H_CRC32: u32 = 0x0000_1000;
+
+/// Block size lzop itself uses.
+const LZOP_BLOCK_SIZE: usize = 256 * 1024;
+const LZOP_MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+fn read_u8<R: Read>(r: &mut R, header: &mut Vec<u8>) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    header.extend_from_slice(&b);
+    Ok(b[0])
+}
+
+fn read_u16<R: Read>(r: &mut R, header: &mut Vec<u8>) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    header.extend_from_slice(&b);
+    Ok(u16::from_be_bytes(b))
+}
+
+fn read_u32<R: Read>(r: &mut R, header: &mut Vec<u8>) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    header.extend_from_slice(&b);
+    Ok(u32::from_be_bytes(b))
+}
+
+fn read_be32<R: Read>(r: &mut R) -> io::Result<u32> {
+    read_u32(r, &mut Vec::new())
+}
+
+fn flags_supported(flags: u32) -> LZOResult<()> {
+    if flags & F_H_FILTER != 0 {
+        return Err(Error::NotYetImplemented);
+    }
+    Ok(())
+}
+
+/// The header checksum of a file with `flags`: CRC-32 with `F_H_CRC32`
+/// set, adler32 otherwise.
+fn header_checksum(flags: u32, header: &[u8]) -> u32 {
+    if flags & F_H_CRC32 != 0 {
+        crc32(header)
+    } else {
+        adler32(header)
+    }
+}
+
+/// The adler32 and CRC-32 of a block that carries them, in file order.
+struct BlockChecksums {
+    adler32: Option<u32>,
+    crc32: Option<u32>,
+}
+
+impl BlockChecksums {
+    fn read<R: Read>(r: &mut R, adler32: bool, crc32: bool) -> io::Result<Self> {
+        Ok(BlockChecksums {
+            adler32: if adler32 { Some(read_be32(r)?) } else { None },
+            crc32: if crc32 { Some(read_be32(r)?) } else { None },
+        })
+    }
+
+    fn verify(&self, data: &[u8]) -> io::Result<()> {
+        if self.adler32.is_some_and(|c| c != adler32(data))
+            || self.crc32.is_some_and(|c| c != crc32(data))
+        {
+            return Err(to_io(Error::ChecksumMismatch));
+        }
+        Ok(())
+    }
+}
+
+/// Decompresses an lzop file read from the inner reader.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// let mut writer = minilzo_rs::LzopWriter::new(Vec::new()).unwrap();
+/// writer.write_all(&[0x00u8; 1024]).unwrap();
+/// let file = writer.finish().unwrap();
+///
+/// let mut reader = minilzo_rs::LzopReader::new(&file[..]).unwrap();
+/// let mut output = Vec::new();
+/// reader.read_to_end(&mut output).unwrap();
+/// assert_eq!(output, vec![0x00u8; 1024]);
+/// ```
+pub struct LzopReader<R: Read> {
+    inner: R,
+    decompressor: Decompressor,
+    flags: u32,
+    name: Vec<u8>,
+    block: Scratch,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> LzopReader<R> {
+    /// Create a reader, parsing and verifying the lzop header.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 9];
+        inner.read_exact(&mut magic)?;
+        if &magic != LZOP_MAGIC {
+            return Err(to_io(Error::InvalidHeader));
+        }
+        let mut header = Vec::new();
+        let version = read_u16(&mut inner, &mut header)?;
+        let _lib_version = read_u16(&mut inner, &mut header)?;
+        if version < 0x0900 {
+            return Err(to_io(Error::InvalidHeader));
+        }
+        if version >= 0x0940 && read_u16(&mut inner, &mut header)? > LZOP_VERSION {
+            return Err(to_io(Error::NotYetImplemented));
+        }
+        let method = read_u8(&mut inner, &mut header)?;
+        if ![M_LZO1X_1, M_LZO1X_1_15, M_LZO1X_999].contains(&method) {
+            return Err(to_io(Error::NotYetImplemented));
+        }
+        if version >= 0x0940 {
+            read_u8(&mut inner, &mut header)?;
+        }
+        let flags = read_u32(&mut inner, &mut header)?;
+        flags_supported(flags).map_err(to_io)?;
+        let _mode = read_u32(&mut inner, &mut header)?;
+        let _mtime_low = read_u32(&mut inner, &mut header)?;
+        if version >= 0x0940 {
+            read_u32(&mut inner, &mut header)?;
+        }
+        let name_len = read_u8(&mut inner, &mut header)? as usize;
+        let mut name = vec![0u8; name_len];
+        inner.read_exact(&mut name)?;
+        header.extend_from_slice(&name);
+        if read_be32(&mut inner)? != header_checksum(flags, &header) {
+            return Err(to_io(Error::ChecksumMismatch));
+        }
+        if flags & F_H_EXTRA_FIELD != 0 {
+            let mut extra = Vec::new();
+            let len = read_u32(&mut inner, &mut extra)? as usize;
+            (&mut inner).take(len as u64).read_to_end(&mut extra)?;
+            if extra.len() != 4 + len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if read_be32(&mut inner)? != header_checksum(flags, &extra) {
+                return Err(to_io(Error::ChecksumMismatch));
+            }
+        }
+        Ok(LzopReader {
+            inner,
+            decompressor: Decompressor::new().map_err(to_io)?,
+            flags,
+            name,
+            block: Scratch::new(),
+            pos: 0,
+            done: false,
+        })
+    }
+
+    /// The original file name stored in the header, if any.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Unwrap the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn next_block(&mut self) -> io::Result<()> {
+        let dst_len = read_be32(&mut self.inner)? as usize;
+        if dst_len == 0 {
+            self.done = true;
+            self.block.clear();
+            self.pos = 0;
+            return Ok(());
+        }
+        let src_len = read_be32(&mut self.inner)? as usize;
+        if dst_len > LZOP_MAX_BLOCK_SIZE || src_len > dst_len {
+            return Err(to_io(Error::InvalidHeader));
+        }
+        let flags = self.flags;
+        let d_checksums = BlockChecksums::read(
+            &mut self.inner,
+            flags & F_ADLER32_D != 0,
+            flags & F_CRC32_D != 0,
+        )?;
+        let compressed = src_len < dst_len;
+        let c_checksums = BlockChecksums::read(
+            &mut self.inner,
+            compressed && flags & F_ADLER32_C != 0,
+            compressed && flags & F_CRC32_C != 0,
+        )?;
+        let mut payload = try_zeroed(src_len).map_err(Error::from)?;
+        self.inner.read_exact(&mut payload)?;
+        c_checksums.verify(&payload)?;
+        self.block = Scratch::from(if src_len < dst_len {
+            self.decompressor
+                .decompress_safe(&payload, dst_len)
+                .map_err(to_io)?
+        } else {
+            payload
+        });
+        if self.block.len() != dst_len {
+            return Err(to_io(Error::InputOverrun));
+        }
+        d_checksums.verify(&self.block)?;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LzopReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.block.len() && !self.done {
+            self.next_block()?;
+        }
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Compresses everything written to it into an lzop file.
+///
+/// Blocks carry adler32 checksums of both the uncompressed and compressed
+/// data. As with `LzoWriter`, call `finish` to write the end marker.
+pub struct LzopWriter<W: Write> {
+    inner: Option<W>,
+    lzo: LZO,
+    buf: Scratch,
+    header_written: bool,
+    name: Vec<u8>,
+    flags: u32,
+}
+
+impl<W: Write> LzopWriter<W> {
+    /// Create a writer for an lzop file without a stored file name.
+    pub fn new(inner: W) -> LZOResult<Self> {
+        Self::with_name(inner, &[])
+    }
+
+    /// Create a writer recording `name` as the original file name.
+    pub fn with_name(inner: W, name: &[u8]) -> LZOResult<Self> {
+        if name.len() > u8::MAX as usize {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(LzopWriter {
+            inner: Some(inner),
+            lzo: LZO::new(),
+            buf: Scratch::with_capacity(LZOP_BLOCK_SIZE),
+            header_written: false,
+            name: name.to_vec(),
+            flags: F_ADLER32_D | F_ADLER32_C,
+        })
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&LZOP_VERSION.to_be_bytes());
+        header.extend_from_slice(&(unsafe { crate::minilzo::lzo_version() } as u16).to_be_bytes());
+        header.extend_from_slice(&LZOP_VERSION_NEEDED.to_be_bytes());
+        header.push(M_LZO1X_1);
+        header.push(5);
+        header.extend_from_slice(&self.flags.to_be_bytes());
+        header.extend_from_slice(&0o100644u32.to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes());
+        header.push(self.name.len() as u8);
+        header.extend_from_slice(&self.name);
+        let checksum = header_checksum(self.flags, &header);
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(LZOP_MAGIC)?;
+        inner.write_all(&header)?;
+        inner.write_all(&checksum.to_be_bytes())?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let compressed = self.lzo.compress(&self.buf).map_err(to_io)?;
+        let payload = if compressed.len() < self.buf.len() {
+            &compressed[..]
+        } else {
+            &self.buf[..]
+        };
+        let mut block = Vec::with_capacity(24);
+        block.extend_from_slice(&(self.buf.len() as u32).to_be_bytes());
+        block.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        let mut push_checksums = |data: &[u8], adler32_flag: u32, crc32_flag: u32| {
+            if self.flags & adler32_flag != 0 {
+                block.extend_from_slice(&adler32(data).to_be_bytes());
+            }
+            if self.flags & crc32_flag != 0 {
+                block.extend_from_slice(&crc32(data).to_be_bytes());
+            }
+        };
+        push_checksums(&self.buf, F_ADLER32_D, F_CRC32_D);
+        if payload.len() < self.buf.len() {
+            push_checksums(payload, F_ADLER32_C, F_CRC32_C);
+        }
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&block)?;
+        inner.write_all(payload)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Compress any buffered data, write the end marker and return the
+    /// inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        let mut inner = self.inner.take().unwrap();
+        inner.write_all(&0u32.to_be_bytes())?;
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for LzopWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(LZOP_BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == LZOP_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header of an empty file named "hello" as written by lzop 1.04 with
+    // `-1` (LZO1X-1(15), adler32 on data), minus the header checksum.
+    const HELLO_LZO: &[u8] = &[
+        0x89, 0x4c, 0x5a, 0x4f, 0x00, 0x0d, 0x0a, 0x1a, 0x0a, 0x10, 0x40, 0x20, 0xa0, 0x09, 0x40,
+        0x02, 0x01, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0x81, 0xa4, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x05, b'h', b'e', b'l', b'l', b'o',
+    ];
+
+    #[test]
+    fn test_lzop_roundtrip() {
+        let input: Vec<u8> = (0..600_000u32).map(|i| (i % 13) as u8).collect();
+        let mut writer = LzopWriter::with_name(Vec::new(), b"data.bin").unwrap();
+        writer.write_all(&input).unwrap();
+        let file = writer.finish().unwrap();
+        assert_eq!(&file[..9], LZOP_MAGIC);
+
+        let mut reader = LzopReader::new(&file[..]).unwrap();
+        assert_eq!(reader.name(), b"data.bin");
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_lzop_header_checksum() {
+        let mut file = HELLO_LZO.to_vec();
+        let checksum = adler32(&file[9..]);
+        file.extend_from_slice(&checksum.to_be_bytes());
+        file.extend_from_slice(&0u32.to_be_bytes());
+        let mut reader = LzopReader::new(&file[..]).unwrap();
+        assert_eq!(reader.name(), b"hello");
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert!(output.is_empty());
+
+        file[20] ^= 0x01;
+        assert!(LzopReader::new(&file[..]).is_err());
+    }
+    #[test]
+    fn test_lzop_crc32_checksums() {
+        let input: Vec<u8> = (0..700_000u32).map(|i| (i / 3 % 17) as u8).collect();
+        let mut writer = LzopWriter::new(Vec::new()).unwrap();
+        writer.flags = F_ADLER32_D | F_CRC32_D | F_CRC32_C | F_H_CRC32;
+        writer.write_all(&input).unwrap();
+        let file = writer.finish().unwrap();
+        let mut output = Vec::new();
+        LzopReader::new(&file[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+
+        // Damage in the second block fails there, after the first block has
+        // been read.
+        let first_block =
+            20 + u32::from_be_bytes([file[42], file[43], file[44], file[45]]) as usize;
+        let mut damaged = file.clone();
+        let at = 38 + first_block + 25;
+        damaged[at] ^= 0x20;
+        let mut reader = LzopReader::new(&damaged[..]).unwrap();
+        let mut block = vec![0u8; LZOP_BLOCK_SIZE];
+        reader.read_exact(&mut block).unwrap();
+        assert_eq!(block, &input[..LZOP_BLOCK_SIZE]);
+        let err = reader.read(&mut block).unwrap_err();
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<Error>(),
+            Some(&Error::ChecksumMismatch)
+        );
+
+        let mut header = file.clone();
+        header[20] ^= 0x01;
+        assert!(LzopReader::new(&header[..]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Metadata-only "manifest" of a block-compressed payload.
+//!
+//! A manifest records, for every fixed-size block of the input, the
+//! uncompressed size, the compressed size and the adler32 of the block,
+//! without keeping any compressed bytes. It is what a full archive *would*
+//! contain, and is cheap to store for dry-run planning and dedupe estimates.
+
+use crate::{adler32, Error, LZOResult, LZO};
+use std::collections::HashSet;
+
+const MANIFEST_MAGIC: &[u8; 4] = b"MLZM";
+const MANIFEST_VERSION: u8 = 1;
+const MANIFEST_HEADER_LEN: usize = 13;
+const BLOCK_META_LEN: usize = 12;
+
+/// Sizes and checksum of a single compressed block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockMeta {
+    /// Length of the block before compression.
+    pub uncompressed_len: u32,
+    /// Length of the block after compression. Equal to `uncompressed_len`
+    /// for a block stored uncompressed.
+    pub compressed_len: u32,
+    /// adler32 of the uncompressed block.
+    pub checksum: u32,
+}
+
+impl BlockMeta {
+    pub(crate) fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.uncompressed_len.to_be_bytes());
+        out.extend_from_slice(&self.compressed_len.to_be_bytes());
+        out.extend_from_slice(&self.checksum.to_be_bytes());
+    }
+
+    pub(crate) fn read_from(buf: &[u8]) -> Self {
+        let be = |i: usize| u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        BlockMeta {
+            uncompressed_len: be(0),
+            compressed_len: be(4),
+            checksum: be(8),
+        }
+    }
+}
+
+/// The block metadata of a payload, without the payload itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Block size the input was split with.
+    pub block_size: u32,
+    /// One entry per block, in input order.
+    pub blocks: Vec<BlockMeta>,
+}
+
+impl Manifest {
+    /// Total length of the input.
+    pub fn total_uncompressed(&self) -> u64 {
+        self.blocks.iter().map(|b| b.uncompressed_len as u64).sum()
+    }
+
+    /// Total length of all compressed blocks.
+    pub fn total_compressed(&self) -> u64 {
+        self.blocks.iter().map(|b| b.compressed_len as u64).sum()
+    }
+
+    /// Number of distinct blocks, an estimate of what dedupe would keep.
+    pub fn unique_blocks(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|b| (b.uncompressed_len, b.checksum))
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Serialize the manifest into its compact binary form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MANIFEST_HEADER_LEN + self.blocks.len() * BLOCK_META_LEN);
+        out.extend_from_slice(MANIFEST_MAGIC);
+        out.push(MANIFEST_VERSION);
+        out.extend_from_slice(&self.block_size.to_be_bytes());
+        out.extend_from_slice(&(self.blocks.len() as u32).to_be_bytes());
+        for block in &self.blocks {
+            block.write_to(&mut out);
+        }
+        out
+    }
+
+    /// Parse a manifest produced by `to_bytes`.
+    pub fn from_bytes(buf: &[u8]) -> LZOResult<Self> {
+        if buf.len() < MANIFEST_HEADER_LEN
+            || &buf[..4] != MANIFEST_MAGIC
+            || buf[4] != MANIFEST_VERSION
+        {
+            return Err(Error::InvalidHeader);
+        }
+        let block_size = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]);
+        let count = u32::from_be_bytes([buf[9], buf[10], buf[11], buf[12]]) as usize;
+        let body = &buf[MANIFEST_HEADER_LEN..];
+        if body.len() != count.saturating_mul(BLOCK_META_LEN) {
+            return Err(Error::InvalidHeader);
+        }
+        let blocks = body
+            .chunks(BLOCK_META_LEN)
+            .map(BlockMeta::read_from)
+            .collect();
+        Ok(Manifest { block_size, blocks })
+    }
+}
+
+impl LZO {
+    /// Compress `src` block by block, keeping only the block metadata.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let input = [0x00u8; 4096];
+    /// let manifest = lzo.manifest(&input, 1024).unwrap();
+    /// assert_eq!(manifest.blocks.len(), 4);
+    /// assert_eq!(manifest.total_uncompressed(), 4096);
+    /// assert_eq!(manifest.unique_blocks(), 1);
+    /// ```
+    pub fn manifest(&mut self, src: &[u8], block_size: usize) -> LZOResult<Manifest> {
+        if block_size == 0 || block_size > u32::MAX as usize {
+            return Err(Error::InvalidArgument);
+        }
+        let mut blocks = Vec::with_capacity(src.len().div_ceil(block_size));
+        for chunk in src.chunks(block_size) {
+            let payload = crate::frame::block_payload(self, chunk)?;
+            blocks.push(BlockMeta {
+                uncompressed_len: chunk.len() as u32,
+                compressed_len: payload.len() as u32,
+                checksum: adler32(chunk),
+            });
+        }
+        Ok(Manifest {
+            block_size: block_size as u32,
+            blocks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let mut lzo = LZO::new();
+        let input: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let manifest = lzo.manifest(&input, 4096).unwrap();
+        assert_eq!(manifest.blocks.len(), 3);
+        assert_eq!(manifest.blocks[2].uncompressed_len, 10000 - 8192);
+        let bytes = manifest.to_bytes();
+        assert_eq!(Manifest::from_bytes(&bytes).unwrap(), manifest);
+        assert_eq!(
+            Manifest::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidHeader)
+        );
+    }
+}
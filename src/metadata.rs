@@ -0,0 +1,221 @@
+//! Descriptive metadata carried in a framed stream's header.
+//!
+//! Streams with `FLAG_METADATA` set follow the fixed header with a `u32`
+//! length and that many bytes of fields, each a tag byte, a `u16` length
+//! and the value, all big-endian. Decoders skip tags they don't know, so
+//! fields can be added without a new version.
+
+use crate::frame;
+use crate::{Error, LZOResult};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Largest metadata section the decoders accept.
+pub(crate) const MAX_METADATA_LEN: usize = 64 * 1024;
+
+const TAG_NAME: u8 = 1;
+const TAG_MTIME: u8 = 2;
+const TAG_CONTENT_TYPE: u8 = 3;
+const TAG_USER: u8 = 4;
+
+/// What a framed stream says about its contents, so archives don't need a
+/// sidecar manifest. Every field is optional.
+///
+/// Set it with `LzoWriter::with_metadata`, and read it back with
+/// `LzoReader::metadata` or `frame_metadata`.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+/// use minilzo_rs::{FrameMetadata, LzoReader, LzoWriter, LZO};
+///
+/// let mut metadata = FrameMetadata {
+///     name: Some("report.csv".into()),
+///     content_type: Some("text/csv".into()),
+///     ..Default::default()
+/// };
+/// metadata.user.insert("owner".into(), "finance".into());
+///
+/// let mut writer = LzoWriter::with_metadata(Vec::new(), LZO::new(), &metadata).unwrap();
+/// writer.write_all(b"a,b\n1,2\n").unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let mut reader = LzoReader::new(&framed[..]).unwrap();
+/// assert_eq!(reader.metadata().unwrap(), Some(&metadata));
+/// let mut data = Vec::new();
+/// reader.read_to_end(&mut data).unwrap();
+/// assert_eq!(data, b"a,b\n1,2\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameMetadata {
+    /// The original file name.
+    pub name: Option<String>,
+    /// The original modification time, from the Unix epoch on.
+    pub mtime: Option<SystemTime>,
+    /// The MIME type of the contents.
+    pub content_type: Option<String>,
+    /// Any other key/value pairs.
+    pub user: BTreeMap<String, String>,
+}
+
+impl FrameMetadata {
+    /// The encoded fields. Fails with `Error::InvalidArgument` if a field
+    /// is longer than 65535 bytes, the whole is longer than 64 KiB, or
+    /// `mtime` is before the epoch.
+    pub(crate) fn encode(&self) -> LZOResult<Vec<u8>> {
+        let mut out = Vec::new();
+        if let Some(name) = &self.name {
+            push_field(&mut out, TAG_NAME, &[name.as_bytes()])?;
+        }
+        if let Some(mtime) = self.mtime {
+            let since = mtime
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| Error::InvalidArgument)?;
+            let secs = since.as_secs().to_be_bytes();
+            let nanos = since.subsec_nanos().to_be_bytes();
+            push_field(&mut out, TAG_MTIME, &[&secs, &nanos])?;
+        }
+        if let Some(content_type) = &self.content_type {
+            push_field(&mut out, TAG_CONTENT_TYPE, &[content_type.as_bytes()])?;
+        }
+        for (key, value) in &self.user {
+            let key_len = u16::try_from(key.len()).map_err(|_| Error::InvalidArgument)?;
+            let parts = [&key_len.to_be_bytes()[..], key.as_bytes(), value.as_bytes()];
+            push_field(&mut out, TAG_USER, &parts)?;
+        }
+        if out.len() > MAX_METADATA_LEN {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(out)
+    }
+
+    /// Parse encoded fields, failing with `Error::InvalidHeader` if they
+    /// are malformed.
+    pub(crate) fn decode(mut buf: &[u8]) -> LZOResult<Self> {
+        let mut metadata = FrameMetadata::default();
+        while !buf.is_empty() {
+            if buf.len() < 3 {
+                return Err(Error::InvalidHeader);
+            }
+            let len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+            let value = buf.get(3..3 + len).ok_or(Error::InvalidHeader)?;
+            match buf[0] {
+                TAG_NAME => metadata.name = Some(utf8(value)?),
+                TAG_MTIME => {
+                    if len != 12 {
+                        return Err(Error::InvalidHeader);
+                    }
+                    let mut secs = [0u8; 8];
+                    secs.copy_from_slice(&value[..8]);
+                    let nanos = u32::from_be_bytes([value[8], value[9], value[10], value[11]]);
+                    let since = Duration::new(u64::from_be_bytes(secs), nanos);
+                    let mtime = UNIX_EPOCH.checked_add(since);
+                    metadata.mtime = Some(mtime.ok_or(Error::InvalidHeader)?);
+                }
+                TAG_CONTENT_TYPE => metadata.content_type = Some(utf8(value)?),
+                TAG_USER => {
+                    if len < 2 {
+                        return Err(Error::InvalidHeader);
+                    }
+                    let key_len = u16::from_be_bytes([value[0], value[1]]) as usize;
+                    let key = value.get(2..2 + key_len).ok_or(Error::InvalidHeader)?;
+                    let value = &value[2 + key_len..];
+                    metadata.user.insert(utf8(key)?, utf8(value)?);
+                }
+                _ => {}
+            }
+            buf = &buf[3 + len..];
+        }
+        Ok(metadata)
+    }
+}
+
+/// The metadata in the header of the framed stream `src` starts with, if
+/// it has any, without decoding any blocks.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Write;
+/// use minilzo_rs::{FrameMetadata, LzoWriter, LZO};
+///
+/// let metadata = FrameMetadata {
+///     name: Some("notes.txt".into()),
+///     ..Default::default()
+/// };
+/// let mut writer = LzoWriter::with_metadata(Vec::new(), LZO::new(), &metadata).unwrap();
+/// writer.write_all(b"remember the milk").unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let found = minilzo_rs::frame_metadata(&framed).unwrap().unwrap();
+/// assert_eq!(found.name.as_deref(), Some("notes.txt"));
+///
+/// let plain = LZO::new().compress_chunked(b"no metadata").unwrap();
+/// assert_eq!(minilzo_rs::frame_metadata(&plain).unwrap(), None);
+/// ```
+pub fn frame_metadata(src: &[u8]) -> LZOResult<Option<FrameMetadata>> {
+    frame::parse_header(src)?;
+    frame::metadata_from_header(src)
+}
+
+fn push_field(out: &mut Vec<u8>, tag: u8, parts: &[&[u8]]) -> LZOResult<()> {
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+    let len = u16::try_from(len).map_err(|_| Error::InvalidArgument)?;
+    out.push(tag);
+    out.extend_from_slice(&len.to_be_bytes());
+    for part in parts {
+        out.extend_from_slice(part);
+    }
+    Ok(())
+}
+
+fn utf8(buf: &[u8]) -> LZOResult<String> {
+    String::from_utf8(buf.to_vec()).map_err(|_| Error::InvalidHeader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_encoding() {
+        let mut metadata = FrameMetadata {
+            name: Some("disk.img".into()),
+            mtime: Some(UNIX_EPOCH + Duration::new(1_700_000_000, 123)),
+            content_type: None,
+            user: BTreeMap::new(),
+        };
+        metadata.user.insert("host".into(), "db-1".into());
+        metadata.user.insert("empty".into(), String::new());
+        let encoded = metadata.encode().unwrap();
+        assert_eq!(FrameMetadata::decode(&encoded).unwrap(), metadata);
+        assert_eq!(
+            FrameMetadata::decode(&[]).unwrap(),
+            FrameMetadata::default()
+        );
+
+        // Unknown fields are skipped; cut-off ones are rejected.
+        let mut extended = vec![99, 0, 2, 0xde, 0xad];
+        extended.extend_from_slice(&encoded);
+        assert_eq!(FrameMetadata::decode(&extended).unwrap(), metadata);
+        for len in 1..encoded.len() {
+            if let Ok(partial) = FrameMetadata::decode(&encoded[..len]) {
+                assert_ne!(partial, metadata);
+            }
+        }
+        assert!(FrameMetadata::decode(&encoded[..encoded.len() - 1]).is_err());
+
+        let long = FrameMetadata {
+            name: Some("x".repeat(70_000)),
+            ..Default::default()
+        };
+        assert_eq!(long.encode(), Err(Error::InvalidArgument));
+        let early = FrameMetadata {
+            mtime: Some(UNIX_EPOCH - Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert_eq!(early.encode(), Err(Error::InvalidArgument));
+    }
+}
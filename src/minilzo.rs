@@ -1,8 +1,23 @@
 /* automatically generated by rust-bindgen */
 #![allow(warnings)]
 
-pub(crate) const LZO1X_1_MEM_COMPRESS: usize = 131072;
-pub(crate) type lzo_uint = ::std::os::raw::c_ulonglong;
+pub const LZO1X_1_MEM_COMPRESS: usize = 131072;
+pub const LZO_E_OK: ::std::os::raw::c_int = 0;
+pub const LZO_E_ERROR: ::std::os::raw::c_int = -1;
+pub const LZO_E_OUT_OF_MEMORY: ::std::os::raw::c_int = -2;
+pub const LZO_E_NOT_COMPRESSIBLE: ::std::os::raw::c_int = -3;
+pub const LZO_E_INPUT_OVERRUN: ::std::os::raw::c_int = -4;
+pub const LZO_E_OUTPUT_OVERRUN: ::std::os::raw::c_int = -5;
+pub const LZO_E_LOOKBEHIND_OVERRUN: ::std::os::raw::c_int = -6;
+pub const LZO_E_EOF_NOT_FOUND: ::std::os::raw::c_int = -7;
+pub const LZO_E_INPUT_NOT_CONSUMED: ::std::os::raw::c_int = -8;
+pub const LZO_E_NOT_YET_IMPLEMENTED: ::std::os::raw::c_int = -9;
+pub const LZO_E_INVALID_ARGUMENT: ::std::os::raw::c_int = -10;
+pub const LZO_E_INVALID_ALIGNMENT: ::std::os::raw::c_int = -11;
+pub const LZO_E_OUTPUT_NOT_CONSUMED: ::std::os::raw::c_int = -12;
+pub const LZO_E_INTERNAL_ERROR: ::std::os::raw::c_int = -99;
+// `lzo_uint` matches `size_t`: 32 bits on 32-bit targets.
+pub type lzo_uint = usize;
 extern "C" {
     pub fn __lzo_init_v2(
         arg1: ::std::os::raw::c_uint,
@@ -20,6 +35,12 @@ extern "C" {
 extern "C" {
     pub fn lzo_version() -> ::std::os::raw::c_uint;
 }
+extern "C" {
+    pub fn lzo_version_string() -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn lzo_version_date() -> *const ::std::os::raw::c_char;
+}
 extern "C" {
     pub fn lzo_adler32(
         c: ::std::os::raw::c_uint,
@@ -56,7 +77,7 @@ pub struct lzo_callback_t {
     pub user3: lzo_uint,
 }
 extern "C" {
-    pub(crate) fn lzo1x_1_compress(
+    pub fn lzo1x_1_compress(
         src: *const ::std::os::raw::c_uchar,
         src_len: lzo_uint,
         dst: *mut ::std::os::raw::c_uchar,
@@ -65,7 +86,7 @@ extern "C" {
     ) -> ::std::os::raw::c_int;
 }
 extern "C" {
-    pub(crate) fn lzo1x_decompress(
+    pub fn lzo1x_decompress(
         src: *const ::std::os::raw::c_uchar,
         src_len: lzo_uint,
         dst: *mut ::std::os::raw::c_uchar,
@@ -74,7 +95,7 @@ extern "C" {
     ) -> ::std::os::raw::c_int;
 }
 extern "C" {
-    pub(crate) fn lzo1x_decompress_safe(
+    pub fn lzo1x_decompress_safe(
         src: *const ::std::os::raw::c_uchar,
         src_len: lzo_uint,
         dst: *mut ::std::os::raw::c_uchar,
@@ -0,0 +1,146 @@
+//! A Node.js addon (`node` feature).
+//!
+//! Exports `compress(buffer)` and `decompress(buffer, outputLength)`,
+//! which code on the libuv thread pool and return a `Promise<Buffer>`,
+//! their blocking counterparts `compressSync`/`decompressSync`, and
+//! `adler32(buffer[, value])`. Build it with `napi build`, or with
+//!
+//! ```text
+//! cargo rustc --release --features node --crate-type cdylib
+//! ```
+//!
+//! and load the library renamed to `minilzo.node` with `require`. The data
+//! is raw LZO1X-1, as `compress`/`decompress` produce and take it, so the
+//! caller keeps the uncompressed length; a failure rejects with an `Error`
+//! carrying the crate's message.
+
+use crate::Error;
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::{Env, Task};
+use napi_derive::napi;
+use std::convert::TryFrom;
+
+fn to_js(e: impl Into<Error>) -> napi::Error {
+    napi::Error::from_reason(e.into().to_string())
+}
+
+fn compress_buffer(data: &[u8]) -> napi::Result<Buffer> {
+    crate::compress(data).map(Buffer::from).map_err(to_js)
+}
+
+fn decompress_buffer(data: &[u8], output_length: u32) -> napi::Result<Buffer> {
+    let len = usize::try_from(output_length).map_err(|_| to_js(Error::InvalidArgument))?;
+    crate::decompress(data, len)
+        .map(Buffer::from)
+        .map_err(to_js)
+}
+
+/// Compresses a buffer off the JavaScript thread.
+pub struct CompressTask {
+    data: Buffer,
+}
+
+impl Task for CompressTask {
+    type Output = Buffer;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> napi::Result<Buffer> {
+        compress_buffer(&self.data)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Buffer) -> napi::Result<Buffer> {
+        Ok(output)
+    }
+}
+
+/// Decompresses a buffer off the JavaScript thread.
+pub struct DecompressTask {
+    data: Buffer,
+    output_length: u32,
+}
+
+impl Task for DecompressTask {
+    type Output = Buffer;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> napi::Result<Buffer> {
+        decompress_buffer(&self.data, self.output_length)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Buffer) -> napi::Result<Buffer> {
+        Ok(output)
+    }
+}
+
+/// compress(buffer) -- resolves to `buffer` compressed.
+// Only registered as exports outside of tests.
+#[cfg_attr(test, allow(dead_code))]
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn compress(data: Buffer) -> AsyncTask<CompressTask> {
+    AsyncTask::new(CompressTask { data })
+}
+
+/// decompress(buffer, outputLength) -- resolves to `buffer` decompressed,
+/// which must come to exactly `outputLength` bytes.
+// Only registered as exports outside of tests.
+#[cfg_attr(test, allow(dead_code))]
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn decompress(data: Buffer, output_length: u32) -> AsyncTask<DecompressTask> {
+    AsyncTask::new(DecompressTask {
+        data,
+        output_length,
+    })
+}
+
+/// compressSync(buffer) -- `buffer` compressed, on the calling thread.
+#[napi]
+pub fn compress_sync(data: Buffer) -> napi::Result<Buffer> {
+    compress_buffer(&data)
+}
+
+/// decompressSync(buffer, outputLength) -- `buffer` decompressed, on the
+/// calling thread.
+#[napi]
+pub fn decompress_sync(data: Buffer, output_length: u32) -> napi::Result<Buffer> {
+    decompress_buffer(&data, output_length)
+}
+
+/// adler32(buffer[, value]) -- continue the adler32 `value` over `buffer`.
+#[napi]
+pub fn adler32(data: Buffer, value: Option<u32>) -> u32 {
+    crate::adler32_with(value.unwrap_or(1), &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_exports() {
+        let data = b"spam".repeat(1000);
+        let mut task = CompressTask {
+            data: Buffer::from(data.clone()),
+        };
+        let packed = task.compute().unwrap();
+        assert_eq!(
+            &packed[..],
+            &compress_sync(Buffer::from(data.clone())).unwrap()[..]
+        );
+        assert!(packed.len() < data.len());
+
+        let mut task = DecompressTask {
+            data: Buffer::from(packed.to_vec()),
+            output_length: data.len() as u32,
+        };
+        assert_eq!(&task.compute().unwrap()[..], &data[..]);
+
+        let err = crate::decompress(&packed[..], 10).unwrap_err();
+        let short = decompress_sync(Buffer::from(packed.to_vec()), 10);
+        assert_eq!(short.err().unwrap().reason, Error::from(err).to_string());
+        assert_eq!(adler32(Buffer::from(Vec::new()), None), 1);
+        assert_eq!(
+            adler32(Buffer::from(data.clone()), Some(7)),
+            crate::adler32_with(7, &data)
+        );
+    }
+}
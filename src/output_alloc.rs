@@ -0,0 +1,36 @@
+//! Output buffers in caller-provided allocators.
+
+/// Where `LzoContext::compress_in` and `decompress_in` put their output:
+/// anything that can copy bytes into memory it owns, such as an arena or a
+/// buffer pool.
+///
+/// It stands in for the unstable `allocator_api` on stable Rust. Closures
+/// taking the bytes implement it, and `&bumpalo::Bump` does with the
+/// `bumpalo` feature.
+pub trait OutputAlloc {
+    /// The allocated copy.
+    type Output;
+
+    /// Copy `data` into a new allocation of exactly its length.
+    fn alloc_copy(self, data: &[u8]) -> Self::Output;
+}
+
+impl<F, T> OutputAlloc for F
+where
+    F: FnOnce(&[u8]) -> T,
+{
+    type Output = T;
+
+    fn alloc_copy(self, data: &[u8]) -> T {
+        self(data)
+    }
+}
+
+#[cfg(feature = "bumpalo")]
+impl<'a> OutputAlloc for &'a bumpalo::Bump {
+    type Output = &'a mut [u8];
+
+    fn alloc_copy(self, data: &[u8]) -> &'a mut [u8] {
+        self.alloc_slice_copy(data)
+    }
+}
@@ -0,0 +1,332 @@
+//! Record logs in fixed-size, checksummed pages, for flash storage.
+//!
+//! `PageLogWriter` packs variable-length records into pages of the size
+//! the flash is programmed in; `PageLogReader` reads the records back out
+//! of a region of such pages, up to the first erased one.
+//!
+//! A page starts with a 20-byte header: `b"MLZP"`, a version byte, a flags
+//! byte (bit 0: the payload is compressed), the record count (`u16`), the
+//! payload length, the length of the records uncompressed, and an adler32
+//! of the first 16 header bytes and the payload (`u32` each, big-endian).
+//! The records follow, each a `u16` length and its bytes, compressed as a
+//! whole when that makes more of them fit. The rest of the page is `0xff`,
+//! the erased state of NOR and NAND flash, so it needn't be programmed.
+
+use crate::{adler32, adler32_with, Decompressor, Error, LZOResult, LZO, MAX_BLOCK_SIZE};
+use std::convert::TryFrom;
+
+const MAGIC: &[u8; 4] = b"MLZP";
+const VERSION: u8 = 1;
+const FLAG_COMPRESSED: u8 = 0x01;
+const ERASED: u8 = 0xff;
+
+/// Length of the header at the start of every page.
+pub const PAGE_HEADER_LEN: usize = 20;
+
+/// Smallest page size `PageLogWriter` accepts.
+const MIN_PAGE_SIZE: usize = 64;
+
+/// Packs records into compressed pages of a fixed size.
+///
+/// Records are buffered until the next one wouldn't fit in the current
+/// page even compressed; `push` then returns the full page, to be written
+/// to flash, and starts a new one with that record. `flush` returns the
+/// page being filled, for a log that must be on flash before power is cut.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{PageLogReader, PageLogWriter};
+///
+/// let mut writer = PageLogWriter::new(512).unwrap();
+/// let mut flash = Vec::new();
+/// for i in 0..200 {
+///     let record = format!("t={} temp=21.{} C", i, i % 10);
+///     if let Some(page) = writer.push(record.as_bytes()).unwrap() {
+///         flash.extend_from_slice(&page);
+///     }
+/// }
+/// flash.extend_from_slice(&writer.flush().unwrap().unwrap());
+/// assert!(flash.len() < 200 * 20);
+///
+/// // Pages never written read as erased and end the log.
+/// flash.resize(flash.len() + 4 * 512, 0xff);
+/// let records: Vec<Vec<u8>> = PageLogReader::new(&flash, 512).map(Result::unwrap).collect();
+/// assert_eq!(records.len(), 200);
+/// assert_eq!(records[7], b"t=7 temp=21.7 C");
+/// ```
+pub struct PageLogWriter {
+    lzo: LZO,
+    page_size: usize,
+    /// The records of the current page, each with its length.
+    records: Vec<u8>,
+    count: u16,
+    scratch: Vec<u8>,
+}
+
+impl PageLogWriter {
+    /// Create a writer of `page_size`-byte pages, typically the flash's
+    /// program or erase size. Fails with `Error::InvalidArgument` for pages
+    /// shorter than 64 bytes or longer than `MAX_BLOCK_SIZE`.
+    pub fn new(page_size: usize) -> LZOResult<Self> {
+        if !(MIN_PAGE_SIZE..=MAX_BLOCK_SIZE).contains(&page_size) {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(PageLogWriter {
+            lzo: LZO::try_new()?,
+            page_size,
+            records: Vec::new(),
+            count: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// The size of the pages written.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Records added to the current page so far.
+    pub fn pending(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Add `record`, returning the previous page once it is full.
+    ///
+    /// Fails with `Error::BudgetExceeded` for a record that doesn't fit in
+    /// a page on its own even compressed, and with `InvalidArgument` for
+    /// one over 65535 bytes; nothing is added either way.
+    pub fn push(&mut self, record: &[u8]) -> LZOResult<Option<Vec<u8>>> {
+        let len = u16::try_from(record.len()).map_err(|_| Error::InvalidArgument)?;
+        let start = self.records.len();
+        self.records.extend_from_slice(&len.to_be_bytes());
+        self.records.extend_from_slice(record);
+        let capacity = self.page_size - PAGE_HEADER_LEN;
+        if self.count < u16::MAX && fits(&mut self.lzo, &self.records, capacity, &mut self.scratch)?
+        {
+            self.count += 1;
+            return Ok(None);
+        }
+        let entry = self.records.split_off(start);
+        if !fits(&mut self.lzo, &entry, capacity, &mut self.scratch)? {
+            return Err(Error::BudgetExceeded { budget: capacity });
+        }
+        let page = self.seal()?;
+        self.records = entry;
+        self.count = 1;
+        Ok(page)
+    }
+
+    /// Return the current page, if it holds any records, even though more
+    /// would fit, and start a new one.
+    pub fn flush(&mut self) -> LZOResult<Option<Vec<u8>>> {
+        self.seal()
+    }
+
+    fn seal(&mut self) -> LZOResult<Option<Vec<u8>>> {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        let records = std::mem::take(&mut self.records);
+        let compressed = records.len() > self.page_size - PAGE_HEADER_LEN;
+        let payload = if compressed {
+            self.lzo.compress_to(&records, &mut self.scratch)?;
+            &self.scratch[..]
+        } else {
+            &records[..]
+        };
+        let mut page = Vec::with_capacity(self.page_size);
+        page.extend_from_slice(MAGIC);
+        page.push(VERSION);
+        page.push(if compressed { FLAG_COMPRESSED } else { 0 });
+        page.extend_from_slice(&self.count.to_be_bytes());
+        page.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        page.extend_from_slice(&(records.len() as u32).to_be_bytes());
+        let checksum = adler32_with(adler32(&page), payload);
+        page.extend_from_slice(&checksum.to_be_bytes());
+        page.extend_from_slice(payload);
+        page.resize(self.page_size, ERASED);
+        self.count = 0;
+        self.records = records;
+        self.records.clear();
+        Ok(Some(page))
+    }
+}
+
+/// Whether `records` fit in `capacity` bytes, stored or compressed.
+fn fits(lzo: &mut LZO, records: &[u8], capacity: usize, scratch: &mut Vec<u8>) -> LZOResult<bool> {
+    if records.len() <= capacity {
+        return Ok(true);
+    }
+    if records.len() > MAX_BLOCK_SIZE {
+        return Ok(false);
+    }
+    lzo.compress_to(records, scratch)?;
+    Ok(scratch.len() <= capacity)
+}
+
+/// Read the records of one page written by `PageLogWriter`, or `None` if
+/// the page is erased.
+///
+/// Fails with `Error::InvalidHeader` for a page that isn't one, and
+/// `Error::ChecksumMismatch` for one that was damaged.
+pub fn read_page(page: &[u8]) -> LZOResult<Option<Vec<Vec<u8>>>> {
+    if page.iter().all(|&b| b == ERASED) {
+        return Ok(None);
+    }
+    if page.len() < PAGE_HEADER_LEN || &page[..4] != MAGIC || page[4] != VERSION {
+        return Err(Error::InvalidHeader);
+    }
+    let be = |i: usize| u32::from_be_bytes([page[i], page[i + 1], page[i + 2], page[i + 3]]);
+    let flags = page[5];
+    let count = u16::from_be_bytes([page[6], page[7]]);
+    let (payload_len, records_len) = (be(8) as usize, be(12) as usize);
+    if flags & !FLAG_COMPRESSED != 0
+        || payload_len > page.len() - PAGE_HEADER_LEN
+        || records_len > MAX_BLOCK_SIZE
+    {
+        return Err(Error::InvalidHeader);
+    }
+    let payload = &page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + payload_len];
+    if adler32_with(adler32(&page[..16]), payload) != be(16) {
+        return Err(Error::ChecksumMismatch);
+    }
+    let decompressed;
+    let mut records = if flags & FLAG_COMPRESSED != 0 {
+        decompressed = Decompressor::new()?.decompress_safe(payload, records_len)?;
+        &decompressed[..]
+    } else if payload_len == records_len {
+        payload
+    } else {
+        return Err(Error::InvalidHeader);
+    };
+    let mut out = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        if records.len() < 2 {
+            return Err(Error::InvalidHeader);
+        }
+        let len = u16::from_be_bytes([records[0], records[1]]) as usize;
+        if records.len() < 2 + len {
+            return Err(Error::InvalidHeader);
+        }
+        out.push(records[2..2 + len].to_vec());
+        records = &records[2 + len..];
+    }
+    if !records.is_empty() {
+        return Err(Error::InvalidHeader);
+    }
+    Ok(Some(out))
+}
+
+/// Iterates over the records of a region of pages written by
+/// `PageLogWriter`, such as a flash partition read into memory or mapped.
+///
+/// Iteration ends at the first erased page or the end of the region. A
+/// damaged page is reported as an error in place of its records, and
+/// iteration goes on with the next page.
+pub struct PageLogReader<'a> {
+    pages: std::slice::Chunks<'a, u8>,
+    records: std::vec::IntoIter<Vec<u8>>,
+}
+
+impl<'a> PageLogReader<'a> {
+    /// Read the records of `region`, made of `page_size`-byte pages.
+    pub fn new(region: &'a [u8], page_size: usize) -> Self {
+        PageLogReader {
+            pages: region.chunks(page_size.max(1)),
+            records: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for PageLogReader<'_> {
+    type Item = LZOResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some(Ok(record));
+            }
+            match read_page(self.pages.next()?) {
+                Ok(Some(records)) => self.records = records.into_iter(),
+                Ok(None) => {
+                    self.pages = [].chunks(1);
+                    return None;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_log_roundtrip() {
+        let records: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| {
+                let len = (i * 37 % 300) as usize;
+                (0..len).map(|j| (i as usize + j / 8) as u8).collect()
+            })
+            .collect();
+        let mut writer = PageLogWriter::new(1024).unwrap();
+        let mut flash = Vec::new();
+        for record in &records {
+            if let Some(page) = writer.push(record).unwrap() {
+                assert_eq!(page.len(), 1024);
+                flash.extend_from_slice(&page);
+            }
+        }
+        assert!(writer.pending() > 0);
+        flash.extend_from_slice(&writer.flush().unwrap().unwrap());
+        assert_eq!(writer.flush().unwrap(), None);
+        let raw: usize = records.iter().map(|r| r.len() + 2).sum();
+        assert!(flash.len() < raw / 2);
+
+        flash.resize(flash.len() + 2048, ERASED);
+        let read: Vec<Vec<u8>> = PageLogReader::new(&flash, 1024)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read, records);
+
+        // A damaged page is skipped with an error; an erased one ends the log.
+        let lost = read_page(&flash[1024..2048]).unwrap().unwrap().len();
+        flash[1024 + 100] ^= 0x01;
+        let results: Vec<_> = PageLogReader::new(&flash, 1024).collect();
+        assert_eq!(
+            results.iter().filter(|r| r.is_err()).collect::<Vec<_>>(),
+            [&Err(Error::ChecksumMismatch)]
+        );
+        assert_eq!(results.len(), records.len() - lost + 1);
+    }
+
+    #[test]
+    fn test_page_log_limits() {
+        assert_eq!(PageLogWriter::new(32).err(), Some(Error::InvalidArgument));
+        let mut writer = PageLogWriter::new(256).unwrap();
+        let mut x = 0x2545_f491u32;
+        let noise: Vec<u8> = (0..300)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        assert_eq!(
+            writer.push(&noise),
+            Err(Error::BudgetExceeded {
+                budget: 256 - PAGE_HEADER_LEN
+            })
+        );
+        assert_eq!(writer.pending(), 0);
+        // Too long for the page as is, but it compresses to fit.
+        assert_eq!(writer.push(&[b'a'; 2000]), Ok(None));
+        let page = writer.flush().unwrap().unwrap();
+        assert_eq!(read_page(&page).unwrap().unwrap(), [vec![b'a'; 2000]]);
+        assert_eq!(read_page(&[ERASED; 256]), Ok(None));
+        assert_eq!(read_page(&[0u8; 256]), Err(Error::InvalidHeader));
+    }
+}
@@ -0,0 +1,128 @@
+//! Multi-core one-shot compression on the rayon thread pool.
+
+use crate::frame::{self, HEADER_LEN, MAX_BLOCK_SIZE};
+use crate::{CancelToken, Decompressor, Error, LZOResult, LZO};
+use rayon::prelude::*;
+
+/// Compress `src` into a framed stream, compressing `block_size` blocks in
+/// parallel on the rayon pool.
+///
+/// Each worker thread gets its own work memory. The output is a regular
+/// framed stream readable by `LzoReader` as well as `decompress_parallel`.
+///
+/// Example
+///
+/// ```rust
+/// let input = vec![0x2au8; 1 << 20];
+/// let framed = minilzo_rs::compress_parallel(&input, 64 * 1024).unwrap();
+/// let output = minilzo_rs::decompress_parallel(&framed).unwrap();
+/// assert_eq!(output, input);
+/// ```
+pub fn compress_parallel(src: &[u8], block_size: usize) -> LZOResult<Vec<u8>> {
+    compress_parallel_cancellable(src, block_size, &CancelToken::new())
+}
+
+/// `compress_parallel`, failing with `Error::Cancelled` once `cancel` is
+/// cancelled. Blocks already being compressed are finished first.
+pub fn compress_parallel_cancellable(
+    src: &[u8],
+    block_size: usize,
+    cancel: &CancelToken,
+) -> LZOResult<Vec<u8>> {
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+    let blocks = src
+        .par_chunks(block_size)
+        .map_init(LZO::new, |lzo, chunk| {
+            cancel.check()?;
+            let mut out = Vec::new();
+            frame::encode_block(lzo, chunk, &mut out)?;
+            Ok(out)
+        })
+        .collect::<LZOResult<Vec<Vec<u8>>>>()?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + blocks.iter().map(Vec::len).sum::<usize>() + 4);
+    frame::encode_header(&mut out);
+    for block in &blocks {
+        out.extend_from_slice(block);
+    }
+    frame::encode_end(&mut out);
+    Ok(out)
+}
+
+/// Decompress a whole framed stream, decoding its blocks in parallel.
+pub fn decompress_parallel(src: &[u8]) -> LZOResult<Vec<u8>> {
+    decompress_parallel_cancellable(src, &CancelToken::new())
+}
+
+/// `decompress_parallel`, failing with `Error::Cancelled` once `cancel` is
+/// cancelled.
+///
+/// Streams written by `LzoWriter::chained`, whose blocks only decode in
+/// order, are decoded on the calling thread.
+pub fn decompress_parallel_cancellable(src: &[u8], cancel: &CancelToken) -> LZOResult<Vec<u8>> {
+    let checksum = frame::checksum_from_flags(frame::parse_header(src)?);
+    if frame::chained(src) {
+        cancel.check()?;
+        return crate::decompress_chunked(src);
+    }
+    let delta = frame::delta_from_header(src);
+    let blocks = frame::split_blocks(src)?;
+    let dict = frame::stream_dict(frame::dict_from_header(src)?, None)?;
+    let dict = dict.as_deref().unwrap_or_default();
+    let decompressor = Decompressor::new()?;
+    let parts = blocks
+        .par_iter()
+        .map(|(meta, payload)| {
+            cancel.check()?;
+            let mut data = frame::decode_block_with(&decompressor, meta, payload, checksum, dict)?;
+            if let Some(stride) = delta {
+                crate::delta::decode(&mut data, stride);
+            }
+            Ok(data)
+        })
+        .collect::<LZOResult<Vec<Vec<u8>>>>()?;
+    Ok(parts.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_parallel_matches_stream_format() {
+        let input: Vec<u8> = (0..300_000u32).map(|i| (i % 211) as u8).collect();
+        let framed = compress_parallel(&input, 10_000).unwrap();
+
+        let mut output = Vec::new();
+        crate::LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+
+        assert_eq!(
+            decompress_parallel(&framed[..framed.len() - 1]),
+            Err(Error::EOFNotFound)
+        );
+        assert_eq!(compress_parallel(&[], 16).unwrap().len(), HEADER_LEN + 4);
+    }
+
+    #[test]
+    fn test_parallel_cancelled() {
+        let input = vec![0x3cu8; 100_000];
+        let framed = compress_parallel(&input, 4096).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert_eq!(
+            compress_parallel_cancellable(&input, 4096, &cancel),
+            Err(Error::Cancelled)
+        );
+        assert_eq!(
+            decompress_parallel_cancellable(&framed, &cancel),
+            Err(Error::Cancelled)
+        );
+    }
+}
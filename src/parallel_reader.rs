@@ -0,0 +1,280 @@
+//! Multi-threaded streaming decompression, the reading side of
+//! `ParallelLzoWriter`.
+
+use crate::frame::{self, Decoder, RawFrame};
+use crate::scratch::Scratch;
+use crate::{
+    BlockMeta, CancelToken, Checksum, Decompressor, Error, LZOResult, DEFAULT_BLOCK_SIZE,
+    DEFAULT_READ_BUFFER_SIZE,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = (u64, BlockMeta, Vec<u8>, Checksum, Option<Arc<[u8]>>);
+type Done = (u64, LZOResult<Vec<u8>>);
+
+/// Decompresses a framed stream read from the inner reader, spreading the
+/// blocks over a set of worker threads and returning the data in order.
+///
+/// The framed format's blocks are independent, so while the reader is
+/// handed one block's data, the blocks after it are being decompressed.
+/// At most `max_in_flight_bytes` of decompressed data (never less than one
+/// block per worker) is queued, being decompressed or waiting to be read
+/// at any time; reading stops until the oldest block has been consumed.
+///
+/// Checksums are verified as with `LzoReader`, but parity frames are
+/// skipped, so damaged blocks are reported rather than rebuilt. Bytes
+/// after the end marker are left unread, as with `TrailingData::Ignore`.
+/// Streams written by `LzoWriter::chained`, whose blocks depend on each
+/// other, fail with `Error::UnsupportedFeatures`; use `LzoReader` for them.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// let input: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+/// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 64 * 1024).unwrap();
+/// writer.write_all(&input).unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let mut reader = minilzo_rs::ParallelLzoReader::new(&framed[..], 4).unwrap();
+/// let mut output = Vec::new();
+/// reader.read_to_end(&mut output).unwrap();
+/// assert_eq!(output, input);
+/// ```
+pub struct ParallelLzoReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    workers_count: usize,
+    max_in_flight: u64,
+    in_flight: u64,
+    /// Uncompressed size of each block sent out and not yet consumed.
+    sizes: BTreeMap<u64, u64>,
+    next_seq: u64,
+    next_read: u64,
+    ready: BTreeMap<u64, LZOResult<Vec<u8>>>,
+    block: Scratch,
+    pos: usize,
+    chunk: Box<[u8]>,
+    end: bool,
+    /// An error met while reading ahead, returned once the blocks before it
+    /// have been read.
+    pending: Option<io::Error>,
+    jobs: Option<SyncSender<Job>>,
+    done: Receiver<Done>,
+    workers: Vec<JoinHandle<()>>,
+    cancel: CancelToken,
+}
+
+impl<R: Read> ParallelLzoReader<R> {
+    /// Create a reader with `workers` threads and up to two default-sized
+    /// blocks in flight per worker.
+    pub fn new(inner: R, workers: usize) -> LZOResult<Self> {
+        let in_flight = DEFAULT_BLOCK_SIZE.saturating_mul(workers.saturating_mul(2));
+        Self::with_limits(inner, workers, in_flight)
+    }
+
+    /// Create a reader with full control over the worker count and the
+    /// in-flight memory bound.
+    pub fn with_limits(inner: R, workers: usize, max_in_flight_bytes: usize) -> LZOResult<Self> {
+        if workers == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let decompressor = Decompressor::new()?;
+        let (job_tx, job_rx) = mpsc::sync_channel::<Job>(workers);
+        let (done_tx, done_rx) = mpsc::channel::<Done>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let job_rx = Arc::clone(&job_rx);
+            let done_tx = done_tx.clone();
+            handles.push(thread::spawn(move || loop {
+                let job = job_rx.lock().unwrap().recv();
+                let (seq, meta, payload, checksum, dict) = match job {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                let dict = dict.as_deref().unwrap_or_default();
+                let result =
+                    frame::decode_block_with(&decompressor, &meta, &payload, checksum, dict);
+                if done_tx.send((seq, result)).is_err() {
+                    return;
+                }
+            }));
+        }
+        Ok(ParallelLzoReader {
+            inner,
+            decoder: Decoder::new(),
+            workers_count: workers,
+            max_in_flight: max_in_flight_bytes as u64,
+            in_flight: 0,
+            sizes: BTreeMap::new(),
+            next_seq: 0,
+            next_read: 0,
+            ready: BTreeMap::new(),
+            block: Scratch::new(),
+            pos: 0,
+            chunk: vec![0; DEFAULT_READ_BUFFER_SIZE].into(),
+            end: false,
+            pending: None,
+            jobs: Some(job_tx),
+            done: done_rx,
+            workers: handles,
+            cancel: CancelToken::new(),
+        })
+    }
+
+    /// Fail with `Error::Cancelled` before handing out the next block once
+    /// `token` is cancelled. Blocks already with the workers are finished.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` (as
+    /// `io::ErrorKind::InvalidData`) once the stream would decompress to
+    /// more than `limit` bytes, checked against each block's declared size.
+    pub fn max_output_size(mut self, limit: u64) -> Self {
+        self.decoder.set_max_output(limit);
+        self
+    }
+
+    /// Read the inner reader up to `size` bytes at a time instead of
+    /// `DEFAULT_READ_BUFFER_SIZE`; see `LzoReader::read_buffer_size`.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.chunk = vec![0; size.max(1)].into();
+        self
+    }
+
+    fn can_send(&self) -> bool {
+        let blocks = (self.next_seq - self.next_read) as usize;
+        blocks < self.workers_count || self.in_flight < self.max_in_flight
+    }
+
+    /// Parse blocks from the inner reader and hand them to the workers
+    /// while the window has room.
+    fn dispatch(&mut self) -> io::Result<()> {
+        while !self.end && self.can_send() {
+            self.cancel.check().map_err(frame::to_io)?;
+            match self.decoder.next_raw().map_err(frame::to_io)? {
+                Some(RawFrame::End) => self.end = true,
+                Some(RawFrame::Block(_, _)) if self.decoder.is_chained() => {
+                    return Err(frame::to_io(Error::UnsupportedFeatures {
+                        features: frame::FEATURE_CHAINED,
+                    }));
+                }
+                Some(RawFrame::Block(meta, payload)) => {
+                    let len = u64::from(meta.uncompressed_len);
+                    let dict = self.decoder.dict().cloned();
+                    let job = (self.next_seq, meta, payload, self.decoder.checksum(), dict);
+                    self.jobs
+                        .as_ref()
+                        .unwrap()
+                        .send(job)
+                        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+                    self.sizes.insert(self.next_seq, len);
+                    self.in_flight += len;
+                    self.next_seq += 1;
+                }
+                None => {
+                    let n = self.inner.read(&mut self.chunk)?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.decoder.feed(&self.chunk[..n]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_block(&mut self) -> io::Result<()> {
+        while self.pos == self.block.len() {
+            if self.pending.is_none() {
+                if let Err(e) = self.dispatch() {
+                    self.pending = Some(e);
+                }
+            }
+            if self.next_read == self.next_seq {
+                return match self.pending.take() {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                };
+            }
+            while !self.ready.contains_key(&self.next_read) {
+                let (seq, result) = self
+                    .done
+                    .recv()
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+                self.ready.insert(seq, result);
+            }
+            let data = self.ready.remove(&self.next_read).unwrap();
+            self.in_flight -= self.sizes.remove(&self.next_read).unwrap_or(0);
+            self.next_read += 1;
+            let mut data = data.map_err(frame::to_io)?;
+            if let Some(stride) = self.decoder.delta() {
+                crate::delta::decode(&mut data, stride);
+            }
+            self.block = data.into();
+            self.pos = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ParallelLzoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_block()?;
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Drop for ParallelLzoReader<R> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for handle in self.workers.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzoWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_parallel_reader_small_window() {
+        let input: Vec<u8> = (0..500_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut reader = ParallelLzoReader::with_limits(&framed[..], 3, 0).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+
+        // The blocks before a truncation come out before the error.
+        let mut reader = ParallelLzoReader::new(&framed[..framed.len() / 2], 2).unwrap();
+        let mut output = Vec::new();
+        let err = reader.read_to_end(&mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(output, &input[..output.len()]);
+        assert!(output.len() > input.len() / 3);
+
+        let mut corrupt = framed.clone();
+        let last = corrupt.len() - 10;
+        corrupt[last] ^= 1;
+        let mut reader = ParallelLzoReader::new(&corrupt[..], 2).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! pigz-style multi-threaded streaming compression.
+
+use crate::frame::{self, MAX_BLOCK_SIZE};
+use crate::scratch::Scratch;
+use crate::{CancelToken, Error, LZOResult, DEFAULT_BLOCK_SIZE, LZO};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = (u64, Scratch);
+type Done = (u64, LZOResult<Vec<u8>>);
+
+/// Compresses everything written to it into a framed stream, spreading the
+/// blocks over a set of worker threads and writing them back in order.
+///
+/// At most `max_in_flight_bytes` of data is held for blocks that haven't
+/// been written out yet (never less than one block per worker): their
+/// uncompressed data while they are queued or being compressed, and their
+/// compressed data while they wait for the blocks before them. Once the
+/// budget is used up, further writes block until the oldest block has been
+/// written out, so a slow inner writer holds up the caller instead of
+/// letting blocks pile up. The output is identical to `LzoWriter`'s.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// let input = vec![0x5au8; 1 << 20];
+/// let mut writer = minilzo_rs::ParallelLzoWriter::new(Vec::new(), 4).unwrap();
+/// writer.write_all(&input).unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let mut output = Vec::new();
+/// minilzo_rs::LzoReader::new(&framed[..]).unwrap().read_to_end(&mut output).unwrap();
+/// assert_eq!(output, input);
+/// ```
+pub struct ParallelLzoWriter<W: Write> {
+    inner: Option<W>,
+    block_size: usize,
+    workers_count: usize,
+    max_in_flight: usize,
+    in_flight_bytes: usize,
+    /// Uncompressed size of each block with the workers.
+    sizes: BTreeMap<u64, usize>,
+    buf: Scratch,
+    next_seq: u64,
+    next_write: u64,
+    ready: BTreeMap<u64, Vec<u8>>,
+    jobs: Option<SyncSender<Job>>,
+    done: Receiver<Done>,
+    workers: Vec<JoinHandle<()>>,
+    cancel: CancelToken,
+}
+
+impl<W: Write> ParallelLzoWriter<W> {
+    /// Create a writer with `workers` threads, `DEFAULT_BLOCK_SIZE` blocks
+    /// and up to two blocks in flight per worker.
+    pub fn new(inner: W, workers: usize) -> LZOResult<Self> {
+        let in_flight = DEFAULT_BLOCK_SIZE.saturating_mul(workers.saturating_mul(2));
+        Self::with_limits(inner, workers, DEFAULT_BLOCK_SIZE, in_flight)
+    }
+
+    /// Create a writer with full control over the worker count, the block
+    /// size and the in-flight memory budget.
+    pub fn with_limits(
+        inner: W,
+        workers: usize,
+        block_size: usize,
+        max_in_flight_bytes: usize,
+    ) -> LZOResult<Self> {
+        if workers == 0 || block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let max_blocks = (max_in_flight_bytes / block_size).max(workers);
+        let (job_tx, job_rx) = mpsc::sync_channel::<Job>(max_blocks);
+        let (done_tx, done_rx) = mpsc::channel::<Done>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let mut lzo = LZO::new();
+            let job_rx = Arc::clone(&job_rx);
+            let done_tx = done_tx.clone();
+            handles.push(thread::spawn(move || loop {
+                let job = job_rx.lock().unwrap().recv();
+                let (seq, data) = match job {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                let mut out = Vec::new();
+                let result = frame::encode_block(&mut lzo, &data, &mut out).map(|_| out);
+                if done_tx.send((seq, result)).is_err() {
+                    return;
+                }
+            }));
+        }
+        let mut header = Vec::new();
+        frame::encode_header(&mut header);
+        let in_flight_bytes = header.len();
+        let mut ready = BTreeMap::new();
+        ready.insert(0, header);
+        Ok(ParallelLzoWriter {
+            inner: Some(inner),
+            block_size,
+            workers_count: workers,
+            max_in_flight: max_in_flight_bytes,
+            in_flight_bytes,
+            sizes: BTreeMap::new(),
+            buf: Scratch::with_capacity(block_size),
+            next_seq: 1,
+            next_write: 0,
+            ready,
+            jobs: Some(job_tx),
+            done: done_rx,
+            workers: handles,
+            cancel: CancelToken::new(),
+        })
+    }
+
+    /// Fail with `Error::Cancelled` before handing out the next block once
+    /// `token` is cancelled. Blocks already with the workers are finished.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Bytes held for blocks not yet written out, uncompressed or
+    /// compressed, which `max_in_flight_bytes` bounds. Data buffered for the
+    /// next block is not included.
+    pub fn in_flight_bytes(&self) -> usize {
+        self.in_flight_bytes
+    }
+
+    fn in_flight(&self) -> usize {
+        (self.next_seq - self.next_write) as usize
+    }
+
+    /// Whether the next block has to wait for room in the budget.
+    fn is_full(&self) -> bool {
+        self.in_flight() >= self.workers_count
+            && self.in_flight_bytes + self.buf.len() > self.max_in_flight
+    }
+
+    /// Write out every finished block that is next in order.
+    fn write_ready(&mut self) -> io::Result<()> {
+        while let Some(block) = self.ready.remove(&self.next_write) {
+            self.inner.as_mut().unwrap().write_all(&block)?;
+            self.in_flight_bytes -= block.len();
+            self.next_write += 1;
+        }
+        Ok(())
+    }
+
+    /// Take a block back from the workers, swapping its uncompressed size
+    /// for its compressed size in the budget.
+    fn receive(&mut self, seq: u64, result: LZOResult<Vec<u8>>) -> io::Result<()> {
+        self.in_flight_bytes -= self.sizes.remove(&seq).unwrap_or(0);
+        let block = result.map_err(frame::to_io)?;
+        self.in_flight_bytes += block.len();
+        self.ready.insert(seq, block);
+        Ok(())
+    }
+
+    /// Wait for one block to come back from the workers.
+    fn wait_one(&mut self) -> io::Result<()> {
+        let (seq, result) = self
+            .done
+            .recv()
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        self.receive(seq, result)?;
+        self.write_ready()
+    }
+
+    fn send_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.cancel.check().map_err(frame::to_io)?;
+        while self.is_full() {
+            self.wait_one()?;
+        }
+        let data = std::mem::replace(&mut self.buf, Scratch::with_capacity(self.block_size));
+        self.in_flight_bytes += data.len();
+        self.sizes.insert(self.next_seq, data.len());
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send((self.next_seq, data))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        self.next_seq += 1;
+        while let Ok((seq, result)) = self.done.try_recv() {
+            self.receive(seq, result)?;
+        }
+        self.write_ready()
+    }
+
+    /// Compress any buffered data, wait for all workers, write the end
+    /// marker and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.send_block()?;
+        self.write_ready()?;
+        while self.next_write < self.next_seq {
+            self.wait_one()?;
+        }
+        let mut end = Vec::new();
+        frame::encode_end(&mut end);
+        let mut inner = self.inner.take().unwrap();
+        inner.write_all(&end)?;
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for ParallelLzoWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == self.block_size {
+            self.send_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_ready()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for ParallelLzoWriter<W> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for handle in self.workers.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_parallel_writer_small_window() {
+        let input: Vec<u8> = (0..500_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        let mut writer = ParallelLzoWriter::with_limits(Vec::new(), 3, 4096, 0).unwrap();
+        for chunk in input.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let framed = writer.finish().unwrap();
+
+        let mut serial = crate::LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        serial.write_all(&input).unwrap();
+        assert_eq!(framed, serial.finish().unwrap());
+
+        let mut output = Vec::new();
+        crate::LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_parallel_writer_memory_budget() {
+        /// A writer that accepts a few bytes per call, like a slow socket.
+        struct Trickle(Vec<u8>);
+        impl Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(100);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let input: Vec<u8> = (0..300_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        let budget = 5 * 4096;
+        let mut writer =
+            ParallelLzoWriter::with_limits(Trickle(Vec::new()), 2, 4096, budget).unwrap();
+        for chunk in input.chunks(777) {
+            writer.write_all(chunk).unwrap();
+            assert!(writer.in_flight_bytes() <= budget);
+        }
+        let framed = writer.finish().unwrap().0;
+        assert_eq!(crate::decompress_chunked(&framed).unwrap(), input);
+
+        // The budget never drops below one block per worker.
+        let mut writer = ParallelLzoWriter::with_limits(Vec::new(), 2, 4096, 0).unwrap();
+        for chunk in input.chunks(4096) {
+            writer.write_all(chunk).unwrap();
+            assert!(writer.in_flight_bytes() <= 2 * crate::compress_bound(4096) + 64);
+        }
+        assert_eq!(writer.finish().unwrap(), framed);
+    }
+}
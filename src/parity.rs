@@ -0,0 +1,217 @@
+//! Reed–Solomon parity frames for the framed format (`parity` feature).
+//!
+//! The writer groups every `data_blocks` blocks and follows each group with
+//! a parity frame of `parity_blocks` shards. A reader can then rebuild up to
+//! `parity_blocks` blocks per group whose payload or checksum was damaged.
+//! Damage to a block's length fields can't be recovered, since it loses the
+//! position of every later block.
+
+use crate::frame::{self, ParityHeader, BLOCK_HEADER_LEN};
+use crate::{BlockMeta, Checksum, Decompressor, Error, LZOResult};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+fn pad(shard: &[u8], len: usize) -> Vec<u8> {
+    let mut padded = Vec::with_capacity(len);
+    padded.extend_from_slice(shard);
+    padded.resize(len, 0);
+    padded
+}
+
+/// Collects the blocks of a group and writes their parity frame.
+pub(crate) struct ParityEncoder {
+    data_blocks: usize,
+    parity_blocks: usize,
+    group: Vec<Vec<u8>>,
+}
+
+impl ParityEncoder {
+    pub(crate) fn new(data_blocks: usize, parity_blocks: usize) -> LZOResult<Self> {
+        ReedSolomon::new(data_blocks, parity_blocks).map_err(|_| Error::InvalidArgument)?;
+        Ok(ParityEncoder {
+            data_blocks,
+            parity_blocks,
+            group: Vec::with_capacity(data_blocks),
+        })
+    }
+
+    /// Record the block encoded at `out[start..]`, appending the parity
+    /// frame to `out` once the group is full.
+    pub(crate) fn push(&mut self, out: &mut Vec<u8>, start: usize) -> LZOResult<()> {
+        self.group.push(out[start..].to_vec());
+        if self.group.len() == self.data_blocks {
+            self.flush(out)?;
+        }
+        Ok(())
+    }
+
+    /// Write the parity frame for a partial group, if any.
+    pub(crate) fn flush(&mut self, out: &mut Vec<u8>) -> LZOResult<()> {
+        if self.group.is_empty() {
+            return Ok(());
+        }
+        let header = ParityHeader {
+            data_shards: self.group.len(),
+            parity_shards: self.parity_blocks,
+            shard_len: self.group.iter().map(Vec::len).max().unwrap(),
+        };
+        let rs = ReedSolomon::new(header.data_shards, header.parity_shards)
+            .map_err(|_| Error::InvalidArgument)?;
+        let mut shards: Vec<Vec<u8>> = self
+            .group
+            .drain(..)
+            .map(|block| pad(&block, header.shard_len))
+            .collect();
+        shards.resize(
+            header.data_shards + header.parity_shards,
+            vec![0; header.shard_len],
+        );
+        rs.encode(&mut shards).map_err(|_| Error::InternalError)?;
+        header.write_to(out);
+        for shard in &shards[header.data_shards..] {
+            out.extend_from_slice(shard);
+        }
+        Ok(())
+    }
+}
+
+/// Decoder-side group state: the raw bytes of every block since the last
+/// parity frame, and which of them failed to decode.
+#[derive(Default)]
+pub(crate) struct Recovery {
+    group: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    held_from: Option<usize>,
+}
+
+impl Recovery {
+    /// Account for one block and its decode result. Returns the data to
+    /// emit now, or `None` while the group is being held for repair.
+    pub(crate) fn block(
+        &mut self,
+        raw: &[u8],
+        result: LZOResult<Vec<u8>>,
+    ) -> LZOResult<Option<Vec<u8>>> {
+        if self.group.len() >= 256 {
+            return Err(Error::InvalidHeader);
+        }
+        let decoded = result.ok();
+        if decoded.is_none() && self.held_from.is_none() {
+            self.held_from = Some(self.group.len());
+        }
+        if self.held_from.is_some() {
+            self.group.push((raw.to_vec(), decoded));
+            return Ok(None);
+        }
+        self.group.push((raw.to_vec(), None));
+        Ok(decoded)
+    }
+
+    /// Close the group with its parity frame, rebuilding damaged blocks.
+    /// Returns the data of every held block, in order.
+    pub(crate) fn parity(
+        &mut self,
+        decompressor: &Decompressor,
+        header: &ParityHeader,
+        parity: &[u8],
+        checksum: Checksum,
+        dict: &[u8],
+    ) -> LZOResult<Option<Vec<u8>>> {
+        let group = std::mem::take(&mut self.group);
+        let held_from = match self.held_from.take() {
+            Some(held_from) => held_from,
+            None => return Ok(None),
+        };
+        if header.data_shards != group.len() {
+            return Err(Error::ChecksumMismatch);
+        }
+        let mut shards: Vec<Option<Vec<u8>>> =
+            Vec::with_capacity(group.len() + header.parity_shards);
+        for (i, (raw, decoded)) in group.iter().enumerate() {
+            if raw.len() > header.shard_len {
+                return Err(Error::ChecksumMismatch);
+            }
+            let damaged = i >= held_from && decoded.is_none();
+            shards.push(if damaged {
+                None
+            } else {
+                Some(pad(raw, header.shard_len))
+            });
+        }
+        shards.extend(
+            parity
+                .chunks(header.shard_len)
+                .map(|shard| Some(shard.to_vec())),
+        );
+        let rs = ReedSolomon::new(header.data_shards, header.parity_shards)
+            .map_err(|_| Error::InvalidHeader)?;
+        rs.reconstruct_data(&mut shards)
+            .map_err(|_| Error::ChecksumMismatch)?;
+
+        let mut out = Vec::new();
+        for (i, (_, decoded)) in group.into_iter().enumerate().skip(held_from) {
+            match decoded {
+                Some(data) => out.extend_from_slice(&data),
+                None => {
+                    let shard = shards[i].as_ref().unwrap();
+                    let meta = BlockMeta::read_from(shard);
+                    let end = BLOCK_HEADER_LEN + meta.compressed_len as usize;
+                    if end > shard.len() {
+                        return Err(Error::ChecksumMismatch);
+                    }
+                    let payload = &shard[BLOCK_HEADER_LEN..end];
+                    let data =
+                        frame::decode_block_with(decompressor, &meta, payload, checksum, dict)?;
+                    out.extend_from_slice(&data);
+                }
+            }
+        }
+        Ok(Some(out))
+    }
+
+    /// Fail if the stream ends while blocks are still waiting for parity.
+    pub(crate) fn check_end(&self) -> LZOResult<()> {
+        match self.held_from {
+            Some(_) => Err(Error::ChecksumMismatch),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LzoReader, LzoWriter};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_parity_repairs_damaged_blocks() {
+        let input: Vec<u8> = (0..40_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut writer = LzoWriter::with_parity(Vec::new(), 4096, 4, 2).unwrap();
+        writer.write_all(&input).unwrap();
+        let mut framed = writer.finish().unwrap();
+
+        // Corrupt the payloads of the first two blocks; both are in the
+        // first group, which has two parity shards.
+        let first = frame::HEADER_LEN;
+        let meta = BlockMeta::read_from(&framed[first..]);
+        let second = first + BLOCK_HEADER_LEN + meta.compressed_len as usize;
+        framed[first + BLOCK_HEADER_LEN + 5] ^= 0x55;
+        framed[second + BLOCK_HEADER_LEN + 9] ^= 0xaa;
+
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+
+        // A third damaged block in the same group is beyond repair.
+        let third = second
+            + BLOCK_HEADER_LEN
+            + BlockMeta::read_from(&framed[second..]).compressed_len as usize;
+        framed[third + BLOCK_HEADER_LEN + 1] ^= 0x0f;
+        assert!(LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Filters, compression and checksums composed into one self-describing
+//! frame.
+//!
+//! A frame starts with `b"MLZP"`, a version byte and the number of stages,
+//! followed by two bytes per stage (a tag and a parameter) and the body. The
+//! encoder runs the stages in order and the decoder undoes them in reverse,
+//! so any order works: a checksum after `lzo` covers the compressed bytes,
+//! one before it covers the original data.
+
+use crate::{adler32, crc32, Decompressor, Error, LZOResult, LZO};
+use std::convert::TryFrom;
+
+const MAGIC: &[u8; 4] = b"MLZP";
+const VERSION: u8 = 1;
+
+const TAG_SHUFFLE: u8 = 1;
+const TAG_LZO: u8 = 2;
+const TAG_CRC32: u8 = 3;
+const TAG_ADLER32: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Shuffle(u8),
+    Lzo(u8),
+    Crc32,
+    Adler32,
+}
+
+impl Stage {
+    fn encode(self) -> [u8; 2] {
+        match self {
+            Stage::Shuffle(width) => [TAG_SHUFFLE, width],
+            Stage::Lzo(level) => [TAG_LZO, level],
+            Stage::Crc32 => [TAG_CRC32, 0],
+            Stage::Adler32 => [TAG_ADLER32, 0],
+        }
+    }
+
+    fn decode(bytes: [u8; 2]) -> LZOResult<Self> {
+        match bytes {
+            [TAG_SHUFFLE, width @ 1..=u8::MAX] => Ok(Stage::Shuffle(width)),
+            [TAG_LZO, 1] => Ok(Stage::Lzo(1)),
+            [TAG_LZO, _] => Err(Error::NotYetImplemented),
+            [TAG_CRC32, 0] => Ok(Stage::Crc32),
+            [TAG_ADLER32, 0] => Ok(Stage::Adler32),
+            _ => Err(Error::InvalidHeader),
+        }
+    }
+}
+
+/// Group the `i`th byte of every `width`-byte element together, which
+/// turns arrays of fixed-size numbers into runs LZO compresses better. A
+/// tail shorter than one element is left as is.
+fn shuffle(data: &[u8], width: usize) -> Vec<u8> {
+    let elements = data.len() / width;
+    let mut out = Vec::with_capacity(data.len());
+    for byte in 0..width {
+        out.extend((0..elements).map(|e| data[e * width + byte]));
+    }
+    out.extend_from_slice(&data[elements * width..]);
+    out
+}
+
+fn unshuffle(data: &[u8], width: usize) -> Vec<u8> {
+    let elements = data.len() / width;
+    let mut out = vec![0u8; data.len()];
+    for byte in 0..width {
+        for e in 0..elements {
+            out[e * width + byte] = data[byte * elements + e];
+        }
+    }
+    out[elements * width..].copy_from_slice(&data[elements * width..]);
+    out
+}
+
+fn split_u32(data: &[u8], at_end: bool) -> LZOResult<(u32, &[u8])> {
+    if data.len() < 4 {
+        return Err(Error::InputOverrun);
+    }
+    let (value, rest) = if at_end {
+        let (rest, value) = data.split_at(data.len() - 4);
+        (value, rest)
+    } else {
+        data.split_at(4)
+    };
+    Ok((
+        u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+        rest,
+    ))
+}
+
+/// Describes a chain of stages to build an encoder and decoder from.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::Pipeline;
+///
+/// let samples: Vec<u8> = (0..4096u32).flat_map(|i| (i * 3).to_le_bytes()).collect();
+/// let pipeline = Pipeline::new().shuffle(4).lzo(1).crc32();
+/// let mut encoder = pipeline.encoder().unwrap();
+/// let frame = encoder.encode(&samples).unwrap();
+/// assert!(frame.len() < samples.len() / 4);
+///
+/// // The frame records its pipeline, so decoding needs no configuration.
+/// assert_eq!(Pipeline::from_header(&frame).unwrap(), pipeline);
+/// assert_eq!(pipeline.decoder().decode(&frame).unwrap(), samples);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Start an empty pipeline, which stores data unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a byte-shuffle filter for elements of `width` bytes.
+    pub fn shuffle(mut self, width: u8) -> Self {
+        self.stages.push(Stage::Shuffle(width));
+        self
+    }
+
+    /// Add LZO1X compression at `level`. Only level 1 is available in
+    /// minilzo.
+    pub fn lzo(mut self, level: u8) -> Self {
+        self.stages.push(Stage::Lzo(level));
+        self
+    }
+
+    /// Append a CRC-32 of the data so far, verified on decode.
+    pub fn crc32(mut self) -> Self {
+        self.stages.push(Stage::Crc32);
+        self
+    }
+
+    /// Append an adler32 of the data so far, verified on decode.
+    pub fn adler32(mut self) -> Self {
+        self.stages.push(Stage::Adler32);
+        self
+    }
+
+    /// Read the pipeline recorded in the header of `frame`.
+    pub fn from_header(frame: &[u8]) -> LZOResult<Self> {
+        Self::parse(frame).map(|(pipeline, _)| pipeline)
+    }
+
+    fn parse(frame: &[u8]) -> LZOResult<(Self, &[u8])> {
+        if frame.len() < 6 || &frame[..4] != MAGIC || frame[4] != VERSION {
+            return Err(Error::InvalidHeader);
+        }
+        let count = frame[5] as usize;
+        let body = frame[6..].get(count * 2..).ok_or(Error::InvalidHeader)?;
+        let stages = frame[6..6 + count * 2]
+            .chunks(2)
+            .map(|b| Stage::decode([b[0], b[1]]))
+            .collect::<LZOResult<_>>()?;
+        Ok((Pipeline { stages }, body))
+    }
+
+    /// Validate the pipeline and create its encoder.
+    ///
+    /// Fails with `Error::InvalidArgument` for a zero shuffle width or more
+    /// than 255 stages, and `Error::NotYetImplemented` for LZO levels other
+    /// than 1.
+    pub fn encoder(&self) -> LZOResult<PipelineEncoder> {
+        if self.stages.len() > u8::MAX as usize {
+            return Err(Error::InvalidArgument);
+        }
+        for &stage in &self.stages {
+            match Stage::decode(stage.encode()) {
+                Err(Error::InvalidHeader) => return Err(Error::InvalidArgument),
+                result => result?,
+            };
+        }
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(self.stages.len() as u8);
+        for stage in &self.stages {
+            header.extend_from_slice(&stage.encode());
+        }
+        let lzo = self
+            .stages
+            .iter()
+            .any(|s| matches!(s, Stage::Lzo(_)))
+            .then(LZO::new);
+        Ok(PipelineEncoder {
+            stages: self.stages.clone(),
+            header,
+            lzo,
+        })
+    }
+
+    /// Create a decoder for frames written by this pipeline.
+    pub fn decoder(&self) -> PipelineDecoder {
+        PipelineDecoder {
+            expected: Some(self.clone()),
+        }
+    }
+}
+
+/// Runs a `Pipeline`'s stages over whole buffers.
+pub struct PipelineEncoder {
+    stages: Vec<Stage>,
+    header: Vec<u8>,
+    lzo: Option<LZO>,
+}
+
+impl PipelineEncoder {
+    /// Encode `data` into a frame.
+    pub fn encode(&mut self, data: &[u8]) -> LZOResult<Vec<u8>> {
+        let mut body = data.to_vec();
+        for &stage in &self.stages {
+            match stage {
+                Stage::Shuffle(width) => body = shuffle(&body, width as usize),
+                Stage::Lzo(_) => {
+                    let len = u32::try_from(body.len()).map_err(|_| Error::InvalidArgument)?;
+                    let lzo = self.lzo.as_mut().expect("encoder has an LZO stage");
+                    let compressed = lzo.compress(&body)?;
+                    body = Vec::with_capacity(4 + compressed.len());
+                    body.extend_from_slice(&len.to_be_bytes());
+                    body.extend_from_slice(&compressed);
+                }
+                Stage::Crc32 => body.extend_from_slice(&crc32(&body).to_be_bytes()),
+                Stage::Adler32 => body.extend_from_slice(&adler32(&body).to_be_bytes()),
+            }
+        }
+        let mut frame = Vec::with_capacity(self.header.len() + body.len());
+        frame.extend_from_slice(&self.header);
+        frame.extend_from_slice(&body);
+        Ok(frame)
+    }
+}
+
+/// Undoes the stages recorded in a frame's header.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineDecoder {
+    expected: Option<Pipeline>,
+}
+
+impl PipelineDecoder {
+    /// Create a decoder that accepts frames from any pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a frame.
+    ///
+    /// A decoder created by `Pipeline::decoder` rejects frames written by a
+    /// different pipeline with `Error::InvalidHeader`. Corruption caught by
+    /// a checksum stage is reported as `Error::ChecksumMismatch`.
+    pub fn decode(&self, frame: &[u8]) -> LZOResult<Vec<u8>> {
+        let (pipeline, body) = Pipeline::parse(frame)?;
+        if self.expected.as_ref().is_some_and(|p| *p != pipeline) {
+            return Err(Error::InvalidHeader);
+        }
+        let mut body = body.to_vec();
+        for &stage in pipeline.stages.iter().rev() {
+            match stage {
+                Stage::Shuffle(width) => body = unshuffle(&body, width as usize),
+                Stage::Lzo(_) => {
+                    let (len, compressed) = split_u32(&body, false)?;
+                    let mut out = crate::scratch::try_zeroed(len as usize)?;
+                    let written =
+                        Decompressor::initialized().decompress_safe_slice(compressed, &mut out)?;
+                    if written != out.len() {
+                        return Err(Error::OutputNotConsumed);
+                    }
+                    body = out;
+                }
+                Stage::Crc32 | Stage::Adler32 => {
+                    let (checksum, data) = split_u32(&body, true)?;
+                    let actual = if stage == Stage::Crc32 {
+                        crc32(data)
+                    } else {
+                        adler32(data)
+                    };
+                    if actual != checksum {
+                        return Err(Error::ChecksumMismatch);
+                    }
+                    body.truncate(data.len());
+                }
+            }
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_orders_and_corruption() {
+        let data: Vec<u8> = (0..10_001u32).map(|i| (i % 251) as u8).collect();
+        for pipeline in [
+            Pipeline::new(),
+            Pipeline::new().lzo(1),
+            Pipeline::new().adler32().shuffle(3).lzo(1).crc32(),
+            Pipeline::new().shuffle(8).shuffle(2),
+        ] {
+            let frame = pipeline.encoder().unwrap().encode(&data).unwrap();
+            assert_eq!(PipelineDecoder::new().decode(&frame).unwrap(), data);
+        }
+
+        assert_eq!(
+            Pipeline::new().shuffle(0).encoder().err(),
+            Some(Error::InvalidArgument)
+        );
+        assert_eq!(
+            Pipeline::new().lzo(9).encoder().err(),
+            Some(Error::NotYetImplemented)
+        );
+
+        let pipeline = Pipeline::new().lzo(1).crc32();
+        let mut frame = pipeline.encoder().unwrap().encode(&data).unwrap();
+        let last = frame.len() - 5;
+        frame[last] ^= 1;
+        assert_eq!(
+            pipeline.decoder().decode(&frame),
+            Err(Error::ChecksumMismatch)
+        );
+        assert_eq!(
+            Pipeline::new().lzo(1).decoder().decode(&frame),
+            Err(Error::InvalidHeader)
+        );
+    }
+}
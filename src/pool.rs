@@ -0,0 +1,178 @@
+//! A fixed-size pool of compressor instances.
+
+use crate::{Error, LZOResult, LZO};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A pool of `LZO` instances, each with its own work memory.
+///
+/// Instances are allocated once, up front, and reused: a server can share
+/// one pool (by reference or in an `Arc`) between its threads instead of
+/// building a context per request or serializing on one locked instance.
+///
+/// `get` blocks until an instance is free. Callers that must not block
+/// indefinitely (real-time-ish threads, request handlers with deadlines)
+/// should use `try_get`, which fails immediately with `Error::WouldBlock`,
+/// or `get_timeout`, which gives up after the given duration.
+///
+/// Example
+///
+/// ```rust
+/// let pool = minilzo_rs::LzoPool::new(1).unwrap();
+/// let mut lzo = pool.try_get().unwrap();
+/// let out = lzo.compress(&[0x00u8; 1024]).unwrap();
+/// assert_eq!(pool.try_get().err(), Some(minilzo_rs::Error::WouldBlock));
+/// drop(lzo);
+/// assert!(pool.try_get().is_ok());
+/// # let _ = out;
+/// ```
+pub struct LzoPool {
+    free: Mutex<Vec<LZO>>,
+    available: Condvar,
+}
+
+impl LzoPool {
+    /// Create a pool of `size` instances.
+    pub fn new(size: usize) -> LZOResult<Self> {
+        if size == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut free = Vec::new();
+        free.try_reserve_exact(size)?;
+        for _ in 0..size {
+            free.push(LZO::try_new()?);
+        }
+        Ok(LzoPool {
+            free: Mutex::new(free),
+            available: Condvar::new(),
+        })
+    }
+
+    /// Take an instance, blocking until one is returned to the pool.
+    pub fn get(&self) -> PooledLzo<'_> {
+        let mut free = self.free.lock().unwrap();
+        loop {
+            if let Some(lzo) = free.pop() {
+                return self.wrap(lzo);
+            }
+            free = self.available.wait(free).unwrap();
+        }
+    }
+
+    /// Take an instance if one is free right now.
+    pub fn try_get(&self) -> LZOResult<PooledLzo<'_>> {
+        match self.free.lock().unwrap().pop() {
+            Some(lzo) => Ok(self.wrap(lzo)),
+            None => Err(Error::WouldBlock),
+        }
+    }
+
+    /// Take an instance, waiting at most `timeout` for one to be returned.
+    pub fn get_timeout(&self, timeout: Duration) -> LZOResult<PooledLzo<'_>> {
+        let deadline = Instant::now() + timeout;
+        let mut free = self.free.lock().unwrap();
+        loop {
+            if let Some(lzo) = free.pop() {
+                return Ok(self.wrap(lzo));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::WouldBlock);
+            }
+            free = self.available.wait_timeout(free, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Number of instances currently free.
+    pub fn idle(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    fn wrap(&self, lzo: LZO) -> PooledLzo<'_> {
+        PooledLzo {
+            pool: self,
+            lzo: Some(lzo),
+        }
+    }
+}
+
+/// An `LZO` borrowed from an `LzoPool`, returned to it on drop.
+///
+/// Poisoned instances are dropped instead of being returned, and replaced
+/// with a fresh one.
+pub struct PooledLzo<'a> {
+    pool: &'a LzoPool,
+    lzo: Option<LZO>,
+}
+
+impl Deref for PooledLzo<'_> {
+    type Target = LZO;
+
+    fn deref(&self) -> &LZO {
+        self.lzo.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledLzo<'_> {
+    fn deref_mut(&mut self) -> &mut LZO {
+        self.lzo.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledLzo<'_> {
+    fn drop(&mut self) {
+        let mut lzo = self.lzo.take().unwrap();
+        if lzo.is_poisoned() {
+            lzo = LZO::new();
+        }
+        self.pool.free.lock().unwrap().push(lzo);
+        self.pool.available.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_timeout() {
+        let pool = LzoPool::new(1).unwrap();
+        let held = pool.get();
+        let start = Instant::now();
+        assert_eq!(
+            pool.get_timeout(Duration::from_millis(20)).err(),
+            Some(Error::WouldBlock)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                drop(held);
+            });
+            assert!(pool.get_timeout(Duration::from_secs(5)).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_pool_shared_between_threads() {
+        let pool = LzoPool::new(2).unwrap();
+        let input = vec![0x33u8; 10_000];
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..20 {
+                        let mut lzo = pool.get();
+                        let compressed = lzo.compress(&input).unwrap();
+                        assert_eq!(
+                            lzo.decompress_safe(&compressed, input.len()).unwrap(),
+                            input
+                        );
+                    }
+                });
+            }
+        });
+        assert_eq!(pool.idle(), 2);
+    }
+}
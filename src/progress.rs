@@ -0,0 +1,153 @@
+//! Progress reporting, statistics and cancellation for long-running
+//! streams.
+//!
+//! minilzo's LZO1X-1 coder never invokes the `lzo_callback_t` its API
+//! threads through, so progress is reported, and cancellation checked, from
+//! the Rust side instead, once per block. A single `compress` call can't be
+//! interrupted.
+
+use crate::{Error, LZOResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Called with the total number of bytes consumed and produced so far.
+pub type ProgressFn = Box<dyn FnMut(u64, u64) + Send>;
+
+/// Totals accumulated by a stream, for logging ratios and throughput.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompressionStats {
+    /// Bytes taken in: uncompressed when compressing, compressed when
+    /// decompressing.
+    pub input_bytes: u64,
+    /// Bytes handed out, including the frame header and block headers when
+    /// compressing.
+    pub output_bytes: u64,
+    /// Data blocks coded.
+    pub blocks: u64,
+    /// Wall-clock time since the stream was created.
+    pub elapsed: Duration,
+}
+
+impl CompressionStats {
+    /// Output bytes per input byte: below 1 when compression paid off,
+    /// above 1 when decompressing. 0 before any input.
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 0.0;
+        }
+        self.output_bytes as f64 / self.input_bytes as f64
+    }
+
+    /// Input bytes per second. 0 if no time has been measured.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.input_bytes as f64 / secs
+    }
+}
+
+/// A flag for stopping an operation from another thread.
+///
+/// Clones share the flag. Operations given a token check it before each
+/// block and fail with `Error::Cancelled` once it is set.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// let token = minilzo_rs::CancelToken::new();
+/// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 4096)
+///     .unwrap()
+///     .with_cancel_token(token.clone());
+/// writer.write_all(&[0u8; 8192]).unwrap();
+///
+/// token.cancel();
+/// let err = writer.write_all(&[0u8; 8192]).unwrap_err();
+/// assert_eq!(err.to_string(), "operation cancelled");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that isn't cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every operation holding a clone of this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with `Error::Cancelled` if the token is cancelled.
+    pub(crate) fn check(&self) -> LZOResult<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Running counts, the callback to report them to and the token to stop
+/// at.
+pub(crate) struct Tracker {
+    pub(crate) consumed: u64,
+    pub(crate) produced: u64,
+    pub(crate) blocks: u64,
+    started: Instant,
+    callback: Option<ProgressFn>,
+    cancel: Option<CancelToken>,
+}
+
+impl Tracker {
+    pub(crate) fn new() -> Self {
+        Tracker {
+            consumed: 0,
+            produced: 0,
+            blocks: 0,
+            started: Instant::now(),
+            callback: None,
+            cancel: None,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            input_bytes: self.consumed,
+            output_bytes: self.produced,
+            blocks: self.blocks,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    pub(crate) fn set_callback(&mut self, callback: ProgressFn) {
+        self.callback = Some(callback);
+    }
+
+    pub(crate) fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    /// Fail with `Error::Cancelled` if the operation has been cancelled.
+    pub(crate) fn check(&self) -> LZOResult<()> {
+        self.cancel.as_ref().map_or(Ok(()), CancelToken::check)
+    }
+
+    /// Add to the counts and report the new totals.
+    pub(crate) fn advance(&mut self, consumed: u64, produced: u64) {
+        self.consumed += consumed;
+        self.produced += produced;
+        if let Some(callback) = &mut self.callback {
+            callback(self.consumed, self.produced);
+        }
+    }
+}
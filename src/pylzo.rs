@@ -0,0 +1,66 @@
+//! Blobs compatible with python-lzo's `compress(data, level, header=True)`.
+//!
+//! python-lzo prefixes the raw LZO1X stream with a 5-byte header: one
+//! algorithm byte (`0xf0`, or `0xf1` from some builds) followed by the
+//! uncompressed length as a big-endian `u32`.
+
+use crate::{Error, LZOResult, LZO};
+
+const PYLZO_HEADER_LEN: usize = 5;
+const PYLZO_MAGIC: u8 = 0xf0;
+const PYLZO_MAGIC_ALT: u8 = 0xf1;
+
+impl LZO {
+    /// Compress `src` into a python-lzo compatible blob (with header).
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let blob = lzo.compress_pylzo(&[0x00u8; 1024]).unwrap();
+    /// assert_eq!(&blob[..5], &[0xf0, 0x00, 0x00, 0x04, 0x00]);
+    /// let data = lzo.decompress_pylzo(&blob).unwrap();
+    /// assert_eq!(data, vec![0x00u8; 1024]);
+    /// ```
+    pub fn compress_pylzo(&mut self, src: &[u8]) -> LZOResult<Vec<u8>> {
+        if src.len() > u32::MAX as usize {
+            return Err(Error::InputTooLarge {
+                len: src.len(),
+                max: u32::MAX as usize,
+            });
+        }
+        let body = self.compress(src)?;
+        let mut out = Vec::with_capacity(PYLZO_HEADER_LEN + body.len());
+        out.push(PYLZO_MAGIC);
+        out.extend_from_slice(&(src.len() as u32).to_be_bytes());
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+
+    /// Decompress a python-lzo blob produced with `header=True`.
+    pub fn decompress_pylzo(&self, src: &[u8]) -> LZOResult<Vec<u8>> {
+        if src.len() < PYLZO_HEADER_LEN || (src[0] != PYLZO_MAGIC && src[0] != PYLZO_MAGIC_ALT) {
+            return Err(Error::InvalidHeader);
+        }
+        let len = u32::from_be_bytes([src[1], src[2], src[3], src[4]]) as usize;
+        Ok(self.decompress_safe(&src[PYLZO_HEADER_LEN..], len)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pylzo_bad_header() {
+        let lzo = LZO::new();
+        assert_eq!(
+            lzo.decompress_pylzo(&[0xf0, 0x00]),
+            Err(Error::InvalidHeader)
+        );
+        assert_eq!(
+            lzo.decompress_pylzo(&[0x11, 0x00, 0x00, 0x00, 0x00]),
+            Err(Error::InvalidHeader)
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! A python-lzo compatible extension module (`python` feature).
+//!
+//! The module is called `lzo` and offers python-lzo's `compress`,
+//! `decompress`, `adler32` and `crc32` with the same signatures and
+//! defaults, raising `lzo.error` on failure, so it can stand in for the C
+//! extension. Build it with maturin, or with
+//!
+//! ```text
+//! cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib
+//! ```
+//!
+//! and install the library as `lzo.so`. Compression always uses LZO1X-1:
+//! `level` is accepted for compatibility, and the output decompresses with
+//! any LZO1X decoder. The GIL is released while data is coded.
+
+use crate::{Error, LZO};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::convert::TryFrom;
+
+create_exception!(lzo, LzoError, PyException, "An LZO operation failed.");
+
+fn to_py(e: impl Into<Error>) -> PyErr {
+    LzoError::new_err(e.into().to_string())
+}
+
+/// compress(string[, level[, header]]) -- compress `string`, prefixed by a
+/// 5-byte header unless `header` is false.
+#[pyfunction]
+#[pyo3(signature = (data, level = 1, header = true))]
+fn compress<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    level: i32,
+    header: bool,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let _ = level;
+    let out = py
+        .detach(|| {
+            let mut lzo = LZO::new();
+            if header {
+                lzo.compress_pylzo(data)
+            } else {
+                Ok(lzo.compress(data)?)
+            }
+        })
+        .map_err(to_py)?;
+    Ok(PyBytes::new(py, &out))
+}
+
+/// decompress(string[, header[, buflen]]) -- decompress `string`. Without
+/// a header the uncompressed size, `buflen`, must be given.
+#[pyfunction]
+#[pyo3(signature = (data, header = true, buflen = -1))]
+fn decompress<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    header: bool,
+    buflen: i64,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let out = py
+        .detach(|| {
+            let lzo = LZO::new();
+            if header {
+                lzo.decompress_pylzo(data)
+            } else {
+                let len = usize::try_from(buflen).map_err(|_| Error::InvalidArgument)?;
+                Ok(lzo.decompress_safe(data, len)?)
+            }
+        })
+        .map_err(to_py)?;
+    Ok(PyBytes::new(py, &out))
+}
+
+/// adler32(string[, value]) -- continue the adler32 `value` over `string`.
+#[pyfunction]
+#[pyo3(signature = (data, value = 1))]
+fn adler32(data: &[u8], value: u32) -> u32 {
+    crate::adler32_with(value, data)
+}
+
+/// crc32(string[, value]) -- continue the CRC-32 `value` over `string`.
+#[pyfunction]
+#[pyo3(signature = (data, value = 0))]
+fn crc32(data: &[u8], value: u32) -> u32 {
+    crate::crc32_with(value, data)
+}
+
+#[pymodule]
+#[pyo3(name = "lzo")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(adler32, m)?)?;
+    m.add_function(wrap_pyfunction!(crc32, m)?)?;
+    m.add("error", m.py().get_type::<LzoError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python_module() {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "lzo").unwrap();
+            python_module(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("lzo", module).unwrap();
+            let script = std::ffi::CString::new(
+                "
+data = b'spam' * 1000
+blob = lzo.compress(data)
+assert blob[0] == 0xf0 and lzo.decompress(blob) == data
+raw = lzo.compress(data, 1, False)
+assert lzo.decompress(raw, False, len(data)) == data
+assert lzo.adler32(b'') == 1 and lzo.crc32(b'123456789') == 0xcbf43926
+try:
+    lzo.decompress(b'\\xf0\\x00\\x00\\x00\\x10\\x00')
+    raise AssertionError
+except lzo.error:
+    pass
+",
+            )
+            .unwrap();
+            py.run(&script, None, Some(&locals)).unwrap();
+        });
+    }
+}
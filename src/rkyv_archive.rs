@@ -0,0 +1,183 @@
+//! rkyv archives kept LZO-compressed (`rkyv` feature).
+
+use crate::{decompress_chunked, LZO};
+use rkyv::api::high::{HighSerializer, HighValidator};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::de::Pool;
+use rkyv::rancor::{self, Source, Strategy};
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Archived, Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// The archived bytes of a `T`, compressed as a framed stream.
+///
+/// Zero-copy access needs the archive in memory, aligned and
+/// uncompressed, so access costs one decompression: `access` validates the
+/// decompressed bytes once and hands back an `ArchivedRef` reading them in
+/// place. In exchange, files and caches hold the much smaller compressed
+/// form.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::CompressedArchive;
+///
+/// #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, PartialEq)]
+/// struct Tile {
+///     id: u32,
+///     cells: Vec<u16>,
+/// }
+///
+/// let tile = Tile { id: 7, cells: vec![3; 10_000] };
+/// let packed = CompressedArchive::new(&tile).unwrap();
+/// assert!(packed.as_bytes().len() < 1000);
+///
+/// // Stored and loaded again, e.g. from a file.
+/// let loaded = CompressedArchive::<Tile>::from_bytes(packed.into_bytes());
+/// let archived = loaded.access().unwrap();
+/// assert_eq!(archived.id, 7);
+/// assert_eq!(archived.cells.len(), 10_000);
+/// assert_eq!(loaded.deserialize().unwrap(), tile);
+/// ```
+pub struct CompressedArchive<T> {
+    bytes: Vec<u8>,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> CompressedArchive<T> {
+    /// Archive `value` with rkyv and compress the archive.
+    pub fn new(value: &T) -> Result<Self, rancor::Error>
+    where
+        T: for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>,
+    {
+        let archived = rkyv::to_bytes::<rancor::Error>(value)?;
+        let bytes = LZO::new()
+            .compress_chunked(&archived)
+            .map_err(rancor::Error::new)?;
+        Ok(Self::from_bytes(bytes))
+    }
+
+    /// Wrap compressed bytes from `as_bytes` or `into_bytes`. They are
+    /// checked on access.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        CompressedArchive {
+            bytes,
+            value: PhantomData,
+        }
+    }
+
+    /// The compressed archive.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Unwrap the compressed archive.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn decompress(&self) -> Result<AlignedVec, rancor::Error> {
+        let raw = decompress_chunked(&self.bytes).map_err(rancor::Error::new)?;
+        let mut aligned = AlignedVec::with_capacity(raw.len());
+        aligned.extend_from_slice(&raw);
+        Ok(aligned)
+    }
+
+    /// Decompress and validate the archive, and return it for reading in
+    /// place. Fails if the stream is damaged or doesn't hold a valid
+    /// archive of `T`.
+    pub fn access(&self) -> Result<ArchivedRef<T>, rancor::Error>
+    where
+        T: Archive,
+        Archived<T>: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>,
+    {
+        let buf = self.decompress()?;
+        rkyv::access::<Archived<T>, rancor::Error>(&buf)?;
+        Ok(ArchivedRef {
+            buf,
+            value: PhantomData,
+        })
+    }
+
+    /// Decompress, validate and deserialize the archive.
+    pub fn deserialize(&self) -> Result<T, rancor::Error>
+    where
+        T: Archive,
+        Archived<T>: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+            + Deserialize<T, Strategy<Pool, rancor::Error>>,
+    {
+        rkyv::from_bytes::<T, rancor::Error>(&self.decompress()?)
+    }
+}
+
+impl<T> Clone for CompressedArchive<T> {
+    fn clone(&self) -> Self {
+        Self::from_bytes(self.bytes.clone())
+    }
+}
+
+impl<T> fmt::Debug for CompressedArchive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedArchive")
+            .field("compressed_len", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// A validated archive decompressed by `CompressedArchive::access`,
+/// dereferencing to the archived `T`.
+pub struct ArchivedRef<T: Archive> {
+    buf: AlignedVec,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T: Archive> Deref for ArchivedRef<T> {
+    type Target = Archived<T>;
+
+    fn deref(&self) -> &Archived<T> {
+        // SAFETY: `access` validated the buffer as an archived `T` before
+        // creating this value, and the buffer isn't changed afterwards.
+        unsafe { rkyv::access_unchecked::<Archived<T>>(&self.buf) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_archive_roundtrip_and_damage() {
+        let index: HashMap<String, Vec<u32>> = (0..200)
+            .map(|i| (format!("key-{}", i), vec![i; 64]))
+            .collect();
+        let packed = CompressedArchive::new(&index).unwrap();
+        let plain_len = rkyv::to_bytes::<rancor::Error>(&index).unwrap().len();
+        assert!(packed.as_bytes().len() * 4 < plain_len);
+
+        let archived = packed.access().unwrap();
+        assert_eq!(archived.len(), 200);
+        assert_eq!(archived.get("key-9").unwrap().as_slice(), [9u32; 64]);
+        assert_eq!(packed.clone().deserialize().unwrap(), index);
+
+        let mut bytes = packed.into_bytes();
+        let len = bytes.len();
+        bytes[len / 2] ^= 0x10;
+        assert!(
+            CompressedArchive::<HashMap<String, Vec<u32>>>::from_bytes(bytes)
+                .access()
+                .is_err()
+        );
+
+        // Intact bytes that aren't an archive of the type are rejected too.
+        let other = CompressedArchive::new(&vec![1u8; 3]).unwrap();
+        assert!(
+            CompressedArchive::<HashMap<String, Vec<u32>>>::from_bytes(other.into_bytes())
+                .access()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Best-effort recovery of damaged framed streams.
+//!
+//! Blocks are independent, so a damaged stretch of the input only costs the
+//! blocks it touches: after a block fails to decode, the decoder steps one
+//! byte at a time until a plausible block header is followed by a payload
+//! that decodes and matches its checksum, and resumes from there.
+
+use crate::frame::{self, ParityHeader, BLOCK_HEADER_LEN, PARITY_MARKER};
+use crate::{BlockMeta, Checksum, Decompressor, LZO, MAX_BLOCK_SIZE};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// A stretch of the input that was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    /// The skipped bytes of the compressed input.
+    pub input: Range<usize>,
+    /// Where in the recovered data the lost blocks would have been.
+    pub output_offset: usize,
+}
+
+/// What `salvage` recovered from a stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Salvaged {
+    /// The data of every block that decoded and matched its checksum, in
+    /// stream order.
+    pub data: Vec<u8>,
+    /// The stretches of input given up on, in order.
+    pub skipped: Vec<Skipped>,
+    /// `true` if the stream ended with its end marker.
+    pub complete: bool,
+}
+
+struct Scanner<'a> {
+    src: &'a [u8],
+    checksum: Checksum,
+    delta: Option<usize>,
+    dict: Option<Arc<[u8]>>,
+    decompressor: Decompressor,
+}
+
+impl Scanner<'_> {
+    /// The block at `pos` decoded against `dict`, still delta-filtered, and
+    /// its end, if there is a good one.
+    fn block_at(&self, pos: usize, dict: &[u8]) -> Option<(Vec<u8>, usize)> {
+        let rest = self.src.get(pos..)?;
+        if rest.len() < BLOCK_HEADER_LEN {
+            return None;
+        }
+        let meta = BlockMeta::read_from(rest);
+        let (len, payload_len) = (meta.uncompressed_len as usize, meta.compressed_len as usize);
+        if len == 0 || len > MAX_BLOCK_SIZE || payload_len == 0 || payload_len > len {
+            return None;
+        }
+        let payload = rest.get(BLOCK_HEADER_LEN..BLOCK_HEADER_LEN + payload_len)?;
+        let data =
+            frame::decode_block_with(&self.decompressor, &meta, payload, self.checksum, dict)
+                .ok()?;
+        Some((data, pos + BLOCK_HEADER_LEN + payload_len))
+    }
+
+    /// The end of a well-formed parity frame at `pos`.
+    fn parity_at(&self, pos: usize) -> Option<usize> {
+        let rest = self.src.get(pos..)?;
+        if rest.len() < BLOCK_HEADER_LEN || rest[..4] != PARITY_MARKER {
+            return None;
+        }
+        let end = pos + ParityHeader::read_from(rest).ok()?.frame_len();
+        (end <= self.src.len()).then_some(end)
+    }
+
+    fn is_end(&self, pos: usize) -> bool {
+        self.src.len() >= 4 && pos == self.src.len() - 4 && self.src[pos..] == [0u8; 4]
+    }
+}
+
+/// Decode as much of a damaged framed stream as possible.
+///
+/// Whenever a block is malformed or fails its checksum, the input is
+/// scanned forward for the next good block and decoding continues there;
+/// each stretch given up on is reported with where its data was missed.
+/// A damaged stream header is skipped the same way, assuming adler32
+/// checksums. Never fails: a stream too damaged to recognize comes back
+/// with no data and one skipped stretch covering it.
+///
+/// Streams without checksums can't tell a good block from garbage that
+/// happens to decode, so salvaging them may let some garbage through. In
+/// chained streams, blocks after a skipped stretch are decoded against
+/// the data recovered before it, so only the ones not reaching back into
+/// the lost data come through.
+///
+/// # Panics
+///
+/// Panics if minilzo's self-check fails, like `LZO::new`.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// let input: Vec<u8> = (0..40_000u32).map(|i| (i % 253) as u8).collect();
+/// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 10_000).unwrap();
+/// writer.write_all(&input).unwrap();
+/// let mut framed = writer.finish().unwrap();
+/// framed[100] ^= 0xff;
+///
+/// let salvaged = minilzo_rs::salvage(&framed);
+/// assert!(salvaged.complete);
+/// assert_eq!(salvaged.skipped.len(), 1);
+/// assert_eq!(salvaged.skipped[0].output_offset, 0);
+/// assert_eq!(salvaged.data, &input[10_000..]);
+/// ```
+pub fn salvage(src: impl AsRef<[u8]>) -> Salvaged {
+    LZO::lzo_init().expect("minilzo self-check failed");
+    let src = src.as_ref();
+    let (mut pos, checksum, delta, chained) = match frame::parse_header(src) {
+        Ok(flags) => (
+            frame::full_header_len(src).min(src.len()),
+            frame::checksum_from_flags(flags),
+            frame::delta_from_header(src),
+            frame::chained(src),
+        ),
+        Err(_) => (0, Checksum::Adler32, None, false),
+    };
+    // Only an embedded dictionary can be used; without it, blocks
+    // compressed against one are skipped as damaged.
+    let dict = frame::dict_from_header(src)
+        .and_then(|header| frame::stream_dict(header, None))
+        .ok()
+        .flatten();
+    let mut chain = if chained {
+        frame::chain_from_header(src, dict.as_deref())
+            .ok()
+            .flatten()
+    } else {
+        None
+    };
+    let scanner = Scanner {
+        src,
+        checksum,
+        delta,
+        dict,
+        decompressor: Decompressor::initialized(),
+    };
+    let mut salvaged = Salvaged::default();
+    let mut damaged: Option<usize> = None;
+    while pos < src.len() {
+        let next = if scanner.is_end(pos) {
+            None
+        } else if let Some(end) = scanner.parity_at(pos) {
+            Some((Vec::new(), end))
+        } else if let Some(block) = scanner.block_at(
+            pos,
+            match &chain {
+                Some(chain) => chain.window(),
+                None => scanner.dict.as_deref().unwrap_or_default(),
+            },
+        ) {
+            Some(block)
+        } else {
+            damaged.get_or_insert(pos);
+            pos += 1;
+            continue;
+        };
+        if let Some(start) = damaged.take() {
+            salvaged.skipped.push(Skipped {
+                input: start..pos,
+                output_offset: salvaged.data.len(),
+            });
+        }
+        match next {
+            Some((mut data, end)) => {
+                if let Some(chain) = &mut chain {
+                    chain.push(&data);
+                }
+                if let Some(stride) = scanner.delta {
+                    crate::delta::decode(&mut data, stride);
+                }
+                salvaged.data.extend_from_slice(&data);
+                pos = end;
+            }
+            None => {
+                salvaged.complete = true;
+                return salvaged;
+            }
+        }
+    }
+    if let Some(start) = damaged {
+        salvaged.skipped.push(Skipped {
+            input: start..src.len(),
+            output_offset: salvaged.data.len(),
+        });
+    }
+    salvaged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzoWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_salvage_skips_damage() {
+        let input: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 10_000).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+        assert_eq!(
+            salvage(&framed),
+            Salvaged {
+                data: input.clone(),
+                skipped: Vec::new(),
+                complete: true,
+            }
+        );
+
+        let starts: Vec<usize> = frame::split_blocks(&framed)
+            .unwrap()
+            .iter()
+            .map(|(_, payload)| payload.as_ptr() as usize - framed.as_ptr() as usize)
+            .map(|payload| payload - BLOCK_HEADER_LEN)
+            .collect();
+        let (third, fifth) = (starts[2], starts[4]);
+        let mut damaged = framed.clone();
+        // Wipe out the third and fourth blocks, all but their last byte.
+        for byte in &mut damaged[third..fifth - 1] {
+            *byte = 0x55;
+        }
+        let salvaged = salvage(&damaged);
+        assert!(salvaged.complete);
+        assert_eq!(
+            salvaged.skipped,
+            vec![Skipped {
+                input: third..fifth,
+                output_offset: 20_000,
+            }]
+        );
+        assert_eq!(&salvaged.data[..20_000], &input[..20_000]);
+        assert_eq!(&salvaged.data[20_000..], &input[40_000..]);
+
+        // A truncated stream keeps what came before the cut.
+        let salvaged = salvage(&framed[..framed.len() - 6]);
+        assert!(!salvaged.complete);
+        assert_eq!(salvaged.data, &input[..90_000]);
+        assert_eq!(salvaged.skipped.len(), 1);
+
+        let salvaged = salvage(LZO::new().compress(&input).unwrap());
+        assert!(salvaged.data.is_empty() && !salvaged.complete);
+    }
+}
@@ -0,0 +1,344 @@
+//! The push/pull core of the streaming encoder and decoder.
+//!
+//! `StreamEncoder` and `StreamDecoder` hold all of the framing state and do
+//! no I/O: input is pushed in with `feed_input` and output pulled out with
+//! `read_output`, so the buffer-to-buffer coders and the tokio and
+//! `futures::io` adapters are thin shims moving bytes between the core and
+//! their own kind of stream.
+
+use crate::frame::{self, Decoder};
+use crate::scratch::Scratch;
+use crate::{DecodeError, Decompressor, Error, LZOResult, DEFAULT_BLOCK_SIZE, LZO};
+
+/// Compresses pushed input into a framed stream, block by block.
+///
+/// Input is only taken while no compressed output is pending, so memory
+/// stays at one block of input and one of output; `read_output` (or
+/// `pending_output` and `consume_output`) makes room. The stream header is
+/// pending from the start.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{StreamDecoder, StreamEncoder};
+///
+/// let input = [0x2au8; 10_000];
+/// let mut encoder = StreamEncoder::with_block_size(4096).unwrap();
+/// let mut framed = Vec::new();
+/// let mut out = [0u8; 512];
+/// let mut pos = 0;
+/// while pos < input.len() {
+///     pos += encoder.feed_input(&input[pos..]).unwrap();
+///     let n = encoder.read_output(&mut out);
+///     framed.extend_from_slice(&out[..n]);
+/// }
+/// encoder.finish().unwrap();
+/// while !encoder.is_done() {
+///     let n = encoder.read_output(&mut out);
+///     framed.extend_from_slice(&out[..n]);
+/// }
+///
+/// let mut decoder = StreamDecoder::new().unwrap();
+/// let mut output = Vec::new();
+/// for chunk in framed.chunks(100) {
+///     assert_eq!(decoder.feed_input(chunk), chunk.len());
+///     loop {
+///         let n = decoder.read_output(&mut out).unwrap();
+///         if n == 0 {
+///             break;
+///         }
+///         output.extend_from_slice(&out[..n]);
+///     }
+/// }
+/// decoder.finish().unwrap();
+/// assert_eq!(output, &input[..]);
+/// ```
+pub struct StreamEncoder {
+    lzo: LZO,
+    block_size: usize,
+    buf: Scratch,
+    out: Scratch,
+    pos: usize,
+    finished: bool,
+}
+
+impl StreamEncoder {
+    /// Create an encoder using `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn new() -> LZOResult<Self> {
+        Self::with_block_size(DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create an encoder that compresses `block_size` bytes at a time.
+    pub fn with_block_size(block_size: usize) -> LZOResult<Self> {
+        Self::from_lzo(LZO::builder().block_size(block_size).build()?)
+    }
+
+    /// Create an encoder compressing with a configured instance, using its
+    /// block size, checksum policy and delta filter.
+    pub fn from_lzo(lzo: LZO) -> LZOResult<Self> {
+        let block_size = lzo.block_size();
+        let mut out = Vec::new();
+        frame::encode_header_for(&mut out, &lzo);
+        Ok(StreamEncoder {
+            lzo,
+            block_size,
+            buf: Scratch::try_with_capacity(block_size)?,
+            out: out.into(),
+            pos: 0,
+            finished: false,
+        })
+    }
+
+    fn encode_buffered(&mut self) -> LZOResult<()> {
+        if !self.buf.is_empty() {
+            frame::encode_block(&mut self.lzo, &self.buf, &mut self.out)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Take as much of `input` as fits before output becomes pending, and
+    /// return how much was taken. A block is compressed as soon as it is
+    /// full. Fails with `Error::InvalidArgument` after `finish`.
+    pub fn feed_input(&mut self, input: &[u8]) -> LZOResult<usize> {
+        if self.finished {
+            return Err(Error::InvalidArgument);
+        }
+        let mut consumed = 0;
+        while self.pending_output().is_empty() && consumed < input.len() {
+            let n = (input.len() - consumed).min(self.block_size - self.buf.len());
+            self.buf.extend_from_slice(&input[consumed..consumed + n]);
+            consumed += n;
+            if self.buf.len() == self.block_size {
+                self.encode_buffered()?;
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Copy pending compressed bytes into `output`, returning how many.
+    pub fn read_output(&mut self, output: &mut [u8]) -> usize {
+        let pending = self.pending_output();
+        let n = output.len().min(pending.len());
+        output[..n].copy_from_slice(&pending[..n]);
+        self.consume_output(n);
+        n
+    }
+
+    /// The compressed bytes waiting to be read, for writing them out
+    /// without a copy; mark what was written with `consume_output`.
+    pub fn pending_output(&self) -> &[u8] {
+        &self.out[self.pos..]
+    }
+
+    /// Drop the first `n` bytes of `pending_output`.
+    pub fn consume_output(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.out.len());
+        if self.pos == self.out.len() {
+            self.out.clear();
+            self.pos = 0;
+        }
+    }
+
+    /// Compress the current partial block, so everything fed so far can be
+    /// decoded once the pending output is delivered.
+    pub fn flush(&mut self) -> LZOResult<()> {
+        self.encode_buffered()
+    }
+
+    /// Compress the current partial block and add the end marker to the
+    /// pending output. Calling it again does nothing.
+    pub fn finish(&mut self) -> LZOResult<()> {
+        if !self.finished {
+            self.encode_buffered()?;
+            frame::encode_end(&mut self.out);
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// `true` once `finish` has been called.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// `true` once the stream is finished and all of it has been read.
+    pub fn is_done(&self) -> bool {
+        self.finished && self.out.is_empty()
+    }
+}
+
+/// Decompresses a framed stream pushed in pieces split anywhere.
+///
+/// `read_output` returning 0 with `is_done` still `false` means more input
+/// is needed. Input is only taken while no decompressed output is pending,
+/// and not at all after the end marker.
+pub struct StreamDecoder {
+    decompressor: Decompressor,
+    decoder: Decoder,
+    limit: Option<u64>,
+    block: Scratch,
+    pos: usize,
+}
+
+impl StreamDecoder {
+    /// Create a decoder for one framed stream.
+    pub fn new() -> LZOResult<Self> {
+        Ok(StreamDecoder {
+            decompressor: Decompressor::new()?,
+            decoder: Decoder::new(),
+            limit: None,
+            block: Scratch::new(),
+            pos: 0,
+        })
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` once a stream would
+    /// decompress to more than `limit` bytes. The limit survives `reset`.
+    pub fn max_output_size(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self.decoder.set_max_output(limit);
+        self
+    }
+
+    /// Start decoding a new stream, discarding any buffered state.
+    pub fn reset(&mut self) {
+        self.decoder = Decoder::new();
+        if let Some(limit) = self.limit {
+            self.decoder.set_max_output(limit);
+        }
+        self.block.clear();
+        self.pos = 0;
+    }
+
+    /// Buffer `input` and return how much was taken: all of it, or nothing
+    /// while output is pending or the stream has ended.
+    pub fn feed_input(&mut self, input: &[u8]) -> usize {
+        if !self.pending_output().is_empty() || self.decoder.is_done() {
+            return 0;
+        }
+        self.decoder.feed(input);
+        input.len()
+    }
+
+    /// Decode as much as the input fed so far allows into `output`, and
+    /// return how many bytes were written.
+    pub fn read_output(&mut self, output: &mut [u8]) -> LZOResult<usize> {
+        while self.pending_output().is_empty() && !self.decoder.is_done() {
+            match self.decoder.decode(&self.decompressor)? {
+                Some(block) => {
+                    self.block = block.into();
+                    self.pos = 0;
+                }
+                None => break,
+            }
+        }
+        let pending = self.pending_output();
+        let n = output.len().min(pending.len());
+        output[..n].copy_from_slice(&pending[..n]);
+        self.consume_output(n);
+        Ok(n)
+    }
+
+    /// The decompressed bytes already decoded and not yet read.
+    pub fn pending_output(&self) -> &[u8] {
+        &self.block[self.pos..]
+    }
+
+    /// Drop the first `n` bytes of `pending_output`.
+    pub fn consume_output(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.block.len());
+        if self.pos == self.block.len() {
+            self.block.clear();
+            self.pos = 0;
+        }
+    }
+
+    /// Input fed but not decoded yet; once the stream is done, whatever
+    /// followed its end marker.
+    #[cfg(feature = "tower")]
+    pub(crate) fn remaining(&self) -> &[u8] {
+        self.decoder.remaining()
+    }
+
+    /// `true` once the end marker has been decoded and all output read.
+    pub fn is_done(&self) -> bool {
+        self.decoder.is_done() && self.block.is_empty()
+    }
+
+    /// Offset in the input of the frame being decoded. After `read_output`
+    /// fails, that is where the damage is.
+    pub fn input_offset(&self) -> u64 {
+        self.decoder.input_offset()
+    }
+
+    /// Index of the data block being decoded, counting from 0. After
+    /// `read_output` fails, that is the damaged block.
+    pub fn block_index(&self) -> u64 {
+        self.decoder.block_index()
+    }
+
+    /// `error` from `read_output`, with where in the stream it happened.
+    pub(crate) fn locate(&self, error: Error) -> DecodeError {
+        self.decoder.locate(error)
+    }
+
+    /// Check, once the input has run out, that the stream reached its end
+    /// marker; fails with `Error::EOFNotFound` otherwise.
+    pub fn finish(&self) -> LZOResult<()> {
+        if !self.decoder.is_done() {
+            return Err(Error::EOFNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sans_io_roundtrip() {
+        let input: Vec<u8> = (0..70_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        let mut encoder = StreamEncoder::with_block_size(5000).unwrap();
+        assert_eq!(encoder.feed_input(&input).unwrap(), 0);
+        let mut framed = Vec::new();
+        let mut pos = 0;
+        while pos < input.len() {
+            pos += encoder.feed_input(&input[pos..]).unwrap();
+            framed.extend_from_slice(encoder.pending_output());
+            let n = encoder.pending_output().len();
+            encoder.consume_output(n);
+        }
+        encoder.finish().unwrap();
+        assert_eq!(encoder.feed_input(b"x"), Err(Error::InvalidArgument));
+        let mut out = [0u8; 7];
+        while !encoder.is_done() {
+            let n = encoder.read_output(&mut out);
+            framed.extend_from_slice(&out[..n]);
+        }
+        let mut output = Vec::new();
+        let mut reader = crate::LzoReader::new(&framed[..]).unwrap();
+        std::io::Read::read_to_end(&mut reader, &mut output).unwrap();
+        assert_eq!(output, input);
+
+        let mut decoder = StreamDecoder::new().unwrap();
+        output.clear();
+        let mut pos = 0;
+        while !decoder.is_done() {
+            let n = decoder.read_output(&mut out).unwrap();
+            output.extend_from_slice(&out[..n]);
+            if n == 0 {
+                pos += decoder.feed_input(&framed[pos..(pos + 3).min(framed.len())]);
+            }
+        }
+        decoder.finish().unwrap();
+        assert_eq!(output, input);
+        assert_eq!(decoder.feed_input(b"x"), 0);
+
+        decoder.reset();
+        decoder.feed_input(&framed[..framed.len() - 1]);
+        while decoder.read_output(&mut out).unwrap() > 0 {}
+        assert_eq!(decoder.finish(), Err(Error::EOFNotFound));
+    }
+}
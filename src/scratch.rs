@@ -0,0 +1,108 @@
+//! Internal buffers that are wiped when dropped (`zeroize` feature).
+//!
+//! Streams and contexts keep recent plaintext, or data derived from it, in
+//! their buffers. With the `zeroize` feature those buffers, including any
+//! spare capacity, are overwritten with zeros when they are dropped, as is
+//! `LZO`'s work memory. Only the final allocation is wiped: when a buffer
+//! grows, the allocation it moves out of is freed as it is. Without the
+//! feature `Scratch` is a plain `Vec`.
+//!
+//! Buffers whose size comes from the input, such as a block's declared
+//! length, are allocated with `try_reserve`, so a corrupt or hostile
+//! stream fails with `OutOfMemory` instead of aborting the process.
+
+use std::collections::TryReserveError;
+use std::ops::{Deref, DerefMut};
+
+/// `Vec::with_capacity`, failing instead of aborting if the memory can't
+/// be had.
+pub(crate) fn try_with_capacity(capacity: usize) -> Result<Vec<u8>, TryReserveError> {
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(capacity)?;
+    Ok(buf)
+}
+
+/// `vec![0; len]`, failing instead of aborting if the memory can't be had.
+pub(crate) fn try_zeroed(len: usize) -> Result<Vec<u8>, TryReserveError> {
+    let mut buf = try_with_capacity(len)?;
+    buf.resize(len, 0);
+    Ok(buf)
+}
+
+/// A `Vec<u8>` that is zeroized on drop with the `zeroize` feature.
+#[derive(Debug, Default)]
+pub(crate) struct Scratch(Vec<u8>);
+
+impl Scratch {
+    pub(crate) fn new() -> Self {
+        Scratch(Vec::new())
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Scratch(Vec::with_capacity(capacity))
+    }
+
+    pub(crate) fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        try_with_capacity(capacity).map(Scratch)
+    }
+}
+
+impl From<Vec<u8>> for Scratch {
+    fn from(buf: Vec<u8>) -> Self {
+        Scratch(buf)
+    }
+}
+
+impl AsRef<[u8]> for Scratch {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for Scratch {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Scratch {
+    /// Overwrite the whole allocation with zeros and empty the buffer.
+    fn wipe(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.0);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+#[cfg(all(test, feature = "zeroize"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wipe_covers_capacity() {
+        let mut buf = Scratch::with_capacity(64);
+        buf.extend_from_slice(b"secret plaintext");
+        buf.truncate(6);
+        let capacity = buf.capacity();
+        buf.wipe();
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), capacity);
+        // Every byte of the allocation was just written with zero.
+        let all = unsafe { std::slice::from_raw_parts(buf.as_ptr(), capacity) };
+        assert!(all.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,373 @@
+//! Vectorized checksum kernels, picked at runtime.
+//!
+//! Each entry point consumes as much of `buf` as its kernel handles, updates
+//! the running checksum state and returns how many bytes it consumed; the
+//! caller finishes the rest with the scalar code. When the CPU lacks the
+//! needed features nothing is consumed.
+
+// Other architectures, such as wasm32, have no kernels.
+#![cfg_attr(
+    not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")),
+    allow(dead_code, unused_imports)
+)]
+
+use crate::checksum::{ADLER_BASE, ADLER_NMAX};
+
+/// Bytes per adler32 vector step.
+const ADLER_BLOCK: usize = 32;
+/// Vector steps between modular reductions, keeping the sums within `u32`.
+const ADLER_STEPS: usize = ADLER_NMAX / ADLER_BLOCK;
+
+/// Advance the adler32 halves `a` and `b` over a prefix of `buf`.
+#[inline]
+pub(crate) fn adler32(a: &mut u32, b: &mut u32, buf: &[u8]) -> usize {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if buf.len() >= ADLER_BLOCK {
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the CPU supports AVX2.
+                return unsafe { x86::adler32_avx2(a, b, buf) };
+            }
+            if is_x86_feature_detected!("ssse3") {
+                // SAFETY: the CPU supports SSSE3.
+                return unsafe { x86::adler32_ssse3(a, b, buf) };
+            }
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if buf.len() >= ADLER_BLOCK {
+            // SAFETY: NEON is part of the aarch64 baseline.
+            return unsafe { arm::adler32_neon(a, b, buf) };
+        }
+    }
+    let _ = (a, b, buf);
+    0
+}
+
+/// Advance the (pre-inverted) CRC-32 state `c` over a prefix of `buf`.
+#[inline]
+pub(crate) fn crc32(c: &mut u32, buf: &[u8]) -> usize {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if buf.len() >= 64
+            && is_x86_feature_detected!("pclmulqdq")
+            && is_x86_feature_detected!("sse4.1")
+        {
+            // SAFETY: the CPU supports PCLMULQDQ and SSE4.1.
+            return unsafe { x86::crc32_pclmul(c, buf) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if buf.len() >= 8 && std::arch::is_aarch64_feature_detected!("crc") {
+            // SAFETY: the CPU supports the CRC32 instructions.
+            return unsafe { arm::crc32_arm(c, buf) };
+        }
+    }
+    let _ = (c, buf);
+    0
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    use super::{ADLER_BASE, ADLER_BLOCK, ADLER_STEPS};
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    unsafe fn sum_lanes(v: __m128i) -> u32 {
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b1011_0001>(v));
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b0100_1110>(v));
+        _mm_cvtsi128_si32(v) as u32
+    }
+
+    /// Adler32 over 32-byte blocks, following the approach of Chromium's
+    /// zlib: `b` gains 32 times the running `a` per block plus the bytes
+    /// weighted by their distance from the block's end.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn adler32_ssse3(a: &mut u32, b: &mut u32, buf: &[u8]) -> usize {
+        let tap1 = _mm_setr_epi8(
+            32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17,
+        );
+        let tap2 = _mm_setr_epi8(16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1);
+        let zero = _mm_setzero_si128();
+        let ones = _mm_set1_epi16(1);
+        let blocks = buf.len() / ADLER_BLOCK;
+        let mut p = buf.as_ptr();
+        let mut done = 0;
+        while done < blocks {
+            let n = (blocks - done).min(ADLER_STEPS);
+            done += n;
+            let mut v_ps = _mm_set_epi32(0, 0, 0, (*a * n as u32) as i32);
+            let mut v_s2 = _mm_set_epi32(0, 0, 0, *b as i32);
+            let mut v_s1 = zero;
+            for _ in 0..n {
+                let bytes1 = _mm_loadu_si128(p as *const __m128i);
+                let bytes2 = _mm_loadu_si128(p.add(16) as *const __m128i);
+                v_ps = _mm_add_epi32(v_ps, v_s1);
+                v_s1 = _mm_add_epi32(v_s1, _mm_sad_epu8(bytes1, zero));
+                let mad1 = _mm_maddubs_epi16(bytes1, tap1);
+                v_s2 = _mm_add_epi32(v_s2, _mm_madd_epi16(mad1, ones));
+                v_s1 = _mm_add_epi32(v_s1, _mm_sad_epu8(bytes2, zero));
+                let mad2 = _mm_maddubs_epi16(bytes2, tap2);
+                v_s2 = _mm_add_epi32(v_s2, _mm_madd_epi16(mad2, ones));
+                p = p.add(ADLER_BLOCK);
+            }
+            v_s2 = _mm_add_epi32(v_s2, _mm_slli_epi32::<5>(v_ps));
+            *a = (*a + sum_lanes(v_s1)) % ADLER_BASE;
+            *b = sum_lanes(v_s2) % ADLER_BASE;
+        }
+        blocks * ADLER_BLOCK
+    }
+
+    /// The SSSE3 kernel with one 32-byte load per block.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn adler32_avx2(a: &mut u32, b: &mut u32, buf: &[u8]) -> usize {
+        let tap = _mm256_setr_epi8(
+            32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11,
+            10, 9, 8, 7, 6, 5, 4, 3, 2, 1,
+        );
+        let zero = _mm256_setzero_si256();
+        let ones = _mm256_set1_epi16(1);
+        let blocks = buf.len() / ADLER_BLOCK;
+        let mut p = buf.as_ptr();
+        let mut done = 0;
+        while done < blocks {
+            let n = (blocks - done).min(ADLER_STEPS);
+            done += n;
+            let mut v_ps = _mm256_setr_epi32((*a * n as u32) as i32, 0, 0, 0, 0, 0, 0, 0);
+            let mut v_s2 = _mm256_setr_epi32(*b as i32, 0, 0, 0, 0, 0, 0, 0);
+            let mut v_s1 = zero;
+            for _ in 0..n {
+                let bytes = _mm256_loadu_si256(p as *const __m256i);
+                v_ps = _mm256_add_epi32(v_ps, v_s1);
+                v_s1 = _mm256_add_epi32(v_s1, _mm256_sad_epu8(bytes, zero));
+                let mad = _mm256_maddubs_epi16(bytes, tap);
+                v_s2 = _mm256_add_epi32(v_s2, _mm256_madd_epi16(mad, ones));
+                p = p.add(ADLER_BLOCK);
+            }
+            v_s2 = _mm256_add_epi32(v_s2, _mm256_slli_epi32::<5>(v_ps));
+            let s1 = _mm_add_epi32(
+                _mm256_castsi256_si128(v_s1),
+                _mm256_extracti128_si256::<1>(v_s1),
+            );
+            let s2 = _mm_add_epi32(
+                _mm256_castsi256_si128(v_s2),
+                _mm256_extracti128_si256::<1>(v_s2),
+            );
+            *a = (*a + sum_lanes(s1)) % ADLER_BASE;
+            *b = sum_lanes(s2) % ADLER_BASE;
+        }
+        blocks * ADLER_BLOCK
+    }
+
+    /// CRC-32 by carry-less multiplication, folding four 128-bit lanes at
+    /// a time and Barrett-reducing at the end, as in Intel's "Fast CRC
+    /// Computation for Generic Polynomials Using PCLMULQDQ". Consumes a
+    /// multiple of 16 bytes; `buf` must hold at least 64.
+    #[target_feature(enable = "pclmulqdq", enable = "sse4.1")]
+    pub(super) unsafe fn crc32_pclmul(c: &mut u32, buf: &[u8]) -> usize {
+        let k1k2 = _mm_set_epi64x(0x01_c6e4_1596, 0x01_5444_2bd4);
+        let k3k4 = _mm_set_epi64x(0x00_ccaa_009e, 0x01_7519_97d0);
+        let k5k0 = _mm_set_epi64x(0, 0x01_63cd_6124);
+        let poly = _mm_set_epi64x(0x01_f701_1641, 0x01_db71_0641);
+        let mask32 = _mm_setr_epi32(!0, 0, !0, 0);
+
+        let mut p = buf.as_ptr();
+        let mut len = buf.len() & !15;
+        let consumed = len;
+
+        let mut x1 = _mm_loadu_si128(p as *const __m128i);
+        let mut x2 = _mm_loadu_si128(p.add(16) as *const __m128i);
+        let mut x3 = _mm_loadu_si128(p.add(32) as *const __m128i);
+        let mut x4 = _mm_loadu_si128(p.add(48) as *const __m128i);
+        x1 = _mm_xor_si128(x1, _mm_cvtsi32_si128(*c as i32));
+        p = p.add(64);
+        len -= 64;
+
+        while len >= 64 {
+            let x5 = _mm_clmulepi64_si128::<0x00>(x1, k1k2);
+            let x6 = _mm_clmulepi64_si128::<0x00>(x2, k1k2);
+            let x7 = _mm_clmulepi64_si128::<0x00>(x3, k1k2);
+            let x8 = _mm_clmulepi64_si128::<0x00>(x4, k1k2);
+            x1 = _mm_clmulepi64_si128::<0x11>(x1, k1k2);
+            x2 = _mm_clmulepi64_si128::<0x11>(x2, k1k2);
+            x3 = _mm_clmulepi64_si128::<0x11>(x3, k1k2);
+            x4 = _mm_clmulepi64_si128::<0x11>(x4, k1k2);
+            let y5 = _mm_loadu_si128(p as *const __m128i);
+            let y6 = _mm_loadu_si128(p.add(16) as *const __m128i);
+            let y7 = _mm_loadu_si128(p.add(32) as *const __m128i);
+            let y8 = _mm_loadu_si128(p.add(48) as *const __m128i);
+            x1 = _mm_xor_si128(_mm_xor_si128(x1, x5), y5);
+            x2 = _mm_xor_si128(_mm_xor_si128(x2, x6), y6);
+            x3 = _mm_xor_si128(_mm_xor_si128(x3, x7), y7);
+            x4 = _mm_xor_si128(_mm_xor_si128(x4, x8), y8);
+            p = p.add(64);
+            len -= 64;
+        }
+
+        // Fold the four lanes, then any remaining 16-byte blocks, into one.
+        for next in [x2, x3, x4] {
+            let x5 = _mm_clmulepi64_si128::<0x00>(x1, k3k4);
+            x1 = _mm_clmulepi64_si128::<0x11>(x1, k3k4);
+            x1 = _mm_xor_si128(_mm_xor_si128(x1, next), x5);
+        }
+        while len >= 16 {
+            let next = _mm_loadu_si128(p as *const __m128i);
+            let x5 = _mm_clmulepi64_si128::<0x00>(x1, k3k4);
+            x1 = _mm_clmulepi64_si128::<0x11>(x1, k3k4);
+            x1 = _mm_xor_si128(_mm_xor_si128(x1, next), x5);
+            p = p.add(16);
+            len -= 16;
+        }
+
+        // 128 bits down to 64.
+        let x2 = _mm_clmulepi64_si128::<0x10>(x1, k3k4);
+        x1 = _mm_xor_si128(_mm_srli_si128::<8>(x1), x2);
+        let x2 = _mm_srli_si128::<4>(x1);
+        x1 = _mm_and_si128(x1, mask32);
+        x1 = _mm_clmulepi64_si128::<0x00>(x1, k5k0);
+        x1 = _mm_xor_si128(x1, x2);
+
+        // Barrett reduction to 32 bits.
+        let mut x2 = _mm_and_si128(x1, mask32);
+        x2 = _mm_clmulepi64_si128::<0x10>(x2, poly);
+        x2 = _mm_and_si128(x2, mask32);
+        x2 = _mm_clmulepi64_si128::<0x00>(x2, poly);
+        x1 = _mm_xor_si128(x1, x2);
+
+        *c = _mm_extract_epi32::<1>(x1) as u32;
+        consumed
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use super::{ADLER_BASE, ADLER_BLOCK, ADLER_STEPS};
+    use std::arch::aarch64::*;
+    use std::convert::TryInto;
+
+    /// Adler32 over 32-byte blocks with per-column byte sums, weighted
+    /// once per reduction instead of per block.
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn adler32_neon(a: &mut u32, b: &mut u32, buf: &[u8]) -> usize {
+        const TAPS: [u16; 32] = [
+            32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11,
+            10, 9, 8, 7, 6, 5, 4, 3, 2, 1,
+        ];
+        let blocks = buf.len() / ADLER_BLOCK;
+        let mut p = buf.as_ptr();
+        let mut done = 0;
+        while done < blocks {
+            let n = (blocks - done).min(ADLER_STEPS);
+            done += n;
+            let mut v_s2 = vsetq_lane_u32::<3>(*a * n as u32, vdupq_n_u32(0));
+            let mut v_s1 = vdupq_n_u32(0);
+            let mut columns = [vdupq_n_u16(0); 4];
+            for _ in 0..n {
+                let bytes1 = vld1q_u8(p);
+                let bytes2 = vld1q_u8(p.add(16));
+                v_s2 = vaddq_u32(v_s2, v_s1);
+                v_s1 = vpadalq_u16(v_s1, vpadalq_u8(vpaddlq_u8(bytes1), bytes2));
+                columns[0] = vaddw_u8(columns[0], vget_low_u8(bytes1));
+                columns[1] = vaddw_u8(columns[1], vget_high_u8(bytes1));
+                columns[2] = vaddw_u8(columns[2], vget_low_u8(bytes2));
+                columns[3] = vaddw_u8(columns[3], vget_high_u8(bytes2));
+                p = p.add(ADLER_BLOCK);
+            }
+            v_s2 = vshlq_n_u32::<5>(v_s2);
+            for (i, column) in columns.iter().enumerate() {
+                let taps = TAPS.as_ptr().add(i * 8);
+                v_s2 = vmlal_u16(v_s2, vget_low_u16(*column), vld1_u16(taps));
+                v_s2 = vmlal_u16(v_s2, vget_high_u16(*column), vld1_u16(taps.add(4)));
+            }
+            *a = (*a + vaddvq_u32(v_s1)) % ADLER_BASE;
+            *b = (*b + vaddvq_u32(v_s2)) % ADLER_BASE;
+        }
+        blocks * ADLER_BLOCK
+    }
+
+    /// CRC-32 with the ARMv8 CRC32 instructions, 8 bytes at a time.
+    #[target_feature(enable = "crc")]
+    pub(super) unsafe fn crc32_arm(c: &mut u32, buf: &[u8]) -> usize {
+        let mut words = buf.chunks_exact(8);
+        for word in &mut words {
+            *c = __crc32d(*c, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+        buf.len() - words.remainder().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{adler32_with, crc32};
+
+    fn scalar_adler32(seed: u32, buf: &[u8]) -> u32 {
+        let (mut a, mut b) = (seed & 0xffff, seed >> 16);
+        for &byte in buf {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        (b << 16) | a
+    }
+
+    fn scalar_crc32(buf: &[u8]) -> u32 {
+        let mut c = !0u32;
+        for &byte in buf {
+            c ^= byte as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+        }
+        !c
+    }
+
+    #[test]
+    fn test_kernels_match_scalar() {
+        let mut state = 0x2545_f491u32;
+        let data: Vec<u8> = (0..40_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let ones = vec![0xffu8; 40_000];
+        for buf in [&data[..], &ones[..]] {
+            for offset in [0, 1, 7] {
+                for len in [31, 32, 63, 64, 65, 100, 5536, 5568, 11_111, 39_990] {
+                    let slice = &buf[offset..offset + len];
+                    assert_eq!(
+                        adler32_with(1, slice),
+                        scalar_adler32(1, slice),
+                        "len {}",
+                        len
+                    );
+                    assert_eq!(
+                        adler32_with(0xfff0_fff0, slice),
+                        scalar_adler32(0xfff0_fff0, slice),
+                        "len {}",
+                        len
+                    );
+                    assert_eq!(crc32(slice), scalar_crc32(slice), "len {}", len);
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    if is_x86_feature_detected!("ssse3") {
+                        let (mut a, mut b) = (1, 0);
+                        let n = unsafe { super::x86::adler32_ssse3(&mut a, &mut b, slice) };
+                        let rest = adler32_with((b << 16) | a, &slice[n..]);
+                        assert_eq!(rest, scalar_adler32(1, slice), "len {}", len);
+                    }
+                }
+            }
+        }
+    }
+}
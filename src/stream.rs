@@ -0,0 +1,1846 @@
+//! Blocking `Write`/`Read` adapters over the framed format.
+
+use crate::frame::{self, Chain, Decoder};
+use crate::progress::Tracker;
+use crate::scratch::Scratch;
+use crate::{
+    BlockChoice, BlockMeta, CancelToken, CompressionStats, Decompressor, Error, FrameMetadata,
+    LZOResult, DEFAULT_BLOCK_SIZE, LZO,
+};
+use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+
+/// How many bytes `LzoReader` and `ParallelLzoReader` ask the inner reader
+/// for at a time, unless set with `read_buffer_size`.
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Compresses everything written to it into a framed stream.
+///
+/// `flush` closes the current block early, so that everything written so
+/// far can be decoded by the peer before the stream ends. The end marker is
+/// written by `finish`, which also returns the inner writer. Dropping the
+/// writer without calling `finish` leaves the stream truncated, unless it
+/// was wrapped by `auto_finish`.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+///
+/// let mut writer = minilzo_rs::LzoWriter::new(Vec::new()).unwrap();
+/// writer.write_all(&[0x00u8; 1024]).unwrap();
+/// let framed = writer.finish().unwrap();
+///
+/// let mut reader = minilzo_rs::LzoReader::new(&framed[..]).unwrap();
+/// let mut output = Vec::new();
+/// reader.read_to_end(&mut output).unwrap();
+/// assert_eq!(output, vec![0x00u8; 1024]);
+/// ```
+pub struct LzoWriter<W: Write> {
+    inner: Option<W>,
+    lzo: LZO,
+    block_size: usize,
+    buf: Scratch,
+    out: Scratch,
+    /// Bytes at the start of `out` already counted by `progress`.
+    buffered: usize,
+    output_buffer_size: usize,
+    dict: Box<[u8]>,
+    /// The tail of the blocks written so far, for a chained stream.
+    chain: Option<Chain>,
+    progress: Tracker,
+    policy: Option<BlockPolicy>,
+    /// An error met after the input of a `write` was taken, returned by
+    /// the next call instead.
+    error: Option<io::Error>,
+    #[cfg(feature = "parity")]
+    parity: Option<crate::parity::ParityEncoder>,
+}
+
+type BlockPolicy = Box<dyn FnMut(&[u8]) -> BlockChoice + Send>;
+
+/// How `policy` has the block `data` encoded.
+fn choose(policy: &mut Option<BlockPolicy>, data: &[u8]) -> BlockChoice {
+    match policy {
+        Some(policy) => policy(data),
+        None => BlockChoice::Compress,
+    }
+}
+
+/// Encode `data` as the next block, against `chain` in a chained stream
+/// and the preset dictionary `dict` otherwise.
+fn encode(
+    lzo: &mut LZO,
+    data: &[u8],
+    dict: &[u8],
+    chain: &mut Option<Chain>,
+    choice: BlockChoice,
+    out: &mut Vec<u8>,
+) -> LZOResult<()> {
+    match chain {
+        Some(chain) => frame::encode_chained_block(lzo, data, chain, choice, out),
+        None => frame::encode_block_as(lzo, data, dict, choice, out),
+    }
+}
+
+impl<W: Write> LzoWriter<W> {
+    /// Create a writer using `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn new(inner: W) -> LZOResult<Self> {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a writer that compresses `block_size` bytes at a time.
+    pub fn with_block_size(inner: W, block_size: usize) -> LZOResult<Self> {
+        Self::from_lzo(inner, LZO::builder().block_size(block_size).build()?)
+    }
+
+    /// Create a writer compressing with a configured instance, using its
+    /// block size, checksum policy and delta filter.
+    pub fn from_lzo(inner: W, lzo: LZO) -> LZOResult<Self> {
+        let block_size = lzo.block_size();
+        let mut out = Vec::new();
+        frame::encode_header_for(&mut out, &lzo);
+        Ok(LzoWriter {
+            inner: Some(inner),
+            lzo,
+            block_size,
+            buf: Scratch::try_with_capacity(block_size)?,
+            out: out.into(),
+            buffered: 0,
+            output_buffer_size: 0,
+            dict: Box::default(),
+            chain: None,
+            progress: Tracker::new(),
+            policy: None,
+            error: None,
+            #[cfg(feature = "parity")]
+            parity: None,
+        })
+    }
+
+    /// Create a writer that compresses every block against the last
+    /// `window` bytes of the blocks before it, as though they were a preset
+    /// dictionary. Fails with `Error::InvalidArgument` unless `window` is
+    /// between 1 and `dict::MAX_DICT_LEN`.
+    ///
+    /// Small blocks, as written by a `flush` after every message, then
+    /// compress much better than on their own, and the stream still
+    /// decodes a block at a time. Each block records the window's length,
+    /// a byte per 255 of it, so a window of a few KiB suits blocks of a few
+    /// dozen bytes and the full `MAX_DICT_LEN` whole blocks of 64 KiB. In
+    /// exchange the blocks only decode in order: `decompress_parallel`
+    /// decodes such streams on one thread, and `ParallelLzoReader`,
+    /// `BoundedLzoReader` and `LzoWriter::append` refuse them. Readers from
+    /// before chaining existed fail with `Error::UnsupportedFeatures`
+    /// rather than misread them.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{LzoWriter, LZO};
+    /// use std::io::Write;
+    ///
+    /// let records: Vec<String> = (0..500)
+    ///     .map(|i| format!(r#"{{"id":{},"kind":"event","status":"active"}}"#, i))
+    ///     .collect();
+    /// let mut chained = LzoWriter::chained(Vec::new(), LZO::new(), 1024).unwrap();
+    /// let mut independent = LzoWriter::new(Vec::new()).unwrap();
+    /// for record in &records {
+    ///     chained.write_all(record.as_bytes()).unwrap();
+    ///     chained.flush().unwrap();
+    ///     independent.write_all(record.as_bytes()).unwrap();
+    ///     independent.flush().unwrap();
+    /// }
+    /// let (chained, independent) = (chained.finish().unwrap(), independent.finish().unwrap());
+    /// assert!(chained.len() < independent.len() * 2 / 3);
+    /// assert_eq!(minilzo_rs::decompress_chunked(&chained).unwrap(), records.concat().as_bytes());
+    /// ```
+    pub fn chained(inner: W, lzo: LZO, window: usize) -> LZOResult<Self> {
+        if !(1..=crate::dict::MAX_DICT_LEN).contains(&window) {
+            return Err(Error::InvalidArgument);
+        }
+        let mut writer = Self::from_lzo(inner, lzo)?;
+        // `out` holds just the header so far.
+        let window_len = (window as u32).to_be_bytes();
+        frame::encode_features(&mut writer.out, frame::FEATURE_CHAINED, &window_len);
+        writer.chain = Some(Chain::new(window, &[]));
+        Ok(writer)
+    }
+
+    /// Create a writer that follows every `data_blocks` blocks with
+    /// `parity_blocks` Reed–Solomon parity shards (`parity` feature).
+    ///
+    /// Readers rebuild up to `parity_blocks` damaged blocks per group
+    /// automatically; `data_blocks + parity_blocks` must not exceed 256.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut writer = minilzo_rs::LzoWriter::with_parity(Vec::new(), 4096, 8, 2).unwrap();
+    /// writer.write_all(&[0x61u8; 100_000]).unwrap();
+    /// let framed = writer.finish().unwrap();
+    ///
+    /// let mut output = Vec::new();
+    /// minilzo_rs::LzoReader::new(&framed[..]).unwrap().read_to_end(&mut output).unwrap();
+    /// assert_eq!(output, vec![0x61u8; 100_000]);
+    /// ```
+    #[cfg(feature = "parity")]
+    pub fn with_parity(
+        inner: W,
+        block_size: usize,
+        data_blocks: usize,
+        parity_blocks: usize,
+    ) -> LZOResult<Self> {
+        let mut writer = Self::with_block_size(inner, block_size)?;
+        writer.parity = Some(crate::parity::ParityEncoder::new(
+            data_blocks,
+            parity_blocks,
+        )?);
+        // `out` holds just the header so far.
+        writer.out[frame::HEADER_LEN - 1] |= frame::FLAG_PARITY;
+        Ok(writer)
+    }
+
+    /// Create a writer whose stream header carries `metadata`, such as the
+    /// original file name, for `LzoReader::metadata` to return.
+    ///
+    /// Fails with `Error::InvalidArgument` if the metadata doesn't fit in
+    /// 64 KiB or has a field over 65535 bytes or an `mtime` before 1970.
+    /// Other decoders in this crate skip it.
+    pub fn with_metadata(inner: W, lzo: LZO, metadata: &FrameMetadata) -> LZOResult<Self> {
+        let mut writer = Self::from_lzo(inner, lzo)?;
+        // `out` holds just the header so far.
+        frame::encode_sections(&mut writer.out, Some(metadata), None)?;
+        Ok(writer)
+    }
+
+    /// Create a writer that compresses every block against the preset
+    /// dictionary `dict`, such as one built by `dict::train`, so streams of
+    /// small records compress as well as `LZO::compress_dict` makes each
+    /// record. Only the last `dict::MAX_DICT_LEN` bytes are used.
+    ///
+    /// With `embed` set the dictionary is stored in the stream header and
+    /// any reader can decode the stream. Otherwise only its `dict::id` is,
+    /// and readers must be given the dictionary with
+    /// `LzoReader::dictionary`, failing with `Error::DictionaryMismatch`
+    /// without it.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    /// use minilzo_rs::{LzoReader, LzoWriter, LZO};
+    ///
+    /// let record = |i: u32| format!(r#"{{"id":{},"kind":"click","page":"/home"}}"#, i);
+    /// let samples: Vec<String> = (0..50).map(record).collect();
+    /// let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_bytes()).collect();
+    /// let dict = minilzo_rs::dict::train(&samples, 512);
+    ///
+    /// // One small record per block, as a request/response protocol flushes.
+    /// let mut writer = LzoWriter::with_dictionary(Vec::new(), LZO::new(), &dict, false).unwrap();
+    /// for i in 0..10 {
+    ///     writer.write_all(record(1000 + i).as_bytes()).unwrap();
+    ///     writer.flush().unwrap();
+    /// }
+    /// let framed = writer.finish().unwrap();
+    ///
+    /// let mut reader = LzoReader::new(&framed[..]).unwrap().dictionary(&dict);
+    /// let mut data = String::new();
+    /// reader.read_to_string(&mut data).unwrap();
+    /// assert!(data.starts_with(r#"{"id":1000,"kind":"click""#));
+    ///
+    /// let err = LzoReader::new(&framed[..]).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    /// ```
+    pub fn with_dictionary(inner: W, lzo: LZO, dict: &[u8], embed: bool) -> LZOResult<Self> {
+        let mut writer = Self::from_lzo(inner, lzo)?;
+        frame::encode_sections(&mut writer.out, None, Some((dict, embed)))?;
+        writer.dict = crate::dict::usable(dict).into();
+        Ok(writer)
+    }
+
+    /// Call `callback` with the input bytes taken so far and the bytes
+    /// written to the inner writer each time a block is written out.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let seen = Arc::new(Mutex::new((0, 0)));
+    /// let report = seen.clone();
+    /// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 4096)
+    ///     .unwrap()
+    ///     .on_progress(move |consumed, produced| *report.lock().unwrap() = (consumed, produced));
+    /// writer.write_all(&[0x61u8; 10_000]).unwrap();
+    /// let framed = writer.finish().unwrap();
+    /// assert_eq!(*seen.lock().unwrap(), (10_000, framed.len() as u64));
+    /// ```
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(u64, u64) + Send + 'static,
+    {
+        self.progress.set_callback(Box::new(callback));
+        self
+    }
+
+    /// Choose how each block is encoded with `policy`, called with the
+    /// block's data before the delta filter, such as `BlockChoice::probe`
+    /// or a rule of the caller's own. Blocks the policy stores cost a copy
+    /// instead of a compression pass, which pays off for mixed data such
+    /// as text interleaved with media that is compressed already.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use minilzo_rs::{BlockChoice, LzoWriter};
+    /// use std::io::Write;
+    ///
+    /// let mut writer = LzoWriter::with_block_size(Vec::new(), 4096)
+    ///     .unwrap()
+    ///     .block_policy(|block| if block.starts_with(b"\x89PNG") { BlockChoice::Store } else { BlockChoice::probe(block) });
+    /// writer.write_all(&[0x61u8; 4096]).unwrap();
+    /// writer.write_all(b"\x89PNG").unwrap();
+    /// writer.write_all(&[0x00u8; 4092]).unwrap();
+    /// let framed = writer.finish().unwrap();
+    /// assert!(framed.len() > 4096);
+    /// assert_eq!(minilzo_rs::decompress_chunked(&framed).unwrap().len(), 8192);
+    /// ```
+    pub fn block_policy<F>(mut self, policy: F) -> Self
+    where
+        F: FnMut(&[u8]) -> BlockChoice + Send + 'static,
+    {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Fail with `Error::Cancelled` before compressing the next block once
+    /// `token` is cancelled.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.progress.set_cancel_token(token);
+        self
+    }
+
+    /// Totals so far. Data still buffered for the current block isn't
+    /// counted until the block is written out.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    ///
+    /// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+    /// writer.write_all(&[0x61u8; 10_000]).unwrap();
+    /// let stats = writer.stats();
+    /// assert_eq!((stats.input_bytes, stats.blocks), (8192, 2));
+    /// assert!(stats.ratio() < 0.1);
+    /// ```
+    pub fn stats(&self) -> CompressionStats {
+        self.progress.stats()
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Hand the block encoded at `self.out[start..]` to the parity encoder.
+    fn add_parity(&mut self, start: usize) -> io::Result<()> {
+        #[cfg(feature = "parity")]
+        if let Some(parity) = &mut self.parity {
+            parity.push(&mut self.out, start).map_err(frame::to_io)?;
+        }
+        #[cfg(not(feature = "parity"))]
+        let _ = start;
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let consumed = self.buf.len();
+        if !self.buf.is_empty() {
+            self.progress.check().map_err(frame::to_io)?;
+            let start = self.out.len();
+            let choice = choose(&mut self.policy, &self.buf);
+            encode(
+                &mut self.lzo,
+                &self.buf,
+                &self.dict,
+                &mut self.chain,
+                choice,
+                &mut self.out,
+            )
+            .map_err(frame::to_io)?;
+            self.buf.clear();
+            self.add_parity(start)?;
+        }
+        self.write_out(consumed)
+    }
+
+    /// Count the newly encoded bytes, which cover `consumed` input bytes,
+    /// and write out everything encoded once `output_buffer_size` is
+    /// reached.
+    fn write_out(&mut self, consumed: usize) -> io::Result<()> {
+        if consumed > 0 {
+            self.progress.blocks += 1;
+        }
+        self.progress
+            .advance(consumed as u64, (self.out.len() - self.buffered) as u64);
+        self.buffered = self.out.len();
+        if self.out.len() >= self.output_buffer_size {
+            self.write_buffered()?;
+        }
+        Ok(())
+    }
+
+    /// Write out everything encoded so far. On failure, what the inner
+    /// writer took is dropped from `out`, so a retry doesn't repeat it.
+    fn write_buffered(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        let mut written = 0;
+        let result = loop {
+            if written == self.out.len() {
+                break Ok(());
+            }
+            match inner.write(&self.out[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.out.drain(..written);
+        self.buffered = self.buffered.saturating_sub(written);
+        result
+    }
+
+    /// Return the error stashed by an earlier `write`, if any.
+    fn take_error(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep encoded blocks until `size` bytes are waiting and write them to
+    /// the inner writer together, so a stream of small blocks, as for RPC
+    /// messages, doesn't cost a write call per block on an unbuffered
+    /// socket or file. `flush` and `finish` write out whatever is waiting.
+    /// The default, 0, writes each block as soon as it is encoded.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    ///
+    /// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 1024)
+    ///     .unwrap()
+    ///     .output_buffer_size(64 * 1024);
+    /// writer.write_all(&[0x61u8; 8192]).unwrap();
+    /// assert!(writer.get_ref().is_empty());
+    /// writer.flush().unwrap();
+    /// assert!(!writer.get_ref().is_empty());
+    /// ```
+    pub fn output_buffer_size(mut self, size: usize) -> Self {
+        self.output_buffer_size = size;
+        self
+    }
+
+    /// Append a block compressed elsewhere (by the kernel, another
+    /// language's binding, an earlier run) without recompressing it.
+    ///
+    /// Buffered data is written out as its own block first. The payload is
+    /// decompressed once to check it against `meta`'s lengths and checksum;
+    /// nothing is written if it doesn't match. Fails with
+    /// `Error::InvalidArgument` on a chained writer, whose blocks must be
+    /// compressed against the ones before them, and on one with a delta
+    /// filter, whose readers would reverse a filter the block never had.
+    pub fn append_precompressed(&mut self, meta: BlockMeta, compressed: &[u8]) -> io::Result<()> {
+        if self.chain.is_some() || self.lzo.delta().is_some() {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        crate::job::validate(
+            &Decompressor::initialized(),
+            &meta,
+            compressed,
+            self.lzo.checksum(),
+        )
+        .map_err(frame::to_io)?;
+        self.write_block()?;
+        let start = self.out.len();
+        meta.write_to(&mut self.out);
+        self.out.extend_from_slice(compressed);
+        self.add_parity(start)?;
+        self.write_out(meta.uncompressed_len as usize)
+    }
+
+    /// Finish the stream when the returned writer is dropped, discarding
+    /// any error. Call `finish` instead to see errors.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut framed = Vec::new();
+    /// {
+    ///     let mut writer = minilzo_rs::LzoWriter::new(&mut framed).unwrap().auto_finish();
+    ///     writer.write_all(b"done on drop").unwrap();
+    /// }
+    /// let mut output = Vec::new();
+    /// minilzo_rs::LzoReader::new(&framed[..]).unwrap().read_to_end(&mut output).unwrap();
+    /// assert_eq!(output, b"done on drop");
+    /// ```
+    pub fn auto_finish(self) -> AutoFinishLzoWriter<W> {
+        AutoFinishLzoWriter(Some(self))
+    }
+
+    /// Compress any buffered data, write the end marker and return the
+    /// inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.take_error()?;
+        self.write_block()?;
+        #[cfg(feature = "parity")]
+        if let Some(parity) = &mut self.parity {
+            parity.flush(&mut self.out).map_err(frame::to_io)?;
+        }
+        frame::encode_end(&mut self.out);
+        self.write_block()?;
+        self.write_buffered()?;
+        let mut inner = self.inner.take().unwrap();
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Read + Write + Seek> LzoWriter<W> {
+    /// Reopen a finished framed stream to add more data to it, such as a
+    /// log file rotated by size rather than rewritten.
+    ///
+    /// The block headers are walked to find the end marker, without
+    /// decompressing anything; new blocks go where it was, and `finish`
+    /// writes it again after them. The stream's checksum and delta filter
+    /// are kept, while `block_size` applies to the new blocks. Fails with
+    /// `Error::EOFNotFound` if the stream doesn't end with its end marker,
+    /// as after a crash, and with `Error::InvalidArgument` for streams with
+    /// parity frames, whose groups can't be continued, and for chained
+    /// ones, whose new blocks would need the old ones decoded. A stream's preset
+    /// dictionary is kept if it is embedded; otherwise appending fails with
+    /// `Error::DictionaryMismatch`.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::fs::{File, OpenOptions};
+    /// use std::io::{Read, Write};
+    /// use minilzo_rs::LzoWriter;
+    ///
+    /// let path = std::env::temp_dir().join("minilzo-rs-append-doc.lzo");
+    /// let mut writer = LzoWriter::new(File::create(&path).unwrap()).unwrap();
+    /// writer.write_all(b"first record\n").unwrap();
+    /// writer.finish().unwrap();
+    ///
+    /// let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    /// let mut writer = LzoWriter::append(file, 4096).unwrap();
+    /// writer.write_all(b"second record\n").unwrap();
+    /// writer.finish().unwrap();
+    ///
+    /// let mut log = String::new();
+    /// let file = File::open(&path).unwrap();
+    /// minilzo_rs::LzoReader::new(file).unwrap().read_to_string(&mut log).unwrap();
+    /// assert_eq!(log, "first record\nsecond record\n");
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn append(mut inner: W, block_size: usize) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(0))?;
+        let mut header = [0u8; frame::MAX_HEADER_LEN];
+        inner.read_exact(&mut header[..frame::HEADER_LEN])?;
+        let len = frame::header_len(header[frame::HEADER_LEN - 1]);
+        inner.read_exact(&mut header[frame::HEADER_LEN..len])?;
+        let flags = frame::parse_header(&header[..len]).map_err(frame::to_io)?;
+        if flags & frame::FLAG_PARITY != 0 || frame::chained(&header[..len]) {
+            return Err(frame::to_io(Error::InvalidArgument));
+        }
+        let mut full = header[..len].to_vec();
+        full.resize(frame::full_header_len(&full), 0);
+        inner.read_exact(&mut full[len..])?;
+        // Only an embedded dictionary can be continued with.
+        let dict = frame::dict_from_header(&full)
+            .and_then(|header| frame::stream_dict(header, None))
+            .map_err(frame::to_io)?;
+        let end = find_end_marker(&mut inner, full.len() as u64)?;
+        inner.seek(SeekFrom::Start(end))?;
+
+        let mut builder = LZO::builder()
+            .block_size(block_size)
+            .checksum(frame::checksum_from_flags(flags));
+        if let Some(stride) = frame::delta_from_header(&header[..len]) {
+            builder = builder.delta(stride);
+        }
+        let lzo = builder.build().map_err(frame::to_io)?;
+        // Unlike `from_lzo`, no header: the stream already has one.
+        Ok(LzoWriter {
+            inner: Some(inner),
+            lzo,
+            block_size,
+            buf: Scratch::try_with_capacity(block_size).map_err(Error::from)?,
+            out: Vec::new().into(),
+            buffered: 0,
+            output_buffer_size: 0,
+            dict: dict.as_deref().unwrap_or_default().into(),
+            chain: None,
+            progress: Tracker::new(),
+            policy: None,
+            error: None,
+            #[cfg(feature = "parity")]
+            parity: None,
+        })
+    }
+}
+
+/// Walk the block headers of a stream from `pos`, just past its header, to
+/// the end marker, and return the marker's position. It must be the last
+/// thing in the stream.
+fn find_end_marker<R: Read + Seek>(inner: &mut R, mut pos: u64) -> io::Result<u64> {
+    let len = inner.seek(SeekFrom::End(0))?;
+    let mut meta = [0u8; frame::BLOCK_HEADER_LEN];
+    loop {
+        inner.seek(SeekFrom::Start(pos))?;
+        let remaining = len.saturating_sub(pos);
+        if remaining == 4 {
+            inner.read_exact(&mut meta[..4])?;
+            if meta[..4] == [0u8; 4] {
+                return Ok(pos);
+            }
+        }
+        if remaining < frame::BLOCK_HEADER_LEN as u64 {
+            return Err(frame::to_io(Error::EOFNotFound));
+        }
+        inner.read_exact(&mut meta)?;
+        let block = BlockMeta::read_from(&meta);
+        if meta[..4] == [0u8; 4] || block.uncompressed_len as usize > crate::MAX_BLOCK_SIZE {
+            return Err(frame::to_io(Error::InvalidHeader));
+        }
+        pos += (frame::BLOCK_HEADER_LEN + block.compressed_len as usize) as u64;
+    }
+}
+
+impl<W: Write> Write for LzoWriter<W> {
+    /// Take input up to the end of the current block, compressing and
+    /// writing out the block once it is full.
+    ///
+    /// An error met after the input was taken, such as from the inner
+    /// writer, is returned by the next call rather than with the input, so
+    /// a caller retrying after an error never writes the same bytes twice.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.take_error()?;
+        if self.buf.len() == self.block_size {
+            // The block failed to compress last time; try it again.
+            self.write_block()?;
+        }
+        if self.buf.is_empty() && buf.len() >= self.block_size {
+            // A whole block is at hand: compress it where it is rather than
+            // copying it into the buffer first.
+            self.progress.check().map_err(frame::to_io)?;
+            let start = self.out.len();
+            let data = &buf[..self.block_size];
+            let choice = choose(&mut self.policy, data);
+            encode(
+                &mut self.lzo,
+                data,
+                &self.dict,
+                &mut self.chain,
+                choice,
+                &mut self.out,
+            )
+            .map_err(frame::to_io)?;
+            if let Err(e) = self
+                .add_parity(start)
+                .and_then(|()| self.write_out(self.block_size))
+            {
+                self.error = Some(e);
+            }
+            return Ok(self.block_size);
+        }
+        let n = buf.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == self.block_size {
+            if let Err(e) = self.write_block() {
+                self.error = Some(e);
+            }
+        }
+        Ok(n)
+    }
+
+    /// Take input from several buffers at once, filling each block from as
+    /// many of them as it spans, so scattered message parts need not be
+    /// joined first.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut written = 0;
+        for buf in bufs {
+            let mut buf = &buf[..];
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(n) => {
+                        written += n;
+                        buf = &buf[n..];
+                    }
+                    // Report what was taken, and the error on the next call.
+                    Err(e) if written > 0 => {
+                        self.error = Some(e);
+                        return Ok(written);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    /// Write out buffered data as a block of its own, ending on a block
+    /// boundary, and flush the inner writer.
+    fn flush(&mut self) -> io::Result<()> {
+        self.take_error()?;
+        self.write_block()?;
+        self.write_buffered()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+/// An `LzoWriter` that finishes its stream when dropped, returned by
+/// `LzoWriter::auto_finish`.
+pub struct AutoFinishLzoWriter<W: Write>(Option<LzoWriter<W>>);
+
+impl<W: Write> AutoFinishLzoWriter<W> {
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.0.as_ref().unwrap().get_ref()
+    }
+}
+
+impl<W: Write> Write for AutoFinishLzoWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for AutoFinishLzoWriter<W> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.0.take() {
+            let _ = writer.finish();
+        }
+    }
+}
+
+/// Decompresses a framed stream read from the inner reader.
+pub struct LzoReader<R: Read> {
+    inner: R,
+    decompressor: Decompressor,
+    decoder: Decoder,
+    block: Scratch,
+    pos: usize,
+    chunk: Box<[u8]>,
+    trailing_data: TrailingData,
+    tail: Option<Vec<u8>>,
+    follow: bool,
+    progress: Tracker,
+}
+
+/// What `LzoReader` does with bytes after the end marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingData {
+    /// Fail with `Error::InputNotConsumed` if there are any, counting the
+    /// trailing bytes read so far.
+    Error,
+    /// Stop at the end marker and leave them unread.
+    #[default]
+    Ignore,
+    /// Read them to the end of the input and keep them for `trailing`.
+    Return,
+}
+
+impl<R: Read> LzoReader<R> {
+    /// Create a reader decoding the framed stream in `inner`.
+    pub fn new(inner: R) -> LZOResult<Self> {
+        Self::with_trailing_data(inner, TrailingData::default())
+    }
+
+    /// Create a reader that handles bytes after the end marker according to
+    /// `policy`, for transports that pad streams to a fixed size.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    /// use minilzo_rs::{LzoReader, LzoWriter, TrailingData};
+    ///
+    /// let mut writer = LzoWriter::new(Vec::new()).unwrap();
+    /// writer.write_all(b"payload").unwrap();
+    /// let mut padded = writer.finish().unwrap();
+    /// padded.resize(512, 0);
+    ///
+    /// let mut reader = LzoReader::with_trailing_data(&padded[..], TrailingData::Return).unwrap();
+    /// let mut data = Vec::new();
+    /// reader.read_to_end(&mut data).unwrap();
+    /// assert_eq!(data, b"payload");
+    /// assert!(reader.trailing().unwrap().iter().all(|&b| b == 0));
+    /// ```
+    pub fn with_trailing_data(inner: R, policy: TrailingData) -> LZOResult<Self> {
+        Ok(LzoReader {
+            inner,
+            decompressor: Decompressor::new()?,
+            decoder: Decoder::new(),
+            block: Scratch::new(),
+            pos: 0,
+            chunk: vec![0; DEFAULT_READ_BUFFER_SIZE].into(),
+            trailing_data: policy,
+            tail: None,
+            follow: false,
+            progress: Tracker::new(),
+        })
+    }
+
+    /// Follow a stream that is still being written, such as a log file
+    /// another process appends to: when the inner reader runs out before
+    /// the end marker, `read` returns 0 instead of failing with
+    /// `io::ErrorKind::UnexpectedEof`, keeping any partial block, and
+    /// reading again after more data has arrived picks up where it stopped.
+    /// `is_done` tells the end of the stream from a pause.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::fs::{File, OpenOptions};
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut writer = minilzo_rs::LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+    /// writer.write_all(&[0x61u8; 10_000]).unwrap();
+    /// let framed = writer.finish().unwrap();
+    ///
+    /// let path = std::env::temp_dir().join("minilzo-rs-follow-doc.lzo");
+    /// std::fs::write(&path, &framed[..100]).unwrap();
+    /// let mut reader = minilzo_rs::LzoReader::new(File::open(&path).unwrap()).unwrap().follow();
+    /// let mut data = Vec::new();
+    /// reader.read_to_end(&mut data).unwrap();
+    /// assert!(!reader.is_done());
+    ///
+    /// let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    /// file.write_all(&framed[100..]).unwrap();
+    /// reader.read_to_end(&mut data).unwrap();
+    /// assert!(reader.is_done());
+    /// assert_eq!(data, vec![0x61u8; 10_000]);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn follow(mut self) -> Self {
+        self.follow = true;
+        self
+    }
+
+    /// Read the inner reader up to `size` bytes at a time instead of
+    /// `DEFAULT_READ_BUFFER_SIZE`: larger for archive streams over an
+    /// unbuffered file, to make fewer read calls, or smaller for a socket
+    /// carrying small messages, so as to hold less memory per connection.
+    /// A `size` of 0 is taken as 1.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut writer = minilzo_rs::LzoWriter::new(Vec::new()).unwrap();
+    /// writer.write_all(&[0x61u8; 100_000]).unwrap();
+    /// let framed = writer.finish().unwrap();
+    ///
+    /// let mut reader = minilzo_rs::LzoReader::new(&framed[..])
+    ///     .unwrap()
+    ///     .read_buffer_size(1024 * 1024);
+    /// let mut data = Vec::new();
+    /// reader.read_to_end(&mut data).unwrap();
+    /// assert_eq!(data, vec![0x61u8; 100_000]);
+    /// ```
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.chunk = vec![0; size.max(1)].into();
+        self
+    }
+
+    /// `true` once the end marker has been decoded and all data read.
+    pub fn is_done(&self) -> bool {
+        self.decoder.is_done() && self.pos == self.block.len()
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` (as
+    /// `io::ErrorKind::InvalidData`) once the stream would decompress to
+    /// more than `limit` bytes, checked against each block's declared size
+    /// before it is decompressed.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut writer = minilzo_rs::LzoWriter::new(Vec::new()).unwrap();
+    /// writer.write_all(&[0u8; 100_000]).unwrap();
+    /// let framed = writer.finish().unwrap();
+    ///
+    /// let mut reader = minilzo_rs::LzoReader::new(&framed[..]).unwrap().max_output_size(4096);
+    /// let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    /// ```
+    pub fn max_output_size(mut self, limit: u64) -> Self {
+        self.decoder.set_max_output(limit);
+        self
+    }
+
+    /// Call `callback` with the bytes read from the inner reader so far and
+    /// the bytes decompressed so far each time a block is decoded.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(u64, u64) + Send + 'static,
+    {
+        self.progress.set_callback(Box::new(callback));
+        self
+    }
+
+    /// Fail with `Error::Cancelled` before decoding the next block once
+    /// `token` is cancelled.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.progress.set_cancel_token(token);
+        self
+    }
+
+    /// Totals so far: bytes read from the inner reader, bytes decompressed
+    /// and blocks decoded.
+    pub fn stats(&self) -> CompressionStats {
+        self.progress.stats()
+    }
+
+    /// The metadata in the stream header, if the writer set any. Reads the
+    /// header first if no data has been read yet.
+    pub fn metadata(&mut self) -> io::Result<Option<&FrameMetadata>> {
+        let mut chunk = [0u8; 256];
+        while !self
+            .decoder
+            .read_header()
+            .map_err(|e| self.decoder.locate(e))?
+        {
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.progress.consumed += n as u64;
+            self.decoder.feed(&chunk[..n]);
+        }
+        Ok(self.decoder.metadata())
+    }
+
+    /// Use `dict` for a stream written by `LzoWriter::with_dictionary`
+    /// without embedding its dictionary. It is matched to the stream by its
+    /// `dict::id`, and is ignored for streams without one or with their own.
+    pub fn dictionary(mut self, dict: &[u8]) -> Self {
+        self.decoder.set_dictionary(dict);
+        self
+    }
+
+    /// The bytes found after the end marker, once it has been reached. With
+    /// `TrailingData::Ignore` this only holds what was already buffered.
+    pub fn trailing(&self) -> Option<&[u8]> {
+        self.tail.as_deref()
+    }
+
+    /// Unwrap the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn check_trailing(&mut self) -> io::Result<()> {
+        let mut tail = self.decoder.remaining().to_vec();
+        match self.trailing_data {
+            TrailingData::Ignore => {}
+            TrailingData::Error => {
+                if !tail.is_empty() || self.inner.read(&mut [0u8; 1])? != 0 {
+                    // Only what has been read so far is counted, so as not
+                    // to drain an unbounded inner reader.
+                    return Err(frame::to_io(crate::Error::InputNotConsumed {
+                        remaining: tail.len().max(1),
+                    }));
+                }
+            }
+            TrailingData::Return => {
+                self.inner.read_to_end(&mut tail)?;
+            }
+        }
+        self.tail = Some(tail);
+        Ok(())
+    }
+
+    fn fill_block(&mut self) -> io::Result<()> {
+        while self.pos == self.block.len() && !self.decoder.is_done() {
+            self.progress.check().map_err(frame::to_io)?;
+            let decoded = self.decoder.decode(&self.decompressor);
+            if let Some(block) = decoded.map_err(|e| self.decoder.locate(e))? {
+                if !block.is_empty() {
+                    self.progress.blocks += 1;
+                }
+                self.progress.advance(0, block.len() as u64);
+                self.block = block.into();
+                self.pos = 0;
+                continue;
+            }
+            let n = self.inner.read(&mut self.chunk)?;
+            if n == 0 && self.follow {
+                return Ok(());
+            }
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.progress.consumed += n as u64;
+            self.decoder.feed(&self.chunk[..n]);
+        }
+        if self.decoder.is_done() && self.tail.is_none() {
+            self.check_trailing()?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LzoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_block()?;
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+    /// Fill the buffers in order from the current block, decoding a block
+    /// first only if none is left, so the call reads the inner reader at
+    /// most once.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        self.fill_block()?;
+        let mut read = 0;
+        for buf in bufs {
+            let n = buf.len().min(self.block.len() - self.pos);
+            buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+            self.pos += n;
+            read += n;
+            if self.pos == self.block.len() {
+                break;
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Checksum, DecodeError};
+
+    #[test]
+    fn test_stream_roundtrip_small_blocks() {
+        let input: Vec<u8> = (0..100_000u32).map(|i| (i % 97) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut reader = LzoReader::new(&framed[..]).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_stream_crc32_checksum() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 89) as u8).collect();
+        let lzo = LZO::builder()
+            .checksum(crate::Checksum::Crc32)
+            .block_size(8192)
+            .build()
+            .unwrap();
+        let mut writer = LzoWriter::from_lzo(Vec::new(), lzo).unwrap();
+        writer.write_all(&input).unwrap();
+        let mut framed = writer.finish().unwrap();
+        assert_eq!(framed[5], frame::FLAG_CRC32);
+        let first = BlockMeta::read_from(&framed[frame::HEADER_LEN..]);
+        assert_eq!(first.checksum, crate::crc32(&input[..8192]));
+
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+
+        framed[frame::HEADER_LEN + 8] ^= 1;
+        let err = LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_reader_progress() {
+        let input = vec![0x5au8; 50_000];
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 8192).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut reader = LzoReader::new(&framed[..])
+            .unwrap()
+            .on_progress(move |consumed, produced| tx.send((consumed, produced)).unwrap());
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        let reports: Vec<(u64, u64)> = rx.try_iter().collect();
+        // Seven blocks and the end marker.
+        assert_eq!(reports.len(), 8);
+        assert!(reports.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(reports[7], (framed.len() as u64, 50_000));
+        let stats = reader.stats();
+        assert_eq!(stats.blocks, 7);
+        assert_eq!(stats.input_bytes, framed.len() as u64);
+        assert_eq!(stats.output_bytes, 50_000);
+    }
+
+    #[test]
+    fn test_stream_stats() {
+        let input: Vec<u8> = (0..5 * 4096 + 100u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        assert_eq!(writer.stats().ratio(), 0.0);
+        writer.write_all(&input).unwrap();
+        // The last 100 bytes wait in the buffer for a block of their own.
+        let stats = writer.stats();
+        assert_eq!((stats.input_bytes, stats.blocks), (5 * 4096, 5));
+        assert_eq!(stats.output_bytes, writer.get_ref().len() as u64);
+        writer.flush().unwrap();
+        let stats = writer.stats();
+        assert_eq!((stats.input_bytes, stats.blocks), (input.len() as u64, 6));
+        assert_eq!(stats.output_bytes, writer.get_ref().len() as u64);
+        assert!(stats.ratio() > 0.0 && stats.ratio() < 1.0);
+        let framed = writer.finish().unwrap();
+
+        let mut reader = LzoReader::new(&framed[..]).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+        let stats = reader.stats();
+        assert_eq!(stats.input_bytes, framed.len() as u64);
+        assert_eq!((stats.output_bytes, stats.blocks), (input.len() as u64, 6));
+        assert!(stats.ratio() > 1.0);
+
+        // Cut in the middle of the fourth block: only three are counted.
+        let mut third_end = frame::HEADER_LEN;
+        for _ in 0..3 {
+            let meta = BlockMeta::read_from(&framed[third_end..]);
+            third_end += frame::BLOCK_HEADER_LEN + meta.compressed_len as usize;
+        }
+        let cut = &framed[..third_end + frame::BLOCK_HEADER_LEN + 10];
+        let mut reader = LzoReader::new(cut).unwrap();
+        let mut output = Vec::new();
+        let err = reader.read_to_end(&mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let stats = reader.stats();
+        assert_eq!((stats.output_bytes, stats.blocks), (3 * 4096, 3));
+        assert_eq!(output, &input[..3 * 4096]);
+        assert!(stats.input_bytes >= third_end as u64 && stats.input_bytes <= cut.len() as u64);
+    }
+
+    #[test]
+    fn test_reader_cancelled() {
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        writer.write_all(&[7u8; 20_000]).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let cancel = CancelToken::new();
+        let mut reader = LzoReader::new(&framed[..])
+            .unwrap()
+            .with_cancel_token(cancel.clone());
+        let mut block = [0u8; 4096];
+        reader.read_exact(&mut block).unwrap();
+        cancel.cancel();
+        let err = reader.read(&mut block).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<crate::Error>(),
+            Some(&crate::Error::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_stream_truncated() {
+        let mut writer = LzoWriter::new(Vec::new()).unwrap();
+        writer.write_all(&[1u8; 1000]).unwrap();
+        let framed = writer.finish().unwrap();
+        let mut reader = LzoReader::new(&framed[..framed.len() - 4]).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_decode_error_position() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 89) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        writer.write_all(&input).unwrap();
+        let mut framed = writer.finish().unwrap();
+        let offset = frame::HEADER_LEN
+            + frame::split_blocks(&framed).unwrap()[..3]
+                .iter()
+                .map(|(meta, _)| frame::BLOCK_HEADER_LEN + meta.compressed_len as usize)
+                .sum::<usize>();
+        framed[offset + 20] ^= 0x10;
+
+        // Read in small chunks, so the offset spans several feeds.
+        let mut reader = LzoReader::new(&framed[..]).unwrap().read_buffer_size(7);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let at = err
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<DecodeError>()
+            .unwrap();
+        assert_eq!((at.offset, at.block), (offset as u64, 3));
+        assert!(err
+            .to_string()
+            .contains(&format!("in block 3 at input offset {}", offset)));
+    }
+
+    #[test]
+    fn test_trailing_data_policies() {
+        let mut writer = LzoWriter::new(Vec::new()).unwrap();
+        writer.write_all(b"record").unwrap();
+        let mut framed = writer.finish().unwrap();
+        let mut ok = Vec::new();
+        LzoReader::with_trailing_data(&framed[..], TrailingData::Error)
+            .unwrap()
+            .read_to_end(&mut ok)
+            .unwrap();
+        assert_eq!(ok, b"record");
+
+        framed.extend_from_slice(&[0xaa; 20_000]);
+        let err = LzoReader::with_trailing_data(&framed[..], TrailingData::Error)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut reader = LzoReader::with_trailing_data(&framed[..], TrailingData::Return).unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"record");
+        assert_eq!(reader.trailing().unwrap(), &[0xaa; 20_000][..]);
+    }
+
+    #[test]
+    fn test_writer_flush_block_boundary() {
+        let mut writer = LzoWriter::new(Vec::new()).unwrap();
+        writer.write_all(b"request").unwrap();
+        writer.flush().unwrap();
+        let sent = writer.get_ref().clone();
+        let mut reader = LzoReader::new(&sent[..]).unwrap();
+        let mut message = [0u8; 7];
+        reader.read_exact(&mut message).unwrap();
+        assert_eq!(&message, b"request");
+
+        // A second flush with nothing buffered writes nothing.
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref().len(), sent.len());
+        writer.write_all(b" and more").unwrap();
+        let framed = writer.finish().unwrap();
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, b"request and more");
+    }
+
+    #[test]
+    fn test_writer_write_vectored() {
+        let parts: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i; 300 + i as usize]).collect();
+        let slices: Vec<IoSlice> = parts.iter().map(|p| IoSlice::new(p)).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        let n = writer.write_vectored(&slices).unwrap();
+        assert_eq!(n, parts.iter().map(Vec::len).sum::<usize>());
+        // Blocks were filled across slice boundaries.
+        assert_eq!(writer.stats().blocks as usize, n / 4096);
+        let framed = writer.finish().unwrap();
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, parts.concat());
+    }
+
+    #[test]
+    fn test_reader_read_vectored() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i % 83) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut reader = LzoReader::new(&framed[..]).unwrap();
+        let (mut a, mut b, mut c) = ([0u8; 1000], [0u8; 2000], [0u8; 3000]);
+        let mut bufs = [
+            IoSliceMut::new(&mut a),
+            IoSliceMut::new(&mut b),
+            IoSliceMut::new(&mut c),
+        ];
+        // Stops at the end of the first block.
+        assert_eq!(reader.read_vectored(&mut bufs).unwrap(), 4096);
+        assert_eq!(&a[..], &input[..1000]);
+        assert_eq!(&b[..], &input[1000..3000]);
+        assert_eq!(&c[..1096], &input[3000..4096]);
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &input[4096..]);
+        assert_eq!(
+            reader
+                .read_vectored(&mut [IoSliceMut::new(&mut a)])
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_append_precompressed() {
+        let foreign = vec![0x42u8; 5000];
+        let compressed = LZO::new().compress(&foreign).unwrap();
+        let meta = BlockMeta {
+            uncompressed_len: foreign.len() as u32,
+            compressed_len: compressed.len() as u32,
+            checksum: crate::adler32(&foreign),
+        };
+
+        let mut writer = LzoWriter::new(Vec::new()).unwrap();
+        writer.write_all(b"head").unwrap();
+        let bad = BlockMeta {
+            checksum: meta.checksum ^ 1,
+            ..meta
+        };
+        assert!(writer.append_precompressed(bad, &compressed).is_err());
+        writer.append_precompressed(meta, &compressed).unwrap();
+        writer.write_all(b"tail").unwrap();
+        let framed = writer.finish().unwrap();
+
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, [&b"head"[..], &foreign, b"tail"].concat());
+
+        // A delta writer's readers would run the block through the filter.
+        let lzo = LZO::builder().delta(2).build().unwrap();
+        let mut writer = LzoWriter::from_lzo(Vec::new(), lzo).unwrap();
+        let err = writer.append_precompressed(meta, &compressed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            crate::decompress_chunked(writer.finish().unwrap()).unwrap(),
+            b""
+        );
+    }
+
+    #[test]
+    fn test_append_precompressed_checksums() {
+        let foreign = vec![0x42u8; 5000];
+        let compressed = LZO::new().compress(&foreign).unwrap();
+        let meta = |checksum| BlockMeta {
+            uncompressed_len: foreign.len() as u32,
+            compressed_len: compressed.len() as u32,
+            checksum,
+        };
+        let adler = meta(crate::adler32(&foreign));
+        let crc = meta(crate::crc32(&foreign));
+        let zero = meta(0);
+
+        for (policy, good, bad) in [
+            (Checksum::Adler32, adler, crc),
+            (Checksum::Crc32, crc, adler),
+            (Checksum::None, zero, zero),
+        ] {
+            let lzo = LZO::builder().checksum(policy).build().unwrap();
+            let mut writer = LzoWriter::from_lzo(Vec::new(), lzo).unwrap();
+            if policy != Checksum::None {
+                assert!(writer.append_precompressed(bad, &compressed).is_err());
+            }
+            writer.append_precompressed(good, &compressed).unwrap();
+            let framed = writer.finish().unwrap();
+            let mut output = Vec::new();
+            LzoReader::new(&framed[..])
+                .unwrap()
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(output, foreign, "{:?}", policy);
+        }
+    }
+
+    #[cfg(feature = "parity")]
+    #[test]
+    fn test_append_precompressed_parity() {
+        let foreign: Vec<u8> = (0..5000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let compressed = LZO::new().compress(&foreign).unwrap();
+        let meta = BlockMeta {
+            uncompressed_len: foreign.len() as u32,
+            compressed_len: compressed.len() as u32,
+            checksum: crate::adler32(&foreign),
+        };
+
+        // Buffered output keeps earlier blocks in `out` when the appended
+        // one is added, so its shard must start at its own header.
+        let mut writer = LzoWriter::with_parity(Vec::new(), 4096, 4, 2)
+            .unwrap()
+            .output_buffer_size(1 << 20);
+        writer.write_all(b"head").unwrap();
+        writer.append_precompressed(meta, &compressed).unwrap();
+        writer.write_all(b"tail").unwrap();
+        let mut framed = writer.finish().unwrap();
+
+        let first = frame::HEADER_LEN;
+        let second = first
+            + frame::BLOCK_HEADER_LEN
+            + BlockMeta::read_from(&framed[first..]).compressed_len as usize;
+        assert_eq!(BlockMeta::read_from(&framed[second..]), meta);
+        framed[second + frame::BLOCK_HEADER_LEN + 7] ^= 0x55;
+
+        let mut output = Vec::new();
+        LzoReader::new(&framed[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, [&b"head"[..], &foreign, b"tail"].concat());
+    }
+
+    #[test]
+    fn test_block_policy() {
+        let text = b"plain text, plain text, plain text. ".repeat(200);
+        let mut x = 0x9e37_79b9u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let input = [&text[..4096], &noise, &text[..4096], &[7u8; 4096]].concat();
+        let mut seen = Vec::new();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096)
+            .unwrap()
+            .block_policy(BlockChoice::probe);
+        writer.write_all(&input).unwrap();
+        let probed = writer.finish().unwrap();
+        assert_eq!(crate::decompress_chunked(&probed).unwrap(), input);
+        for (meta, _) in frame::split_blocks(&probed).unwrap() {
+            seen.push(meta.compressed_len == meta.uncompressed_len);
+        }
+        assert_eq!(seen, [false, true, false, false]);
+
+        // Blocks the policy stores are stored even if they would compress.
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 4096)
+            .unwrap()
+            .block_policy(|_| BlockChoice::Store);
+        writer.write_all(&input).unwrap();
+        let stored = writer.finish().unwrap();
+        assert!(frame::split_blocks(&stored)
+            .unwrap()
+            .iter()
+            .all(|(meta, _)| meta.compressed_len == meta.uncompressed_len));
+        assert_eq!(crate::decompress_chunked(&stored).unwrap(), input);
+    }
+
+    #[test]
+    fn test_reader_follow_growing_input() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        /// Hands out `data` up to the shared `available` length.
+        struct Growing<'a> {
+            data: &'a [u8],
+            pos: usize,
+            available: Rc<Cell<usize>>,
+        }
+
+        impl Read for Growing<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = buf.len().min(self.available.get() - self.pos);
+                buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+                self.pos += n;
+                Ok(n)
+            }
+        }
+
+        let input: Vec<u8> = (0..30_000u32).map(|i| (i % 71) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Vec::new(), 2048).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        let available = Rc::new(Cell::new(0));
+        let inner = Growing {
+            data: &framed,
+            pos: 0,
+            available: Rc::clone(&available),
+        };
+        let mut reader = LzoReader::new(inner).unwrap().follow();
+        let mut output = Vec::new();
+        while !reader.is_done() {
+            available.set((available.get() + 97).min(framed.len()));
+            reader.read_to_end(&mut output).unwrap();
+        }
+        assert_eq!(output, input);
+
+        let cut = Growing {
+            data: &framed,
+            pos: 0,
+            available: Rc::new(Cell::new(framed.len() / 2)),
+        };
+        let err = LzoReader::new(cut).unwrap().read_to_end(&mut Vec::new());
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_append_to_finished_stream() {
+        let lzo = LZO::builder()
+            .checksum(crate::Checksum::Crc32)
+            .delta(3)
+            .build()
+            .unwrap();
+        let mut writer = LzoWriter::from_lzo(io::Cursor::new(Vec::new()), lzo).unwrap();
+        writer.write_all(&[7u8; 10_000]).unwrap();
+        let mut file = writer.finish().unwrap();
+
+        let mut expected = vec![7u8; 10_000];
+        for round in 0..2u8 {
+            let record: Vec<u8> = (0..5000u32).map(|i| (i as u8) ^ round).collect();
+            let mut writer = LzoWriter::append(file, 1024).unwrap();
+            writer.write_all(&record).unwrap();
+            file = writer.finish().unwrap();
+            expected.extend_from_slice(&record);
+        }
+        let framed = file.into_inner();
+        assert_eq!(framed[5] & frame::FLAG_DELTA, frame::FLAG_DELTA);
+        assert_eq!(crate::decompress_chunked(&framed).unwrap(), expected);
+
+        // An unfinished stream, e.g. left by a crash, isn't appended to.
+        let cut = io::Cursor::new(framed[..framed.len() - 4].to_vec());
+        let err = LzoWriter::append(cut, 1024).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_metadata_in_header() {
+        let mut metadata = FrameMetadata {
+            name: Some("vm.img".into()),
+            mtime: Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000)),
+            content_type: Some("application/octet-stream".into()),
+            ..Default::default()
+        };
+        metadata.user.insert("snapshot".into(), "nightly".into());
+        let lzo = LZO::builder().delta(2).block_size(4096).build().unwrap();
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 13) as u8).collect();
+        let mut writer = LzoWriter::with_metadata(Vec::new(), lzo, &metadata).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+
+        // Every decoder skips it.
+        assert_eq!(
+            crate::frame_metadata(&framed).unwrap(),
+            Some(metadata.clone())
+        );
+        assert_eq!(crate::decompress_chunked(&framed).unwrap(), input);
+        assert_eq!(crate::salvage(&framed).data, input);
+        let mut data = Vec::new();
+        crate::BoundedLzoReader::new(&framed[..], 4096)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, input);
+
+        // Fed a byte at a time, the header completes only with the metadata.
+        struct OneByte<'a>(&'a [u8]);
+        impl Read for OneByte<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let mut reader = LzoReader::new(OneByte(&framed)).unwrap();
+        assert_eq!(reader.metadata().unwrap(), Some(&metadata));
+        data.clear();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, input);
+
+        let mut cut = framed.clone();
+        cut.truncate(20);
+        assert_eq!(crate::frame_metadata(&cut), Err(Error::EOFNotFound));
+        let err = LzoReader::new(&cut[..]).unwrap().metadata().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let plain = LzoWriter::new(Vec::new()).unwrap().finish().unwrap();
+        assert_eq!(
+            LzoReader::new(&plain[..]).unwrap().metadata().unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dictionary_streams() {
+        let record = |i: u32| format!(r#"{{"id":{},"user":"u{}","status":"active"}}"#, i, i * 3);
+        let samples: Vec<String> = (0..100).map(record).collect();
+        let samples: Vec<&[u8]> = samples.iter().map(|s| s.as_bytes()).collect();
+        let dict = crate::dict::train(&samples, 1024);
+        let write = |writer: &mut LzoWriter<Vec<u8>>| {
+            for i in 500..600 {
+                writer.write_all(record(i).as_bytes()).unwrap();
+                writer.flush().unwrap();
+            }
+        };
+        let expected: String = (500..600).map(record).collect();
+
+        let mut plain = LzoWriter::new(Vec::new()).unwrap();
+        write(&mut plain);
+        let plain = plain.finish().unwrap();
+        let mut by_id = LzoWriter::with_dictionary(Vec::new(), LZO::new(), &dict, false).unwrap();
+        write(&mut by_id);
+        let by_id = by_id.finish().unwrap();
+        let mut embedded = LzoWriter::with_dictionary(Vec::new(), LZO::new(), &dict, true).unwrap();
+        write(&mut embedded);
+        let embedded = embedded.finish().unwrap();
+        assert!(
+            by_id.len() < plain.len() * 3 / 4,
+            "{} vs {}",
+            by_id.len(),
+            plain.len()
+        );
+        assert_eq!(embedded.len(), by_id.len() + dict.len());
+
+        let mut data = String::new();
+        let reader = LzoReader::new(&by_id[..]).unwrap();
+        reader.dictionary(&dict).read_to_string(&mut data).unwrap();
+        assert_eq!(data, expected);
+        assert_eq!(
+            crate::decompress_chunked(&by_id),
+            Err(Error::DictionaryMismatch {
+                id: crate::dict::id(&dict)
+            })
+        );
+        let mut reader = LzoReader::new(&by_id[..]).unwrap().dictionary(b"other");
+        let err = reader.read(&mut [0u8; 64]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // An embedded dictionary needs no help, and wins over a given one.
+        let data = crate::decompress_chunked(&embedded).unwrap();
+        assert_eq!(data, expected.as_bytes());
+        assert_eq!(crate::salvage(&embedded).data, expected.as_bytes());
+        let mut data = Vec::new();
+        let mut reader = LzoReader::new(&embedded[..]).unwrap().dictionary(b"other");
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, expected.as_bytes());
+
+        // Appending keeps compressing against it.
+        let mut writer = LzoWriter::append(io::Cursor::new(embedded), 4096).unwrap();
+        writer.write_all(record(600).as_bytes()).unwrap();
+        let appended = writer.finish().unwrap().into_inner();
+        let data = crate::decompress_chunked(&appended).unwrap();
+        assert_eq!(data, [expected.as_bytes(), record(600).as_bytes()].concat());
+        let err = LzoWriter::append(io::Cursor::new(by_id), 4096)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Metadata and a dictionary together.
+        let metadata = FrameMetadata {
+            name: Some("events.jsonl".into()),
+            ..Default::default()
+        };
+        let mut header = Vec::new();
+        frame::encode_header(&mut header);
+        frame::encode_sections(&mut header, Some(&metadata), Some((&dict, true))).unwrap();
+        frame::parse_header(&header).unwrap();
+        assert_eq!(frame::full_header_len(&header), header.len());
+        assert_eq!(frame::metadata_from_header(&header), Ok(Some(metadata)));
+        let (id, stored) = frame::dict_from_header(&header).unwrap().unwrap();
+        assert_eq!((id, stored), (crate::dict::id(&dict), &dict[..]));
+    }
+
+    #[test]
+    fn test_buffer_sizes() {
+        struct CountWrites(Vec<u8>, usize);
+        impl Write for CountWrites {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.1 += 1;
+                self.0.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let input: Vec<u8> = (0..50_000u32).map(|i| (i % 97) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(CountWrites(Vec::new(), 0), 512).unwrap();
+        writer.write_all(&input).unwrap();
+        let unbuffered = writer.finish().unwrap();
+        let mut writer = LzoWriter::with_block_size(CountWrites(Vec::new(), 0), 512)
+            .unwrap()
+            .output_buffer_size(16 * 1024);
+        writer.write_all(&input).unwrap();
+        assert_eq!(writer.stats().input_bytes, (input.len() / 512 * 512) as u64);
+        let buffered = writer.finish().unwrap();
+        assert_eq!(buffered.0, unbuffered.0);
+        assert!(buffered.1 * 10 < unbuffered.1);
+
+        for size in [0, 1, 100, 1 << 20] {
+            let mut reader = LzoReader::new(&buffered.0[..])
+                .unwrap()
+                .read_buffer_size(size);
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            assert_eq!(data, input);
+            let mut reader = crate::ParallelLzoReader::new(&buffered.0[..], 2)
+                .unwrap()
+                .read_buffer_size(size);
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            assert_eq!(data, input);
+        }
+    }
+
+    #[test]
+    fn test_write_errors_dont_duplicate() {
+        // Refuses every other write, taking nothing, for its first 40 calls.
+        struct Flaky(Vec<u8>, usize);
+        impl Write for Flaky {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.1 += 1;
+                if self.1 < 40 && self.1 % 2 == 1 {
+                    return Err(io::ErrorKind::Other.into());
+                }
+                // Take part of it, so retries must resume mid-block.
+                let n = buf.len().min(700);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let input: Vec<u8> = (0..40_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut writer = LzoWriter::with_block_size(Flaky(Vec::new(), 0), 1024).unwrap();
+        let (mut pos, mut errors) = (0, 0);
+        while pos < input.len() {
+            // Whole blocks, partial ones and several buffers at once.
+            let end = (pos + [3000, 500, 1024][pos % 3]).min(input.len());
+            let (a, b) = input[pos..end].split_at((end - pos) / 2);
+            let result = match pos % 2 {
+                0 => writer.write(&input[pos..end]),
+                _ => writer.write_vectored(&[IoSlice::new(a), IoSlice::new(b)]),
+            };
+            match result {
+                Ok(n) => pos += n,
+                Err(_) => errors += 1,
+            }
+        }
+        while writer.flush().is_err() {
+            errors += 1;
+        }
+        assert!(errors > 0);
+        let framed = writer.finish().unwrap().0;
+        assert_eq!(crate::decompress_chunked(&framed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_chained_blocks() {
+        let records: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| {
+                format!(
+                    "{{\"seq\":{},\"host\":\"node-{}\",\"ok\":true}}\n",
+                    i,
+                    i % 7
+                )
+                .into_bytes()
+            })
+            .collect();
+        let input = records.concat();
+        let lzo = || {
+            LZO::builder()
+                .block_size(4096)
+                .checksum(crate::Checksum::Crc32)
+                .build()
+                .unwrap()
+        };
+        // A block per record, as a message stream flushed after each.
+        let write = |mut writer: LzoWriter<Vec<u8>>| {
+            for record in &records {
+                writer.write_all(record).unwrap();
+                writer.flush().unwrap();
+            }
+            writer.finish().unwrap()
+        };
+        let chained = write(LzoWriter::chained(Vec::new(), lzo(), 1024).unwrap());
+        let independent = write(LzoWriter::from_lzo(Vec::new(), lzo()).unwrap());
+        assert!(chained.len() * 3 < independent.len() * 2);
+
+        let mut output = Vec::new();
+        LzoReader::new(&chained[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+        assert_eq!(crate::decompress_chunked(&chained).unwrap(), input);
+        assert_eq!(crate::decompress_chunked_partial(&chained).unwrap(), input);
+        assert_eq!(crate::salvage(&chained).data, input);
+        #[cfg(feature = "rayon")]
+        assert_eq!(crate::decompress_parallel(&chained).unwrap(), input);
+
+        // Whole blocks written at once chain too, through the delta filter.
+        let lzo = LZO::builder().block_size(4096).delta(2).build().unwrap();
+        let mut writer = LzoWriter::chained(Vec::new(), lzo, crate::dict::MAX_DICT_LEN).unwrap();
+        writer.write_all(&input).unwrap();
+        let framed = writer.finish().unwrap();
+        assert_eq!(crate::decompress_chunked(&framed).unwrap(), input);
+
+        // Readers that decode blocks out of order refuse the stream.
+        let err = crate::ParallelLzoReader::new(&chained[..], 2)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<crate::Error>(),
+            Some(&Error::UnsupportedFeatures {
+                features: frame::FEATURE_CHAINED
+            })
+        );
+        assert!(crate::BoundedLzoReader::new(&chained[..], 4096)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+        assert_eq!(
+            LzoWriter::append(io::Cursor::new(chained.clone()), 4096)
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let mut writer = LzoWriter::chained(Vec::new(), LZO::new(), 1024).unwrap();
+        let compressed = LZO::new().compress(&input).unwrap();
+        let meta = BlockMeta {
+            uncompressed_len: input.len() as u32,
+            compressed_len: compressed.len() as u32,
+            checksum: crate::adler32(&input),
+        };
+        assert!(writer.append_precompressed(meta, &compressed).is_err());
+        assert!(LzoWriter::chained(Vec::new(), LZO::new(), 0).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! `.tar.lzo` archives: tar streamed through the lzop format (`tar`
+//! feature), readable by `lzop -dc | tar x` and `tar --lzop`.
+
+use crate::frame;
+use crate::{LzopReader, LzopWriter};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Write a `.tar.lzo` archive of `paths` into `out`, returning `out`.
+///
+/// Each path is stored under its last component, as `tar -C parent name`
+/// would store it; directories are added with everything below them, and
+/// symlinks are stored as links. Fails with `InvalidInput` for a path
+/// without a last component, such as `/` or `..`.
+///
+/// Example
+///
+/// ```rust
+/// let dir = std::env::temp_dir().join("minilzo-rs-tar-doc");
+/// std::fs::create_dir_all(dir.join("logs")).unwrap();
+/// std::fs::write(dir.join("logs/app.log"), "started\n".repeat(1000)).unwrap();
+///
+/// let archive = minilzo_rs::create_tar_lzo([dir.join("logs")], Vec::new()).unwrap();
+/// minilzo_rs::extract_tar_lzo(&archive[..], dir.join("restored")).unwrap();
+/// let restored = std::fs::read(dir.join("restored/logs/app.log")).unwrap();
+/// assert_eq!(restored, "started\n".repeat(1000).as_bytes());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn create_tar_lzo<P, W>(paths: impl IntoIterator<Item = P>, out: W) -> io::Result<W>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut builder = tar::Builder::new(LzopWriter::new(out).map_err(frame::to_io)?);
+    builder.follow_symlinks(false);
+    for path in paths {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+        if path.symlink_metadata()?.is_dir() {
+            builder.append_dir_all(name, path)?;
+        } else {
+            builder.append_path_with_name(path, name)?;
+        }
+    }
+    builder.into_inner()?.finish()
+}
+
+/// Unpack the `.tar.lzo` archive read from `input` into `dir`, creating
+/// `dir` if needed.
+///
+/// Entries that would land outside `dir`, through `..` or an absolute
+/// path, are skipped, as the `tar` crate does.
+pub fn extract_tar_lzo<R: Read, P: AsRef<Path>>(input: R, dir: P) -> io::Result<()> {
+    tar::Archive::new(LzopReader::new(input)?).unpack(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_tar_lzo_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("minilzo-rs-tar-{}", std::process::id()));
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("nested/deeper")).unwrap();
+        let big: Vec<u8> = (0..600_000u32).map(|i| (i / 5 % 251) as u8).collect();
+        fs::write(src.join("nested/deeper/big.bin"), &big).unwrap();
+        fs::write(src.join("nested/empty"), b"").unwrap();
+        fs::write(dir.join("single.txt"), b"one file").unwrap();
+
+        let archive = create_tar_lzo([&src, &dir.join("single.txt")], Vec::new()).unwrap();
+        assert_eq!(crate::detect(&archive), Some(crate::Format::Lzop));
+        assert!(archive.len() < big.len() / 2);
+
+        let out = dir.join("out");
+        extract_tar_lzo(&archive[..], &out).unwrap();
+        assert_eq!(
+            fs::read(out.join("src/nested/deeper/big.bin")).unwrap(),
+            big
+        );
+        assert_eq!(fs::read(out.join("src/nested/empty")).unwrap(), b"");
+        assert_eq!(fs::read(out.join("single.txt")).unwrap(), b"one file");
+
+        let err = create_tar_lzo([Path::new("/")], Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(extract_tar_lzo(&archive[..archive.len() / 2], dir.join("cut")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! Helpers for testing code built on this crate (`test-utils` feature).
+//!
+//! `assert_roundtrip` runs data through every format the crate writes,
+//! the corpus generators produce the same bytes for the same seed on every
+//! platform and release, and `FRAME_VECTORS`/`LZOP_VECTORS` pin encoded
+//! streams byte for byte, so an integration can check in CI that it reads
+//! and writes what this crate does.
+//!
+//! Example
+//!
+//! ```rust
+//! use minilzo_rs::test_utils;
+//!
+//! for (name, data) in test_utils::corpus(7) {
+//!     test_utils::assert_roundtrip(&data);
+//!     assert!(!name.is_empty());
+//! }
+//! for vector in test_utils::FRAME_VECTORS {
+//!     assert_eq!(minilzo_rs::decompress_chunked(vector.encoded).unwrap(), vector.data);
+//! }
+//! ```
+
+use crate::{decompress_chunked, LzoReader, LzoWriter, LzopReader, LzopWriter, LZO, MAX_INPUT_LEN};
+use std::io::{Read, Write};
+
+/// An input and its encoding, fixed byte for byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vector {
+    /// A short name for the input.
+    pub name: &'static str,
+    /// The uncompressed input.
+    pub data: &'static [u8],
+    /// The input as this crate encodes it.
+    pub encoded: &'static [u8],
+}
+
+/// Framed streams as `LzoWriter::new` writes them: an empty stream, a
+/// stored block, a compressed block and a block with overlapping matches.
+pub const FRAME_VECTORS: &[Vector] = &[
+    Vector {
+        name: "empty",
+        data: b"",
+        encoded: &[0x4d, 0x4c, 0x5a, 0x4f, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+    },
+    Vector {
+        name: "hello",
+        data: b"hello, world\n",
+        encoded: &[
+            0x4d, 0x4c, 0x5a, 0x4f, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x00, 0x00, 0x0d,
+            0x21, 0xe7, 0x04, 0x93, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72,
+            0x6c, 0x64, 0x0a, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "zeros_256",
+        data: &[0; 256],
+        encoded: &[
+            0x4d, 0x4c, 0x5a, 0x4f, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x1d,
+            0x01, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0xcb, 0x10, 0x00,
+            0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "abc_repeat",
+        data: b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc",
+        encoded: &[
+            0x4d, 0x4c, 0x5a, 0x4f, 0x01, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x00, 0x21,
+            0xbc, 0xbe, 0x16, 0xf9, 0x03, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x20, 0x03, 0x14,
+            0x00, 0x0f, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63,
+            0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+];
+
+/// The lzop header `LzopWriter` writes, up to the first block.
+macro_rules! lzop_header {
+    ($($block:expr),* $(,)?) => {
+        &[
+            0x89, 0x4c, 0x5a, 0x4f, 0x00, 0x0d, 0x0a, 0x1a, 0x0a, 0x10, 0x30, 0x20, 0xa0, 0x09,
+            0x40, 0x01, 0x05, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x81, 0xa4, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x29, 0x10, 0x02, 0x78, $($block),*
+        ]
+    };
+}
+
+/// lzop files as `LzopWriter::new` writes them, for the same inputs as
+/// `FRAME_VECTORS`.
+pub const LZOP_VECTORS: &[Vector] = &[
+    Vector {
+        name: "empty",
+        data: b"",
+        encoded: lzop_header![0x00, 0x00, 0x00, 0x00],
+    },
+    Vector {
+        name: "hello",
+        data: b"hello, world\n",
+        encoded: lzop_header![
+            0x00, 0x00, 0x00, 0x0d, 0x00, 0x00, 0x00, 0x0d, 0x21, 0xe7, 0x04, 0x93, 0x68, 0x65,
+            0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a, 0x00, 0x00, 0x00,
+            0x00,
+        ],
+    },
+    Vector {
+        name: "zeros_256",
+        data: &[0; 256],
+        encoded: lzop_header![
+            0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x1d, 0x01, 0x00, 0x00, 0x01, 0x17, 0x10,
+            0x01, 0x1b, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0xcb, 0x10, 0x00, 0x0c, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+    Vector {
+        name: "abc_repeat",
+        data: b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc",
+        encoded: lzop_header![
+            0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x00, 0x21, 0xbc, 0xbe, 0x16, 0xf9, 0xa1, 0x40,
+            0x09, 0x8b, 0x03, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x20, 0x03, 0x14, 0x00, 0x0f,
+            0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62,
+            0x63, 0x61, 0x62, 0x63, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ],
+    },
+];
+
+/// Compress `data` as a raw block (if it fits in one), a framed stream
+/// (one-shot, and streamed in small blocks through uneven writes) and an
+/// lzop file, and panic if any of them doesn't decompress back to it.
+#[track_caller]
+pub fn assert_roundtrip(data: &[u8]) {
+    let mut lzo = LZO::new();
+    if data.len() <= MAX_INPUT_LEN {
+        let compressed = lzo.compress(data).expect("raw compression failed");
+        let out = lzo
+            .decompress_safe(&compressed, data.len())
+            .expect("raw decompression failed");
+        check("raw", data, &out);
+    }
+
+    let framed = lzo
+        .compress_chunked(data)
+        .expect("framed compression failed");
+    let out = decompress_chunked(&framed).expect("framed decompression failed");
+    check("compress_chunked", data, &out);
+
+    let mut writer = LzoWriter::with_block_size(Vec::new(), 4096).expect("LzoWriter failed");
+    for piece in data.chunks(1000) {
+        writer.write_all(piece).expect("LzoWriter write failed");
+    }
+    let framed = writer.finish().expect("LzoWriter finish failed");
+    let mut out = Vec::new();
+    LzoReader::new(&framed[..])
+        .expect("LzoReader failed")
+        .read_to_end(&mut out)
+        .expect("LzoReader read failed");
+    check("LzoWriter", data, &out);
+
+    let mut writer = LzopWriter::new(Vec::new()).expect("LzopWriter failed");
+    writer.write_all(data).expect("LzopWriter write failed");
+    let lzop = writer.finish().expect("LzopWriter finish failed");
+    let mut out = Vec::new();
+    LzopReader::new(&lzop[..])
+        .expect("LzopReader failed")
+        .read_to_end(&mut out)
+        .expect("LzopReader read failed");
+    check("lzop", data, &out);
+}
+
+#[track_caller]
+fn check(format: &str, expected: &[u8], actual: &[u8]) {
+    if let Some(at) = expected.iter().zip(actual).position(|(a, b)| a != b) {
+        panic!(
+            "{} round trip differs at byte {} of {}",
+            format,
+            at,
+            expected.len()
+        );
+    }
+    assert!(
+        expected.len() == actual.len(),
+        "{} round trip gave {} bytes instead of {}",
+        format,
+        actual.len(),
+        expected.len()
+    );
+}
+
+/// A xorshift generator, for output that doesn't depend on the platform.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero would get stuck.
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+const WORDS: [&str; 16] = [
+    "the",
+    "frame",
+    "block",
+    "of",
+    "stream",
+    "and",
+    "data",
+    "is",
+    "compressed",
+    "to",
+    "a",
+    "header",
+    "with",
+    "checksum",
+    "in",
+    "order",
+];
+
+/// `len` bytes of text-like data drawn from a small vocabulary, which
+/// compresses well.
+pub fn compressible(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    let mut out = Vec::with_capacity(len + 16);
+    while out.len() < len {
+        out.extend_from_slice(WORDS[rng.below(WORDS.len())].as_bytes());
+        out.push(if rng.below(12) == 0 { b'\n' } else { b' ' });
+    }
+    out.truncate(len);
+    out
+}
+
+/// `len` bytes of uniform noise, which LZO can't shrink.
+pub fn incompressible(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    (0..len).map(|_| (rng.next() >> 56) as u8).collect()
+}
+
+/// `len` bytes mixing what stresses an LZO coder: long runs, short
+/// periods, minimal matches between literals, matches at the largest
+/// distance LZO1X reaches, end-marker lookalikes and every byte value.
+pub fn pathological(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    let mut out: Vec<u8> = Vec::with_capacity(len + 4096);
+    while out.len() < len {
+        match rng.below(6) {
+            0 => {
+                let byte = if rng.below(2) == 0 {
+                    0
+                } else {
+                    rng.next() as u8
+                };
+                let run = 1 + rng.below(70_000);
+                out.resize(out.len() + run, byte);
+            }
+            1 => {
+                let period = 1 + rng.below(7);
+                let pattern: Vec<u8> = (0..period).map(|_| rng.next() as u8).collect();
+                out.extend(pattern.iter().cycle().take(1 + rng.below(2000)));
+            }
+            2 => {
+                for _ in 0..rng.below(500) {
+                    let byte = rng.next() as u8;
+                    out.extend_from_slice(&[byte, b'x', byte, b'y', byte]);
+                }
+            }
+            3 if out.len() > 0xbfff + 64 => {
+                // LZO1X's farthest match distance.
+                let from = out.len() - 0xbfff;
+                let copy = out[from..from + 3 + rng.below(60)].to_vec();
+                out.extend_from_slice(&copy);
+            }
+            4 => {
+                for _ in 0..1 + rng.below(50) {
+                    out.extend_from_slice(&[0x11, 0x00, 0x00]);
+                    out.push(rng.next() as u8);
+                }
+            }
+            _ => out.extend(0..=255u8),
+        }
+    }
+    out.truncate(len);
+    out
+}
+
+/// A named set of inputs: the edge cases (empty, one byte, one short of,
+/// at and one past the default block size) and each generator at a small
+/// size and one spanning several blocks.
+pub fn corpus(seed: u64) -> Vec<(&'static str, Vec<u8>)> {
+    let block = crate::DEFAULT_BLOCK_SIZE;
+    vec![
+        ("empty", Vec::new()),
+        ("one_byte", vec![0x61]),
+        ("block_minus_one", compressible(block - 1, seed)),
+        ("block", pathological(block, seed)),
+        ("block_plus_one", incompressible(block + 1, seed)),
+        ("compressible_4k", compressible(4096, seed)),
+        ("compressible_1m", compressible(1 << 20, seed)),
+        ("incompressible_4k", incompressible(4096, seed)),
+        ("incompressible_1m", incompressible(1 << 20, seed)),
+        ("pathological_4k", pathological(4096, seed)),
+        ("pathological_1m", pathological(1 << 20, seed)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors_and_generators() {
+        for vector in FRAME_VECTORS {
+            let mut writer = LzoWriter::new(Vec::new()).unwrap();
+            writer.write_all(vector.data).unwrap();
+            assert_eq!(writer.finish().unwrap(), vector.encoded, "{}", vector.name);
+            let decoded = decompress_chunked(vector.encoded).unwrap();
+            assert_eq!(decoded, vector.data, "{}", vector.name);
+        }
+        for vector in LZOP_VECTORS {
+            let mut writer = LzopWriter::new(Vec::new()).unwrap();
+            writer.write_all(vector.data).unwrap();
+            assert_eq!(writer.finish().unwrap(), vector.encoded, "{}", vector.name);
+            let mut decoded = Vec::new();
+            let mut reader = LzopReader::new(vector.encoded).unwrap();
+            reader.read_to_end(&mut decoded).unwrap();
+            assert_eq!(decoded, vector.data, "{}", vector.name);
+        }
+
+        assert_eq!(compressible(1000, 3), compressible(1000, 3));
+        assert_ne!(incompressible(1000, 3), incompressible(1000, 4));
+        let ratio = |data: &[u8]| LZO::new().compress(data).unwrap().len() * 100 / data.len();
+        assert!(ratio(&compressible(100_000, 1)) < 40);
+        assert!(ratio(&incompressible(100_000, 1)) >= 100);
+        for (name, data) in corpus(11) {
+            assert_roundtrip(&data);
+            assert!(name.len() > 2);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "differs at byte 2")]
+    fn test_mismatch_is_reported() {
+        check("raw", b"abcd", b"abXd");
+    }
+}
@@ -0,0 +1,240 @@
+//! Tokio `AsyncWrite`/`AsyncRead` adapters over the framed format.
+//!
+//! These produce and consume exactly the same streams as `LzoWriter` and
+//! `LzoReader`. Blocks are compressed inline in the poll functions, which is
+//! cheap enough at the default block size not to need `spawn_blocking`.
+
+use crate::frame;
+use crate::{LZOResult, StreamDecoder, StreamEncoder};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Compresses everything written to it into a framed stream.
+///
+/// `poll_shutdown` writes the end marker before shutting down the inner
+/// writer.
+///
+/// At most one block of input and its compressed form are held: input is
+/// only taken while no compressed data is waiting for the inner writer, so
+/// while a slow inner writer catches up, writes return `Poll::Pending`.
+pub struct AsyncLzoWriter<W> {
+    inner: W,
+    encoder: StreamEncoder,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncLzoWriter<W> {
+    /// Create a writer using `DEFAULT_BLOCK_SIZE` blocks.
+    pub fn new(inner: W) -> LZOResult<Self> {
+        Ok(AsyncLzoWriter {
+            inner,
+            encoder: StreamEncoder::new()?,
+        })
+    }
+
+    /// Create a writer that compresses `block_size` bytes at a time.
+    pub fn with_block_size(inner: W, block_size: usize) -> LZOResult<Self> {
+        Ok(AsyncLzoWriter {
+            inner,
+            encoder: StreamEncoder::with_block_size(block_size)?,
+        })
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Unwrap the writer, returning the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.encoder.pending_output().is_empty() {
+            let pending = self.encoder.pending_output();
+            let n = match Pin::new(&mut self.inner).poll_write(cx, pending) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.encoder.consume_output(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncLzoWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.encoder.is_finished() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        if !this.encoder.pending_output().is_empty() {
+            return Poll::Pending;
+        }
+        Poll::Ready(this.encoder.feed_input(buf).map_err(frame::to_io))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.encoder.finish().map_err(frame::to_io)?;
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+/// Decompresses a framed stream read from the inner reader.
+pub struct AsyncLzoReader<R> {
+    inner: R,
+    decoder: StreamDecoder,
+}
+
+impl<R: AsyncRead + Unpin> AsyncLzoReader<R> {
+    /// Create a reader decoding the framed stream in `inner`.
+    pub fn new(inner: R) -> LZOResult<Self> {
+        Ok(AsyncLzoReader {
+            inner,
+            decoder: StreamDecoder::new()?,
+        })
+    }
+
+    /// Fail with `Error::OutputLimitExceeded` once the stream would
+    /// decompress to more than `limit` bytes; see
+    /// `LzoReader::max_output_size`.
+    pub fn max_output_size(self, limit: u64) -> Self {
+        AsyncLzoReader {
+            inner: self.inner,
+            decoder: self.decoder.max_output_size(limit),
+        }
+    }
+
+    /// Unwrap the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncLzoReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut chunk = [0u8; 8192];
+        loop {
+            let n = this
+                .decoder
+                .read_output(buf.initialize_unfilled())
+                .map_err(|e| this.decoder.locate(e))?;
+            if n > 0 || buf.remaining() == 0 || this.decoder.is_done() {
+                buf.advance(n);
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.decoder.feed_input(chunk_buf.filled());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let input: Vec<u8> = (0..100_000u32).map(|i| (i % 89) as u8).collect();
+        let mut writer = AsyncLzoWriter::with_block_size(Vec::new(), 4096).unwrap();
+        writer.write_all(&input).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let framed = writer.into_inner();
+
+        let mut sync_reader = crate::LzoReader::new(&framed[..]).unwrap();
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut sync_reader, &mut output).unwrap();
+        assert_eq!(output, input);
+
+        let mut reader = AsyncLzoReader::new(&framed[..]).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_async_writer_backpressure() {
+        /// An inner writer that takes `room` bytes and then stalls.
+        struct Stalling {
+            room: usize,
+        }
+        impl AsyncWrite for Stalling {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                let n = buf.len().min(self.room);
+                if n == 0 {
+                    return Poll::Pending;
+                }
+                self.room -= n;
+                Poll::Ready(Ok(n))
+            }
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+            fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let noise: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut writer = AsyncLzoWriter::with_block_size(Stalling { room: 100 }, 4096).unwrap();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut taken = 0;
+        loop {
+            match Pin::new(&mut writer).poll_write(&mut cx, &noise[taken..]) {
+                Poll::Ready(Ok(n)) => taken += n,
+                Poll::Ready(Err(e)) => panic!("{}", e),
+                Poll::Pending => break,
+            }
+        }
+        // One block was taken; the rest waits for the inner writer.
+        assert_eq!(taken, 4096);
+        assert!(Pin::new(&mut writer)
+            .poll_write(&mut cx, &noise[taken..])
+            .is_pending());
+    }
+}
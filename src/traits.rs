@@ -0,0 +1,140 @@
+//! Object-safe traits over the compressor types, for picking an
+//! implementation at runtime or substituting one in tests.
+
+use crate::{CompressError, Compressor, DecompressError, Decompressor, PooledLzo, LZO};
+
+/// Something that compresses whole buffers.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::{Compress, Decompress, Store, LZO};
+///
+/// fn pick(compress: bool) -> (Box<dyn Compress>, Box<dyn Decompress>) {
+///     if compress {
+///         (Box::new(LZO::new()), Box::new(LZO::new()))
+///     } else {
+///         (Box::new(Store), Box::new(Store))
+///     }
+/// }
+///
+/// for choice in [true, false] {
+///     let (mut compressor, decompressor) = pick(choice);
+///     let out = compressor.compress(&[0x00u8; 1024]).unwrap();
+///     assert_eq!(decompressor.decompress(&out, 1024).unwrap(), vec![0x00u8; 1024]);
+/// }
+/// ```
+pub trait Compress {
+    /// Compress `src`.
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError>;
+}
+
+/// Something that decompresses whole buffers of known size.
+pub trait Decompress {
+    /// Decompress `src` into at most `dst_len` bytes, with overrun testing.
+    fn decompress(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError>;
+}
+
+/// Stores data as is, for when compression is switched off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Store;
+
+impl Compress for Store {
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        Ok(src.to_vec())
+    }
+}
+
+impl Decompress for Store {
+    fn decompress(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+        if src.len() > dst_len {
+            return Err(DecompressError::OutputOverrun {
+                capacity: dst_len,
+                needed: Some(src.len()),
+            });
+        }
+        Ok(src.to_vec())
+    }
+}
+
+impl Compress for LZO {
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        LZO::compress(self, src)
+    }
+}
+
+impl Decompress for LZO {
+    fn decompress(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+        self.decompress_safe(src, dst_len)
+    }
+}
+
+impl Compress for Compressor {
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        Compressor::compress(self, src)
+    }
+}
+
+impl Decompress for Decompressor {
+    fn decompress(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+        self.decompress_safe(src, dst_len)
+    }
+}
+
+impl Compress for PooledLzo<'_> {
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        LZO::compress(self, src)
+    }
+}
+
+impl<T: Compress + ?Sized> Compress for &mut T {
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        (**self).compress(src)
+    }
+}
+
+impl<T: Compress + ?Sized> Compress for Box<T> {
+    fn compress(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        (**self).compress(src)
+    }
+}
+
+impl<T: Decompress + ?Sized> Decompress for &T {
+    fn decompress(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+        (**self).decompress(src, dst_len)
+    }
+}
+
+impl<T: Decompress + ?Sized> Decompress for Box<T> {
+    fn decompress(&self, src: &[u8], dst_len: usize) -> Result<Vec<u8>, DecompressError> {
+        (**self).decompress(src, dst_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzoPool;
+
+    fn roundtrip(compress: &mut dyn Compress, decompress: &dyn Decompress) {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+        let out = compress.compress(&input).unwrap();
+        assert_eq!(decompress.decompress(&out, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    fn test_trait_objects() {
+        let decompressor = Decompressor::new().unwrap();
+        roundtrip(&mut LZO::new(), &LZO::new());
+        roundtrip(&mut Compressor::new(), &decompressor);
+        roundtrip(&mut LzoPool::new(1).unwrap().get(), &decompressor);
+        roundtrip(&mut Store, &Store);
+        assert_eq!(
+            Store.decompress(b"abc", 2),
+            Err(DecompressError::OutputOverrun {
+                capacity: 2,
+                needed: Some(3)
+            })
+        );
+    }
+}
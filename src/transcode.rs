@@ -0,0 +1,272 @@
+//! Converting compressed data between containers a block at a time.
+
+use crate::builder::Checksum;
+use crate::frame::{self, BLOCK_HEADER_LEN};
+use crate::scratch::try_zeroed;
+use crate::{
+    BlockMeta, Decompressor, Error, LzoReader, LzoWriter, LzopReader, LzopWriter,
+    DEFAULT_BLOCK_SIZE, LZO, MAX_BLOCK_SIZE,
+};
+use std::io::{self, Read, Write};
+
+/// A container `transcode` reads or writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Container {
+    /// Bare blocks, each a `BlockMeta` and its payload with the adler32 of
+    /// its data, one after another up to the end of the input: what
+    /// `LzoCodec` sends, without the framed format's header and end marker.
+    Blocks,
+    /// The crate's framed format, as written by `LzoWriter`.
+    Framed,
+    /// An lzop file, as written by `LzopWriter` or the `lzop` tool.
+    Lzop,
+}
+
+/// Convert the `from` data read from `input` into `to`, written to
+/// `output`, returning `output`.
+///
+/// Every block is decompressed, checked and recompressed on its way
+/// through, so only about one block of each format is held at a time,
+/// however large the input. To move data into an archive, where
+/// `ArchiveReader::read_range` can reach any part of it, use
+/// `ArchiveWriter::add_transcoded`.
+///
+/// Example
+///
+/// ```rust
+/// use minilzo_rs::Container;
+/// use std::io::Write;
+///
+/// let mut writer = minilzo_rs::LzopWriter::new(Vec::new()).unwrap();
+/// writer.write_all(&b"log line\n".repeat(10_000)).unwrap();
+/// let lzop = writer.finish().unwrap();
+///
+/// let framed =
+///     minilzo_rs::transcode(&lzop[..], Container::Lzop, Vec::new(), Container::Framed).unwrap();
+/// let data = minilzo_rs::decompress_chunked(&framed).unwrap();
+/// assert_eq!(data, b"log line\n".repeat(10_000));
+/// ```
+pub fn transcode<R: Read, W: Write>(
+    input: R,
+    from: Container,
+    output: W,
+    to: Container,
+) -> io::Result<W> {
+    let mut decoder = Decoder::new(input, from)?;
+    match to {
+        Container::Blocks => {
+            let mut writer = BlocksWriter::new(output);
+            io::copy(&mut decoder, &mut writer)?;
+            writer.finish()
+        }
+        Container::Framed => {
+            let mut writer = LzoWriter::new(output).map_err(frame::to_io)?;
+            io::copy(&mut decoder, &mut writer)?;
+            writer.finish()
+        }
+        Container::Lzop => {
+            let mut writer = LzopWriter::new(output).map_err(frame::to_io)?;
+            io::copy(&mut decoder, &mut writer)?;
+            writer.finish()
+        }
+    }
+}
+
+/// Reads the data of any `Container`.
+pub(crate) enum Decoder<R: Read> {
+    Blocks(BlocksReader<R>),
+    Framed(Box<LzoReader<R>>),
+    Lzop(LzopReader<R>),
+}
+
+impl<R: Read> Decoder<R> {
+    pub(crate) fn new(input: R, from: Container) -> io::Result<Self> {
+        Ok(match from {
+            Container::Blocks => Decoder::Blocks(BlocksReader::new(input)?),
+            Container::Framed => {
+                Decoder::Framed(Box::new(LzoReader::new(input).map_err(frame::to_io)?))
+            }
+            Container::Lzop => Decoder::Lzop(LzopReader::new(input)?),
+        })
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoder::Blocks(reader) => reader.read(buf),
+            Decoder::Framed(reader) => reader.read(buf),
+            Decoder::Lzop(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Decodes `Container::Blocks` a block at a time.
+pub(crate) struct BlocksReader<R> {
+    inner: R,
+    decompressor: Decompressor,
+    block: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> BlocksReader<R> {
+    fn new(inner: R) -> io::Result<Self> {
+        Ok(BlocksReader {
+            inner,
+            decompressor: Decompressor::new().map_err(frame::to_io)?,
+            block: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Decode the next block into `self.block`, returning false at the end
+    /// of the input.
+    fn next_block(&mut self) -> io::Result<bool> {
+        let mut head = [0u8; BLOCK_HEADER_LEN];
+        let mut filled = 0;
+        while filled < head.len() {
+            match self.inner.read(&mut head[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let meta = BlockMeta::read_from(&head);
+        if meta.uncompressed_len as usize > MAX_BLOCK_SIZE
+            || meta.compressed_len as usize > crate::compress_bound(MAX_BLOCK_SIZE)
+        {
+            return Err(frame::to_io(Error::InvalidHeader));
+        }
+        let mut payload = try_zeroed(meta.compressed_len as usize).map_err(Error::from)?;
+        self.inner.read_exact(&mut payload)?;
+        self.block = if meta.uncompressed_len == 0 {
+            Vec::new()
+        } else {
+            frame::decode_block(&self.decompressor, &meta, &payload, Checksum::Adler32)
+                .map_err(frame::to_io)?
+        };
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for BlocksReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.block.len() {
+            if buf.is_empty() || !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Encodes `Container::Blocks`, a `DEFAULT_BLOCK_SIZE` block at a time.
+struct BlocksWriter<W: Write> {
+    inner: W,
+    lzo: LZO,
+    block: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl<W: Write> BlocksWriter<W> {
+    fn new(inner: W) -> Self {
+        BlocksWriter {
+            inner,
+            lzo: LZO::new(),
+            block: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        self.out.clear();
+        frame::encode_block(&mut self.lzo, &self.block, &mut self.out).map_err(frame::to_io)?;
+        self.block.clear();
+        self.inner.write_all(&self.out)
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BlocksWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(DEFAULT_BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() == DEFAULT_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArchiveReader, ArchiveWriter};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_transcode_between_containers() {
+        let data: Vec<u8> = (0..700_000u32).map(|i| (i / 7 % 253) as u8).collect();
+        let containers = [Container::Blocks, Container::Framed, Container::Lzop];
+        let framed = LZO::new().compress_chunked(&data).unwrap();
+        let encoded: Vec<Vec<u8>> = containers
+            .iter()
+            .map(|&to| transcode(&framed[..], Container::Framed, Vec::new(), to).unwrap())
+            .collect();
+        for (&from, input) in containers.iter().zip(&encoded) {
+            for (&to, expected) in containers.iter().zip(&encoded) {
+                let out = transcode(&input[..], from, Vec::new(), to).unwrap();
+                assert_eq!(&out, expected, "{:?} -> {:?}", from, to);
+            }
+        }
+        assert!(encoded[0].len() < data.len() / 2);
+
+        // Into an archive, reachable by range.
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer
+            .add_transcoded("data.bin", &encoded[2][..], Container::Lzop)
+            .unwrap();
+        let mut reader = ArchiveReader::open(Cursor::new(writer.finish().unwrap())).unwrap();
+        assert_eq!(
+            reader.read_range("data.bin", 500_000..500_100).unwrap(),
+            &data[500_000..500_100]
+        );
+
+        // Empty input, and bare blocks cut short or damaged.
+        assert!(
+            transcode(&b""[..], Container::Blocks, Vec::new(), Container::Blocks)
+                .unwrap()
+                .is_empty()
+        );
+        let blocks = &encoded[0];
+        let cut = &blocks[..blocks.len() - 1];
+        assert!(transcode(cut, Container::Blocks, Vec::new(), Container::Framed).is_err());
+        let mut damaged = blocks.clone();
+        damaged[BLOCK_HEADER_LEN + 20] ^= 0x40;
+        assert!(transcode(
+            &damaged[..],
+            Container::Blocks,
+            Vec::new(),
+            Container::Framed
+        )
+        .is_err());
+    }
+}
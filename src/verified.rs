@@ -0,0 +1,112 @@
+//! Compressed blocks carrying their own checksum.
+//!
+//! The block is the raw LZO1X stream followed by the adler32 of the
+//! uncompressed data as a big-endian `u32`, the byte order the framed
+//! format uses too.
+
+use crate::{adler32, CompressError, DecompressError, Decompressor, LZO};
+
+const TRAILER_LEN: usize = 4;
+
+/// Split `src` into the compressed stream and the checksum it carries.
+fn split_trailer(src: &[u8]) -> Result<(&[u8], u32), DecompressError> {
+    if src.len() < TRAILER_LEN {
+        return Err(DecompressError::InputOverrun);
+    }
+    let (body, trailer) = src.split_at(src.len() - TRAILER_LEN);
+    Ok((
+        body,
+        u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]),
+    ))
+}
+
+fn verify(data: Vec<u8>, checksum: u32) -> Result<Vec<u8>, DecompressError> {
+    if adler32(&data) != checksum {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+    Ok(data)
+}
+
+impl LZO {
+    /// Compress `src` and append the adler32 of `src`, for
+    /// `decompress_verified` to check.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let mut lzo = minilzo_rs::LZO::new();
+    /// let mut block = lzo.compress_with_checksum(&[0x00u8; 1024]).unwrap();
+    /// assert_eq!(lzo.decompress_verified(&block, 1024).unwrap(), vec![0x00u8; 1024]);
+    ///
+    /// let last = block.len() - 1;
+    /// block[last] ^= 1;
+    /// assert_eq!(
+    ///     lzo.decompress_verified(&block, 1024),
+    ///     Err(minilzo_rs::DecompressError::ChecksumMismatch)
+    /// );
+    /// ```
+    pub fn compress_with_checksum(&mut self, src: &[u8]) -> Result<Vec<u8>, CompressError> {
+        let mut out = self.compress(src)?;
+        out.extend_from_slice(&adler32(src).to_be_bytes());
+        Ok(out)
+    }
+
+    /// Decompress a block from `compress_with_checksum` into at most
+    /// `dst_len` bytes, failing with `DecompressError::ChecksumMismatch` if
+    /// the output doesn't match the checksum it carries.
+    pub fn decompress_verified(
+        &self,
+        src: &[u8],
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        let (body, checksum) = split_trailer(src)?;
+        verify(self.decompress_safe(body, dst_len)?, checksum)
+    }
+}
+
+impl Decompressor {
+    /// Decompress a block from `LZO::compress_with_checksum`; see
+    /// `LZO::decompress_verified`.
+    pub fn decompress_verified(
+        &self,
+        src: &[u8],
+        dst_len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        let (body, checksum) = split_trailer(src)?;
+        verify(self.decompress_safe(body, dst_len)?, checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verified_roundtrip_and_damage() {
+        let input: Vec<u8> = (0..20_000u32).map(|i| (i % 37) as u8).collect();
+        let mut lzo = LZO::new();
+        let block = lzo.compress_with_checksum(&input).unwrap();
+        assert_eq!(
+            &block[block.len() - 4..],
+            &adler32(&input).to_be_bytes()[..]
+        );
+        let decompressor = Decompressor::new().unwrap();
+        assert_eq!(
+            decompressor
+                .decompress_verified(&block, input.len())
+                .unwrap(),
+            input
+        );
+
+        let mut damaged = block.clone();
+        damaged[block.len() - 2] ^= 0x10;
+        assert_eq!(
+            lzo.decompress_verified(&damaged, input.len()),
+            Err(DecompressError::ChecksumMismatch)
+        );
+        assert_eq!(
+            lzo.decompress_verified(&block[..3], input.len()),
+            Err(DecompressError::InputOverrun)
+        );
+    }
+}
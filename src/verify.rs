@@ -0,0 +1,298 @@
+//! Integrity checks that don't keep the decompressed data.
+//!
+//! A raw LZO1X stream is checked by walking its instructions, counting the
+//! bytes each would produce and checking every match against the output
+//! so far, without writing anything: the same checks, in the same order,
+//! as minilzo's safe decoder. A framed stream is decoded one block at a
+//! time into a buffer that is reused, so each block's checksum is checked
+//! while memory stays at one block.
+
+use crate::{DecompressError, Decompressor, LzoReader, LZO};
+use std::io::{self, Read};
+
+/// Where the decoder is between instructions.
+enum State {
+    /// Expecting a literal run or a match.
+    Loop,
+    /// Just after a literal run, where a short instruction is a match with
+    /// a longer distance.
+    FirstLiteralRun,
+    /// Decoding the match instruction `t`.
+    Match(usize),
+    /// Copying the `t` literals that trail a match.
+    MatchNext(usize),
+}
+
+struct Walker<'a> {
+    src: &'a [u8],
+    ip: usize,
+    op: usize,
+}
+
+impl Walker<'_> {
+    /// Fail like the decoder's `NEED_IP` unless `n` more bytes remain.
+    fn need(&self, n: usize) -> Result<(), DecompressError> {
+        if self.src.len() - self.ip < n {
+            return Err(DecompressError::InputOverrun);
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self) -> Result<usize, DecompressError> {
+        let byte = *self.src.get(self.ip).ok_or(DecompressError::InputOverrun)?;
+        self.ip += 1;
+        Ok(byte as usize)
+    }
+
+    fn le16(&mut self) -> Result<usize, DecompressError> {
+        Ok(self.byte()? | self.byte()? << 8)
+    }
+
+    /// A length extended by zero bytes, each adding 255, then `base` and a
+    /// final nonzero byte.
+    fn run(&mut self, base: usize) -> Result<usize, DecompressError> {
+        let mut t = 0usize;
+        while self.src.get(self.ip) == Some(&0) {
+            t = t.saturating_add(255);
+            self.ip += 1;
+            self.need(1)?;
+        }
+        Ok(t.saturating_add(base + self.byte()?))
+    }
+
+    /// Count `n` more bytes of output, failing if the total can't be
+    /// represented on this target.
+    fn produce(&mut self, n: usize) -> Result<(), DecompressError> {
+        self.op = self
+            .op
+            .checked_add(n)
+            .ok_or(DecompressError::OutputOverrun {
+                capacity: usize::MAX,
+                needed: None,
+            })?;
+        Ok(())
+    }
+
+    fn literals(&mut self, n: usize) -> Result<(), DecompressError> {
+        self.ip += n;
+        self.produce(n)
+    }
+
+    fn copy_match(&mut self, distance: usize, len: usize) -> Result<State, DecompressError> {
+        if distance > self.op {
+            return Err(DecompressError::LookbehindOverrun);
+        }
+        self.produce(len)?;
+        // The low bits of the instruction's second-to-last byte count the
+        // literals that follow.
+        Ok(match self.src[self.ip - 2] & 3 {
+            0 => State::Loop,
+            t => State::MatchNext(t as usize),
+        })
+    }
+
+    fn walk(mut self) -> Result<usize, DecompressError> {
+        self.need(1)?;
+        let mut state = if self.src[0] > 17 {
+            let t = self.byte()? - 17;
+            if t < 4 {
+                State::MatchNext(t)
+            } else {
+                self.need(t + 3)?;
+                self.literals(t)?;
+                State::FirstLiteralRun
+            }
+        } else {
+            State::Loop
+        };
+        loop {
+            state = match state {
+                State::Loop => {
+                    self.need(3)?;
+                    match self.byte()? {
+                        t if t >= 16 => State::Match(t),
+                        t => {
+                            let t = if t == 0 { self.run(15)? } else { t };
+                            self.need(t.saturating_add(6))?;
+                            self.literals(t.saturating_add(3))?;
+                            State::FirstLiteralRun
+                        }
+                    }
+                }
+                State::FirstLiteralRun => match self.byte()? {
+                    t if t >= 16 => State::Match(t),
+                    t => {
+                        let distance = 1 + 0x0800 + (t >> 2) + (self.byte()? << 2);
+                        self.copy_match(distance, 3)?
+                    }
+                },
+                State::Match(t) if t >= 64 => {
+                    let distance = 1 + ((t >> 2) & 7) + (self.byte()? << 3);
+                    self.copy_match(distance, (t >> 5) + 1)?
+                }
+                State::Match(t) if t >= 32 => {
+                    let len = match t & 31 {
+                        0 => {
+                            let len = self.run(31)?;
+                            self.need(2)?;
+                            len
+                        }
+                        len => len,
+                    };
+                    let distance = 1 + (self.le16()? >> 2);
+                    self.copy_match(distance, len.saturating_add(2))?
+                }
+                State::Match(t) if t >= 16 => {
+                    let len = match t & 7 {
+                        0 => {
+                            let len = self.run(7)?;
+                            self.need(2)?;
+                            len
+                        }
+                        len => len,
+                    };
+                    let distance = ((t & 8) << 11) + (self.le16()? >> 2);
+                    if distance == 0 {
+                        break;
+                    }
+                    self.copy_match(distance + 0x4000, len.saturating_add(2))?
+                }
+                State::Match(t) => {
+                    let distance = 1 + (t >> 2) + (self.byte()? << 2);
+                    self.copy_match(distance, 2)?
+                }
+                State::MatchNext(t) => {
+                    self.need(t + 3)?;
+                    self.literals(t)?;
+                    State::Match(self.byte()?)
+                }
+            };
+        }
+        if self.ip < self.src.len() {
+            return Err(DecompressError::InputNotConsumed {
+                remaining: self.src.len() - self.ip,
+            });
+        }
+        Ok(self.op)
+    }
+}
+
+impl Decompressor {
+    /// Check that `src` is a well-formed LZO1X stream and return the size
+    /// it decompresses to, without producing the output.
+    ///
+    /// Fails with the error `decompress_safe` would give with a large
+    /// enough buffer. Without the data there is nothing to checksum, so a
+    /// stream that decodes cleanly to the wrong bytes passes; use
+    /// `verify_stream` on framed streams, whose blocks carry checksums.
+    ///
+    /// Example
+    ///
+    /// ```rust
+    /// let out = minilzo_rs::compress(&[0x42u8; 1 << 20]).unwrap();
+    ///
+    /// let decompressor = minilzo_rs::Decompressor::new().unwrap();
+    /// assert_eq!(decompressor.verify(&out), Ok(1 << 20));
+    /// assert!(decompressor.verify(&out[..out.len() - 1]).is_err());
+    /// ```
+    pub fn verify(&self, src: &[u8]) -> Result<usize, DecompressError> {
+        Walker { src, ip: 0, op: 0 }.walk()
+    }
+}
+
+impl LZO {
+    /// Check a raw LZO1X stream without producing the output; see
+    /// `Decompressor::verify`.
+    pub fn verify(&self, src: impl AsRef<[u8]>) -> Result<usize, DecompressError> {
+        self.check_decompress(0)?;
+        Decompressor::initialized().verify(src.as_ref())
+    }
+}
+
+/// Check a framed stream, decoding each block and checking its checksum
+/// but keeping none of the data, and return the decompressed size.
+///
+/// Memory use is bounded by one block however large the stream is, which
+/// suits checking backups.
+///
+/// Example
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// let mut writer = minilzo_rs::LzoWriter::new(Vec::new()).unwrap();
+/// writer.write_all(&[7u8; 300_000]).unwrap();
+/// let mut framed = writer.finish().unwrap();
+/// assert_eq!(minilzo_rs::verify_stream(&framed[..]).unwrap(), 300_000);
+///
+/// let last_block = framed.len() - 10;
+/// framed[last_block] ^= 1;
+/// assert!(minilzo_rs::verify_stream(&framed[..]).is_err());
+/// ```
+pub fn verify_stream<R: Read>(reader: R) -> io::Result<u64> {
+    let mut reader = LzoReader::new(reader).map_err(crate::frame::to_io)?;
+    io::copy(&mut reader, &mut io::sink())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_agrees_with_decoder() {
+        let decompressor = Decompressor::new().unwrap();
+        let mut x = 0x9e37_79b9u32;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x
+        };
+        for round in 0..200 {
+            let len = (next() % 70_000) as usize;
+            let input: Vec<u8> = (0..len)
+                .map(|i| match round % 3 {
+                    0 => next() as u8,
+                    1 => (i % 251) as u8 ^ (next() % 4 == 0) as u8,
+                    _ => (next() % 3) as u8,
+                })
+                .collect();
+            let mut out = LZO::new().compress(&input).unwrap();
+            assert_eq!(decompressor.verify(&out), Ok(input.len()));
+
+            // Damage the stream and compare with what the decoder makes of it.
+            match next() % 3 {
+                0 => out.truncate(next() as usize % (out.len() + 1)),
+                1 => out.extend_from_slice(&[0x11, 0, 0][..(next() % 4).min(3) as usize]),
+                _ => {
+                    let at = next() as usize % out.len();
+                    out[at] = next() as u8;
+                }
+            }
+            let expected = decompressor
+                .decompress_safe(&out, 1 << 22)
+                .map(|data| data.len());
+            if !matches!(expected, Err(DecompressError::OutputOverrun { .. })) {
+                assert_eq!(decompressor.verify(&out), expected, "round {}", round);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_output_size_overflow() {
+        let out = LZO::new().compress([7u8; 1000]).unwrap();
+        // As if the stream came after more output than the target can count.
+        let walker = Walker {
+            src: &out,
+            ip: 0,
+            op: usize::MAX - 10,
+        };
+        assert_eq!(
+            walker.walk(),
+            Err(DecompressError::OutputOverrun {
+                capacity: usize::MAX,
+                needed: None
+            })
+        );
+    }
+}
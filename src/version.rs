@@ -0,0 +1,84 @@
+//! Version and capability introspection.
+
+use crate::{frame, minilzo, Algorithm};
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_char;
+
+/// What this build supports, for logs and capability negotiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// The crate's version.
+    pub crate_version: &'static str,
+    /// The bundled minilzo's version, e.g. `"2.10"`.
+    pub minilzo_version: &'static str,
+    /// The bundled minilzo's release date, e.g. `"Mar 01 2017"`.
+    pub minilzo_date: &'static str,
+    /// The version of the framed stream format written and read.
+    pub frame_version: u8,
+    /// The algorithms `LZO::builder` can build.
+    pub algorithms: &'static [Algorithm],
+}
+
+/// Formats as one line, e.g.
+/// `minilzo-rs 0.6.0 (minilzo 2.10, Mar 01 2017; frame v1; lzo1x-1, auto)`.
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "minilzo-rs {} (minilzo {}, {}; frame v{};",
+            self.crate_version, self.minilzo_version, self.minilzo_date, self.frame_version
+        )?;
+        for (i, algorithm) in self.algorithms.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, algorithm)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// A string returned by minilzo, which are static and ASCII.
+fn c_str(s: *const c_char) -> &'static str {
+    unsafe { CStr::from_ptr(s) }.to_str().unwrap_or("")
+}
+
+/// The crate, bundled minilzo and frame format versions, and the supported
+/// algorithms.
+///
+/// Example
+///
+/// ```rust
+/// let info = minilzo_rs::version();
+/// assert_eq!(info.minilzo_version, "2.10");
+/// assert!(info.algorithms.contains(&minilzo_rs::Algorithm::Lzo1x1));
+/// println!("{}", info);
+/// ```
+pub fn version() -> VersionInfo {
+    VersionInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        minilzo_version: c_str(unsafe { minilzo::lzo_version_string() }),
+        minilzo_date: c_str(unsafe { minilzo::lzo_version_date() }),
+        frame_version: frame::VERSION,
+        // minilzo only ships LZO1X-1, which `Auto` picks from.
+        algorithms: &[Algorithm::Lzo1x1, Algorithm::Auto],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_info() {
+        let info = version();
+        assert_eq!(
+            info.to_string(),
+            format!(
+                "minilzo-rs {} (minilzo 2.10, {}; frame v1; lzo1x-1, auto)",
+                env!("CARGO_PKG_VERSION"),
+                info.minilzo_date
+            )
+        );
+        assert!(!info.minilzo_date.is_empty());
+    }
+}